@@ -0,0 +1,69 @@
+//! Parsing, writing, and diffing a YAML of 10,000 packages.
+//!
+//! `nixman status` on such a file should stay under about 50 ms; run with `cargo bench`.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use nixman::{Config, InstallReason, PackageList};
+use std::collections::BTreeMap;
+
+const PACKAGES: usize = 10_000;
+
+/// A YAML declaring `PACKAGES` packages, a tenth of them as versioned or dependency entries.
+fn large_yaml() -> String {
+    let mut yml = String::from("schema: 2\npackages:\n");
+    for i in 0..PACKAGES {
+        match i % 10 {
+            0 => yml.push_str(&format!("  - name: package-{i}\n    version: 1.{i}-1\n")),
+            1 => yml.push_str(&format!("  - name: package-{i}\n    reason: dependency\n")),
+            _ => yml.push_str(&format!("  - package-{i}\n")),
+        }
+    }
+    yml
+}
+
+/// Installed names overlapping the declared ones by half.
+fn installed() -> Vec<String> {
+    (PACKAGES / 2..PACKAGES * 3 / 2)
+        .map(|i| format!("package-{i}"))
+        .collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let yml = large_yaml();
+    c.bench_function("parse 10k packages", |b| {
+        b.iter(|| nixman::schema::parse(black_box(&yml)).unwrap());
+    });
+}
+
+fn write(c: &mut Criterion) {
+    let config: Config = nixman::schema::parse(&large_yaml()).unwrap();
+    let path = std::env::temp_dir().join(format!("nixman-bench-{}.yml", std::process::id()));
+    c.bench_function("write 10k packages", |b| {
+        b.iter(|| nixman::write_config(black_box(&config), &path).unwrap());
+    });
+    std::fs::remove_file(path).ok();
+}
+
+fn diff(c: &mut Criterion) {
+    let config: Config = nixman::schema::parse(&large_yaml()).unwrap();
+    let list: PackageList = config.packages;
+    let installed = installed();
+    c.bench_function("diff 10k packages", |b| {
+        b.iter(|| {
+            let wanted = nixman::expand_groups(black_box(&list), &installed, &BTreeMap::new());
+            nixman::package_changes(&wanted, &installed, &[])
+        });
+    });
+    let wanted = nixman::expand_groups(&list, &installed, &BTreeMap::new());
+    let (to_install, to_remove) = nixman::package_changes(&wanted, &installed, &[]);
+    assert!(
+        wanted
+            .iter()
+            .any(|(_, reason)| *reason == InstallReason::Dependency)
+    );
+    assert_eq!(to_remove.len(), PACKAGES / 2);
+    assert!(to_install.len() < PACKAGES / 2);
+}
+
+criterion_group!(benches, parse, write, diff);
+criterion_main!(benches);
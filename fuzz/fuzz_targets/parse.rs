@@ -0,0 +1,33 @@
+//! Feed arbitrary text to the YAML and version parsers; none of them may panic.
+//!
+//! Run with `cargo +nightly fuzz run parse` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nixman::versioning::{FullVersion, VersionReq, vercmp};
+
+fuzz_target!(
+    init: {
+        // nixman catches libyml's panics and reports them as errors; only a panic that escapes
+        // should count as a crash, which libfuzzer-sys still detects without its abort hook.
+        drop(std::panic::take_hook());
+    },
+    |data: &[u8]| {
+        let Ok(text) = std::str::from_utf8(data) else {
+            return;
+        };
+        if let Ok(mut config) = nixman::schema::parse(text) {
+            let _ = nixman::vars::expand(&mut config);
+            let _ = nixman::strict::unknown_keys(text);
+        }
+        let _ = nixman::local::LocalConfig::parse(text);
+
+        let (a, b) = text.split_once('\n').unwrap_or((text, ""));
+        let _ = a.parse::<FullVersion>();
+        let _ = b.parse::<VersionReq>().map(|req| req.matches(a));
+        let _ = FullVersion::from(a).cmp(&FullVersion::from(b));
+        let _ = vercmp(a, b);
+        let _ = nixman::Package::from(a);
+    }
+);
@@ -0,0 +1,236 @@
+//! The state left by the last successful apply
+//!
+//! # Overview
+//!
+//! After every successful `nixman apply`, the pacman packages the YAML declared for this
+//! machine (groups expanded) and the packages explicitly installed afterwards are written to
+//! `<state dir>/last-applied.json` as a [`LastApplied`]. Comparing them with the present tells
+//! which side changed since, which a plain difference between the system and the YAML loses.
+//! [`LastApplied::classify`] sorts each pending pacman change into a [`DriftKind`]:
+//!
+//! | Pending change | Declared at the last apply | Kind |
+//! |----------------|----------------------------|------|
+//! | install | no | added to config |
+//! | install | yes | removed outside nixman |
+//! | remove | yes | removed from config |
+//! | remove | no | installed outside nixman |
+//!
+//! `status` and `diff` show the kinds, and [conflict resolution](crate::conflicts) asks about
+//! the changes made outside nixman. Without a recorded apply, e.g. before the first one, nothing
+//! is known about the direction of a difference.
+
+use crate::SourceChanges;
+#[cfg(target_os = "linux")]
+use crate::{Config, condition::Host};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which side moved since the last apply, for a package the system and the YAML disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    /// Declared since; `apply` installs it.
+    AddedToConfig,
+    /// Dropped from the YAML since; `apply` removes it.
+    RemovedFromConfig,
+    /// Installed by hand since; `apply` removes it.
+    InstalledOutside,
+    /// Removed by hand since; `apply` installs it.
+    RemovedOutside,
+}
+
+impl fmt::Display for DriftKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::AddedToConfig => "added to config",
+            Self::RemovedFromConfig => "removed from config",
+            Self::InstalledOutside => "installed outside nixman",
+            Self::RemovedOutside => "removed outside nixman",
+        })
+    }
+}
+
+/// A pending pacman change and which side moved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Drift {
+    pub name: String,
+    pub kind: DriftKind,
+}
+
+/// The packages at the end of the last successful apply.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastApplied {
+    /// When the apply finished, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The pacman packages the YAML declared for this machine, groups expanded.
+    pub declared: Vec<String>,
+    /// The explicitly installed packages afterwards.
+    pub installed: Vec<String>,
+}
+
+impl LastApplied {
+    /// The state for `config` with the explicitly `installed` packages, at the current time.
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub fn new(config: &Config, installed: Vec<String>) -> Self {
+        let mut list = config.packages.clone();
+        list.retain_active(&Host::current());
+        let groups = if list.groups.is_empty() {
+            std::collections::BTreeMap::new()
+        } else {
+            crate::available_groups().unwrap_or_default()
+        };
+        let mut declared: Vec<String> = crate::expand_groups(&list, &installed, &groups)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        declared.sort();
+        declared.dedup();
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            declared,
+            installed,
+        }
+    }
+
+    /// Read the state at `path`, or `None` if no apply was recorded.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn load(path: &Path) -> std::io::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = std::fs::read_to_string(path)?;
+        serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Write the state to `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+
+    /// Whether `name` was explicitly installed at the last apply.
+    #[must_use]
+    pub fn was_installed(&self, name: &str) -> bool {
+        self.installed.iter().any(|installed| installed == name)
+    }
+
+    /// Whether the YAML declared `name` at the last apply.
+    #[must_use]
+    pub fn was_declared(&self, name: &str) -> bool {
+        self.declared.iter().any(|declared| declared == name)
+    }
+
+    /// Classify the pending pacman changes, `to_install` and `to_remove`, following the table in
+    /// the [module docs](self).
+    #[must_use]
+    pub fn classify(&self, to_install: &[String], to_remove: &[String]) -> Vec<Drift> {
+        let drift = |name: &String, declared, undeclared| Drift {
+            name: name.clone(),
+            kind: if self.was_declared(name) {
+                declared
+            } else {
+                undeclared
+            },
+        };
+        to_install
+            .iter()
+            .map(|name| drift(name, DriftKind::RemovedOutside, DriftKind::AddedToConfig))
+            .chain(to_remove.iter().map(|name| {
+                drift(
+                    name,
+                    DriftKind::RemovedFromConfig,
+                    DriftKind::InstalledOutside,
+                )
+            }))
+            .collect()
+    }
+}
+
+/// Where the state is kept (`<state dir>/last-applied.json`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn path() -> std::io::Result<PathBuf> {
+    crate::paths::applied_file()
+}
+
+/// Classify the pacman part of `changes` against the last recorded apply; empty if there is
+/// none.
+///
+/// # Errors
+/// Returns an error if the recorded state cannot be read.
+pub fn drift(changes: &[SourceChanges]) -> std::io::Result<Vec<Drift>> {
+    let Some(last) = LastApplied::load(&path()?)? else {
+        return Ok(Vec::new());
+    };
+    Ok(changes
+        .iter()
+        .filter(|change| change.source == "pacman")
+        .flat_map(|change| last.classify(&change.to_install, &change.to_remove))
+        .collect())
+}
+
+/// Record the current state after a successful apply of the YAML at `yml_path`.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read, pacman could not be queried, or the state
+/// cannot be written.
+#[cfg(target_os = "linux")]
+pub fn record<P: AsRef<Path>>(yml_path: P) -> std::io::Result<LastApplied> {
+    let config = crate::read_effective_config(&yml_path)?;
+    let state = LastApplied::new(&config, crate::installed_explicit_packages()?);
+    state.save(&path()?)?;
+    Ok(state)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// The state survives a round trip, a missing file means no apply was recorded, and pending
+    /// changes are classified by what was declared.
+    #[test]
+    fn saves_and_loads_state() {
+        let path = std::env::temp_dir().join(format!("nixman-applied-{}.json", std::process::id()));
+        assert_eq!(LastApplied::load(&path).unwrap(), None);
+        let config: Config =
+            crate::schema::parse("packages:\n  - htop\n  - git\n  - git\n").unwrap();
+        let state = LastApplied::new(&config, vec!["git".to_string(), "vim".to_string()]);
+        assert_eq!(state.declared, vec!["git", "htop"]);
+        assert!(state.was_installed("vim"));
+        assert!(!state.was_installed("htop"));
+        state.save(&path).unwrap();
+        assert_eq!(LastApplied::load(&path).unwrap(), Some(state.clone()));
+
+        let names =
+            |names: &[&str]| -> Vec<String> { names.iter().map(ToString::to_string).collect() };
+        let kinds: Vec<(String, DriftKind)> = state
+            .classify(&names(&["htop", "vim"]), &names(&["git", "steam"]))
+            .into_iter()
+            .map(|drift| (drift.name, drift.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("htop".to_string(), DriftKind::RemovedOutside),
+                ("vim".to_string(), DriftKind::AddedToConfig),
+                ("git".to_string(), DriftKind::RemovedFromConfig),
+                ("steam".to_string(), DriftKind::InstalledOutside),
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+//! Downgrades through the Arch Linux Archive
+//!
+//! # Overview
+//!
+//! The sync repositories only carry the latest version of each package, so a versioned freeze
+//! cannot be reproduced with `pacman -S` once packages have been updated. The
+//! [Arch Linux Archive](https://archive.archlinux.org) (ALA) keeps every version ever published.
+//!
+//! When the YAML pins an older version than the one installed, `apply --allow-downgrade`:
+//!
+//! 1. looks up the exact `epoch:version-release` in the package's ALA directory,
+//! 2. downloads the package and its detached signature with `curl`,
+//! 3. verifies the signature against pacman's keyring with `pacman-key --verify`,
+//! 4. installs the verified files with `pacman -U`.
+//!
+//! With `--chroot`, the files are copied into the chroot's package cache first, where pacman
+//! running inside it can read them.
+
+use crate::audit::AuditAction;
+use crate::plan::Selection;
+use crate::versioning::FullVersion;
+use crate::{ApplyError, Config, FailureOutput, pacman};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// Base URL of the package tree of the Arch Linux Archive.
+pub const ALA_URL: &str = "https://archive.archlinux.org/packages";
+
+/// A package file listed in a package's ALA directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// The file name, e.g. `git-2.45.0-1-x86_64.pkg.tar.zst`.
+    pub file: String,
+    pub version: FullVersion,
+    /// The package architecture, e.g. `x86_64` or `any`.
+    pub arch: String,
+}
+
+/// A package pinned to an older version than the one installed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Downgrade {
+    pub name: String,
+    pub installed: FullVersion,
+    pub pinned: FullVersion,
+}
+
+/// The ALA directory holding every published version of `name`.
+#[must_use]
+pub fn package_dir_url(name: &str) -> String {
+    let first = name.chars().next().unwrap_or('_');
+    format!("{ALA_URL}/{first}/{name}/")
+}
+
+/// Parse the HTML index of a package's ALA directory into its package files (signatures are
+/// skipped).
+#[must_use]
+pub fn parse_listing(html: &str, name: &str) -> Vec<ArchiveEntry> {
+    let prefix = format!("{name}-");
+    html.split("href=\"")
+        .skip(1)
+        .filter_map(|rest| rest.split('"').next())
+        .map(percent_decode)
+        .filter(|file| !has_extension(file, "sig"))
+        .filter_map(|file| {
+            let stem = file.strip_prefix(&prefix)?;
+            let stem = &stem[..stem.find(".pkg.tar.")?];
+            let (version, arch) = stem.rsplit_once('-')?;
+            Some(ArchiveEntry {
+                version: FullVersion::from(version),
+                arch: arch.to_string(),
+                file,
+            })
+        })
+        .collect()
+}
+
+fn has_extension(file: &str, extension: &str) -> bool {
+    Path::new(file)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Decode `%XX` escapes, as used for `:` (epochs) and `+` in ALA links.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(byte) = escaped {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Pick the file for `version`, preferring this machine's architecture over `any`, and zstd
+/// over older compression formats.
+#[must_use]
+pub fn select_entry<'a>(
+    entries: &'a [ArchiveEntry],
+    version: &FullVersion,
+) -> Option<&'a ArchiveEntry> {
+    entries
+        .iter()
+        .filter(|entry| &entry.version == version)
+        .filter(|entry| entry.arch == std::env::consts::ARCH || entry.arch == "any")
+        .max_by_key(|entry| {
+            (
+                entry.arch == std::env::consts::ARCH,
+                has_extension(&entry.file, "zst"),
+            )
+        })
+}
+
+/// Packages whose pinned version in `config` is older than the installed one.
+///
+/// `installed` holds `(name, version)` pairs, e.g. from `pacman -Qe`.
+#[must_use]
+pub fn downgrades_needed(config: &Config, installed: &[(String, FullVersion)]) -> Vec<Downgrade> {
+    config
+        .packages
+        .packages
+        .values()
+        .filter_map(|pkg| {
+            let pinned = pkg.version.as_ref()?;
+            let (_, current) = installed.iter().find(|(name, _)| name == &pkg.name)?;
+            (pinned < current).then(|| Downgrade {
+                name: pkg.name.clone(),
+                installed: current.clone(),
+                pinned: pinned.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Explicitly installed packages with their versions (`pacman -Qe`).
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+pub fn installed_versions() -> std::io::Result<Vec<(String, FullVersion)>> {
+    let output = pacman::pacman_list_explicit()?;
+    Ok(pacman::parse_package_lines(&output.stdout)
+        .map(|package| (package.name.to_string(), FullVersion::from(package.version)))
+        .collect())
+}
+
+/// Find the ALA URL of `name` at exactly `version`.
+///
+/// # Errors
+/// Returns an error if the listing cannot be fetched or the version is not in the archive.
+pub fn find_package(name: &str, version: &FullVersion) -> std::io::Result<String> {
+    let dir = package_dir_url(name);
+    let output = Command::new("curl").args(["-fsSL", &dir]).output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "failed to fetch {dir}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let entries = parse_listing(&String::from_utf8_lossy(&output.stdout), name);
+    select_entry(&entries, version)
+        .map(|entry| format!("{dir}{}", entry.file.replace(':', "%3A")))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{name} {version} is not in the Arch Linux Archive"),
+            )
+        })
+}
+
+/// Download `url` and its `.sig` into `dir`, returning the path of the package file.
+///
+/// # Errors
+/// Returns an error if either download fails.
+pub fn download(url: &str, dir: &Path) -> std::io::Result<PathBuf> {
+    let file = url
+        .rsplit('/')
+        .next()
+        .map(percent_decode)
+        .unwrap_or_default();
+    let path = dir.join(&file);
+    for (source, target) in [
+        (url.to_string(), path.clone()),
+        (format!("{url}.sig"), dir.join(format!("{file}.sig"))),
+    ] {
+        let status = Command::new("curl")
+            .args(["-fsSL", "-o"])
+            .arg(&target)
+            .arg(&source)
+            .status()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "failed to download {source}"
+            )));
+        }
+    }
+    Ok(path)
+}
+
+/// Verify a downloaded package against its `.sig` with pacman's keyring (requires root).
+///
+/// # Errors
+/// Returns an error if `pacman-key` could not be executed.
+pub fn verify(package: &Path) -> std::io::Result<ExitStatus> {
+    let mut sig = package.as_os_str().to_owned();
+    sig.push(".sig");
+    pacman::elevated("pacman-key")
+        .arg("--verify")
+        .arg(sig)
+        .arg(package)
+        .status()
+}
+
+/// Downgrade every package in `selection` the YAML pins to an older version than installed.
+///
+/// Returns the downgraded package names.
+///
+/// # Errors
+/// Returns an [`ApplyError`] if a package cannot be found, downloaded, or verified, or if
+/// `pacman -U` fails.
+pub fn downgrade_from_yaml<P: AsRef<Path>>(
+    yml_path: P,
+    noconfirm: bool,
+    selection: &Selection,
+) -> Result<Vec<String>, ApplyError> {
+    let config = crate::read_effective_config(yml_path).map_err(|e| e.to_string())?;
+    let installed = installed_versions().map_err(|e| e.to_string())?;
+    let downgrades: Vec<(String, FullVersion)> = downgrades_needed(&config, &installed)
+        .into_iter()
+        .filter(|d| selection.includes(&d.name))
+        .map(|d| (d.name, d.pinned))
+        .collect();
+    install_from_archive(&downgrades, noconfirm)?;
+    Ok(downgrades.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Download, verify, and install exact package versions from the archive with `pacman -U`.
+///
+/// # Errors
+/// Returns an [`ApplyError`] if a package cannot be found, downloaded, or verified, or if
+/// `pacman -U` fails.
+pub fn install_from_archive(
+    packages: &[(String, FullVersion)],
+    noconfirm: bool,
+) -> Result<(), ApplyError> {
+    if packages.is_empty() {
+        return Ok(());
+    }
+    if crate::offline::is_offline() {
+        return install_from_cache(packages, noconfirm);
+    }
+    let dir = crate::paths::archive_dir().map_err(|e| e.to_string())?;
+    let mut files = Vec::new();
+    for (name, version) in packages {
+        let file = find_package(name, version)
+            .and_then(|url| download(&url, &dir))
+            .map_err(|e| e.to_string())?;
+        if !verify(&file).map_err(|e| e.to_string())?.success() {
+            return Err(format!("Signature verification failed for {}", file.display()).into());
+        }
+        files.push(file);
+    }
+    install_files(packages, &files, noconfirm)
+}
+
+/// Install the exact versions of `packages` from pacman's cache, as [`install_from_archive`]
+/// does offline.
+fn install_from_cache(
+    packages: &[(String, FullVersion)],
+    noconfirm: bool,
+) -> Result<(), ApplyError> {
+    let pinned: Vec<(String, Option<FullVersion>)> = packages
+        .iter()
+        .map(|(name, version)| (name.clone(), Some(version.clone())))
+        .collect();
+    let (files, missing) = crate::offline::cached_files(&pinned).map_err(|e| e.to_string())?;
+    if !missing.is_empty() {
+        return Err(format!(
+            "Not in {}, so they cannot be installed offline: {}",
+            crate::clean::PACMAN_CACHE,
+            missing.join(", ")
+        )
+        .into());
+    }
+    install_files(packages, &files, noconfirm)
+}
+
+/// Install the package `files` of `packages` with `pacman -U`.
+fn install_files(
+    packages: &[(String, FullVersion)],
+    files: &[PathBuf],
+    noconfirm: bool,
+) -> Result<(), ApplyError> {
+    let files = visible_to_pacman(files).map_err(|e| e.to_string())?;
+    let mut cmd = pacman::elevated_pacman();
+    cmd.arg("-U").args(&files);
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    let names: Vec<String> = packages.iter().map(|(name, _)| name.clone()).collect();
+    let captured =
+        pacman::run_audited(&mut cmd, AuditAction::Install, &names).map_err(|e| e.to_string())?;
+    if captured.status.success() {
+        Ok(())
+    } else {
+        Err(
+            ApplyError::from("Failed to install some packages from the archive".to_string())
+                .with_output(vec![FailureOutput {
+                    packages: names,
+                    lines: captured.tail,
+                }]),
+        )
+    }
+}
+
+/// Where pacman sees `files`: where they are, or, when it runs in a
+/// [chroot](pacman::Paths::chroot), in the chroot's package cache, where they are copied with
+/// their signatures.
+fn visible_to_pacman(files: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let Some(chroot) = pacman::paths().chroot else {
+        return Ok(files.to_vec());
+    };
+    let cache = Path::new(crate::clean::PACMAN_CACHE);
+    let mut copies = Vec::new();
+    let mut visible = Vec::new();
+    for file in files {
+        let mut sig = file.clone().into_os_string();
+        sig.push(".sig");
+        copies.push(file.clone());
+        copies.extend(Some(PathBuf::from(sig)).filter(|sig| sig.is_file()));
+        visible.extend(file.file_name().map(|name| cache.join(name)));
+    }
+    let target = chroot.join(cache.strip_prefix("/").unwrap_or(cache));
+    let status = pacman::elevated("cp").args(&copies).arg(&target).status()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "could not copy the packages into {}",
+            target.display()
+        )));
+    }
+    Ok(visible)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = r#"<html><body><pre>
+<a href="../">../</a>
+<a href="git-2.44.0-1-x86_64.pkg.tar.zst">git-2.44.0-1-x86_64.pkg.tar.zst</a>
+<a href="git-2.44.0-1-x86_64.pkg.tar.zst.sig">git-2.44.0-1-x86_64.pkg.tar.zst.sig</a>
+<a href="git-2.45.0-1-x86_64.pkg.tar.zst">git-2.45.0-1-x86_64.pkg.tar.zst</a>
+<a href="git-1%3A2.46.0-1-x86_64.pkg.tar.zst">git-1:2.46.0-1-x86_64.pkg.tar.zst</a>
+</pre></body></html>"#;
+
+    /// Links are decoded, signatures skipped, and versions parsed including epochs.
+    #[test]
+    fn parses_archive_listing() {
+        let entries = parse_listing(LISTING, "git");
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].version, FullVersion::from("2.44.0-1"));
+        assert_eq!(entries[2].file, "git-1:2.46.0-1-x86_64.pkg.tar.zst");
+        assert_eq!(entries[2].version, FullVersion::from("1:2.46.0-1"));
+        assert_eq!(
+            package_dir_url("git"),
+            "https://archive.archlinux.org/packages/g/git/"
+        );
+    }
+
+    /// Only packages pinned below the installed version are downgraded.
+    #[test]
+    fn finds_pinned_downgrades() {
+        let config: Config = serde_yml::from_str(
+            "packages:\n  - name: git\n    version: 2.44.0-1\n  - name: htop\n    version: 3.3.0-1\n  - vim\n  - name: spotify\n    version: 1.2.40.599-1\n  - name: zstd\n    version: 1.5.6rc1-1\n",
+        )
+        .unwrap();
+        let installed = vec![
+            ("git".to_string(), FullVersion::from("2.45.0-1")),
+            ("htop".to_string(), FullVersion::from("3.3.0-1")),
+            ("vim".to_string(), FullVersion::from("9.1.0-1")),
+            ("spotify".to_string(), FullVersion::from("1.2.40.1000-1")),
+            ("zstd".to_string(), FullVersion::from("1.5.6-1")),
+        ];
+        let downgrades = downgrades_needed(&config, &installed);
+        let names: Vec<&str> = downgrades.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["git", "spotify", "zstd"]);
+        assert_eq!(downgrades[0].pinned, FullVersion::from("2.44.0-1"));
+    }
+}
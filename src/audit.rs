@@ -0,0 +1,203 @@
+//! The audit log
+//!
+//! # Overview
+//!
+//! Every install, removal, upgrade, and change of install reason that nixman runs through
+//! pacman, paru, or pacstrap is appended to `<state dir>/audit.log`, one JSON object per line.
+//! An [`AuditEntry`] records when the command ran, who invoked nixman (`$SUDO_USER`, else
+//! `$USER`), the nixman command line, the command it executed, the packages, and the exit
+//! code. Where the [generation log](crate::generations) has one entry per `apply` or `freeze`,
+//! the audit log has one per transaction, whatever started it, so that on a shared machine
+//! every change can be traced to a person.
+//!
+//! The action has already happened when it is recorded, so failing to write the log only prints
+//! a warning ([`record`]). `nixman audit` shows the log, optionally only the entries of one user
+//! or package ([`AuditEntry::matches`]).
+
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What a recorded command did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Install,
+    Remove,
+    /// A full system upgrade.
+    Update,
+    /// A change of install reason (`pacman -D`).
+    Mark,
+}
+
+impl AuditAction {
+    /// The action as written in the log.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Install => "install",
+            Self::Remove => "remove",
+            Self::Update => "update",
+            Self::Mark => "mark",
+        }
+    }
+}
+
+/// One entry of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the command finished, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Who invoked nixman.
+    pub user: String,
+    pub action: AuditAction,
+    /// The nixman command line, e.g. `apply --paru`.
+    pub command: String,
+    /// The command nixman executed, e.g. `sudo pacman -S --needed htop`.
+    pub executed: String,
+    /// The packages acted on; empty for a system upgrade.
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// The exit code, or `None` if the command was killed by a signal.
+    pub exit_code: Option<i32>,
+}
+
+impl AuditEntry {
+    /// An entry for `cmd`, which just carried out `action` on `packages` and exited with
+    /// `exit_code`.
+    #[must_use]
+    pub fn new(
+        action: AuditAction,
+        packages: &[String],
+        cmd: &Command,
+        exit_code: Option<i32>,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            user: user(),
+            action,
+            command: std::env::args().skip(1).collect::<Vec<_>>().join(" "),
+            executed: command_line(cmd),
+            packages: packages.to_vec(),
+            exit_code,
+        }
+    }
+
+    /// Whether the command succeeded.
+    #[must_use]
+    pub const fn success(&self) -> bool {
+        matches!(self.exit_code, Some(0))
+    }
+
+    /// Whether the entry was made by `user` (if given) and acted on `package` (if given).
+    #[must_use]
+    pub fn matches(&self, user: Option<&str>, package: Option<&str>) -> bool {
+        user.is_none_or(|user| self.user == user)
+            && package.is_none_or(|package| self.packages.iter().any(|name| name == package))
+    }
+}
+
+/// Who invoked nixman: the user who ran sudo (`$SUDO_USER`), else `$USER` or `$LOGNAME`, else
+/// `unknown`.
+#[must_use]
+pub fn user() -> String {
+    ["SUDO_USER", "USER", "LOGNAME"]
+        .into_iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|user| !user.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// `cmd`'s program and arguments, separated by spaces.
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(OsStr::to_string_lossy)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Default location of the audit log (`<state dir>/audit.log`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn log_path() -> std::io::Result<PathBuf> {
+    crate::paths::audit_file()
+}
+
+/// Read every entry of the log at `path`, oldest first; a missing log has none.
+///
+/// # Errors
+/// Returns an error if the log cannot be read or a line is malformed.
+pub fn read(path: &Path) -> std::io::Result<Vec<AuditEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
+
+/// Append `entry` to the log at `path`.
+///
+/// # Errors
+/// Returns an error if the log cannot be written.
+pub fn append(path: &Path, entry: &AuditEntry) -> std::io::Result<()> {
+    let line = serde_json::to_string(entry).map_err(std::io::Error::other)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{line}")
+}
+
+/// Record in the [log](log_path) that `cmd` carried out `action` on `packages` and exited with
+/// `status`, warning if the log cannot be written.
+pub fn record(action: AuditAction, packages: &[String], cmd: &Command, status: ExitStatus) {
+    let entry = AuditEntry::new(action, packages, cmd, status.code());
+    if let Err(e) = log_path().and_then(|path| append(&path, &entry)) {
+        eprintln!("Warning: failed to write the audit log: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entries are read back in order and can be picked by user and package.
+    #[test]
+    fn records_entries() {
+        let path = std::env::temp_dir().join(format!("nixman-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(read(&path).unwrap().is_empty());
+        let mut cmd = Command::new("pacman");
+        cmd.args(["-S", "--needed", "htop"]);
+        let mut entry = AuditEntry::new(AuditAction::Install, &["htop".to_string()], &cmd, Some(0));
+        entry.user = "alice".to_string();
+        append(&path, &entry).unwrap();
+        let mut upgrade = AuditEntry::new(AuditAction::Update, &[], &Command::new("paru"), Some(1));
+        upgrade.user = "bob".to_string();
+        append(&path, &upgrade).unwrap();
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries, vec![entry, upgrade]);
+        assert_eq!(entries[0].executed, "pacman -S --needed htop");
+        assert!(entries[0].success());
+        assert!(!entries[1].success());
+        assert!(entries[0].matches(Some("alice"), Some("htop")));
+        assert!(!entries[0].matches(Some("bob"), None));
+        assert!(entries[1].matches(None, None));
+        assert!(!entries[1].matches(None, Some("htop")));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,295 @@
+//! AUR metadata through the AUR RPC
+//!
+//! # Overview
+//!
+//! Packages that are not in any sync repository usually come from the AUR, which pacman knows
+//! nothing about. This module asks the [AUR RPC](https://aur.archlinux.org/rpc) (`/rpc/v5/info`)
+//! about them, so that:
+//!
+//! - `check` can reject names that are neither in a repository nor in the AUR,
+//! - `status` can report AUR packages flagged out of date,
+//! - `outdated` can list AUR packages with a newer version than installed,
+//! - `apply` can order [parallel builds](crate::builds) by their dependencies.
+//!
+//! Requests are made with `curl`, at most [`MAX_BATCH`] names each, and [`BATCH_DELAY`] apart
+//! to stay well within the AUR's rate limit.
+
+use crate::Config;
+use crate::pacman;
+use crate::versioning::vercmp;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::process::Command;
+use std::time::Duration;
+
+/// The AUR RPC info endpoint.
+pub const RPC_INFO_URL: &str = "https://aur.archlinux.org/rpc/v5/info";
+
+/// Most package names queried in one request, keeping the URL well under the AUR's limit.
+pub const MAX_BATCH: usize = 100;
+
+/// Pause between consecutive requests.
+pub const BATCH_DELAY: Duration = Duration::from_millis(500);
+
+/// A package as described by the AUR RPC.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AurPackage {
+    pub name: String,
+    pub version: String,
+    /// When the package was flagged out of date (Unix time), if it is.
+    #[serde(default)]
+    pub out_of_date: Option<i64>,
+    #[serde(default)]
+    pub maintainer: Option<String>,
+    /// The package base, which names the AUR git repository; split packages share it.
+    #[serde(default)]
+    pub package_base: Option<String>,
+    /// Run-time dependencies, possibly with a version constraint, e.g. `python>=3.11`.
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// Dependencies needed only to build the package.
+    #[serde(default)]
+    pub make_depends: Vec<String>,
+}
+
+/// An AUR package with a newer version than installed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AurUpdate {
+    pub name: String,
+    pub installed: String,
+    pub available: String,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    results: Vec<AurPackage>,
+}
+
+/// Percent-encode a package name for a query string (names may contain `+` and `@`).
+fn encode(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// The info URL for one batch of package names.
+#[must_use]
+pub fn info_url(names: &[String]) -> String {
+    let args: Vec<String> = names
+        .iter()
+        .map(|name| format!("arg[]={}", encode(name)))
+        .collect();
+    format!("{RPC_INFO_URL}?{}", args.join("&"))
+}
+
+/// Parse an info response.
+///
+/// # Errors
+/// Returns a message if the JSON is malformed or the RPC reported an error.
+pub fn parse_info(json: &str) -> Result<Vec<AurPackage>, String> {
+    let response: Response =
+        serde_json::from_str(json).map_err(|e| format!("invalid AUR response: {e}"))?;
+    if response.kind == "error" {
+        return Err(format!(
+            "AUR error: {}",
+            response.error.unwrap_or_else(|| "unknown".to_string())
+        ));
+    }
+    Ok(response.results)
+}
+
+/// Look up `names` in the AUR; names that are not in the AUR are simply missing from the result.
+///
+/// # Errors
+/// Returns an error if `curl` fails or the AUR returns an error.
+pub fn info(names: &[String]) -> std::io::Result<Vec<AurPackage>> {
+    let mut packages = Vec::new();
+    for (index, batch) in names.chunks(MAX_BATCH).enumerate() {
+        if index > 0 {
+            std::thread::sleep(BATCH_DELAY);
+        }
+        // -g keeps curl from globbing the `[]` in `arg[]`.
+        let url = info_url(batch);
+        let output = Command::new("curl").args(["-fsSLg", &url]).output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "failed to query the AUR: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        packages.extend(
+            parse_info(&String::from_utf8_lossy(&output.stdout)).map_err(std::io::Error::other)?,
+        );
+    }
+    Ok(packages)
+}
+
+/// Installed packages that are in no sync repository (`pacman -Qm`), with their versions.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+pub fn foreign_packages() -> std::io::Result<Vec<(String, String)>> {
+    let output = crate::run::output(pacman::pacman_command().arg("-Qm"))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, version)| (name.to_string(), version.trim().to_string()))
+        .collect())
+}
+
+/// Names of every package in the sync repositories (`pacman -Slq`).
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+pub fn repo_packages() -> std::io::Result<Vec<String>> {
+    let output = crate::run::output(pacman::pacman_command().arg("-Slq"))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Declared packages that are in no sync repository, no group, not provided by an installed
+/// package, and not in the AUR; most likely typos.
+///
+/// # Errors
+/// Returns an error if pacman cannot be queried or the AUR cannot be reached.
+pub fn unknown_packages(config: &Config) -> std::io::Result<Vec<String>> {
+    let repo: BTreeSet<String> = repo_packages()?.into_iter().collect();
+    let groups = crate::available_groups().unwrap_or_default();
+    let providers = crate::provides::installed_providers().unwrap_or_default();
+    let candidates: Vec<String> = config
+        .packages
+        .packages
+        .values()
+        .map(|pkg| pkg.name.clone())
+        .filter(|name| {
+            !repo.contains(name) && !groups.contains_key(name) && !providers.contains_key(name)
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+    let found = info(&candidates)?;
+    Ok(candidates
+        .into_iter()
+        .filter(|name| !found.iter().any(|package| &package.name == name))
+        .collect())
+}
+
+/// Declared packages installed from outside the sync repositories that the AUR flags out of
+/// date.
+///
+/// # Errors
+/// Returns an error if pacman cannot be queried or the AUR cannot be reached.
+pub fn declared_out_of_date(config: &Config) -> std::io::Result<Vec<String>> {
+    let declared: Vec<String> = foreign_packages()?
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| config.packages.contains(name))
+        .collect();
+    if declared.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(flagged_out_of_date(&info(&declared)?))
+}
+
+/// The installed packages for which the AUR has a newer version.
+#[must_use]
+pub fn updates(installed: &[(String, String)], aur: &[AurPackage]) -> Vec<AurUpdate> {
+    installed
+        .iter()
+        .filter_map(|(name, version)| {
+            let package = aur.iter().find(|package| &package.name == name)?;
+            (vercmp(&package.version, version) == Ordering::Greater).then(|| AurUpdate {
+                name: name.clone(),
+                installed: version.clone(),
+                available: package.version.clone(),
+            })
+        })
+        .collect()
+}
+
+/// The names in `aur` that are flagged out of date.
+#[must_use]
+pub fn flagged_out_of_date(aur: &[AurPackage]) -> Vec<String> {
+    aur.iter()
+        .filter(|package| package.out_of_date.is_some())
+        .map(|package| package.name.clone())
+        .collect()
+}
+
+/// The full version (`epoch:pkgver-pkgrel`) of the package base described by a `.SRCINFO`.
+#[must_use]
+pub fn srcinfo_version(srcinfo: &str) -> Option<String> {
+    let field = |key: &str| {
+        srcinfo.lines().find_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            (name.trim() == key).then(|| value.trim().to_string())
+        })
+    };
+    let version = format!("{}-{}", field("pkgver")?, field("pkgrel")?);
+    Some(match field("epoch") {
+        Some(epoch) => format!("{epoch}:{version}"),
+        None => version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str = r#"{"resultcount":2,"results":[
+        {"Name":"paru","Version":"2.0.4-1","OutOfDate":null,"Maintainer":"Morganamilo","NumVotes":1000},
+        {"Name":"spotify","Version":"1:1.2.40.599-1","OutOfDate":1720000000,"Maintainer":null}
+    ],"type":"multiinfo","version":5}"#;
+
+    /// Responses are parsed into packages, and newer AUR versions become updates.
+    #[test]
+    fn parses_info_and_finds_updates() {
+        let aur = parse_info(RESPONSE).unwrap();
+        assert_eq!(aur.len(), 2);
+        assert_eq!(flagged_out_of_date(&aur), vec!["spotify"]);
+        let installed = vec![
+            ("paru".to_string(), "2.0.3-1".to_string()),
+            ("spotify".to_string(), "1:1.2.40.599-1".to_string()),
+            ("yay".to_string(), "12.0.0-1".to_string()),
+        ];
+        assert_eq!(
+            updates(&installed, &aur),
+            vec![AurUpdate {
+                name: "paru".to_string(),
+                installed: "2.0.3-1".to_string(),
+                available: "2.0.4-1".to_string(),
+            }]
+        );
+        assert!(
+            parse_info(r#"{"type":"error","error":"Too many package results.","results":[]}"#)
+                .is_err()
+        );
+        assert_eq!(
+            info_url(&["gtk2+extra".to_string(), "paru".to_string()]),
+            "https://aur.archlinux.org/rpc/v5/info?arg[]=gtk2%2Bextra&arg[]=paru"
+        );
+        assert_eq!(
+            srcinfo_version(
+                "pkgbase = spotify\n\tpkgver = 1.2.40.599\n\tpkgrel = 1\n\tepoch = 1\n"
+            )
+            .as_deref(),
+            Some("1:1.2.40.599-1")
+        );
+        assert_eq!(srcinfo_version("pkgbase = paru\n"), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! Packages that must never be installed
+//!
+//! # Overview
+//!
+//! The YAML's `banned:` section lists packages that are not allowed on the system, e.g.
+//! `telnet` or a driver that conflicts with the one in use. Where `ignore:` leaves a package
+//! alone, a ban is enforced:
+//!
+//! - `apply` removes a banned package that is installed, explicitly or as a dependency, and
+//!   never installs one, even when a declared group contains it,
+//! - `nixman -S` refuses to install it,
+//! - `freeze` keeps it out of the package list,
+//! - `check` fails if the YAML also declares it.
+//!
+//! pacman refuses to remove a banned package that other installed packages still depend on,
+//! and names them.
+
+use crate::Config;
+#[cfg(target_os = "linux")]
+use crate::pacman;
+
+/// The banned packages of `config`.
+#[must_use]
+pub fn banned(config: &Config) -> &[String] {
+    config.banned.as_deref().unwrap_or_default()
+}
+
+/// Drop the `banned` packages from `to_install`, and add the ones in `installed` to
+/// `to_remove`.
+pub fn enforce(
+    banned: &[String],
+    installed: &[String],
+    to_install: &mut Vec<String>,
+    to_remove: &mut Vec<String>,
+) {
+    to_install.retain(|name| !banned.contains(name));
+    for name in installed.iter().filter(|name| banned.contains(name)) {
+        if !to_remove.contains(name) {
+            to_remove.push(name.clone());
+        }
+    }
+}
+
+/// The `banned` packages that are installed, explicitly or as dependencies.
+///
+/// # Errors
+/// Returns an error if pacman could not be queried.
+#[cfg(target_os = "linux")]
+pub fn installed(banned: &[String]) -> std::io::Result<Vec<String>> {
+    if banned.is_empty() {
+        return Ok(Vec::new());
+    }
+    let output = pacman::pacman_list_installed()?;
+    Ok(pacman::parse_package_lines(&output.stdout)
+        .filter(|package| banned.iter().any(|name| name == package.name))
+        .map(|package| package.name.to_string())
+        .collect())
+}
+
+/// The packages of `packages` that `config` bans.
+#[must_use]
+pub fn blocked<'a>(config: &Config, packages: &'a [String]) -> Vec<&'a str> {
+    let banned = banned(config);
+    packages
+        .iter()
+        .filter(|name| banned.contains(name))
+        .map(String::as_str)
+        .collect()
+}
+
+/// The banned packages that `config` also declares, with their groups.
+#[must_use]
+pub fn declared(config: &Config) -> Vec<&str> {
+    let banned = banned(config);
+    config
+        .packages
+        .packages
+        .keys()
+        .chain(config.packages.groups.iter().map(|group| &group.name))
+        .filter(|name| banned.contains(name))
+        .map(String::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Banned packages are never installed and are removed however they were installed.
+    #[test]
+    fn enforces_bans() {
+        let banned = vec!["telnet".to_string(), "nvidia".to_string()];
+        let mut to_install = vec!["htop".to_string(), "telnet".to_string()];
+        let mut to_remove = vec!["nano".to_string()];
+        enforce(
+            &banned,
+            &["nvidia".to_string(), "git".to_string()],
+            &mut to_install,
+            &mut to_remove,
+        );
+        assert_eq!(to_install, vec!["htop"]);
+        assert_eq!(to_remove, vec!["nano", "nvidia"]);
+
+        let mut config: Config = crate::schema::parse(
+            "packages:\n  - git\n  - telnet\n  - group: nvidia\nbanned:\n  - telnet\n  - nvidia\n",
+        )
+        .unwrap();
+        assert_eq!(declared(&config), vec!["telnet", "nvidia"]);
+        config.banned = None;
+        assert!(declared(&config).is_empty());
+    }
+}
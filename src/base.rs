@@ -0,0 +1,267 @@
+//! Signed base manifests for managed machines
+//!
+//! # Overview
+//!
+//! On lab machines and other fleets, an administrator ships a base manifest with the packages
+//! every machine gets, e.g. `/etc/nixman/base.yml`. The `base` setting points at it, and it is
+//! merged under `packages.yml` whenever nixman decides what to install or remove:
+//!
+//! ```yaml
+//! settings:
+//!   base: /etc/nixman/base.yml
+//!   base_key: /etc/nixman/minisign.pub   # or an ssh allowed_signers file
+//! ```
+//!
+//! Entries in `packages.yml` replace base entries of the same name, the list sections and
+//! `repos:` are combined, and the [machine-local overrides](crate::local) apply last. The
+//! base's own `settings:` are not used. nixman never writes to the base, and `freeze` keeps its
+//! packages out of `packages.yml`.
+//!
+//! The signature lives next to the base: `base.yml.minisig` is checked with `minisign -V`
+//! against the minisign public key in `base_key`, and `base.yml.sig`, made with
+//! `ssh-keygen -Y sign -n nixman`, is checked with `ssh-keygen -Y verify` against the
+//! `allowed_signers` file in `base_key`. Once `base_key` is set, the base must be signed, so
+//! deleting the signature does not get an altered base past the check. A signature that does not
+//! verify is always an error. Without a `base_key`, the base is used unverified, unless
+//! [`set_require_signature`] (`apply --verify-signature`) refuses it.
+
+use crate::{Config, local};
+#[cfg(target_os = "linux")]
+use std::fs::File;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::process::{Command, Output};
+use std::sync::{Mutex, PoisonError};
+
+/// The namespace SSH signatures of a base manifest are made for.
+pub const SSH_NAMESPACE: &str = "nixman";
+
+/// A detached signature of a base manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signature {
+    /// A minisign signature (`<base>.minisig`).
+    Minisign(PathBuf),
+    /// An SSH signature (`<base>.sig`).
+    Ssh(PathBuf),
+}
+
+impl Signature {
+    /// The signature next to `base`, preferring minisign; `None` if it is unsigned.
+    #[must_use]
+    pub fn find(base: &Path) -> Option<Self> {
+        let next_to = |extension: &str| {
+            let mut path = base.as_os_str().to_owned();
+            path.push(extension);
+            Some(PathBuf::from(path)).filter(|path| path.exists())
+        };
+        next_to(".minisig")
+            .map(Self::Minisign)
+            .or_else(|| next_to(".sig").map(Self::Ssh))
+    }
+}
+
+static REQUIRE_SIGNATURE: Mutex<bool> = Mutex::new(false);
+
+/// Refuse base manifests that are not signed, for the rest of the process.
+pub fn set_require_signature(require: bool) {
+    *REQUIRE_SIGNATURE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = require;
+}
+
+/// Whether unsigned base manifests are refused.
+#[must_use]
+pub fn require_signature() -> bool {
+    *REQUIRE_SIGNATURE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Check the signature next to `base` against `key`.
+///
+/// # Errors
+/// Returns an error if `base` is unsigned, the signature does not verify, or minisign or
+/// ssh-keygen cannot be run.
+#[cfg(target_os = "linux")]
+pub fn verify(base: &Path, key: &Path) -> std::io::Result<()> {
+    let output = match Signature::find(base) {
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not signed", base.display()),
+            ));
+        }
+        Some(Signature::Minisign(signature)) => Command::new("minisign")
+            .arg("-Vq")
+            .arg("-m")
+            .arg(base)
+            .arg("-x")
+            .arg(&signature)
+            .arg("-p")
+            .arg(key)
+            .output()?,
+        Some(Signature::Ssh(signature)) => {
+            let principals = Command::new("ssh-keygen")
+                .args(["-Y", "find-principals", "-s"])
+                .arg(&signature)
+                .arg("-f")
+                .arg(key)
+                .output()?;
+            let stdout = String::from_utf8_lossy(&principals.stdout);
+            match stdout
+                .lines()
+                .next()
+                .filter(|_| principals.status.success())
+            {
+                Some(principal) => Command::new("ssh-keygen")
+                    .args(["-Y", "verify", "-n", SSH_NAMESPACE, "-I", principal, "-s"])
+                    .arg(&signature)
+                    .arg("-f")
+                    .arg(key)
+                    .stdin(File::open(base)?)
+                    .output()?,
+                None => principals,
+            }
+        }
+    };
+    check(base, &output)
+}
+
+/// Turn a failed verification into an error naming `base`.
+#[cfg(target_os = "linux")]
+fn check(base: &Path, output: &Output) -> std::io::Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    let reason = String::from_utf8_lossy(&output.stderr);
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!(
+            "the signature of {} does not verify: {}",
+            base.display(),
+            reason.trim()
+        ),
+    ))
+}
+
+/// Read the base manifest configured for the YAML at `yml_path`, verifying its signature if a
+/// `base_key` is set; `None` if no `base` is set. A relative `base` is resolved next to the YAML.
+///
+/// # Errors
+/// Returns an error if the settings or the base cannot be read or parsed, its signature does
+/// not verify, or it is unsigned while a `base_key` is set or
+/// [signatures are required](set_require_signature).
+#[cfg(target_os = "linux")]
+pub fn load<P: AsRef<Path>>(yml_path: P) -> std::io::Result<Option<Config>> {
+    let yml_path = yml_path.as_ref();
+    let settings = crate::settings::resolve(yml_path)?;
+    let Some(base) = settings.base else {
+        return Ok(None);
+    };
+    let dir = yml_path.parent().unwrap_or_else(|| Path::new(""));
+    let base = dir.join(base);
+    match settings.base_key {
+        Some(key) => verify(&base, &dir.join(key))?,
+        None if require_signature() => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "refusing {}: a signature is required but no base_key is set",
+                    base.display()
+                ),
+            ));
+        }
+        _ => {}
+    }
+    crate::read_config(&base).map(Some)
+}
+
+/// Merge `base` under `config` following the rules in the [module docs](self).
+pub fn merge(config: &mut Config, base: Config) {
+    config.packages = config.packages.union(&base.packages);
+    local::extend_section(&mut config.ignore, base.ignore);
+    local::extend_section(&mut config.critical, base.critical);
+    local::extend_section(&mut config.banned, base.banned);
+    local::extend_section(&mut config.flatpak, base.flatpak);
+    local::extend_section(&mut config.cargo, base.cargo);
+    local::extend_section(&mut config.pipx, base.pipx);
+    local::extend_section(&mut config.npm, base.npm);
+    for repo in base.repos.into_iter().flatten() {
+        let repos = config.repos.get_or_insert_with(Vec::new);
+        if !repos.iter().any(|existing| existing.name == repo.name) {
+            repos.push(repo);
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// The YAML's entries win over the base's, and unsigned bases are refused when required.
+    #[test]
+    fn merges_base_and_requires_signatures() {
+        let mut config: Config = serde_yml::from_str(
+            "packages:\n  - name: git\n    version: 2.45.0-1\n  - steam\nignore:\n  - vim\n",
+        )
+        .unwrap();
+        let base: Config = serde_yml::from_str(
+            "packages:\n  - git\n  - firefox\n  - group: base-devel\nignore:\n  - vim\n  - emacs\nsettings:\n  helper: paru\n",
+        )
+        .unwrap();
+        merge(&mut config, base);
+        let names: Vec<&str> = config
+            .packages
+            .packages
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(names, vec!["git", "steam", "firefox"]);
+        assert_eq!(
+            config
+                .packages
+                .get("git")
+                .and_then(|pkg| pkg.version.as_ref())
+                .map(ToString::to_string)
+                .as_deref(),
+            Some("2.45.0-1")
+        );
+        assert_eq!(config.packages.groups.len(), 1);
+        assert_eq!(
+            config.ignore,
+            Some(vec!["vim".to_string(), "emacs".to_string()])
+        );
+        assert!(config.settings.is_none());
+
+        let dir = std::env::temp_dir().join(format!("nixman-base-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let yml = dir.join("packages.yml");
+        std::fs::write(&yml, "settings:\n  base: base.yml\npackages:\n  - git\n").unwrap();
+        std::fs::write(dir.join("base.yml"), "packages:\n  - firefox\n").unwrap();
+        assert_eq!(Signature::find(&dir.join("base.yml")), None);
+        let loaded = load(&yml).unwrap().unwrap();
+        assert!(loaded.packages.contains("firefox"));
+        set_require_signature(true);
+        let refused = load(&yml);
+        set_require_signature(false);
+        assert!(refused.unwrap_err().to_string().contains("no base_key"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// With a `base_key`, an unsigned base is refused even when signatures are not required.
+    #[test]
+    fn refuses_unsigned_base_with_key() {
+        let dir = std::env::temp_dir().join(format!("nixman-base-key-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let yml = dir.join("packages.yml");
+        std::fs::write(
+            &yml,
+            "settings:\n  base: base.yml\n  base_key: minisign.pub\npackages:\n  - git\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("base.yml"), "packages:\n  - firefox\n").unwrap();
+        let refused = load(&yml);
+        assert!(refused.unwrap_err().to_string().contains("is not signed"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,722 @@
+//! Building AUR packages in parallel
+//!
+//! # Overview
+//!
+//! paru builds AUR packages one after another, which makes an apply with many of them slow.
+//! With [`set_jobs`] above 1 (`apply --aur-jobs`, or the `aur_jobs` setting), an apply with
+//! paru builds its AUR packages itself instead:
+//!
+//! 1. [`resolve`] follows their `Depends` and `MakeDepends` through the [AUR RPC](crate::aur)
+//!    and adds the AUR dependencies that are not installed,
+//! 2. [`levels`] orders them so that each package comes after its AUR dependencies; the
+//!    packages of one level do not depend on each other, and split packages of one base share
+//!    a level, so each base is built once,
+//! 3. level by level, the repository dependencies are installed with
+//!    `pacman -S --asdeps --needed`, the packages are cloned and built with `makepkg` in up to
+//!    `jobs` processes at once, and the built packages are installed with one `pacman -U`:
+//!    dependencies as such, the requested packages as explicitly installed. Parallel builds
+//!    never run pacman themselves, which only one process at a time can.
+//!
+//! `apply --locked` builds AUR packages the same way, one after another, at the git commit the
+//! [lockfile](crate::lockfile) recorded ([`install_at_commits`]).
+//!
+//! A failed build is reported with its last lines of output, and the packages depending on it
+//! are not built. Builds run in `<cache dir>/builds`. Unlike paru, nothing offers the PKGBUILDs
+//! for review first, so only turn this on for AUR packages you trust.
+
+use crate::audit::AuditAction;
+use crate::aur::{self, AurPackage};
+use crate::report::Step;
+use crate::{ApplyError, FailureOutput, PackageAction, pacman, run};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+/// The AUR's git repositories, by package base.
+pub const AUR_GIT_URL: &str = "https://aur.archlinux.org";
+
+static JOBS: AtomicUsize = AtomicUsize::new(1);
+
+/// How many AUR packages an apply builds at once; 1 leaves them to paru.
+#[must_use]
+pub fn jobs() -> usize {
+    JOBS.load(Ordering::Relaxed)
+}
+
+/// Build up to `jobs` AUR packages at once for the rest of the process.
+pub fn set_jobs(jobs: usize) {
+    JOBS.store(jobs.max(1), Ordering::Relaxed);
+}
+
+/// The package name of a dependency, without its version constraint.
+#[must_use]
+pub fn dependency_name(dependency: &str) -> &str {
+    dependency
+        .split(['<', '>', '='])
+        .next()
+        .unwrap_or(dependency)
+}
+
+/// The git repository `package` is built from.
+fn base(package: &AurPackage) -> &str {
+    package.package_base.as_deref().unwrap_or(&package.name)
+}
+
+/// The dependencies of `package` needed to build and run it, without version constraints.
+fn all_depends(package: &AurPackage) -> impl Iterator<Item = &str> {
+    package
+        .depends
+        .iter()
+        .chain(&package.make_depends)
+        .map(|dependency| dependency_name(dependency))
+}
+
+/// The dependencies of `package` among `packages`, leaving out its own split packages, which
+/// are built with it.
+fn aur_depends<'a>(
+    package: &'a AurPackage,
+    packages: &'a [AurPackage],
+) -> impl Iterator<Item = &'a str> {
+    all_depends(package).filter(move |name| {
+        packages
+            .iter()
+            .any(|other| other.name == *name && base(other) != base(package))
+    })
+}
+
+/// The names of the installed packages.
+fn installed_names() -> std::io::Result<BTreeSet<String>> {
+    let output = pacman::pacman_list_installed()?;
+    Ok(pacman::parse_package_lines(&output.stdout)
+        .map(|package| package.name.to_string())
+        .collect())
+}
+
+/// The AUR packages `targets` and their AUR dependencies that are not installed.
+///
+/// # Errors
+/// Returns an error if pacman could not be queried or the AUR could not be reached.
+pub fn resolve(targets: &[String]) -> std::io::Result<Vec<AurPackage>> {
+    let repo: BTreeSet<String> = aur::repo_packages()?.into_iter().collect();
+    let installed = installed_names()?;
+    resolve_with(targets, &repo, &installed, &mut |names| aur::info(names))
+}
+
+/// [`resolve`] with the `repo` and `installed` package names given, looking packages up with
+/// `info`.
+fn resolve_with(
+    targets: &[String],
+    repo: &BTreeSet<String>,
+    installed: &BTreeSet<String>,
+    info: &mut dyn FnMut(&[String]) -> std::io::Result<Vec<AurPackage>>,
+) -> std::io::Result<Vec<AurPackage>> {
+    let mut queried: BTreeSet<String> = targets.iter().cloned().collect();
+    let mut pending = targets.to_vec();
+    let mut packages = Vec::new();
+    while !pending.is_empty() {
+        let found = info(&pending)?;
+        // Dependencies in no repository may be AUR packages; the rest are left to makepkg.
+        pending = found
+            .iter()
+            .flat_map(all_depends)
+            .filter(|name| !repo.contains(*name) && !installed.contains(*name))
+            .map(ToString::to_string)
+            .filter(|name| queried.insert(name.clone()))
+            .collect();
+        packages.extend(found);
+    }
+    Ok(packages)
+}
+
+/// `packages` in the order they can be built: each level only depends on earlier ones.
+///
+/// makepkg builds all split packages of a base at once, so they share a level: the first one
+/// after the AUR dependencies of every one of them.
+///
+/// # Errors
+/// Returns a message naming the packages if they depend on each other in a cycle.
+pub fn levels(packages: &[AurPackage]) -> Result<Vec<Vec<&AurPackage>>, String> {
+    let mut built: BTreeSet<&str> = BTreeSet::new();
+    let mut levels = Vec::new();
+    while built.len() < packages.len() {
+        let level: Vec<&AurPackage> = packages
+            .iter()
+            .filter(|package| {
+                !built.contains(package.name.as_str())
+                    && packages
+                        .iter()
+                        .filter(|other| base(other) == base(package))
+                        .flat_map(|other| aur_depends(other, packages))
+                        .all(|name| built.contains(name))
+            })
+            .collect();
+        if level.is_empty() {
+            let stuck: Vec<&str> = packages
+                .iter()
+                .map(|package| package.name.as_str())
+                .filter(|name| !built.contains(name))
+                .collect();
+            return Err(format!(
+                "AUR packages depend on each other in a cycle: {}",
+                stuck.join(", ")
+            ));
+        }
+        built.extend(level.iter().map(|package| package.name.as_str()));
+        levels.push(level);
+    }
+    Ok(levels)
+}
+
+/// A finished build of one package base.
+struct Build {
+    base: String,
+    step: Step,
+    /// The packages it produced.
+    files: Vec<PathBuf>,
+}
+
+/// Clone and build the package base `base` under `dir`, at `commit` if given, letting makepkg
+/// install missing dependencies if `syncdeps`.
+fn build(
+    dir: &Path,
+    base: &str,
+    commit: Option<&str>,
+    packages: &[String],
+    syncdeps: bool,
+) -> std::io::Result<Build> {
+    let started = Instant::now();
+    let path = dir.join(base);
+    if path.exists() {
+        std::fs::remove_dir_all(&path)?;
+    }
+    let finished = |output: run::RunOutput, files| Build {
+        base: base.to_string(),
+        step: Step::finished(
+            "aur",
+            PackageAction::Install,
+            packages,
+            output.status,
+            started,
+            output.tail,
+        ),
+        files,
+    };
+    let git =
+        |args: &[&str]| run::run_command(Command::new("git").args(args), false, &mut |_, _| {});
+    let url = format!("{AUR_GIT_URL}/{base}.git");
+    let path_arg = path.to_string_lossy();
+    // A shallow clone only has the latest revision.
+    let clone = match commit {
+        Some(_) => git(&["clone", "--quiet", &url, &path_arg])?,
+        None => git(&["clone", "--quiet", "--depth", "1", &url, &path_arg])?,
+    };
+    if !clone.status.success() {
+        return Ok(finished(clone, Vec::new()));
+    }
+    if let Some(commit) = commit {
+        let checkout = git(&["-C", &path_arg, "checkout", "--quiet", commit])?;
+        if !checkout.status.success() {
+            return Ok(finished(checkout, Vec::new()));
+        }
+    }
+    let makepkg = |args: &[&str]| {
+        run::run_command(
+            Command::new("makepkg").args(args).current_dir(&path),
+            false,
+            &mut |_, _| {},
+        )
+    };
+    // Repository dependencies are normally installed beforehand; --syncdeps catches the rest.
+    let mut args = vec!["--noconfirm", "--cleanbuild", "--force"];
+    if syncdeps {
+        args.push("--syncdeps");
+    }
+    let output = makepkg(&args)?;
+    if !output.status.success() {
+        return Ok(finished(output, Vec::new()));
+    }
+    let files = makepkg(&["--packagelist"])?
+        .stdout_lines()
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|file| {
+            crate::clean::parse_file_name(file).is_some_and(|file| packages.contains(&file.name))
+        })
+        .collect();
+    Ok(finished(output, files))
+}
+
+/// Build each of `bases` with its `packages` under `dir`, up to `jobs` at once.
+///
+/// Only a lone build may install missing dependencies: makepkg does so with pacman, whose
+/// database lock would fail the builds running beside it.
+fn build_all(
+    dir: &Path,
+    bases: &[(String, Vec<String>)],
+    jobs: usize,
+) -> Vec<std::io::Result<Build>> {
+    let queue = Mutex::new(bases.iter());
+    let results = Mutex::new(Vec::new());
+    let workers = jobs.min(bases.len());
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                    let Some((base, packages)) = next else {
+                        break;
+                    };
+                    let build = build(dir, base, None, packages, workers == 1);
+                    results
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(build);
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The dependencies of `ready` that are not among the AUR `packages`, with their version
+/// constraints, sorted and without duplicates.
+fn repo_depends<'a>(ready: &[&'a AurPackage], packages: &[AurPackage]) -> Vec<&'a str> {
+    let mut depends: Vec<&str> = ready
+        .iter()
+        .flat_map(|package| package.depends.iter().chain(&package.make_depends))
+        .map(String::as_str)
+        .filter(|dependency| {
+            !packages
+                .iter()
+                .any(|package| package.name == dependency_name(dependency))
+        })
+        .collect();
+    depends.sort_unstable();
+    depends.dedup();
+    depends
+}
+
+/// The [`repo_depends`] of `ready` that no installed package satisfies (`pacman -T`), without
+/// version constraints.
+fn missing_depends(ready: &[&AurPackage], packages: &[AurPackage]) -> std::io::Result<Vec<String>> {
+    let depends = repo_depends(ready, packages);
+    if depends.is_empty() {
+        return Ok(Vec::new());
+    }
+    let output = run::output(pacman::pacman_command().arg("-T").args(&depends))?;
+    let mut missing: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| dependency_name(line.trim()).to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    missing.dedup();
+    Ok(missing)
+}
+
+/// Run the elevated pacman command `args` on `targets` as a step of the report, which, like the
+/// [audit log](crate::audit), names `names`.
+fn pacman_step(
+    args: &[&str],
+    targets: &[String],
+    names: &[String],
+    noconfirm: bool,
+) -> std::io::Result<Step> {
+    let started = Instant::now();
+    let mut cmd = pacman::elevated_pacman();
+    cmd.args(args).args(targets);
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    let action = if args.first() == Some(&"-D") {
+        AuditAction::Mark
+    } else {
+        AuditAction::Install
+    };
+    let captured = pacman::run_audited(&mut cmd, action, names)?;
+    Ok(Step::finished(
+        "pacman",
+        PackageAction::Install,
+        names,
+        captured.status,
+        started,
+        captured.tail,
+    ))
+}
+
+/// The failure of `step`, or `None` if it succeeded.
+fn failure_of(step: &Step) -> Option<FailureOutput> {
+    (step.outcome != crate::report::Outcome::Succeeded).then(|| FailureOutput {
+        packages: step.packages.clone(),
+        lines: step.tail.clone(),
+    })
+}
+
+/// The packages of `level` to build, recording the others in `failed`: those of a base with a
+/// package depending on one that failed or was not built, since makepkg builds them together.
+fn ready_packages<'a>(
+    level: Vec<&'a AurPackage>,
+    packages: &'a [AurPackage],
+    failed: &mut Vec<FailureOutput>,
+) -> Vec<&'a AurPackage> {
+    let blocked: Vec<(&str, &str)> = level
+        .iter()
+        .filter_map(|package| {
+            aur_depends(package, packages)
+                .find(|name| {
+                    failed
+                        .iter()
+                        .any(|failure| failure.packages.iter().any(|failed| failed == name))
+                })
+                .map(|dependency| (base(package), dependency))
+        })
+        .collect();
+    let mut ready = Vec::new();
+    for package in level {
+        match blocked.iter().find(|(name, _)| *name == base(package)) {
+            Some((_, dependency)) => failed.push(FailureOutput {
+                packages: vec![package.name.clone()],
+                lines: vec![format!("not built because {dependency} failed")],
+            }),
+            None => ready.push(package),
+        }
+    }
+    ready
+}
+
+/// Build and install the AUR packages `targets` and their AUR dependencies, up to `jobs` builds
+/// at once.
+///
+/// # Returns
+/// The steps that ran, and the packages that failed or were not built because a dependency
+/// failed.
+///
+/// # Errors
+/// Returns an error if the dependencies cannot be resolved or ordered, or a command could not
+/// be executed.
+pub fn install(
+    targets: &[String],
+    jobs: usize,
+    noconfirm: bool,
+) -> std::io::Result<(Vec<Step>, Vec<FailureOutput>)> {
+    let packages = resolve(targets)?;
+    let levels = levels(&packages).map_err(std::io::Error::other)?;
+    let dir = crate::paths::ensure_cache_subdir("builds")?;
+    let mut steps = Vec::new();
+    let mut failed: Vec<FailureOutput> = Vec::new();
+    for level in levels {
+        let ready = ready_packages(level, &packages, &mut failed);
+        let repo_depends = missing_depends(&ready, &packages)?;
+        if !repo_depends.is_empty() {
+            let step = pacman_step(
+                &["-S", "--asdeps", "--needed"],
+                &repo_depends,
+                &repo_depends,
+                noconfirm,
+            )?;
+            let failure = failure_of(&step);
+            steps.push(step);
+            if let Some(mut failure) = failure {
+                failure.packages = ready.iter().map(|package| package.name.clone()).collect();
+                failed.push(failure);
+                continue;
+            }
+        }
+        let mut bases: Vec<(String, Vec<String>)> = Vec::new();
+        for package in &ready {
+            match bases.iter_mut().find(|(name, _)| name == base(package)) {
+                Some((_, names)) => names.push(package.name.clone()),
+                None => bases.push((base(package).to_string(), vec![package.name.clone()])),
+            }
+        }
+        let mut files = Vec::new();
+        let mut produced = Vec::new();
+        for build in build_all(&dir, &bases, jobs) {
+            let build = build?;
+            if let Some(failure) = failure_of(&build.step) {
+                failed.push(failure);
+            } else {
+                files.extend(build.files);
+                produced.extend(
+                    bases
+                        .iter()
+                        .filter(|(name, _)| *name == build.base)
+                        .flat_map(|(_, names)| names.clone()),
+                );
+            }
+            steps.push(build.step);
+        }
+        if files.is_empty() {
+            continue;
+        }
+        let files: Vec<String> = files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        let step = pacman_step(
+            &["-U", "--asdeps", "--needed"],
+            &files,
+            &produced,
+            noconfirm,
+        )?;
+        let failure = failure_of(&step);
+        steps.push(step);
+        if let Some(failure) = failure {
+            failed.push(failure);
+            continue;
+        }
+        let explicit: Vec<String> = produced
+            .into_iter()
+            .filter(|name| targets.contains(name))
+            .collect();
+        if !explicit.is_empty() {
+            let step = pacman_step(&["-D", "--asexplicit"], &explicit, &explicit, true)?;
+            failed.extend(failure_of(&step));
+            steps.push(step);
+        }
+    }
+    Ok((steps, failed))
+}
+
+/// Build each package base at a recorded commit and install the named packages from it.
+///
+/// `sources` holds `(base, commit, names)`, as `apply --locked` finds them in the
+/// [lockfile](crate::lockfile); the bases are built one after another.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with the build's output if a build or the install fails.
+pub fn install_at_commits(
+    sources: &[(String, String, Vec<String>)],
+    noconfirm: bool,
+) -> Result<(), ApplyError> {
+    if sources.is_empty() {
+        return Ok(());
+    }
+    let dir = crate::paths::ensure_cache_subdir("builds").map_err(|e| e.to_string())?;
+    let mut files = Vec::new();
+    let mut names = Vec::new();
+    for (base, commit, packages) in sources {
+        let build = build(&dir, base, Some(commit), packages, true).map_err(|e| e.to_string())?;
+        if let Some(failure) = failure_of(&build.step) {
+            return Err(
+                ApplyError::from(format!("Failed to build {base} at {commit}"))
+                    .with_output(vec![failure]),
+            );
+        }
+        files.extend(build.files.iter().map(|file| file.display().to_string()));
+        names.extend(packages.iter().cloned());
+    }
+    let step = pacman_step(&["-U"], &files, &names, noconfirm).map_err(|e| e.to_string())?;
+    failure_of(&step).map_or(Ok(()), |failure| {
+        Err(
+            ApplyError::from("Failed to install the locked AUR packages".to_string())
+                .with_output(vec![failure]),
+        )
+    })
+}
+
+/// The commit of the AUR repository of `base` that builds `version`, if it can be found.
+///
+/// A clone left by paru or by an earlier build is used when its `.SRCINFO` describes
+/// `version`; otherwise, if `version` is the `latest` one in the AUR, the repository's current
+/// commit is asked for with `git ls-remote`.
+#[must_use]
+pub fn source_commit(base: &str, version: &str, latest: bool) -> Option<String> {
+    let git = |args: &[&str]| {
+        let output = run::output(Command::new("git").args(args)).ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let dirs = [
+        crate::paths::paru_clone_dir().join(base),
+        crate::paths::cache_dir().join("builds").join(base),
+    ];
+    let cloned = dirs.into_iter().find_map(|dir| {
+        let srcinfo = std::fs::read_to_string(dir.join(".SRCINFO")).ok()?;
+        (aur::srcinfo_version(&srcinfo)? == version)
+            .then(|| git(&["-C", &dir.to_string_lossy(), "rev-parse", "HEAD"]))?
+    });
+    let commit = match cloned {
+        Some(commit) => commit,
+        None if latest => git(&["ls-remote", &format!("{AUR_GIT_URL}/{base}.git"), "HEAD"])?,
+        None => return None,
+    };
+    commit.split_whitespace().next().map(ToString::to_string)
+}
+
+/// The names of `to_install` to build in parallel: with paru and more than one [job](jobs),
+/// those in no sync repository that the AUR knows.
+///
+/// Installed ones, which only need marking as explicitly installed, are left to paru.
+///
+/// # Errors
+/// Returns an error if pacman could not be queried or the AUR could not be reached.
+pub fn parallel_targets(to_install: &[String], use_paru: bool) -> std::io::Result<Vec<String>> {
+    if !use_paru || jobs() <= 1 || to_install.is_empty() || crate::offline::is_offline() {
+        return Ok(Vec::new());
+    }
+    let repo: BTreeSet<String> = aur::repo_packages()?.into_iter().collect();
+    let installed = installed_names()?;
+    let candidates: Vec<String> = to_install
+        .iter()
+        .filter(|name| !repo.contains(*name) && !installed.contains(*name))
+        .cloned()
+        .collect();
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+    let found = aur::info(&candidates)?;
+    Ok(candidates
+        .into_iter()
+        .filter(|name| found.iter().any(|package| package.name == *name))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(
+        name: &str,
+        base: Option<&str>,
+        depends: &[&str],
+        make_depends: &[&str],
+    ) -> AurPackage {
+        let names = |names: &[&str]| names.iter().map(ToString::to_string).collect();
+        AurPackage {
+            name: name.to_string(),
+            version: "1.0-1".to_string(),
+            out_of_date: None,
+            maintainer: None,
+            package_base: base.map(ToString::to_string),
+            depends: names(depends),
+            make_depends: names(make_depends),
+        }
+    }
+
+    /// Packages come after their AUR dependencies, split packages of one base do not wait for
+    /// each other, and cycles are refused.
+    #[test]
+    fn orders_builds_by_dependencies() {
+        let packages = vec![
+            package("app", None, &["lib-a>=2", "glibc"], &["tool"]),
+            package("lib-a", Some("lib"), &["lib-b"], &[]),
+            package("lib-b", Some("lib"), &[], &[]),
+            package("tool", None, &[], &["cmake"]),
+            package("other", None, &[], &[]),
+        ];
+        let names: Vec<Vec<&str>> = levels(&packages)
+            .unwrap()
+            .iter()
+            .map(|level| level.iter().map(|package| package.name.as_str()).collect())
+            .collect();
+        assert_eq!(
+            names,
+            vec![vec!["lib-a", "lib-b", "tool", "other"], vec!["app"]]
+        );
+
+        let cycle = vec![
+            package("a", None, &["b"], &[]),
+            package("b", None, &["a"], &[]),
+        ];
+        assert_eq!(
+            levels(&cycle).unwrap_err(),
+            "AUR packages depend on each other in a cycle: a, b"
+        );
+        assert_eq!(dependency_name("python>=3.11"), "python");
+    }
+
+    /// Split packages of one base share the level of the base's latest dependency, so the base
+    /// is cloned and built once.
+    #[test]
+    fn keeps_split_packages_together() {
+        let packages = vec![
+            package("tool", None, &[], &[]),
+            package("lib-a", Some("lib"), &[], &["tool"]),
+            package("lib-b", Some("lib"), &[], &[]),
+            package("app", None, &["lib-b"], &[]),
+        ];
+        let names: Vec<Vec<&str>> = levels(&packages)
+            .unwrap()
+            .iter()
+            .map(|level| level.iter().map(|package| package.name.as_str()).collect())
+            .collect();
+        assert_eq!(
+            names,
+            vec![vec!["tool"], vec!["lib-a", "lib-b"], vec!["app"]]
+        );
+    }
+
+    /// A failed build skips the whole base of a package depending on it, and the packages
+    /// depending on the skipped ones in later levels.
+    #[test]
+    fn skips_dependents_of_failed_builds() {
+        let packages = vec![
+            package("broken", None, &[], &[]),
+            package("lib-a", Some("lib"), &["broken"], &[]),
+            package("lib-b", Some("lib"), &[], &[]),
+            package("other", None, &[], &[]),
+            package("app", None, &["lib-b"], &[]),
+        ];
+        let mut failed = vec![FailureOutput {
+            packages: vec!["broken".to_string()],
+            lines: vec!["==> ERROR: A failure occurred in build().".to_string()],
+        }];
+        let level = vec![&packages[1], &packages[2], &packages[3]];
+        let ready = ready_packages(level, &packages, &mut failed);
+        assert_eq!(ready, vec![&packages[3]]);
+        let ready = ready_packages(vec![&packages[4]], &packages, &mut failed);
+        assert!(ready.is_empty());
+        let skipped: Vec<(&str, &str)> = failed[1..]
+            .iter()
+            .map(|failure| (failure.packages[0].as_str(), failure.lines[0].as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("lib-a", "not built because broken failed"),
+                ("lib-b", "not built because broken failed"),
+                ("app", "not built because lib-b failed"),
+            ]
+        );
+    }
+
+    /// AUR dependencies are looked up until none are left, each once, skipping repository and
+    /// installed packages; repository dependencies are left for pacman.
+    #[test]
+    fn resolves_aur_dependencies() {
+        let aur = vec![
+            package("app", None, &["lib>=2", "glibc"], &["tool"]),
+            package("lib", None, &["installed", "tool"], &[]),
+            package("tool", None, &[], &["cmake"]),
+        ];
+        let names = |names: &[&str]| -> BTreeSet<String> {
+            names.iter().map(ToString::to_string).collect()
+        };
+        let mut queries = Vec::new();
+        let packages = resolve_with(
+            &["app".to_string()],
+            &names(&["glibc", "cmake"]),
+            &names(&["installed"]),
+            &mut |pending| {
+                queries.push(pending.to_vec());
+                Ok(aur
+                    .iter()
+                    .filter(|package| pending.contains(&package.name))
+                    .cloned()
+                    .collect())
+            },
+        )
+        .unwrap();
+        assert_eq!(packages, aur);
+        assert_eq!(queries, vec![vec!["app"], vec!["lib", "tool"]]);
+        assert_eq!(
+            repo_depends(&[&aur[0], &aur[2]], &aur),
+            vec!["cmake", "glibc"]
+        );
+    }
+}
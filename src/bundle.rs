@@ -0,0 +1,304 @@
+//! Portable bundles of the YAML's packages for air-gapped provisioning
+//!
+//! # Overview
+//!
+//! `nixman bundle create <dir>` turns the config into an install medium: every package the YAML
+//! declares for this machine, with all of its dependencies, is put into one directory together
+//! with an index (`bundle.yml`) and a copy of the config (`packages.yml`):
+//!
+//! - repository packages and their dependencies are downloaded with `pacman -Sw` against an
+//!   empty local database, so dependencies that are already installed here are included too,
+//! - versions pinned in the YAML that the repositories no longer have come from the
+//!   [Arch Linux Archive](crate::archive),
+//! - AUR packages are copied from paru's build cache, so they must have been built once; those
+//!   that were not are listed as missing in the index.
+//!
+//! `nixman bundle apply <dir>` installs whatever the machine lacks from the bundle with a
+//! single `pacman -U`, without the network, and marks the dependencies as such.
+//!
+//! ```text
+//! bundle/
+//! ├── bundle.yml
+//! ├── packages.yml
+//! ├── htop-3.3.0-3-x86_64.pkg.tar.zst
+//! └── ...
+//! ```
+
+use crate::audit::AuditAction;
+use crate::clean::{self, CachedFile};
+use crate::condition::Host;
+use crate::{ApplyError, FailureOutput, InstallReason, archive, offline, pacman};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The file name of a bundle's index.
+pub const INDEX: &str = "bundle.yml";
+
+/// The file name of the config copied into a bundle.
+pub const CONFIG: &str = "packages.yml";
+
+/// A package file in a bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundledPackage {
+    pub name: String,
+    pub version: String,
+    /// The file name within the bundle.
+    pub file: String,
+    /// Whether the YAML declares it as explicit, or it is only there as a dependency.
+    pub reason: InstallReason,
+}
+
+/// The index of a bundle (`bundle.yml`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleIndex {
+    pub packages: Vec<BundledPackage>,
+    /// Declared packages that could not be bundled, such as AUR packages that were never built.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<String>,
+}
+
+impl BundleIndex {
+    /// Index the package `files` of a bundle; the newest version of each name is used, and
+    /// the names in `explicit` are marked as explicit.
+    #[must_use]
+    pub fn new(files: &[CachedFile], explicit: &[String], missing: Vec<String>) -> Self {
+        let mut newest: BTreeMap<&str, &CachedFile> = BTreeMap::new();
+        for file in files {
+            if let Some(found) = offline::find(files, &file.name, None) {
+                newest.insert(&found.name, found);
+            }
+        }
+        let packages = newest
+            .into_values()
+            .map(|file| BundledPackage {
+                name: file.name.clone(),
+                version: file.version.clone(),
+                file: file
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                reason: if explicit.contains(&file.name) {
+                    InstallReason::Explicit
+                } else {
+                    InstallReason::Dependency
+                },
+            })
+            .collect();
+        Self { packages, missing }
+    }
+
+    /// Read the index of the bundle in `dir`.
+    ///
+    /// # Errors
+    /// Returns an error if the index cannot be read or parsed.
+    pub fn load(dir: &Path) -> std::io::Result<Self> {
+        let content = std::fs::read_to_string(dir.join(INDEX))?;
+        crate::schema::from_str(&content)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Write the index into the bundle in `dir`.
+    ///
+    /// # Errors
+    /// Returns an error if the index cannot be written.
+    pub fn save(&self, dir: &Path) -> std::io::Result<()> {
+        let content = serde_yml::to_string(self).map_err(std::io::Error::other)?;
+        std::fs::write(dir.join(INDEX), content)
+    }
+
+    /// The packages that are not installed at their bundled version, given `installed`
+    /// `(name, version)` pairs.
+    #[must_use]
+    pub fn to_install(&self, installed: &[(String, String)]) -> Vec<&BundledPackage> {
+        self.packages
+            .iter()
+            .filter(|pkg| {
+                !installed
+                    .iter()
+                    .any(|(name, version)| *name == pkg.name && *version == pkg.version)
+            })
+            .collect()
+    }
+}
+
+/// Put every package the YAML at `yml_path` declares for this machine, with its dependencies,
+/// into `dir`, and write the index and a copy of the config next to them.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read, pacman or the archive fail to download a
+/// package, or the bundle cannot be written.
+pub fn create<P: AsRef<Path>>(yml_path: P, dir: &Path) -> std::io::Result<BundleIndex> {
+    let config = crate::read_effective_config(&yml_path)?;
+    let mut list = config.packages.clone();
+    list.retain_active(&Host::current());
+    let groups = crate::available_groups().unwrap_or_default();
+    let wanted = crate::expand_groups(&list, &[], &groups);
+    let names: Vec<String> = wanted.iter().map(|(name, _)| name.clone()).collect();
+    let explicit: Vec<String> = wanted
+        .iter()
+        .filter(|(_, reason)| *reason == InstallReason::Explicit)
+        .map(|(name, _)| name.clone())
+        .collect();
+    std::fs::create_dir_all(dir)?;
+
+    let available = pacman::pacman_sync_infos(&names)?;
+    let pinned = |name: &str| list.get(name).and_then(|pkg| pkg.version.clone());
+    let mut from_repos = Vec::new();
+    let mut from_archive = Vec::new();
+    let mut from_aur = Vec::new();
+    for name in names {
+        match (
+            available.iter().find(|info| info.name == name),
+            pinned(&name),
+        ) {
+            (Some(info), Some(version)) if info.version != version => {
+                from_archive.push((name, version));
+            }
+            (Some(_), _) => from_repos.push(name),
+            (None, _) => from_aur.push(name),
+        }
+    }
+    download_with_dependencies(&from_repos, dir)?;
+    for (name, version) in &from_archive {
+        archive::find_package(name, version).and_then(|url| archive::download(&url, dir))?;
+    }
+    let missing = copy_built(&from_aur, dir)?;
+
+    let index = BundleIndex::new(&clean::cache_files(dir)?, &explicit, missing);
+    index.save(dir)?;
+    crate::write_config(&config, dir.join(CONFIG))?;
+    Ok(index)
+}
+
+/// Download `names` and their whole dependency tree into `dir` with `pacman -Sw`, against an
+/// empty local database that shares the system's sync databases.
+fn download_with_dependencies(names: &[String], dir: &Path) -> std::io::Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    let db = crate::paths::bundle_db();
+    if db.exists() {
+        std::fs::remove_dir_all(&db)?;
+    }
+    std::fs::create_dir_all(db.join("local"))?;
+    std::os::unix::fs::symlink(crate::syncdb::sync_dir(), db.join("sync"))?;
+    let programs = pacman::programs();
+    let status = pacman::elevated(programs.pacman())
+        .args(&programs.extra_args)
+        .env("LC_ALL", "C")
+        .args(["-Sw", "--noconfirm", "--dbpath"])
+        .arg(&db)
+        .arg("--cachedir")
+        .arg(dir)
+        .args(names)
+        .status();
+    let _ = std::fs::remove_dir_all(&db);
+    if status?.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(
+            "pacman -Sw failed to download some packages",
+        ))
+    }
+}
+
+/// Copy the newest package paru built for each of `names` into `dir`; returns the names paru
+/// has not built.
+fn copy_built(names: &[String], dir: &Path) -> std::io::Result<Vec<String>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let built = clean::paru_files()?;
+    let mut missing = Vec::new();
+    for name in names {
+        match offline::find(&built, name, None) {
+            Some(file) => {
+                let target: PathBuf = dir.join(file.path.file_name().unwrap_or_default());
+                std::fs::copy(&file.path, target)?;
+            }
+            None => missing.push(name.clone()),
+        }
+    }
+    Ok(missing)
+}
+
+/// Install what the machine lacks from the bundle in `dir` with `pacman -U`, then mark the
+/// bundled dependencies as dependencies. Returns the installed package names.
+///
+/// # Errors
+/// Returns an [`ApplyError`] if the index cannot be read or pacman fails.
+pub fn apply(dir: &Path, noconfirm: bool) -> Result<Vec<String>, ApplyError> {
+    let index =
+        BundleIndex::load(dir).map_err(|e| format!("{}: {e}", dir.join(INDEX).display()))?;
+    let installed = crate::lockfile::installed_exact_versions().map_err(|e| e.to_string())?;
+    let to_install = index.to_install(&installed);
+    if to_install.is_empty() {
+        return Ok(Vec::new());
+    }
+    let names: Vec<String> = to_install.iter().map(|pkg| pkg.name.clone()).collect();
+    let mut cmd = pacman::elevated_pacman();
+    cmd.arg("-U")
+        .args(to_install.iter().map(|pkg| dir.join(&pkg.file)));
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    let captured =
+        pacman::run_audited(&mut cmd, AuditAction::Install, &names).map_err(|e| e.to_string())?;
+    if !captured.status.success() {
+        return Err(
+            ApplyError::from("Failed to install from the bundle".to_string()).with_output(vec![
+                FailureOutput {
+                    packages: names,
+                    lines: captured.tail,
+                },
+            ]),
+        );
+    }
+    let dependencies: Vec<String> = to_install
+        .iter()
+        .filter(|pkg| pkg.reason == InstallReason::Dependency)
+        .map(|pkg| pkg.name.clone())
+        .collect();
+    if !dependencies.is_empty() {
+        pacman::pacman_mark(&dependencies, InstallReason::Dependency).map_err(|e| e.to_string())?;
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The newest file of each package is indexed, and installed versions are skipped.
+    #[test]
+    fn indexes_bundles() {
+        let files: Vec<CachedFile> = [
+            "htop-3.3.0-3-x86_64.pkg.tar.zst",
+            "htop-3.3.0-3-x86_64.pkg.tar.zst.sig",
+            "ncurses-6.4-1-x86_64.pkg.tar.zst",
+            "ncurses-6.5-1-x86_64.pkg.tar.zst",
+        ]
+        .iter()
+        .filter_map(|name| clean::parse_file_name(Path::new(name)))
+        .collect();
+        let index = BundleIndex::new(&files, &["htop".to_string()], vec!["paru-bin".to_string()]);
+        assert_eq!(index.packages.len(), 2);
+        assert_eq!(index.packages[0].file, "htop-3.3.0-3-x86_64.pkg.tar.zst");
+        assert_eq!(index.packages[0].reason, InstallReason::Explicit);
+        assert_eq!(index.packages[1].version, "6.5-1");
+        assert_eq!(index.packages[1].reason, InstallReason::Dependency);
+
+        let yaml = serde_yml::to_string(&index).unwrap();
+        let parsed: BundleIndex = serde_yml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, index);
+        let installed = vec![("htop".to_string(), "3.3.0-3".to_string())];
+        let names: Vec<&str> = index
+            .to_install(&installed)
+            .iter()
+            .map(|pkg| pkg.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["ncurses"]);
+    }
+}
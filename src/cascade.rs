@@ -0,0 +1,170 @@
+//! What removing packages takes with it
+//!
+//! # Overview
+//!
+//! `apply` and `nixman -R` remove with `pacman -Rns`, which also removes the dependencies
+//! nothing else needs any more, so the names in a plan understate what goes. [`cascade`] asks
+//! pacman for the full list without removing anything (`pacman -Rns --print`), and groups the
+//! extra packages under the target whose dependency tree (`pactree -lu`, as for the
+//! [critical packages](crate::safety)) holds them. A package in several trees, or in none, is
+//! listed as shared: it goes because all of the targets go together.
+//!
+//! `nixman why-remove` prints the report, and `apply --confirm` shows it before asking.
+
+#[cfg(target_os = "linux")]
+use crate::pacman::{self, PrintedPackage};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A target and the packages only it takes out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TargetCascade {
+    pub target: String,
+    /// The dependencies removed with it, sorted.
+    pub also_removes: Vec<String>,
+}
+
+/// The full result of removing a set of targets.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Cascade {
+    pub targets: Vec<TargetCascade>,
+    /// Removed packages that no single target accounts for, sorted.
+    pub shared: Vec<String>,
+    /// How many packages are removed in all, targets included.
+    pub total: usize,
+}
+
+impl Cascade {
+    /// Whether anything besides the targets is removed.
+    #[must_use]
+    pub fn has_extras(&self) -> bool {
+        !self.shared.is_empty()
+            || self
+                .targets
+                .iter()
+                .any(|target| !target.also_removes.is_empty())
+    }
+}
+
+/// Group the `removed` packages under `targets`, given each target's dependency tree in
+/// `trees`.
+#[must_use]
+pub fn group(
+    targets: &[String],
+    removed: &[String],
+    trees: &BTreeMap<String, Vec<String>>,
+) -> Cascade {
+    let mut cascade = Cascade {
+        targets: targets
+            .iter()
+            .map(|target| TargetCascade {
+                target: target.clone(),
+                also_removes: Vec::new(),
+            })
+            .collect(),
+        shared: Vec::new(),
+        total: removed.len(),
+    };
+    for name in removed.iter().filter(|name| !targets.contains(name)) {
+        let mut owners = cascade.targets.iter_mut().filter(|target| {
+            trees
+                .get(&target.target)
+                .is_some_and(|tree| tree.contains(name))
+        });
+        match (owners.next(), owners.next()) {
+            (Some(owner), None) => owner.also_removes.push(name.clone()),
+            _ => cascade.shared.push(name.clone()),
+        }
+    }
+    for target in &mut cascade.targets {
+        target.also_removes.sort();
+    }
+    cascade.shared.sort();
+    cascade
+}
+
+/// The packages `pacman -Rns` would remove for `targets`, the targets included.
+///
+/// # Errors
+/// Returns an error if pacman could not be executed or refuses the removal, e.g. because a
+/// target is not installed or another package needs it.
+#[cfg(target_os = "linux")]
+pub fn removed_packages(targets: &[String]) -> std::io::Result<Vec<PrintedPackage>> {
+    let output = pacman::print_transaction(&["-Rns"], targets)?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "pacman refuses to remove them: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(pacman::parse_printed(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// What removing `targets` with `pacman -Rns` takes out, grouped per target.
+///
+/// Without `pactree`, every extra package is listed as shared.
+///
+/// # Errors
+/// Returns an error if pacman could not be executed or refuses the removal.
+#[cfg(target_os = "linux")]
+pub fn cascade(targets: &[String]) -> std::io::Result<Cascade> {
+    if targets.is_empty() {
+        return Ok(Cascade::default());
+    }
+    let removed: Vec<String> = removed_packages(targets)?
+        .into_iter()
+        .map(|package| package.name)
+        .collect();
+    let trees = targets
+        .iter()
+        .filter_map(|target| {
+            crate::safety::dependency_tree(target)
+                .ok()
+                .map(|tree| (target.clone(), tree))
+        })
+        .collect();
+    Ok(group(targets, &removed, &trees))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Extra packages go under the one target that needs them; the rest are shared.
+    #[test]
+    fn groups_removals_per_target() {
+        let names =
+            |names: &[&str]| -> Vec<String> { names.iter().map(ToString::to_string).collect() };
+        let trees = BTreeMap::from([
+            (
+                "gimp".to_string(),
+                names(&["gimp", "babl", "gegl", "libmypaint"]),
+            ),
+            (
+                "krita".to_string(),
+                names(&["krita", "libmypaint", "quazip"]),
+            ),
+        ]);
+        let cascade = group(
+            &names(&["gimp", "krita"]),
+            &names(&[
+                "gimp",
+                "gegl",
+                "babl",
+                "krita",
+                "quazip",
+                "libmypaint",
+                "mystery",
+            ]),
+            &trees,
+        );
+        assert_eq!(cascade.total, 7);
+        assert_eq!(cascade.targets[0].also_removes, vec!["babl", "gegl"]);
+        assert_eq!(cascade.targets[1].also_removes, vec!["quazip"]);
+        assert_eq!(cascade.shared, vec!["libmypaint", "mystery"]);
+        assert!(cascade.has_extras());
+        assert!(!group(&names(&["htop"]), &names(&["htop"]), &trees).has_extras());
+    }
+}
@@ -0,0 +1,209 @@
+//! Package cache cleanup
+//!
+//! # Overview
+//!
+//! pacman keeps every package it downloads in `/var/cache/pacman/pkg`, and paru keeps the
+//! packages it builds under `~/.cache/paru/clone`. `nixman clean` applies a retention policy to
+//! both, like `paccache -r` but aware of the YAML:
+//!
+//! - the newest [`DEFAULT_KEEP`] versions of each package are kept (`cache_keep` in the
+//!   [settings](crate::settings), or `--keep`),
+//! - versions pinned in the YAML are always kept, so `apply` can reinstall them offline,
+//! - everything else is removed, together with its `.sig` file.
+//!
+//! `nixman watch --clean` (and `install-timer --clean`) run the same cleanup after each check.
+
+use crate::Config;
+use crate::pacman;
+use crate::versioning::{FullVersion, vercmp};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// pacman's package cache.
+pub const PACMAN_CACHE: &str = "/var/cache/pacman/pkg";
+
+/// How many versions of each package are kept by default, as with `paccache`.
+pub const DEFAULT_KEEP: usize = 3;
+
+/// A package file in a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub name: String,
+    /// `version-release`, with the epoch if there is one.
+    pub version: String,
+    pub arch: String,
+}
+
+/// Parse a package file name such as `htop-3.3.0-1-x86_64.pkg.tar.zst` (or its `.sig`).
+#[must_use]
+pub fn parse_file_name(path: &Path) -> Option<CachedFile> {
+    let file_name = path.file_name()?.to_str()?;
+    let stem = &file_name[..file_name.find(".pkg.tar")?];
+    let mut parts = stem.rsplitn(4, '-');
+    let arch = parts.next()?;
+    let release = parts.next()?;
+    let version = parts.next()?;
+    let name = parts.next()?;
+    Some(CachedFile {
+        path: path.to_path_buf(),
+        name: name.to_string(),
+        version: format!("{version}-{release}"),
+        arch: arch.to_string(),
+    })
+}
+
+/// The package files directly in `dir`; a missing directory has none.
+///
+/// # Errors
+/// Returns an error if the directory exists but cannot be read.
+pub fn cache_files(dir: &Path) -> std::io::Result<Vec<CachedFile>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        if let Some(file) = parse_file_name(&entry?.path()) {
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+/// paru's build directory (`$XDG_CACHE_HOME/paru/clone`).
+#[must_use]
+pub fn paru_cache() -> PathBuf {
+    crate::paths::paru_clone_dir()
+}
+
+/// The packages paru has built, one clone directory per package base.
+///
+/// # Errors
+/// Returns an error if the cache exists but cannot be read.
+pub fn paru_files() -> std::io::Result<Vec<CachedFile>> {
+    let dir = paru_cache();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(cache_files(&path)?);
+        }
+    }
+    Ok(files)
+}
+
+/// The versions pinned in `config`, by package name.
+#[must_use]
+pub fn pinned_versions(config: &Config) -> BTreeMap<String, FullVersion> {
+    config
+        .packages
+        .packages
+        .values()
+        .filter_map(|pkg| Some((pkg.name.clone(), pkg.version.clone()?)))
+        .collect()
+}
+
+/// The files to remove: all but the newest `keep` versions of each package and architecture,
+/// sparing pinned versions.
+#[must_use]
+pub fn files_to_remove(
+    files: &[CachedFile],
+    keep: usize,
+    pinned: &BTreeMap<String, FullVersion>,
+) -> Vec<PathBuf> {
+    let mut versions: BTreeMap<(&str, &str), Vec<&str>> = BTreeMap::new();
+    for file in files {
+        let list = versions.entry((&file.name, &file.arch)).or_default();
+        if !list.contains(&file.version.as_str()) {
+            list.push(&file.version);
+        }
+    }
+    for list in versions.values_mut() {
+        list.sort_by(|a, b| vercmp(b, a));
+        list.truncate(keep);
+    }
+    let mut remove: Vec<PathBuf> = files
+        .iter()
+        .filter(|file| {
+            !versions[&(file.name.as_str(), file.arch.as_str())].contains(&file.version.as_str())
+        })
+        .filter(|file| {
+            pinned
+                .get(&file.name)
+                .is_none_or(|pin| *pin != FullVersion::from(file.version.as_str()))
+        })
+        .map(|file| file.path.clone())
+        .collect();
+    remove.sort();
+    remove
+}
+
+/// Apply the retention policy to the pacman and paru caches.
+///
+/// Returns the files removed, or with `dry_run` the files that would be. The pacman cache is
+/// root-owned, so its files are removed through the configured elevation tool.
+///
+/// # Errors
+/// Returns an error if a cache cannot be read or a file cannot be removed.
+pub fn clean(config: &Config, keep: usize, dry_run: bool) -> std::io::Result<Vec<PathBuf>> {
+    let pinned = pinned_versions(config);
+    let system = files_to_remove(&cache_files(Path::new(PACMAN_CACHE))?, keep, &pinned);
+    let paru = files_to_remove(&paru_files()?, keep, &pinned);
+    if !dry_run {
+        for batch in system.chunks(500) {
+            let status = pacman::elevated("rm").arg("-f").args(batch).status()?;
+            if !status.success() {
+                return Err(std::io::Error::other(
+                    "failed to remove files from the pacman cache",
+                ));
+            }
+        }
+        for path in &paru {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(system.into_iter().chain(paru).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The newest versions and pinned ones survive; signatures go with their package.
+    #[test]
+    fn keeps_newest_and_pinned_versions() {
+        let files: Vec<CachedFile> = [
+            "htop-3.2.1-1-x86_64.pkg.tar.zst",
+            "htop-3.2.1-1-x86_64.pkg.tar.zst.sig",
+            "htop-3.2.2-1-x86_64.pkg.tar.zst",
+            "htop-3.3.0-1-x86_64.pkg.tar.zst",
+            "htop-3.10.0-1-x86_64.pkg.tar.zst",
+            "vim-9.1.0330-1-x86_64.pkg.tar.zst",
+            "vim-9.1.0400-1-x86_64.pkg.tar.zst",
+            "vim-9.1.0500-1-x86_64.pkg.tar.zst",
+            "lib32-glibc-1:2.39-1-x86_64.pkg.tar.zst",
+            "not-a-package.txt",
+        ]
+        .iter()
+        .filter_map(|name| parse_file_name(&Path::new("/cache").join(name)))
+        .collect();
+        assert_eq!(files.len(), 9);
+        assert_eq!(files[8].name, "lib32-glibc");
+        assert_eq!(files[8].version, "1:2.39-1");
+
+        let config: Config =
+            serde_yml::from_str("packages:\n  - name: vim\n    version: 9.1.0330-1\n").unwrap();
+        let remove = files_to_remove(&files, 2, &pinned_versions(&config));
+        assert_eq!(
+            remove,
+            vec![
+                PathBuf::from("/cache/htop-3.2.1-1-x86_64.pkg.tar.zst"),
+                PathBuf::from("/cache/htop-3.2.1-1-x86_64.pkg.tar.zst.sig"),
+                PathBuf::from("/cache/htop-3.2.2-1-x86_64.pkg.tar.zst"),
+            ]
+        );
+    }
+}
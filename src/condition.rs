@@ -0,0 +1,199 @@
+//! Conditional packages
+//!
+//! # Overview
+//!
+//! One YAML can serve several machines: packages and groups may carry a `when:` condition that
+//! is evaluated against the machine running `apply`:
+//!
+//! ```yaml
+//! packages:
+//!   - git
+//!   - name: intel-ucode
+//!     when: { arch: x86_64 }
+//!   - name: tlp
+//!     when: { hostname: laptop }
+//!   - group: xorg
+//!     when: { hostname: desktop }
+//! ```
+//!
+//! Every key in a condition must match. Entries whose condition does not match are ignored by
+//! `apply`, `diff`, and `bootstrap`, and kept untouched by `freeze`.
+//!
+//! `any:` lists conditions of which one must match, e.g. for a package declared once per
+//! machine; repeated entries of a package are merged that way ([`Condition::or`]):
+//!
+//! ```yaml
+//! packages:
+//!   - name: nvidia-open
+//!     when:
+//!       any:
+//!         - hostname: desktop
+//!         - hostname: workstation
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A `when:` condition on a package or group.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Condition {
+    /// Machine architecture as reported by `uname -m`, e.g. `x86_64` or `aarch64`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Conditions of which at least one must match as well, unless empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub any: Vec<Self>,
+}
+
+impl Condition {
+    /// Whether every key of the condition matches `host`.
+    #[must_use]
+    pub fn matches(&self, host: &Host) -> bool {
+        self.arch.as_ref().is_none_or(|arch| arch == &host.arch)
+            && self
+                .hostname
+                .as_ref()
+                .is_none_or(|hostname| Some(hostname) == host.hostname.as_ref())
+            && (self.any.is_empty() || self.any.iter().any(|condition| condition.matches(host)))
+    }
+
+    /// A condition that matches where `self` or `other` does, listing both under `any:`.
+    #[must_use]
+    pub fn or(self, other: Self) -> Self {
+        let mut any: Vec<Self> = Vec::new();
+        for condition in self.branches().into_iter().chain(other.branches()) {
+            if !any.contains(&condition) {
+                any.push(condition);
+            }
+        }
+        if any.len() == 1 {
+            any.remove(0)
+        } else {
+            Self {
+                any,
+                ..Self::default()
+            }
+        }
+    }
+
+    /// The conditions of an `any:`-only condition that do not match `host`, combined with
+    /// [`Condition::or`], or `None` if there are none or the condition is not of that form.
+    #[must_use]
+    pub fn others(&self, host: &Host) -> Option<Self> {
+        if self.arch.is_some() || self.hostname.is_some() {
+            return None;
+        }
+        self.any
+            .iter()
+            .filter(|condition| !condition.matches(host))
+            .cloned()
+            .reduce(Self::or)
+    }
+
+    /// The conditions this one is either of: those under `any:` if it has no other key, or
+    /// itself.
+    fn branches(self) -> Vec<Self> {
+        if self.arch.is_none() && self.hostname.is_none() && !self.any.is_empty() {
+            self.any
+        } else {
+            vec![self]
+        }
+    }
+}
+
+/// The facts conditions are evaluated against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Host {
+    pub arch: String,
+    /// `None` when the hostname cannot be determined; `hostname` conditions then never match.
+    pub hostname: Option<String>,
+}
+
+impl Host {
+    /// The machine nixman is running on.
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            arch: std::env::consts::ARCH.to_string(),
+            hostname: read_hostname(Path::new("/proc/sys/kernel/hostname"))
+                .or_else(|| read_hostname(Path::new("/etc/hostname"))),
+        }
+    }
+
+    /// A new system mounted at `root`, e.g. during `bootstrap`: this machine's architecture
+    /// and the hostname configured in `<root>/etc/hostname`, if any.
+    #[must_use]
+    pub fn for_root(root: &Path) -> Self {
+        Self {
+            arch: std::env::consts::ARCH.to_string(),
+            hostname: read_hostname(&root.join("etc/hostname")),
+        }
+    }
+}
+
+fn read_hostname(path: &Path) -> Option<String> {
+    let hostname = std::fs::read_to_string(path).ok()?;
+    let hostname = hostname.trim();
+    (!hostname.is_empty()).then(|| hostname.to_string())
+}
+
+/// Whether an entry with the optional condition `when` applies to `host`.
+#[must_use]
+pub fn applies(when: Option<&Condition>, host: &Host) -> bool {
+    when.is_none_or(|condition| condition.matches(host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All keys of a condition must match, and an unknown hostname never matches.
+    #[test]
+    fn matches_all_keys() {
+        let host = Host {
+            arch: "aarch64".to_string(),
+            hostname: Some("pi".to_string()),
+        };
+        let condition = Condition {
+            arch: Some("aarch64".to_string()),
+            hostname: Some("pi".to_string()),
+            any: Vec::new(),
+        };
+        assert!(condition.matches(&host));
+        assert!(applies(None, &host));
+        let x86 = Condition {
+            arch: Some("x86_64".to_string()),
+            ..Condition::default()
+        };
+        assert!(!applies(Some(&x86), &host));
+        let unnamed = Host {
+            hostname: None,
+            ..host
+        };
+        assert!(!condition.matches(&unnamed));
+    }
+
+    /// Combined conditions match where either does, and the branches for other hosts can be
+    /// split off again.
+    #[test]
+    fn combines_conditions() {
+        let on = |hostname: &str| Condition {
+            hostname: Some(hostname.to_string()),
+            ..Condition::default()
+        };
+        let host = |hostname: &str| Host {
+            arch: "x86_64".to_string(),
+            hostname: Some(hostname.to_string()),
+        };
+        let both = on("desktop").or(on("workstation"));
+        assert_eq!(both.any, vec![on("desktop"), on("workstation")]);
+        assert!(both.matches(&host("desktop")) && both.matches(&host("workstation")));
+        assert!(!both.matches(&host("laptop")));
+        assert_eq!(both.clone().or(on("desktop")), both);
+        assert_eq!(on("desktop").or(on("desktop")), on("desktop"));
+        assert_eq!(both.others(&host("desktop")), Some(on("workstation")));
+        assert_eq!(on("desktop").others(&host("desktop")), None);
+    }
+}
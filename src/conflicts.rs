@@ -0,0 +1,293 @@
+//! Resolving disagreements between the YAML and manual changes
+//!
+//! # Overview
+//!
+//! When a package the YAML declares was removed by hand since the
+//! [last apply](crate::applied), `apply` would install it again; when one was installed by
+//! hand, `apply` would remove it. Either may undo something the user meant to do. [`find`]
+//! picks these [`Conflict`]s out of a plan, and `apply` asks about each:
+//!
+//! - **keep** the config: carry out the planned change,
+//! - **update** the config to match the system: [`update_config`] drops (or adds) the entry,
+//!   and the planned change is left out,
+//! - **skip** it: leave the package and the YAML alone for now.
+//!
+//! Keep and skip answers are recorded in `<state dir>/conflicts.json` ([`Decisions`]) and
+//! reused without asking for as long as the same change is planned; once it no longer is,
+//! because the system and the YAML agree again, the decision is forgotten. When nobody can be
+//! asked (`--noconfirm`, or no terminal), recorded decisions still apply and the other
+//! conflicts keep the config.
+
+use crate::Package;
+use crate::applied::{DriftKind, LastApplied};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Which way the system moved away from the YAML outside nixman.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// A declared package was removed; `apply` would install it.
+    RemovedOutside,
+    /// An undeclared package was installed; `apply` would remove it.
+    InstalledOutside,
+}
+
+/// A planned change that would undo a manual one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Conflict {
+    pub name: String,
+    pub direction: Direction,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.direction {
+            Direction::RemovedOutside => write!(
+                f,
+                "{} was removed outside nixman since the last apply, but the YAML declares it",
+                self.name
+            ),
+            Direction::InstalledOutside => write!(
+                f,
+                "{} was installed outside nixman since the last apply, but the YAML does not declare it",
+                self.name
+            ),
+        }
+    }
+}
+
+/// What to do about a [`Conflict`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    /// Carry out the planned change.
+    Keep,
+    /// Change the YAML to match the system instead.
+    Update,
+    /// Leave both alone.
+    Skip,
+}
+
+impl Resolution {
+    /// Parse an answer: `k`/`keep`, `u`/`update`, or `s`/`skip`, in any case.
+    #[must_use]
+    pub fn parse(answer: &str) -> Option<Self> {
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "k" | "keep" => Some(Self::Keep),
+            "u" | "update" => Some(Self::Update),
+            "s" | "skip" => Some(Self::Skip),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Keep => "keep the config",
+            Self::Update => "update the config",
+            Self::Skip => "skip it",
+        })
+    }
+}
+
+/// A recorded answer to a conflict.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decision {
+    pub name: String,
+    pub direction: Direction,
+    pub resolution: Resolution,
+}
+
+/// The recorded answers, as kept in `<state dir>/conflicts.json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decisions {
+    pub decisions: Vec<Decision>,
+}
+
+impl Decisions {
+    /// Read the decisions at `path`; a missing file has none.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = std::fs::read_to_string(path)?;
+        serde_json::from_str(&json)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Write the decisions to `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+
+    /// The recorded answer to `conflict`.
+    #[must_use]
+    pub fn get(&self, conflict: &Conflict) -> Option<Resolution> {
+        self.decisions
+            .iter()
+            .find(|decision| {
+                decision.name == conflict.name && decision.direction == conflict.direction
+            })
+            .map(|decision| decision.resolution)
+    }
+
+    /// Record the answer to `conflict`. Updates change the YAML, so they are not recorded.
+    pub fn record(&mut self, conflict: &Conflict, resolution: Resolution) {
+        if resolution == Resolution::Update {
+            return;
+        }
+        self.decisions
+            .retain(|decision| decision.name != conflict.name);
+        self.decisions.push(Decision {
+            name: conflict.name.clone(),
+            direction: conflict.direction,
+            resolution,
+        });
+    }
+
+    /// Forget the decisions about changes that are no longer planned: `to_install` and
+    /// `to_remove` are the pacman changes of the current plan.
+    pub fn retain_planned(&mut self, to_install: &[String], to_remove: &[String]) {
+        self.decisions.retain(|decision| match decision.direction {
+            Direction::RemovedOutside => to_install.contains(&decision.name),
+            Direction::InstalledOutside => to_remove.contains(&decision.name),
+        });
+    }
+}
+
+/// Where the decisions are kept (`<state dir>/conflicts.json`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn decisions_path() -> std::io::Result<PathBuf> {
+    crate::paths::conflicts_file()
+}
+
+/// The conflicts among the pacman changes of a plan, `to_install` and `to_remove`.
+///
+/// A change conflicts if it undoes a manual change since the `last` apply, or if `decisions`
+/// records an answer to it, which keeps a conflict known after the last apply moved on.
+#[must_use]
+pub fn find(
+    to_install: &[String],
+    to_remove: &[String],
+    last: Option<&LastApplied>,
+    decisions: &Decisions,
+) -> Vec<Conflict> {
+    let drift = last.map_or_else(Vec::new, |last| last.classify(to_install, to_remove));
+    let conflict = |name: &String, direction, kind| {
+        let conflict = Conflict {
+            name: name.clone(),
+            direction,
+        };
+        let outside = drift
+            .iter()
+            .any(|drift| drift.name == *name && drift.kind == kind);
+        (outside || decisions.get(&conflict).is_some()).then_some(conflict)
+    };
+    let removed = to_install
+        .iter()
+        .filter_map(|name| conflict(name, Direction::RemovedOutside, DriftKind::RemovedOutside));
+    let installed = to_remove.iter().filter_map(|name| {
+        conflict(
+            name,
+            Direction::InstalledOutside,
+            DriftKind::InstalledOutside,
+        )
+    });
+    removed.chain(installed).collect()
+}
+
+/// Change the YAML at `yml_path` to match the system for each of `conflicts`.
+///
+/// A package removed outside nixman is dropped from `packages:`, or added to `ignore:` when it
+/// is only declared through a group or another file; one installed outside nixman is added.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read or written.
+pub fn update_config<P: AsRef<Path>>(yml_path: P, conflicts: &[Conflict]) -> std::io::Result<()> {
+    let mut config = crate::read_config(&yml_path)?;
+    for conflict in conflicts {
+        match conflict.direction {
+            Direction::RemovedOutside => {
+                if config
+                    .packages
+                    .packages
+                    .shift_remove(&conflict.name)
+                    .is_none()
+                {
+                    let ignore = config.ignore.get_or_insert_with(Vec::new);
+                    if !ignore.contains(&conflict.name) {
+                        ignore.push(conflict.name.clone());
+                    }
+                }
+            }
+            Direction::InstalledOutside => {
+                config
+                    .packages
+                    .insert(Package::from(conflict.name.as_str()));
+            }
+        }
+    }
+    crate::write_config(&config, yml_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only changes that undo a manual one since the last apply are conflicts, and recorded
+    /// decisions keep them known until the change is no longer planned.
+    #[test]
+    fn finds_conflicts() {
+        let names =
+            |names: &[&str]| -> Vec<String> { names.iter().map(ToString::to_string).collect() };
+        let last = LastApplied {
+            timestamp: 0,
+            declared: names(&["git", "htop", "nano"]),
+            installed: names(&["git", "htop", "nano"]),
+        };
+        // htop was removed by hand, vim added to the YAML; steam was installed by hand, nano
+        // dropped from the YAML.
+        let to_install = names(&["htop", "vim"]);
+        let to_remove = names(&["nano", "steam"]);
+        let mut decisions = Decisions::default();
+        let found = find(&to_install, &to_remove, Some(&last), &decisions);
+        assert_eq!(
+            found,
+            vec![
+                Conflict {
+                    name: "htop".to_string(),
+                    direction: Direction::RemovedOutside
+                },
+                Conflict {
+                    name: "steam".to_string(),
+                    direction: Direction::InstalledOutside
+                },
+            ]
+        );
+        assert!(find(&to_install, &to_remove, None, &decisions).is_empty());
+
+        decisions.record(&found[0], Resolution::Skip);
+        decisions.record(&found[1], Resolution::Update);
+        assert_eq!(decisions.get(&found[0]), Some(Resolution::Skip));
+        assert_eq!(decisions.get(&found[1]), None);
+        assert_eq!(
+            find(&to_install, &[], None, &decisions),
+            vec![found[0].clone()]
+        );
+        decisions.retain_planned(&names(&["vim"]), &[]);
+        assert!(decisions.decisions.is_empty());
+        assert_eq!(Resolution::parse(" U "), Some(Resolution::Update));
+    }
+}
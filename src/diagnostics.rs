@@ -0,0 +1,170 @@
+//! Parse errors that point at the file, line, and entry
+//!
+//! # Overview
+//!
+//! serde reports where parsing failed as a bare `at line 5 column 5`, without saying in which
+//! file: the YAML, its [local overrides](crate::local), the [base manifest](crate::base), or
+//! the global settings. [`locate`] turns such an error into a [`ParseError`] that names the
+//! file and quotes the entry that failed:
+//!
+//! ```text
+//! packages.local.yml:5:13: packages[1].reason: unknown variant `dependecy`, expected `explicit` or `dependency`
+//!   3 |   - name: vim
+//!   4 |     version: 9.1.0330-1
+//!   5 |     reason: dependecy
+//!     |             ^
+//! ```
+//!
+//! The [`ParseError`] stays inside the [`std::io::Error`] that the readers return, so library
+//! users can get at the position with [`std::io::Error::get_ref`] and `downcast_ref`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// How many lines above the failing one are searched for the start of its entry.
+const ENTRY_LINES: usize = 4;
+
+/// A YAML file that failed to parse, with the position and text of the failing entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub path: PathBuf,
+    /// 1-based line and column.
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    /// The lines of the entry up to the failing one, as `(line number, text)`.
+    pub snippet: Vec<(usize, String)>,
+}
+
+impl ParseError {
+    /// Locate `error` in `content`, the text of the file at `path`; `None` if serde did not
+    /// report a position.
+    #[must_use]
+    pub fn new(path: &Path, content: &str, error: &serde_yml::Error) -> Option<Self> {
+        let location = error.location()?;
+        let (line, column) = (location.line(), location.column());
+        let full = error.to_string();
+        let message = full
+            .strip_suffix(&format!(" at line {line} column {column}"))
+            .unwrap_or(&full);
+        // serde_yml escapes the brackets of sequence indices in the path it puts first.
+        let message = match message.split_once(": ") {
+            Some((path, rest)) if !path.contains(' ') => {
+                let path = path
+                    .replace("\\[", "[")
+                    .replace("\\]", "]")
+                    .replace(".[", "[");
+                format!("{path}: {rest}")
+            }
+            _ => message.to_string(),
+        };
+        Some(Self {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message,
+            snippet: entry_lines(content, line),
+        })
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.message
+        )?;
+        let width = self.line.to_string().len();
+        for (number, text) in &self.snippet {
+            write!(f, "\n  {number:>width$} | {text}")?;
+        }
+        if !self.snippet.is_empty() {
+            write!(
+                f,
+                "\n  {:width$} | {:>column$}",
+                "",
+                "^",
+                column = self.column
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The lines of `content` from the start of the entry containing `line` (1-based) to `line`:
+/// back to the nearest `- ` item that is not indented deeper, if there is one close above.
+fn entry_lines(content: &str, line: usize) -> Vec<(usize, String)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let Some(failing) = line.checked_sub(1).and_then(|index| lines.get(index)) else {
+        return Vec::new();
+    };
+    let indent = |text: &str| text.len() - text.trim_start().len();
+    let start = (line.saturating_sub(ENTRY_LINES + 1)..line)
+        .rev()
+        .find(|&index| {
+            let text = lines[index];
+            text.trim_start().starts_with("- ") && indent(text) <= indent(failing)
+        })
+        .unwrap_or(line - 1);
+    (start..line)
+        .map(|index| (index + 1, lines[index].to_string()))
+        .collect()
+}
+
+/// Point `error`, returned while parsing `content` from the file at `path`, at the file: a
+/// serde error with a position becomes a [`ParseError`], any other error is prefixed with the
+/// path.
+#[must_use]
+pub fn locate(path: &Path, content: &str, error: std::io::Error) -> std::io::Error {
+    let located = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<serde_yml::Error>())
+        .and_then(|inner| ParseError::new(path, content, inner));
+    match located {
+        Some(located) => std::io::Error::new(std::io::ErrorKind::InvalidData, located),
+        None if error.kind() == std::io::ErrorKind::InvalidData => std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {error}", path.display()),
+        ),
+        None => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bad value is reported with its file and position and the lines of its entry.
+    #[test]
+    fn locates_parse_errors() {
+        let content = "packages:\n  - git\n  - name: vim\n    reason: dependecy\n";
+        let error = crate::schema::parse(content).unwrap_err();
+        let located = locate(Path::new("packages.yml"), content, error);
+        let parse_error = located
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<ParseError>())
+            .unwrap();
+        assert_eq!((parse_error.line, parse_error.column), (4, 13));
+        assert!(
+            parse_error
+                .message
+                .starts_with("packages[1].reason: unknown variant")
+        );
+        assert_eq!(
+            parse_error.snippet,
+            vec![
+                (3, "  - name: vim".to_string()),
+                (4, "    reason: dependecy".to_string()),
+            ]
+        );
+        let shown = located.to_string();
+        assert!(shown.starts_with("packages.yml:4:13: "), "{shown}");
+        assert!(shown.ends_with("\n    |             ^"), "{shown}");
+    }
+}
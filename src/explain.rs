@@ -0,0 +1,139 @@
+//! Explaining why a package is (or is not) on the system and in the config
+//!
+//! # Overview
+//!
+//! `nixman explain <pkg>` gathers everything nixman knows about a single package: which YAML
+//! sections declare it, whether pacman has it installed, when, and why (through
+//! [`pacman_query_info`](crate::pacman::pacman_query_info)), what requires it, and what `apply`
+//! would do with it. This makes it easy to
+//! audit drift one package at a time.
+
+use crate::pacman::{self, PackageInfo};
+use crate::{Config, InstallReason, installed_explicit_packages, read_effective_config};
+use serde::Serialize;
+use std::path::Path;
+
+/// What `apply` would do with a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlannedAction {
+    /// The package is in sync; apply leaves it alone.
+    Keep,
+    /// The package is declared as explicit but not explicitly installed.
+    Install,
+    /// The package is explicitly installed but not declared.
+    Remove,
+}
+
+/// Everything nixman knows about a single package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Explanation {
+    pub name: String,
+    /// YAML sections declaring the package, e.g. `["packages"]` or `["cargo"]`.
+    pub declared_in: Vec<String>,
+    /// The version pinned in the `packages` section, or its version requirement, if any.
+    pub declared_version: Option<String>,
+    /// The install reason declared in the `packages` section, if declared there.
+    pub declared_reason: Option<InstallReason>,
+    /// Whether pacman has the package installed (explicitly or as a dependency).
+    pub installed: bool,
+    pub installed_version: Option<String>,
+    /// pacman's install reason.
+    pub install_reason: Option<InstallReason>,
+    /// The installed size in bytes.
+    pub installed_size: Option<u64>,
+    /// When pacman installed it.
+    pub install_date: Option<String>,
+    /// Installed packages that depend on this one.
+    pub required_by: Vec<String>,
+    /// What `apply` would do with the package on the pacman side.
+    pub apply_action: PlannedAction,
+}
+
+/// Explain the state of `name` with respect to the YAML at `yml_path` and the pacman database.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read or pacman could not be executed.
+pub fn explain<P: AsRef<Path>>(yml_path: P, name: &str) -> std::io::Result<Explanation> {
+    let config = read_effective_config(yml_path)?;
+    let info = pacman::pacman_query_info(name)?;
+    let explicit = installed_explicit_packages()?.iter().any(|pkg| pkg == name);
+    Ok(build(&config, name, info.as_ref(), explicit))
+}
+
+/// Combine the config, what pacman reports about the package (`None` when not installed), and
+/// whether it is explicitly installed into an [`Explanation`].
+fn build(config: &Config, name: &str, info: Option<&PackageInfo>, explicit: bool) -> Explanation {
+    let declared = config.packages.get(name);
+    let mut declared_in = Vec::new();
+    if declared.is_some() {
+        declared_in.push("packages".to_string());
+    }
+    for source in crate::sources::builtin() {
+        if config
+            .source(source.name())
+            .is_some_and(|names| names.iter().any(|n| n == name))
+        {
+            declared_in.push(source.name().to_string());
+        }
+    }
+    let declared_reason = declared.map(|pkg| pkg.reason);
+    let apply_action = match (declared_reason, explicit) {
+        (Some(InstallReason::Explicit), false) => PlannedAction::Install,
+        (None, true) => PlannedAction::Remove,
+        _ => PlannedAction::Keep,
+    };
+    Explanation {
+        name: name.to_string(),
+        declared_in,
+        declared_version: declared.and_then(|pkg| {
+            pkg.version
+                .as_ref()
+                .map(ToString::to_string)
+                .or_else(|| pkg.requirement.as_ref().map(ToString::to_string))
+        }),
+        declared_reason,
+        installed: info.is_some(),
+        installed_version: info.map(|info| info.version.to_string()),
+        install_reason: info.map(|info| info.reason),
+        installed_size: info.map(|info| info.installed_size),
+        install_date: info.map(|info| info.install_date.clone()),
+        required_by: info
+            .map(|info| info.required_by.clone())
+            .unwrap_or_default(),
+        apply_action,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIT_INFO: &str = "\
+Name            : git
+Version         : 2.45.0-1
+Depends On      : curl  expat  perl  perl-error  perl-mailtools  openssl
+                  pcre2  grep  shadow  zlib
+Required By     : github-cli  lazygit
+Installed Size  : 29.38 MiB
+Install Date    : Tue 07 May 2024 09:12:40 AM UTC
+Install Reason  : Explicitly installed
+";
+
+    /// An installed package missing from the YAML would be removed by apply.
+    #[test]
+    fn explains_undeclared_explicit_package() {
+        let config: Config = serde_yml::from_str("packages:\n  - htop\ncargo:\n  - git\n").unwrap();
+        let info = pacman::parse_package_info(GIT_INFO);
+        let explanation = build(&config, "git", info.as_ref(), true);
+        assert_eq!(explanation.declared_in, vec!["cargo"]);
+        assert_eq!(explanation.installed_version.as_deref(), Some("2.45.0-1"));
+        assert_eq!(explanation.required_by, vec!["github-cli", "lazygit"]);
+        assert_eq!(explanation.install_reason, Some(InstallReason::Explicit));
+        assert_eq!(explanation.apply_action, PlannedAction::Remove);
+
+        let explanation = build(&config, "htop", None, false);
+        assert!(!explanation.installed);
+        assert_eq!(explanation.apply_action, PlannedAction::Install);
+    }
+}
@@ -0,0 +1,192 @@
+//! Exporting the package list to other formats
+//!
+//! # Overview
+//!
+//! `nixman export --to <format>` renders the YAML's pacman packages for use elsewhere:
+//!
+//! - [`ExportFormat::Nix`]: an `environment.systemPackages` (or, for Home Manager,
+//!   `home.packages`) snippet.
+//! - [`ExportFormat::Txt`]: one explicit package per line, e.g. for `pacman -S --needed - < list`.
+//! - [`ExportFormat::Sh`]: a shell script that installs the packages with `pacman -S --needed`,
+//!   installing dependency-declared packages with `--asdeps`.
+//!
+//! Only names are exported: version pins cannot be expressed with `pacman -S`, and nixpkgs
+//! versions are independent of Arch's.
+
+use crate::{Config, InstallReason};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// A format the package list can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Nix,
+    Txt,
+    Sh,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nix" => Ok(Self::Nix),
+            "txt" => Ok(Self::Txt),
+            "sh" => Ok(Self::Sh),
+            _ => Err(format!("unknown format `{s}`: expected nix, txt, or sh")),
+        }
+    }
+}
+
+/// Names of the packages declared with `reason`, in YAML order.
+///
+/// Groups are installed explicitly by name, so they are included with the explicit packages,
+/// as is the first of each set of alternatives.
+fn names(config: &Config, reason: InstallReason) -> Vec<&str> {
+    let groups = config
+        .packages
+        .groups
+        .iter()
+        .filter(|_| reason == InstallReason::Explicit);
+    let alternatives = config
+        .packages
+        .alternatives
+        .iter()
+        .filter(|_| reason == InstallReason::Explicit)
+        .filter_map(|alternatives| alternatives.names.first());
+    config
+        .packages
+        .packages
+        .values()
+        .filter(|pkg| pkg.reason == reason)
+        .map(|pkg| pkg.name.as_str())
+        .chain(groups.map(|group| group.name.as_str()))
+        .chain(alternatives.map(String::as_str))
+        .collect()
+}
+
+/// Render the package list of `config` in `format`.
+///
+/// `home_manager` selects `home.packages` instead of `environment.systemPackages` for
+/// [`ExportFormat::Nix`] and is ignored otherwise.
+#[must_use]
+pub fn render(config: &Config, format: ExportFormat, home_manager: bool) -> String {
+    match format {
+        // nixpkgs has no pacman groups, so only packages are exported.
+        ExportFormat::Nix => {
+            let packages: Vec<&str> = config
+                .packages
+                .packages
+                .values()
+                .filter(|pkg| pkg.reason == InstallReason::Explicit)
+                .map(|pkg| pkg.name.as_str())
+                .collect();
+            render_nix(&packages, home_manager)
+        }
+        ExportFormat::Txt => {
+            names(config, InstallReason::Explicit)
+                .iter()
+                .fold(String::new(), |mut out, name| {
+                    let _ = writeln!(out, "{name}");
+                    out
+                })
+        }
+        ExportFormat::Sh => render_sh(
+            &names(config, InstallReason::Explicit),
+            &names(config, InstallReason::Dependency),
+        ),
+    }
+}
+
+fn render_nix(packages: &[&str], home_manager: bool) -> String {
+    let attribute = if home_manager {
+        "home.packages"
+    } else {
+        "environment.systemPackages"
+    };
+    let mut out = format!(
+        "# Generated by nixman export\n{{ pkgs, ... }}:\n{{\n  {attribute} = with pkgs; [\n"
+    );
+    for name in packages {
+        if is_nix_identifier(name) {
+            let _ = writeln!(out, "    {name}");
+        } else {
+            let _ = writeln!(out, "    pkgs.\"{name}\"");
+        }
+    }
+    out.push_str("  ];\n}\n");
+    out
+}
+
+/// Whether `name` can be written as a bare Nix identifier.
+fn is_nix_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''))
+}
+
+fn render_sh(explicit: &[&str], dependencies: &[&str]) -> String {
+    let mut out = String::from("#!/bin/sh\n# Generated by nixman export\nset -e\n");
+    for (packages, extra) in [(explicit, ""), (dependencies, " --asdeps")] {
+        if packages.is_empty() {
+            continue;
+        }
+        let _ = write!(out, "sudo pacman -S --needed{extra}");
+        for name in packages {
+            let _ = write!(out, " {}", shell_quote(name));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Quote `word` for a POSIX shell unless it only has characters that are safe unquoted.
+fn shell_quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+' | '@'))
+    {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_yml::from_str(
+            "packages:\n  - git\n  - name: gtk2+extra\n    version: 1.0-1\n  - name: perl-error\n    reason: dependency\n",
+        )
+        .unwrap()
+    }
+
+    /// Names that are not Nix identifiers are quoted; dependencies are left out.
+    #[test]
+    fn renders_nix_snippet() {
+        let nix = render(&config(), ExportFormat::Nix, true);
+        assert!(
+            nix.contains("home.packages = with pkgs; [\n    git\n    pkgs.\"gtk2+extra\"\n  ];")
+        );
+        assert!(!nix.contains("perl-error"));
+        assert_eq!(
+            render(&config(), ExportFormat::Txt, false),
+            "git\ngtk2+extra\n"
+        );
+    }
+
+    /// The script installs explicit packages, then dependency-declared ones with `--asdeps`.
+    #[test]
+    fn renders_install_script() {
+        let sh = render(&config(), ExportFormat::Sh, false);
+        assert!(sh.starts_with("#!/bin/sh\n"));
+        assert!(sh.contains("sudo pacman -S --needed git gtk2+extra\n"));
+        assert!(sh.contains("sudo pacman -S --needed --asdeps perl-error\n"));
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
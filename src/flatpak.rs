@@ -0,0 +1,118 @@
+//! Flatpak as an additional package source
+//!
+//! # Overview
+//!
+//! Many desktop users split their software between pacman and Flatpak. This module wraps the
+//! `flatpak` CLI so applications listed under the `flatpak:` section of the YAML can be frozen,
+//! diffed, and applied alongside pacman packages.
+//!
+//! Flatpak management is opt-in: when the YAML has no `flatpak:` section, installed flatpaks are
+//! left alone.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use nixman::flatpak::FlatpakBackend;
+//! let backend = FlatpakBackend;
+//! let apps = backend.list()?;
+//! backend.install(&["org.gimp.GIMP".to_string()], true)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::process::{Command, ExitStatus};
+
+/// Wrapper around the `flatpak` CLI for listing, installing, and removing applications.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlatpakBackend;
+
+impl FlatpakBackend {
+    /// Lists installed flatpak applications by application ID
+    /// (`flatpak list --app --columns=application`).
+    ///
+    /// # Errors
+    /// Returns an error if the flatpak command could not be executed or exited unsuccessfully.
+    pub fn list(self) -> std::io::Result<Vec<String>> {
+        let output = crate::run::output(Command::new("flatpak").args([
+            "list",
+            "--app",
+            "--columns=application",
+        ]))?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "flatpak list failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(parse_application_list(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    /// Installs the given applications (`flatpak install`), without prompting if `noconfirm`.
+    ///
+    /// # Errors
+    /// Returns an error if the flatpak command could not be executed.
+    pub fn install(self, apps: &[String], noconfirm: bool) -> std::io::Result<ExitStatus> {
+        crate::run::status(command("install", noconfirm).args(apps))
+    }
+
+    /// Removes the given applications (`flatpak uninstall`), without prompting if `noconfirm`.
+    ///
+    /// # Errors
+    /// Returns an error if the flatpak command could not be executed.
+    pub fn remove(self, apps: &[String], noconfirm: bool) -> std::io::Result<ExitStatus> {
+        crate::run::status(command("uninstall", noconfirm).args(apps))
+    }
+}
+
+/// `flatpak <action>`, answering every question with yes and skipping the progress output if
+/// `noconfirm`, so unattended applies never stop at a prompt.
+fn command(action: &str, noconfirm: bool) -> Command {
+    let mut command = Command::new("flatpak");
+    command.arg(action);
+    if noconfirm {
+        command.args(["--assumeyes", "--noninteractive"]);
+    }
+    command
+}
+
+/// Parse the output of `flatpak list --app --columns=application` into application IDs.
+///
+/// Blank lines and a possible `Application ID` header are skipped.
+#[must_use]
+pub fn parse_application_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.eq_ignore_ascii_case("application id"))
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that application IDs are extracted and headers/blank lines are dropped.
+    #[test]
+    fn parses_application_list() {
+        let output = "Application ID\norg.gimp.GIMP\n\ncom.spotify.Client\n";
+        assert_eq!(
+            parse_application_list(output),
+            vec!["org.gimp.GIMP", "com.spotify.Client"]
+        );
+    }
+
+    /// With `noconfirm`, flatpak is told not to ask before installing or removing.
+    #[test]
+    fn noconfirm_skips_prompts() {
+        let args = |noconfirm| -> Vec<String> {
+            command("uninstall", noconfirm)
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(args(true), ["uninstall", "--assumeyes", "--noninteractive"]);
+        assert_eq!(args(false), ["uninstall"]);
+    }
+}
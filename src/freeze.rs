@@ -0,0 +1,743 @@
+//! Recording the installed packages in the YAML
+//!
+//! # Overview
+//!
+//! `freeze` (and `-S`/`update`, which freeze afterwards) rewrite `packages:` from
+//! `pacman -Qe`, while keeping everything in the existing YAML that pacman cannot know about:
+//!
+//! - declared dependencies that are still installed,
+//! - groups, instead of listing their members, and alternatives, instead of the one installed,
+//! - `when:` conditions, and entries meant for other machines,
+//! - `vars:`, and entries that refer to [variables](crate::vars), in place of the packages
+//!   they name,
+//! - recorded [origins](crate::origin), which are refreshed,
+//! - the `ignore:`, `critical:`, `banned:`, `settings:`, and `repos:` sections, and no banned
+//!   packages,
+//! - and the shared file's view of [machine-local overrides](crate::local).
+//!
+//! Packages only the [base manifest](crate::base) declares are left out as well.
+//!
+//! `freeze` itself first shows a [`FreezeDiff`] of the entries it would add, remove, or change
+//! in the current file, and asks before writing, so curated entries are not lost by accident.
+//!
+//! `freeze --merge` ([`preview_merge`]) only adds: it records the explicitly installed
+//! packages and groups the YAML lacks, without removing or changing any entry, and does not ask.
+//! The [pacman hook](crate::hook) runs it after every transaction.
+//!
+//! `freeze --stream` ([`write_streamed`]) is for very large systems: it writes each package to
+//! the YAML as `pacman -Qe` lists it, so neither pacman's output nor the package list is held
+//! in memory. It keeps nothing of the existing file, and entries are in pacman's order.
+
+use crate::condition::{self, Host};
+use crate::local::{self, LocalConfig};
+use crate::{
+    Config, InstallReason, Package, base, origin, pacman, parse_explicit_packages, sources, vars,
+};
+use serde::Serialize;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// An entry whose version, reason, or origin a freeze would change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedEntry {
+    pub name: String,
+    /// The entry in the current file, e.g. `9.1.0330-1, dependency`.
+    pub before: String,
+    /// The entry as the freeze would write it.
+    pub after: String,
+}
+
+/// What a freeze would change in the YAML, by package name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FreezeDiff {
+    /// Entries it would add; extra-source packages are shown as `source:name`.
+    pub added: Vec<String>,
+    /// Entries it would remove.
+    pub removed: Vec<String>,
+    pub changed: Vec<ChangedEntry>,
+}
+
+impl FreezeDiff {
+    /// Compare the `existing` config with the `frozen` one that would replace it.
+    #[must_use]
+    pub fn new(existing: &Config, frozen: &Config) -> Self {
+        let mut diff = Self::default();
+        for pkg in frozen.packages.packages.values() {
+            match existing.packages.get(&pkg.name) {
+                None => diff.added.push(pkg.name.clone()),
+                Some(old) if describe(old) != describe(pkg) => diff.changed.push(ChangedEntry {
+                    name: pkg.name.clone(),
+                    before: describe(old),
+                    after: describe(pkg),
+                }),
+                Some(_) => {}
+            }
+        }
+        diff.removed.extend(
+            existing
+                .packages
+                .packages
+                .keys()
+                .filter(|name| !frozen.packages.contains(name))
+                .cloned(),
+        );
+        let group_names = |config: &Config| -> Vec<String> {
+            config
+                .packages
+                .groups
+                .iter()
+                .map(|group| group.name.clone())
+                .collect()
+        };
+        let (old_groups, new_groups) = (group_names(existing), group_names(frozen));
+        diff.added.extend(
+            new_groups
+                .iter()
+                .filter(|g| !old_groups.contains(g))
+                .cloned(),
+        );
+        diff.removed.extend(
+            old_groups
+                .iter()
+                .filter(|g| !new_groups.contains(g))
+                .cloned(),
+        );
+        for source in sources::builtin() {
+            let name = source.name();
+            let old = existing.source(name).unwrap_or_default();
+            let new = frozen.source(name).unwrap_or_default();
+            let qualified = |pkg: &String| format!("{name}:{pkg}");
+            diff.added
+                .extend(new.iter().filter(|pkg| !old.contains(pkg)).map(qualified));
+            diff.removed
+                .extend(old.iter().filter(|pkg| !new.contains(pkg)).map(qualified));
+        }
+        diff
+    }
+
+    /// Whether the freeze would change no entry.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// What a freeze records about an entry besides its name, e.g. `9.1.0330-1, dependency`, or
+/// `-` if nothing.
+fn describe(pkg: &Package) -> String {
+    let mut parts = Vec::new();
+    if let Some(version) = &pkg.version {
+        parts.push(version.to_string());
+    }
+    if let Some(requirement) = &pkg.requirement {
+        parts.push(requirement.to_string());
+    }
+    if pkg.reason == InstallReason::Dependency {
+        parts.push("dependency".to_string());
+    }
+    if let Some(origin) = &pkg.origin {
+        parts.push(format!("from {origin}"));
+    }
+    if parts.is_empty() {
+        "-".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// Re-read the explicitly installed packages and write them to the YAML file, with versions if
+/// `versioned`, and with [origins](crate::origin) if `origins` or if the YAML already records
+/// them.
+///
+/// The extra sources the YAML has a section for are refreshed when their package manager is
+/// available; otherwise the section is kept as-is. A source without a section is not managed
+/// and stays that way, unless `add_sources`.
+///
+/// # Errors
+/// Returns an error if pacman cannot be run, or the YAML cannot be parsed or written; a YAML
+/// that does not parse is left as it is.
+pub fn write_installed<P: AsRef<Path>>(
+    yml_path: P,
+    versioned: bool,
+    origins: bool,
+    add_sources: bool,
+) -> std::io::Result<()> {
+    let config = frozen_config(&yml_path, versioned, origins, add_sources)?;
+    crate::write_config(&config, yml_path)
+}
+
+/// Preview a freeze of the YAML at `yml_path`: the config [`write_installed`] would write, and
+/// how it differs from the current file (empty if there is none).
+///
+/// # Errors
+/// Returns an error if pacman cannot be run, or the current file cannot be read or parsed.
+pub fn preview<P: AsRef<Path>>(
+    yml_path: P,
+    versioned: bool,
+    origins: bool,
+    add_sources: bool,
+) -> std::io::Result<(Config, FreezeDiff)> {
+    let config = frozen_config(&yml_path, versioned, origins, add_sources)?;
+    let diff = read_existing(yml_path.as_ref())?
+        .map(|existing| FreezeDiff::new(&existing, &config))
+        .unwrap_or_default();
+    Ok((config, diff))
+}
+
+/// The YAML at `yml_path`, or `None` if there is no file yet.
+///
+/// A file that cannot be read or parsed is an error, so a freeze never replaces it with what
+/// little it could rebuild.
+fn read_existing(yml_path: &Path) -> io::Result<Option<Config>> {
+    match crate::read_config(yml_path) {
+        Ok(config) => Ok(Some(config)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Preview a merging freeze of the YAML at `yml_path`, which only adds entries.
+///
+/// Returns the current file with the packages and groups a full freeze would add, and their
+/// names; nothing is removed or changed. Without a file, this is a full freeze.
+///
+/// # Errors
+/// Returns an error if pacman cannot be run or the YAML cannot be parsed.
+pub fn preview_merge<P: AsRef<Path>>(
+    yml_path: P,
+    versioned: bool,
+    origins: bool,
+) -> std::io::Result<(Config, Vec<String>)> {
+    let frozen = frozen_config(&yml_path, versioned, origins, false)?;
+    if !yml_path.as_ref().exists() {
+        let added = frozen
+            .packages
+            .packages
+            .keys()
+            .chain(frozen.packages.groups.iter().map(|group| &group.name))
+            .cloned()
+            .collect();
+        return Ok((frozen, added));
+    }
+    let mut config = crate::read_config(&yml_path)?;
+    let diff = FreezeDiff::new(&config, &frozen);
+    let mut added = Vec::new();
+    for pkg in frozen.packages.packages.into_values() {
+        if diff.added.contains(&pkg.name) {
+            added.push(pkg.name.clone());
+            config.packages.insert(pkg);
+        }
+    }
+    for group in frozen.packages.groups {
+        if diff.added.contains(&group.name) {
+            added.push(group.name.clone());
+            config.packages.groups.push(group);
+        }
+    }
+    Ok((config, added))
+}
+
+/// The config a freeze of the YAML at `yml_path` writes.
+fn frozen_config<P: AsRef<Path>>(
+    yml_path: P,
+    versioned: bool,
+    origins: bool,
+    add_sources: bool,
+) -> std::io::Result<Config> {
+    let yml_path = yml_path.as_ref();
+    let output = pacman::pacman_list_explicit()?;
+    let existing = read_existing(yml_path)?;
+    let mut config = Config::new(parse_explicit_packages(&output.stdout, versioned));
+    // -Qe only lists explicit packages; keep declared dependencies that are still installed.
+    if let Some(existing) = &existing
+        && let Ok(output) = pacman::pacman_list_dependencies()
+    {
+        let declared = |name: &str| {
+            existing
+                .packages
+                .get(name)
+                .is_some_and(|pkg| pkg.reason == InstallReason::Dependency)
+        };
+        for pkg in parse_explicit_packages(&output.stdout, versioned)
+            .packages
+            .into_values()
+            .filter(|pkg| declared(&pkg.name))
+        {
+            config.packages.insert(Package {
+                reason: InstallReason::Dependency,
+                ..pkg
+            });
+        }
+    }
+    if let Some(existing) = &existing {
+        config.critical.clone_from(&existing.critical);
+        config.banned.clone_from(&existing.banned);
+        config.settings.clone_from(&existing.settings);
+        config.repos.clone_from(&existing.repos);
+        // Ignored packages stay out of the package list.
+        if let Some(ignore) = &existing.ignore {
+            config
+                .packages
+                .packages
+                .retain(|name, _| !ignore.contains(name));
+        }
+        config.ignore.clone_from(&existing.ignore);
+        // So are banned ones, which `apply` is about to remove.
+        let banned = crate::banned::banned(existing);
+        config
+            .packages
+            .packages
+            .retain(|name, _| !banned.contains(name));
+        let host = Host::current();
+        keep_declared_groups(&mut config, existing, &host);
+        keep_alternatives(&mut config, existing);
+        keep_conditional_entries(&mut config, existing, &host);
+        let local = load_local(yml_path);
+        keep_variables(&mut config, existing, local.as_ref());
+        // A version requirement is kept instead of the exact installed version, and so is the
+        // recorded install time.
+        for pkg in config.packages.packages.values_mut() {
+            let Some(declared) = existing.packages.get(&pkg.name) else {
+                continue;
+            };
+            if let Some(requirement) = declared.requirement.clone() {
+                pkg.version = None;
+                pkg.requirement = Some(requirement);
+            }
+            pkg.installed_at.clone_from(&declared.installed_at);
+        }
+        if let Some(local) = &local {
+            keep_local_out(&mut config, existing, local);
+        }
+        match base::load(yml_path) {
+            Ok(Some(base)) => config.packages.packages.retain(|name, _| {
+                !base.packages.contains(name) || existing.packages.contains(name)
+            }),
+            Ok(None) => {}
+            Err(e) => eprintln!("Ignoring the base manifest: {e}"),
+        }
+    }
+    let origins = origins
+        || existing.as_ref().is_some_and(|existing| {
+            existing
+                .packages
+                .packages
+                .values()
+                .any(|pkg| pkg.origin.is_some())
+        });
+    if origins {
+        let index = origin::repo_index()?;
+        let groups = crate::available_groups().unwrap_or_default();
+        let host = Host::current();
+        for pkg in config.packages.packages.values_mut() {
+            // Groups have no repository, and entries kept for other machines keep the origin
+            // they were recorded with.
+            if !groups.contains_key(&pkg.name) && condition::applies(pkg.when.as_ref(), &host) {
+                pkg.origin = Some(origin::origin_of(&pkg.name, &index));
+            }
+        }
+    }
+    for source in sources::builtin() {
+        let declared = existing
+            .as_ref()
+            .and_then(|config| config.source(source.name()));
+        let section = frozen_source(declared, add_sources, || source.list().ok());
+        if let Some(slot) = config.source_mut(source.name()) {
+            *slot = section;
+        }
+    }
+    Ok(config)
+}
+
+/// The section a freeze writes for an extra source: the packages `list` finds, if the YAML
+/// already has a section for it (`declared`) or `add_sources`.
+///
+/// When `list` finds nothing, e.g. because the tool is missing, the declared section is kept.
+fn frozen_source(
+    declared: Option<&[String]>,
+    add_sources: bool,
+    list: impl FnOnce() -> Option<Vec<String>>,
+) -> Option<Vec<String>> {
+    if declared.is_none() && !add_sources {
+        return None;
+    }
+    list().or_else(|| declared.map(<[String]>::to_vec))
+}
+
+/// Writes `packages:` entries one at a time, reusing one buffer for serializing them.
+struct StreamedList<W: Write> {
+    writer: W,
+    versioned: bool,
+    buffer: Vec<u8>,
+    written: usize,
+}
+
+impl<W: Write> StreamedList<W> {
+    /// Start the file, before the first entry.
+    fn new(mut writer: W, versioned: bool) -> io::Result<Self> {
+        writeln!(writer, "schema: {}", crate::schema::CURRENT)?;
+        Ok(Self {
+            writer,
+            versioned,
+            buffer: Vec::new(),
+            written: 0,
+        })
+    }
+
+    /// Write the package of a `pacman -Q` line; other lines are skipped.
+    fn line(&mut self, line: &[u8]) -> io::Result<()> {
+        let Some(package) = std::str::from_utf8(line)
+            .ok()
+            .and_then(pacman::parse_package_line)
+        else {
+            return Ok(());
+        };
+        if self.written == 0 {
+            self.writer.write_all(b"packages:\n")?;
+        }
+        self.buffer.clear();
+        serde_yml::to_writer(
+            &mut self.buffer,
+            std::slice::from_ref(&package.to_package(self.versioned)),
+        )
+        .map_err(io::Error::other)?;
+        self.writer.write_all(&self.buffer)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// End the file; returns how many packages were written.
+    fn finish(mut self) -> io::Result<usize> {
+        if self.written == 0 {
+            self.writer.write_all(b"packages: []\n")?;
+        }
+        self.writer.flush()?;
+        Ok(self.written)
+    }
+}
+
+/// Write the explicitly installed packages to `writer` as a YAML file while `pacman -Qe` lists
+/// them, with versions if `versioned`. Returns how many were written.
+///
+/// # Errors
+/// Returns an error if pacman cannot be run or fails, or `writer` cannot be written.
+pub fn stream_installed<W: Write>(writer: W, versioned: bool) -> io::Result<usize> {
+    let mut list = StreamedList::new(writer, versioned)?;
+    let output = crate::run::stream_stdout(pacman::pacman_command().arg("-Qe"), &mut |line| {
+        list.line(line)
+    })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "pacman -Qe failed: {}",
+            output.tail.join("; ")
+        )));
+    }
+    list.finish()
+}
+
+/// Replace the YAML at `yml_path` with the explicitly installed packages, streamed from
+/// `pacman -Qe` as [`stream_installed`] does. Returns how many were written.
+///
+/// The file is written next to the YAML and moved over it once complete, so a failure leaves
+/// the YAML as it was.
+///
+/// # Errors
+/// Returns an error if pacman cannot be run or fails, or the YAML cannot be written.
+pub fn write_streamed<P: AsRef<Path>>(yml_path: P, versioned: bool) -> io::Result<usize> {
+    let yml_path = yml_path.as_ref();
+    let mut partial = yml_path.as_os_str().to_owned();
+    partial.push(".partial");
+    let written = std::fs::File::create(&partial)
+        .and_then(|file| stream_installed(BufWriter::new(file), versioned));
+    match written {
+        Ok(written) => std::fs::rename(&partial, yml_path).map(|()| written),
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// Keep the groups declared in `existing` instead of listing their members one by one.
+fn keep_declared_groups(config: &mut Config, existing: &Config, host: &Host) {
+    let Ok(available) = crate::available_groups() else {
+        return;
+    };
+    // Plain entries such as `- xorg` can name a group too.
+    let plain_groups = existing.packages.packages.values().filter(|pkg| {
+        condition::applies(pkg.when.as_ref(), host)
+            && available.contains_key(&pkg.name)
+            && !config.packages.contains(&pkg.name)
+    });
+    let mut members: Vec<&String> = existing
+        .packages
+        .groups
+        .iter()
+        .filter(|group| condition::applies(group.when.as_ref(), host))
+        .filter_map(|group| available.get(&group.name))
+        .flatten()
+        .collect();
+    let mut kept = Vec::new();
+    for pkg in plain_groups {
+        members.extend(&available[&pkg.name]);
+        kept.push(Package {
+            name: pkg.name.clone(),
+            version: None,
+            requirement: None,
+            reason: pkg.reason,
+            when: pkg.when.clone(),
+            origin: pkg.origin.clone(),
+            installed_at: pkg.installed_at.clone(),
+        });
+    }
+    config
+        .packages
+        .packages
+        .retain(|name, _| !members.contains(&name));
+    for pkg in kept {
+        config.packages.insert(pkg);
+    }
+    config.packages.groups.clone_from(&existing.packages.groups);
+}
+
+/// Keep the alternatives declared in `existing`, instead of listing whichever of them is
+/// installed as a package of its own.
+fn keep_alternatives(config: &mut Config, existing: &Config) {
+    let alternatives = &existing.packages.alternatives;
+    config.packages.packages.retain(|name, _| {
+        existing.packages.contains(name)
+            || !alternatives
+                .iter()
+                .any(|alternatives| alternatives.names.contains(name))
+    });
+    config.packages.alternatives.clone_from(alternatives);
+}
+
+/// Carry `when:` conditions over to packages that are still installed, and keep the entries
+/// meant for other machines as they are, including the `any:` branches for other machines of
+/// a package no longer installed here.
+fn keep_conditional_entries(config: &mut Config, existing: &Config, host: &Host) {
+    for old in existing
+        .packages
+        .packages
+        .values()
+        .filter(|pkg| pkg.when.is_some())
+    {
+        if condition::applies(old.when.as_ref(), host) {
+            match config.packages.packages.get_mut(&old.name) {
+                Some(pkg) if pkg.when.is_none() => pkg.when.clone_from(&old.when),
+                Some(_) => {}
+                None => {
+                    if let Some(others) = old.when.as_ref().and_then(|when| when.others(host)) {
+                        config.packages.insert(Package {
+                            when: Some(others),
+                            ..old.clone()
+                        });
+                    }
+                }
+            }
+        } else {
+            config.packages.insert(old.clone());
+        }
+    }
+}
+
+/// The local overrides of the YAML at `yml_path`, if there are any and they can be read.
+fn load_local(yml_path: &Path) -> Option<LocalConfig> {
+    LocalConfig::load(&local::local_path(yml_path)).unwrap_or_else(|e| {
+        eprintln!("Ignoring local overrides: {e}");
+        None
+    })
+}
+
+/// Keep the `vars:` of `existing`, and its entries whose name refers to variables as written,
+/// in place of the installed package they name with the variables of `existing` and `local`.
+fn keep_variables(config: &mut Config, existing: &Config, local: Option<&LocalConfig>) {
+    config.vars.clone_from(&existing.vars);
+    let mut declared = existing.vars.clone().unwrap_or_default();
+    declared.extend(
+        local
+            .and_then(|local| local.sections.vars.clone())
+            .into_iter()
+            .flatten(),
+    );
+    let Ok(vars) = vars::resolve(Some(&declared), &vars::builtins()) else {
+        return;
+    };
+    for old in existing.packages.packages.values() {
+        let Ok(Some(name)) = vars::substitute(&old.name, &vars) else {
+            continue;
+        };
+        if let Some(pkg) = config.packages.packages.shift_remove(&name) {
+            config.packages.insert(Package {
+                name: old.name.clone(),
+                reason: old.reason,
+                when: old.when.clone(),
+                ..pkg
+            });
+        }
+    }
+}
+
+/// Keep machine-local changes out of the shared file: packages only the local file declares and
+/// locally ignored packages are dropped, and shared entries the local file excludes are kept.
+fn keep_local_out(config: &mut Config, existing: &Config, local: &LocalConfig) {
+    let local_only =
+        |name: &str| local.packages.contains(name) && !existing.packages.contains(name);
+    let locally_ignored = |name: &str| {
+        local
+            .sections
+            .ignore
+            .iter()
+            .flatten()
+            .any(|ignored| ignored == name)
+    };
+    config
+        .packages
+        .packages
+        .retain(|name, _| !local_only(name) && !locally_ignored(name));
+    for old in existing.packages.packages.values() {
+        if local.exclude.contains(&old.name) {
+            config.packages.insert(old.clone());
+        }
+    }
+    for group in &existing.packages.groups {
+        if local.exclude.contains(&group.name)
+            && !config.packages.groups.iter().any(|g| g.name == group.name)
+        {
+            config.packages.groups.push(group.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Added, removed, and changed entries are found, across packages, groups, and sources.
+    #[test]
+    fn diffs_frozen_configs() {
+        let existing: Config = serde_yml::from_str(
+            "packages:\n  - git\n  - name: vim\n    version: 9.1.0330-1\n  - nano\n  \
+             - group: base-devel\nflatpak:\n  - org.gimp.GIMP\n",
+        )
+        .unwrap();
+        let frozen: Config = serde_yml::from_str(
+            "packages:\n  - git\n  - name: vim\n    version: 9.1.0400-1\n  - htop\n\
+             flatpak:\n  - org.gimp.GIMP\n  - org.kde.krita\n",
+        )
+        .unwrap();
+        let diff = FreezeDiff::new(&existing, &frozen);
+        assert_eq!(diff.added, vec!["htop", "flatpak:org.kde.krita"]);
+        assert_eq!(diff.removed, vec!["nano", "base-devel"]);
+        assert_eq!(
+            diff.changed,
+            vec![ChangedEntry {
+                name: "vim".to_string(),
+                before: "9.1.0330-1".to_string(),
+                after: "9.1.0400-1".to_string(),
+            }]
+        );
+        assert!(FreezeDiff::new(&frozen, &frozen).is_empty());
+    }
+
+    /// A package no longer installed here keeps its entry for the other hosts it is declared
+    /// for.
+    #[test]
+    fn keeps_conditional_entries_for_other_hosts() {
+        let existing: Config = serde_yml::from_str(
+            "packages:\n  - name: nvidia-open\n    when: { hostname: desktop }\n  - name: nvidia-open\n    when: { hostname: workstation }\n  - name: tlp\n    when: { hostname: desktop }\n",
+        )
+        .unwrap();
+        let mut config: Config = serde_yml::from_str("packages:\n  - git\n").unwrap();
+        let host = Host {
+            arch: "x86_64".to_string(),
+            hostname: Some("desktop".to_string()),
+        };
+        keep_conditional_entries(&mut config, &existing, &host);
+        let names: Vec<&String> = config.packages.packages.keys().collect();
+        assert_eq!(names, vec!["git", "nvidia-open"]);
+        let when = config.packages.get("nvidia-open").unwrap().when.as_ref();
+        assert_eq!(
+            when.and_then(|when| when.hostname.as_deref()),
+            Some("workstation")
+        );
+    }
+
+    /// Only a missing file counts as no YAML; one that does not parse is an error.
+    #[test]
+    fn reads_existing_yaml_or_nothing() {
+        let dir = std::env::temp_dir().join(format!("nixman-freeze-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("packages.yml");
+        assert!(read_existing(&path).unwrap().is_none());
+        std::fs::write(&path, "packages:\n  - git\n").unwrap();
+        assert!(
+            read_existing(&path)
+                .unwrap()
+                .unwrap()
+                .packages
+                .contains("git")
+        );
+        std::fs::write(&path, "packages:\n  - git\n  bad: [\n").unwrap();
+        assert_eq!(
+            read_existing(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Only sources the YAML already manages are refreshed, unless new ones are asked for, and
+    /// a section is kept when its tool cannot list anything.
+    #[test]
+    fn refreshes_declared_sources_only() {
+        let listed = || Some(vec!["ripgrep".to_string(), "bat".to_string()]);
+        let declared = ["ripgrep".to_string()];
+        assert_eq!(frozen_source(None, false, listed), None);
+        assert_eq!(frozen_source(None, true, listed), listed());
+        assert_eq!(frozen_source(Some(&declared), false, listed), listed());
+        assert_eq!(
+            frozen_source(Some(&declared), false, || None),
+            Some(declared.to_vec())
+        );
+        assert_eq!(frozen_source(Some(&[]), false, listed), listed());
+    }
+
+    /// Streamed entries form a current YAML of the listed packages, skipping noise.
+    #[test]
+    fn streams_a_valid_yaml() {
+        let mut yml = Vec::new();
+        let mut list = StreamedList::new(&mut yml, true).unwrap();
+        for line in [
+            "htop 3.3.0-3",
+            "warning: database file for 'extra' does not exist",
+            "yes 1.0-1",
+        ] {
+            list.line(line.as_bytes()).unwrap();
+        }
+        assert_eq!(list.finish().unwrap(), 2);
+        let config = crate::schema::parse(std::str::from_utf8(&yml).unwrap()).unwrap();
+        assert_eq!(config.schema, Some(crate::schema::CURRENT));
+        let names: Vec<&String> = config.packages.packages.keys().collect();
+        assert_eq!(names, vec!["htop", "yes"]);
+        assert_eq!(
+            config
+                .packages
+                .get("htop")
+                .unwrap()
+                .version
+                .as_ref()
+                .unwrap()
+                .to_string(),
+            "3.3.0-3"
+        );
+
+        let mut empty = Vec::new();
+        StreamedList::new(&mut empty, false)
+            .unwrap()
+            .finish()
+            .unwrap();
+        let config = crate::schema::parse(std::str::from_utf8(&empty).unwrap()).unwrap();
+        assert!(config.packages.packages.is_empty());
+    }
+}
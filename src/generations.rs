@@ -0,0 +1,247 @@
+//! The generation log
+//!
+//! # Overview
+//!
+//! Every `nixman apply` that runs (not a dry run) and every `nixman freeze` appends a generation
+//! to `<state dir>/generations.jsonl`, one JSON object per line. A generation records when the
+//! command ran, how it was invoked, whether it succeeded, the explicitly installed packages
+//! afterwards, and the [snapshot](crate::snapshot) taken before it, if any. Generations are
+//! numbered from 1 and never rewritten.
+//!
+//! `nixman history` lists them with the changes each made ([`history`]), and
+//! `nixman history diff <a> <b>` compares any two ([`diff`]).
+
+use crate::snapshot::Snapshot;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One entry of the generation log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Generation {
+    pub id: u64,
+    /// When the generation was recorded, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The command line that created it, e.g. `apply --paru`.
+    pub command: String,
+    pub success: bool,
+    /// The snapshot taken before the command ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<Snapshot>,
+    /// The explicitly installed packages afterwards.
+    #[serde(default)]
+    pub packages: Vec<String>,
+}
+
+/// The packages added and removed between two generations, sorted by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GenerationDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl GenerationDiff {
+    /// How many packages were added or removed.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.added.len() + self.removed.len()
+    }
+
+    /// Whether both generations have the same packages.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The packages added and removed going from generation `from` to generation `to`.
+#[must_use]
+pub fn diff(from: &Generation, to: &Generation) -> GenerationDiff {
+    let before: BTreeSet<&str> = from.packages.iter().map(String::as_str).collect();
+    let after: BTreeSet<&str> = to.packages.iter().map(String::as_str).collect();
+    GenerationDiff {
+        added: after
+            .difference(&before)
+            .map(|name| (*name).to_string())
+            .collect(),
+        removed: before
+            .difference(&after)
+            .map(|name| (*name).to_string())
+            .collect(),
+    }
+}
+
+/// Each generation with the changes since the one before it; the first is compared with an
+/// empty system.
+#[must_use]
+pub fn history(generations: &[Generation]) -> Vec<(&Generation, GenerationDiff)> {
+    let empty = Generation {
+        id: 0,
+        timestamp: 0,
+        command: String::new(),
+        success: true,
+        snapshot: None,
+        packages: Vec::new(),
+    };
+    generations
+        .iter()
+        .scan(&empty, |previous, generation| {
+            let changes = diff(previous, generation);
+            *previous = generation;
+            Some((generation, changes))
+        })
+        .collect()
+}
+
+/// The generation numbered `id`.
+#[must_use]
+pub fn find(generations: &[Generation], id: u64) -> Option<&Generation> {
+    generations.iter().find(|generation| generation.id == id)
+}
+
+/// Format a Unix timestamp as `YYYY-MM-DD HH:MM` in UTC.
+#[must_use]
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = timestamp / 86_400;
+    let minutes = timestamp % 86_400 / 60;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Default location of the generation log (`<state dir>/generations.jsonl`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn log_path() -> std::io::Result<PathBuf> {
+    crate::paths::generations_file()
+}
+
+/// Read every generation in the log; a missing log has none.
+///
+/// # Errors
+/// Returns an error if the log cannot be read or a line is malformed.
+pub fn read(path: &Path) -> std::io::Result<Vec<Generation>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
+
+/// Append a generation to the log at `path`, numbered after the last one.
+///
+/// # Errors
+/// Returns an error if the log cannot be read or written.
+pub fn record(
+    path: &Path,
+    command: &str,
+    success: bool,
+    snapshot: Option<Snapshot>,
+    packages: Vec<String>,
+) -> std::io::Result<Generation> {
+    let id = read(path)?.last().map_or(1, |last| last.id + 1);
+    let generation = Generation {
+        id,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        command: command.to_string(),
+        success,
+        snapshot,
+        packages,
+    };
+    let line = serde_json::to_string(&generation).map_err(std::io::Error::other)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{line}")?;
+    Ok(generation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::SnapshotTool;
+
+    /// Generations are numbered in order and read back with their snapshots.
+    #[test]
+    fn records_numbered_generations() {
+        let path = std::env::temp_dir().join(format!("nixman-generations-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(read(&path).unwrap().is_empty());
+        let snapshot = Snapshot {
+            tool: SnapshotTool::Snapper,
+            id: "42".to_string(),
+        };
+        record(
+            &path,
+            "apply",
+            true,
+            Some(snapshot.clone()),
+            vec!["git".to_string()],
+        )
+        .unwrap();
+        let second = record(&path, "apply", false, None, Vec::new()).unwrap();
+        assert_eq!(second.id, 2);
+        let generations = read(&path).unwrap();
+        assert_eq!(generations.len(), 2);
+        assert_eq!(generations[0].snapshot, Some(snapshot));
+        assert_eq!(generations[0].packages, vec!["git"]);
+        assert!(!generations[1].success);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Each generation is compared with the one before it, and any two can be compared.
+    #[test]
+    fn diffs_generations() {
+        let generation = |id: u64, packages: &[&str]| Generation {
+            id,
+            timestamp: 1_714_557_600,
+            command: "apply".to_string(),
+            success: true,
+            snapshot: None,
+            packages: packages.iter().map(ToString::to_string).collect(),
+        };
+        let generations = vec![
+            generation(1, &["git", "vim"]),
+            generation(2, &["git", "vim", "htop"]),
+            generation(3, &["git", "htop", "neovim"]),
+        ];
+        let counts: Vec<usize> = history(&generations)
+            .iter()
+            .map(|(_, changes)| changes.len())
+            .collect();
+        assert_eq!(counts, vec![2, 1, 2]);
+        let changes = diff(
+            find(&generations, 1).unwrap(),
+            find(&generations, 3).unwrap(),
+        );
+        assert_eq!(changes.added, vec!["htop", "neovim"]);
+        assert_eq!(changes.removed, vec!["vim"]);
+        assert!(find(&generations, 4).is_none());
+        assert_eq!(format_timestamp(1_714_557_600), "2024-05-01 10:00");
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+    }
+}
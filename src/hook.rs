@@ -0,0 +1,191 @@
+//! A pacman hook that keeps the YAML up to date
+//!
+//! # Overview
+//!
+//! `nixman install-hook` writes an alpm hook ([`HOOK_PATH`]) that runs after every pacman
+//! transaction that installs packages, including a plain `pacman -S` outside nixman. Depending
+//! on the [`HookMode`], it records the new explicit packages in the YAML with
+//! `nixman freeze --merge`, or only lists the ones the YAML lacks.
+//!
+//! pacman runs hooks as root, so the hook switches back with `runuser` to the user who installed
+//! it, and passes the config and state directories that user had: the YAML keeps its owner, and
+//! the state files stay in the user's directory. A transaction nixman started itself holds
+//! nixman's lock, and the hook leaves the YAML to that nixman.
+//!
+//! `nixman install-hook --remove` deletes the hook again.
+
+#[cfg(target_os = "linux")]
+use crate::pacman;
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::process::{ExitStatus, Stdio};
+use std::str::FromStr;
+
+/// The directory pacman reads hooks from by default.
+#[cfg(target_os = "linux")]
+const HOOKS_DIR: &str = "/etc/pacman.d/hooks";
+
+/// Where the hook is installed.
+pub const HOOK_PATH: &str = "/etc/pacman.d/hooks/nixman.hook";
+
+/// What the hook does after a transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HookMode {
+    /// Add the new explicit packages to the YAML.
+    #[default]
+    Add,
+    /// Only list the explicit packages the YAML lacks, leaving it as it is.
+    Flag,
+}
+
+impl FromStr for HookMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "add" => Ok(Self::Add),
+            "flag" => Ok(Self::Flag),
+            _ => Err(format!("unknown hook mode `{s}`: expected add or flag")),
+        }
+    }
+}
+
+/// Quote `arg` for an `Exec =` line, which pacman splits at whitespace.
+fn quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "'\"\\".contains(c)) {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    } else {
+        arg.to_string()
+    }
+}
+
+/// Render the hook that runs `exe` in `mode`, as `user` (or as root if `None`) with nixman's
+/// config and state directories set to `config_dir` and `state_dir`.
+#[must_use]
+pub fn hook_contents(
+    exe: &Path,
+    user: Option<&str>,
+    config_dir: &Path,
+    state_dir: &Path,
+    mode: HookMode,
+) -> String {
+    let mut exec: Vec<String> = Vec::new();
+    if let Some(user) = user {
+        exec.extend(["/usr/bin/runuser", "-u", user, "--"].map(String::from));
+    }
+    exec.push("/usr/bin/env".to_string());
+    exec.push(format!("NIXMAN_CONFIG_DIR={}", config_dir.display()));
+    exec.push(format!("NIXMAN_STATE_DIR={}", state_dir.display()));
+    exec.push(exe.display().to_string());
+    exec.extend(["freeze", "--merge", "--hook"].map(String::from));
+    let description = match mode {
+        HookMode::Add => "Recording new packages in the nixman package list...",
+        HookMode::Flag => {
+            exec.push("--diff".to_string());
+            "Checking for packages missing from the nixman package list..."
+        }
+    };
+    let exec: Vec<String> = exec.iter().map(|arg| quote(arg)).collect();
+    format!(
+        "# Written by `nixman install-hook`; remove it with `nixman install-hook --remove`.\n\
+         [Trigger]\n\
+         Operation = Install\n\
+         Type = Package\n\
+         Target = *\n\
+         \n\
+         [Action]\n\
+         Description = {description}\n\
+         When = PostTransaction\n\
+         Exec = {exec}\n",
+        exec = exec.join(" "),
+    )
+}
+
+/// Write the hook for the current user, or for root when running as root (requires root).
+///
+/// # Errors
+/// Returns an error if the current executable or user cannot be determined, or a command could
+/// not be executed.
+#[cfg(target_os = "linux")]
+pub fn install(mode: HookMode) -> std::io::Result<ExitStatus> {
+    use std::io::Write;
+
+    let exe = std::env::current_exe()?;
+    let user = if pacman::is_root() {
+        None
+    } else {
+        let user = crate::audit::user();
+        if user == "unknown" {
+            return Err(std::io::Error::other(
+                "cannot tell which user the hook should run as; set $USER",
+            ));
+        }
+        Some(user)
+    };
+    let contents = hook_contents(
+        &exe,
+        user.as_deref(),
+        &crate::paths::config_dir(),
+        &crate::paths::state_dir(),
+        mode,
+    );
+    let status = pacman::elevated("mkdir")
+        .arg("-p")
+        .arg(HOOKS_DIR)
+        .status()?;
+    if !status.success() {
+        return Ok(status);
+    }
+    let mut tee = pacman::elevated("tee")
+        .arg(HOOK_PATH)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = tee.stdin.take() {
+        stdin.write_all(contents.as_bytes())?;
+    }
+    tee.wait()
+}
+
+/// Delete the hook if it is installed (requires root).
+///
+/// # Errors
+/// Returns an error if `rm` could not be executed.
+#[cfg(target_os = "linux")]
+pub fn uninstall() -> std::io::Result<ExitStatus> {
+    pacman::elevated("rm").args(["-f", HOOK_PATH]).status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The hook runs a merging freeze as the user, with quoted paths, and only checks when
+    /// flagging.
+    #[test]
+    fn renders_hook() {
+        let hook = hook_contents(
+            Path::new("/usr/bin/nixman"),
+            Some("alice"),
+            Path::new("/home/alice/My Config/nixman"),
+            Path::new("/home/alice/.local/state/nixman"),
+            HookMode::Add,
+        );
+        assert!(hook.contains("When = PostTransaction\n"));
+        assert!(hook.contains(
+            "Exec = /usr/bin/runuser -u alice -- /usr/bin/env 'NIXMAN_CONFIG_DIR=/home/alice/My Config/nixman' \
+             NIXMAN_STATE_DIR=/home/alice/.local/state/nixman /usr/bin/nixman freeze --merge --hook\n"
+        ));
+        let hook = hook_contents(
+            Path::new("/usr/bin/nixman"),
+            None,
+            Path::new("/root/.config/nixman"),
+            Path::new("/root/.local/state/nixman"),
+            HookMode::Flag,
+        );
+        assert!(hook.contains("Exec = /usr/bin/env NIXMAN_CONFIG_DIR="));
+        assert!(hook.contains("freeze --merge --hook --diff\n"));
+        assert_eq!("flag".parse(), Ok(HookMode::Flag));
+        assert!("auto".parse::<HookMode>().is_err());
+    }
+}
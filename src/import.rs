@@ -0,0 +1,351 @@
+//! Importing package lists from other formats
+//!
+//! # Overview
+//!
+//! `nixman import --from <format> <path>` converts another package manifest into package names
+//! and merges them into the YAML's `packages:` list, keeping everything already declared:
+//!
+//! - [`ImportFormat::Txt`]: one package per line; `#` starts a comment.
+//! - [`ImportFormat::Brewfile`]: `brew "name"` entries (tap prefixes are dropped). Casks, taps,
+//!   and Mac App Store entries have no pacman equivalent and are reported as skipped.
+//! - [`ImportFormat::Ansible`]: `pacman`/`package` tasks in a playbook or task file, including
+//!   `loop`/`with_items` lists. Tasks with `state: absent` and templated names are skipped.
+//!
+//! Names are taken as-is; packages whose names differ between ecosystems have to be renamed by
+//! hand after importing.
+//!
+//! [`ImportFormat::PacmanLog`] is different: it adds no packages, but records when each package
+//! the YAML declares was last installed, according to [pacman's log](crate::pacman_log), as its
+//! `installed_at:` ([`import_install_times`]).
+
+use crate::{Config, Package, PackageList, generations, pacman_log, vars};
+use serde_yml::Value;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A manifest format that can be imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Brewfile,
+    Txt,
+    Ansible,
+    /// pacman's log, for the install times of the declared packages.
+    PacmanLog,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "brewfile" => Ok(Self::Brewfile),
+            "txt" => Ok(Self::Txt),
+            "ansible" => Ok(Self::Ansible),
+            "pacman-log" => Ok(Self::PacmanLog),
+            _ => Err(format!(
+                "unknown format `{s}`: expected brewfile, txt, ansible, or pacman-log"
+            )),
+        }
+    }
+}
+
+/// Package names found in a manifest.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Imported {
+    pub packages: Vec<String>,
+    /// Entries that could not be converted, as they appeared in the manifest.
+    pub skipped: Vec<String>,
+}
+
+/// Parse a manifest in the given format; for pacman's log, the packages it shows as installed.
+///
+/// # Errors
+/// Returns a message if an Ansible file is not valid YAML.
+pub fn parse(format: ImportFormat, content: &str) -> Result<Imported, String> {
+    match format {
+        ImportFormat::Brewfile => Ok(parse_brewfile(content)),
+        ImportFormat::Txt => Ok(Imported {
+            packages: parse_txt(content),
+            skipped: Vec::new(),
+        }),
+        ImportFormat::Ansible => parse_ansible(content),
+        ImportFormat::PacmanLog => {
+            let events = pacman_log::parse(content);
+            let installed = pacman_log::install_times(&events);
+            let mut packages: Vec<String> =
+                installed.keys().map(|name| (*name).to_string()).collect();
+            packages.sort();
+            Ok(Imported {
+                packages,
+                skipped: Vec::new(),
+            })
+        }
+    }
+}
+
+/// Parse a plain list: whitespace-separated names, with `#` comments.
+#[must_use]
+pub fn parse_txt(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(str::split_whitespace)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse the `brew` entries of a Brewfile.
+#[must_use]
+pub fn parse_brewfile(content: &str) -> Imported {
+    let mut imported = Imported::default();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (kind, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let name = rest
+            .trim_start()
+            .strip_prefix('"')
+            .and_then(|rest| rest.split('"').next());
+        match (kind, name) {
+            // `brew "user/tap/name"` installs `name`.
+            ("brew", Some(name)) => imported
+                .packages
+                .push(name.rsplit('/').next().unwrap_or(name).to_string()),
+            _ => imported.skipped.push(line.to_string()),
+        }
+    }
+    imported
+}
+
+/// Ansible modules that install system packages.
+const ANSIBLE_MODULES: &[&str] = &[
+    "pacman",
+    "community.general.pacman",
+    "package",
+    "ansible.builtin.package",
+];
+
+/// Parse the package tasks of an Ansible playbook or task file.
+///
+/// # Errors
+/// Returns a message if the content is not valid YAML.
+pub fn parse_ansible(content: &str) -> Result<Imported, String> {
+    let value: Value = crate::schema::from_str(content).map_err(|e| e.to_string())?;
+    let mut imported = Imported::default();
+    collect_ansible(&value, &mut imported);
+    Ok(imported)
+}
+
+/// Walk plays, blocks, and tasks, collecting packages from every package module.
+fn collect_ansible(value: &Value, imported: &mut Imported) {
+    match value {
+        Value::Sequence(items) => {
+            for item in items {
+                collect_ansible(item, imported);
+            }
+        }
+        Value::Mapping(map) => {
+            let module = ANSIBLE_MODULES.iter().find_map(|module| map.get(*module));
+            match module {
+                Some(args) => collect_task(args, map, imported),
+                None => map
+                    .values()
+                    .for_each(|value| collect_ansible(value, imported)),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collect the packages of one task, given its module arguments and the whole task.
+fn collect_task(args: &Value, task: &serde_yml::Mapping, imported: &mut Imported) {
+    let (names, state) = match args {
+        // Free-form arguments: `pacman: name=git,vim state=present`.
+        Value::String(args) => {
+            let arg = |key: &str| {
+                args.split_whitespace()
+                    .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+                    .map(|value| Value::String(value.to_string()))
+            };
+            (arg("name"), arg("state"))
+        }
+        Value::Mapping(args) => (
+            args.get("name").or_else(|| args.get("pkg")).cloned(),
+            args.get("state").cloned(),
+        ),
+        _ => (None, None),
+    };
+    if matches!(
+        state.as_ref().and_then(Value::as_str),
+        Some("absent" | "removed")
+    ) {
+        return;
+    }
+    let mut names = names_of(names.as_ref());
+    if names
+        .iter()
+        .any(|name| name.contains("{{") && name.contains("item"))
+    {
+        let items = task.get("loop").or_else(|| task.get("with_items"));
+        names = names_of(items);
+    }
+    for name in names {
+        if name.contains("{{") {
+            imported.skipped.push(name);
+        } else {
+            imported.packages.push(name);
+        }
+    }
+}
+
+/// Names from a module's `name` argument: a string (possibly comma-separated) or a list.
+fn names_of(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::String(names)) => names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Some(Value::Sequence(items)) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Add `names` to `packages` as explicit packages, skipping ones already declared.
+///
+/// Returns the names that were added.
+pub fn merge(packages: &mut PackageList, names: &[String]) -> Vec<String> {
+    let imported: PackageList = names.iter().cloned().map(Package::new).collect();
+    let added = imported.difference(packages);
+    *packages = packages.union(&added);
+    added.packages.into_keys().collect()
+}
+
+/// Set the `installed_at:` of the packages `config` declares to their last install in
+/// `events`; an entry like `${editor}` is looked up as the package it names.
+///
+/// Returns the names whose install time was found, and the declared names the log does not
+/// show as installed.
+pub fn record_install_times(config: &mut Config, events: &[pacman_log::LogEvent]) -> Imported {
+    let times = pacman_log::install_times(events);
+    let vars = vars::resolve(config.vars.as_ref(), &vars::builtins()).unwrap_or_default();
+    let mut imported = Imported::default();
+    for pkg in config.packages.packages.values_mut() {
+        let name = vars::substitute(&pkg.name, &vars).ok().flatten();
+        match times.get(name.as_deref().unwrap_or(&pkg.name)) {
+            Some(&timestamp) => {
+                pkg.installed_at = Some(generations::format_timestamp(timestamp));
+                imported.packages.push(pkg.name.clone());
+            }
+            None => imported.skipped.push(pkg.name.clone()),
+        }
+    }
+    imported
+}
+
+/// Record the install times found in pacman's log at `source` in the YAML, as
+/// [`record_install_times`] does.
+///
+/// # Errors
+/// Returns an error if the log cannot be read, or the YAML cannot be read or written.
+pub fn import_install_times<P: AsRef<Path>>(
+    yml_path: P,
+    source: &Path,
+) -> std::io::Result<Imported> {
+    let events = pacman_log::read(source)?;
+    let mut config = crate::read_config(&yml_path)?;
+    let imported = record_install_times(&mut config, &events);
+    crate::write_config(&config, yml_path)?;
+    Ok(imported)
+}
+
+/// Import the manifest at `source` into the YAML; pacman's log is imported with
+/// [`import_install_times`].
+///
+/// Returns the names that were added (or whose install time was recorded) and the entries that
+/// were skipped.
+///
+/// # Errors
+/// Returns an error if the manifest cannot be read or parsed, or the YAML cannot be read or
+/// written.
+pub fn import_into_yaml<P: AsRef<Path>>(
+    yml_path: P,
+    format: ImportFormat,
+    source: &Path,
+) -> std::io::Result<Imported> {
+    if format == ImportFormat::PacmanLog {
+        return import_install_times(yml_path, source);
+    }
+    let content = std::fs::read_to_string(source)?;
+    let imported = parse(format, &content)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    // Importing is often the first thing done with a freshly created (empty) YAML.
+    let mut config = if std::fs::read_to_string(&yml_path)?.trim().is_empty() {
+        Config::new(PackageList::default())
+    } else {
+        crate::read_config(&yml_path)?
+    };
+    let added = merge(&mut config.packages, &imported.packages);
+    crate::write_config(&config, yml_path)?;
+    Ok(Imported {
+        packages: added,
+        skipped: imported.skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Brew formulae are imported without their tap; casks and taps are skipped.
+    #[test]
+    fn parses_brewfile() {
+        let brewfile = "tap \"homebrew/bundle\"\n# tools\nbrew \"git\"\nbrew \"neovim/neovim/neovim\", args: [\"HEAD\"]\ncask \"firefox\"\n";
+        let imported = parse_brewfile(brewfile);
+        assert_eq!(imported.packages, vec!["git", "neovim"]);
+        assert_eq!(
+            imported.skipped,
+            vec!["tap \"homebrew/bundle\"", "cask \"firefox\""]
+        );
+        assert_eq!(
+            parse_txt("git vim # editors\n\n# none\nhtop\n"),
+            vec!["git", "vim", "htop"]
+        );
+    }
+
+    /// Package tasks are found in plays and blocks, with loops expanded and removals ignored.
+    #[test]
+    fn parses_ansible_tasks() {
+        let playbook = r#"
+- hosts: all
+  tasks:
+    - name: Install tools
+      community.general.pacman:
+        name:
+          - git
+          - htop
+    - block:
+        - pacman: name=vim,tmux state=present
+        - ansible.builtin.package:
+            name: "{{ item }}"
+          loop: [ripgrep, fd]
+    - pacman:
+        name: nano
+        state: absent
+    - pacman:
+        name: "{{ editor }}"
+"#;
+        let imported = parse_ansible(playbook).unwrap();
+        assert_eq!(
+            imported.packages,
+            vec!["git", "htop", "vim", "tmux", "ripgrep", "fd"]
+        );
+        assert_eq!(imported.skipped, vec!["{{ editor }}"]);
+    }
+}
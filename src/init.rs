@@ -0,0 +1,171 @@
+//! First-time setup
+//!
+//! # Overview
+//!
+//! `nixman init` sets up the [config directory](crate::paths::config_dir) in one step, instead
+//! of leaving the first command that records packages to create an empty YAML:
+//!
+//! - `packages.yml` in the current [schema](crate::schema), whose `ignore:` section starts out
+//!   with [`DEFAULT_IGNORE`]: the kernel, firmware, and `base` differ between machines and are
+//!   installed by the installer, not by nixman,
+//! - optionally, a versioned [freeze](crate::freeze) of the installed packages into it,
+//! - `config.yml` with the global [settings](crate::settings), the common ones commented out,
+//! - optionally, a git repository with a `.gitignore` that keeps the
+//!   [machine-local overrides](crate::local) out of it.
+//!
+//! An existing `packages.yml` is never overwritten; `nixman freeze` updates it. An existing
+//! `config.yml` or git repository is kept as it is.
+
+use crate::{Config, PackageList};
+use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::{io, path::Path, process::Command};
+
+/// The packages a new YAML ignores.
+pub const DEFAULT_IGNORE: &[&str] = &["base", "linux", "linux-firmware"];
+
+/// The settings `init` can turn on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InitOptions {
+    /// Record the installed packages, with their versions.
+    pub freeze: bool,
+    /// Put the config directory under git.
+    pub git: bool,
+}
+
+/// What `init` created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitReport {
+    /// The new YAML.
+    pub config: PathBuf,
+    /// The settings file, unless it existed already.
+    pub settings: Option<PathBuf>,
+    /// Whether the installed packages were recorded.
+    pub frozen: bool,
+    /// The git repository, unless git was not requested or it existed already.
+    pub git: Option<PathBuf>,
+}
+
+/// The YAML `init` starts from: no packages, and [`DEFAULT_IGNORE`] ignored.
+#[must_use]
+pub fn starting_config() -> Config {
+    let mut config = Config::new(PackageList::new([], Vec::new()));
+    config.schema = Some(crate::schema::CURRENT);
+    config.ignore = Some(DEFAULT_IGNORE.iter().map(ToString::to_string).collect());
+    config
+}
+
+/// The `config.yml` `init` writes; `versioned` makes later freezes record versions too.
+#[must_use]
+pub fn settings_template(versioned: bool) -> String {
+    format!(
+        "# Defaults for nixman's flags; flags on the command line always win.\n\
+         versioned: {versioned}\n\
+         # helper: paru            # pacman (default) or paru\n\
+         # elevation: doas         # sudo, doas, run0, or none\n\
+         # noconfirm: true\n\
+         # pre_apply_snapshot: snapper  # or timeshift\n\
+         # notify: all             # drift (default), all, or off\n"
+    )
+}
+
+/// Set up `dir` as nixman's config directory.
+///
+/// # Errors
+/// Returns an error if `dir` already holds a `packages.yml`, a file cannot be written, pacman
+/// could not be queried for the freeze, or git failed.
+#[cfg(target_os = "linux")]
+pub fn init(dir: &Path, options: InitOptions) -> io::Result<InitReport> {
+    std::fs::create_dir_all(dir)?;
+    let config = dir.join(crate::paths::CONFIG_FILE);
+    if config.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists; run `nixman freeze` to update it",
+                config.display()
+            ),
+        ));
+    }
+    crate::write_config(&starting_config(), &config)?;
+    // A failed freeze leaves nothing behind, so `init` can simply be run again.
+    if options.freeze
+        && let Err(e) = crate::freeze::write_installed(&config, true, false, false)
+    {
+        let _ = std::fs::remove_file(&config);
+        return Err(io::Error::new(
+            e.kind(),
+            format!("failed to record the installed packages: {e}"),
+        ));
+    }
+    let settings = dir.join(crate::paths::SETTINGS_FILE);
+    let settings = if settings.exists() {
+        None
+    } else {
+        std::fs::write(&settings, settings_template(options.freeze))?;
+        Some(settings)
+    };
+    let git = if options.git && !dir.join(".git").exists() {
+        init_git(dir, &config)?;
+        Some(dir.to_path_buf())
+    } else {
+        None
+    };
+    Ok(InitReport {
+        config,
+        settings,
+        frozen: options.freeze,
+        git,
+    })
+}
+
+/// `git init` in `dir`, ignoring the local overrides of `config`, and stage the files.
+#[cfg(target_os = "linux")]
+fn init_git(dir: &Path, config: &Path) -> io::Result<()> {
+    let git = |args: &[&str]| -> io::Result<()> {
+        let status = Command::new("git").arg("-C").arg(dir).args(args).status()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "`git {}` failed with {status}",
+                args.join(" ")
+            )))
+        }
+    };
+    git(&["init", "--quiet"])?;
+    let local = crate::local::local_path(config);
+    if let Some(name) = local.file_name() {
+        let gitignore = dir.join(".gitignore");
+        if !gitignore.exists() {
+            std::fs::write(gitignore, format!("{}\n", name.to_string_lossy()))?;
+        }
+    }
+    git(&["add", "."])
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// A new directory gets the starting YAML and settings; a second run refuses to overwrite.
+    #[test]
+    fn sets_up_config_dir() {
+        let dir = std::env::temp_dir().join(format!("nixman-init-{}", std::process::id()));
+        let report = init(&dir, InitOptions::default()).unwrap();
+        let config = crate::read_config(&report.config).unwrap();
+        assert_eq!(config.schema, Some(crate::schema::CURRENT));
+        assert!(config.packages.packages.is_empty());
+        assert_eq!(
+            config.ignore.unwrap(),
+            vec!["base", "linux", "linux-firmware"]
+        );
+        let settings = crate::settings::Settings::load(&report.settings.clone().unwrap()).unwrap();
+        assert_eq!(settings.versioned, Some(false));
+        assert_eq!(report.git, None);
+
+        let err = init(&dir, InitOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+//! Keyring health checks
+//!
+//! # Overview
+//!
+//! pacman verifies every package against the keys in its keyring (`/etc/pacman.d/gnupg`). When
+//! the keyring is broken, a transaction fails on the first package signed by a key pacman does
+//! not trust, after everything has been downloaded, so a 40-package apply dies with
+//! `signature from ... is unknown trust` and installs nothing. Before installing, nixman
+//! [`check`]s for the usual causes ([`KeyringIssue`]):
+//!
+//! - the keyring was never set up with `pacman-key --init`,
+//! - `archlinux-keyring` is older than the one in the sync databases, so it lacks the keys of
+//!   newer packagers,
+//! - keys the installed keyring packages trust are missing from the keyring or have expired
+//!   there, because `pacman-key --populate` did not run. Only root can read the keyring, so
+//!   this check is skipped otherwise.
+//!
+//! Depending on the `keyring_check` setting, nixman then:
+//!
+//! - `ask` (default): lists the problems and offers to refresh the keyring first; with
+//!   `--noconfirm` or without a terminal it only warns,
+//! - `refresh`: refreshes it without asking ([`refresh_steps`]),
+//! - `off`: does nothing.
+
+#[cfg(target_os = "linux")]
+use crate::pacman;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::process::{Command, ExitStatus};
+
+/// The package that ships the Arch Linux packagers' keys.
+pub const KEYRING_PACKAGE: &str = "archlinux-keyring";
+
+/// What to do before installing when the keyring has problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyringPolicy {
+    /// Offer to refresh the keyring, or warn if there is no one to ask.
+    #[default]
+    Ask,
+    /// Refresh the keyring without asking.
+    Refresh,
+    Off,
+}
+
+/// A keyring problem that makes signature checks fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyringIssue {
+    /// `pacman-key --init` never ran: the keyring has no trust database.
+    Uninitialized,
+    /// [`KEYRING_PACKAGE`] is older than the version in the sync databases.
+    Outdated {
+        installed: String,
+        available: String,
+    },
+    /// Fingerprints of trusted keys that are missing from the keyring.
+    MissingKeys(Vec<String>),
+    /// Fingerprints of trusted keys that have expired in the keyring.
+    ExpiredKeys(Vec<String>),
+}
+
+impl fmt::Display for KeyringIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uninitialized => write!(
+                f,
+                "pacman's keyring is not set up (`pacman-key --init` has not run)"
+            ),
+            Self::Outdated {
+                installed,
+                available,
+            } => write!(
+                f,
+                "{KEYRING_PACKAGE} {installed} is older than {available} in the sync databases"
+            ),
+            Self::MissingKeys(keys) => write!(
+                f,
+                "{} trusted key(s) are missing from pacman's keyring",
+                keys.len()
+            ),
+            Self::ExpiredKeys(keys) => write!(
+                f,
+                "{} trusted key(s) have expired in pacman's keyring",
+                keys.len()
+            ),
+        }
+    }
+}
+
+/// One command of a keyring refresh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshStep {
+    /// `pacman-key --init`
+    Init,
+    /// Install the current [`KEYRING_PACKAGE`], which imports its keys itself.
+    UpdateKeyring,
+    /// `pacman-key --populate`, which imports and trusts the keys of every keyring package.
+    Populate,
+}
+
+impl fmt::Display for RefreshStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Init => write!(f, "pacman-key --init"),
+            Self::UpdateKeyring => write!(f, "pacman -S {KEYRING_PACKAGE}"),
+            Self::Populate => write!(f, "pacman-key --populate"),
+        }
+    }
+}
+
+/// The commands that fix `issues`, in the order they must run.
+#[must_use]
+pub fn refresh_steps(issues: &[KeyringIssue]) -> Vec<RefreshStep> {
+    let has = |wanted: fn(&KeyringIssue) -> bool| issues.iter().any(wanted);
+    let mut steps = Vec::new();
+    if has(|issue| matches!(issue, KeyringIssue::Uninitialized)) {
+        steps.push(RefreshStep::Init);
+    }
+    if has(|issue| matches!(issue, KeyringIssue::Outdated { .. })) {
+        steps.push(RefreshStep::UpdateKeyring);
+    }
+    if has(|issue| {
+        matches!(
+            issue,
+            KeyringIssue::Uninitialized
+                | KeyringIssue::MissingKeys(_)
+                | KeyringIssue::ExpiredKeys(_)
+        )
+    }) {
+        steps.push(RefreshStep::Populate);
+    }
+    steps
+}
+
+/// A public key of the keyring, as `gpg --list-keys --with-colons` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    pub fingerprint: String,
+    pub expired: bool,
+}
+
+/// Parse the primary keys of `gpg --list-keys --with-colons` output; subkeys are skipped.
+#[must_use]
+pub fn parse_keys(output: &str) -> Vec<Key> {
+    let mut keys = Vec::new();
+    // The validity of the last `pub` record, until its `fpr` record follows.
+    let mut validity = None;
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.first() {
+            Some(&"pub") => validity = Some(fields.get(1).copied().unwrap_or_default()),
+            Some(&"sub") => validity = None,
+            Some(&"fpr") => {
+                if let (Some(validity), Some(fingerprint)) = (validity.take(), fields.get(9)) {
+                    keys.push(Key {
+                        fingerprint: (*fingerprint).to_string(),
+                        expired: validity == "e",
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    keys
+}
+
+/// Parse a keyring package's `*-trusted` file, one `FINGERPRINT:TRUST:` line per key.
+#[must_use]
+pub fn parse_trusted(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| line.split(':').next())
+        .map(str::trim)
+        .filter(|fingerprint| !fingerprint.is_empty() && !fingerprint.starts_with('#'))
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// The trusted keys that are missing from `keys` or have expired there.
+#[must_use]
+pub fn key_issues(trusted: &[String], keys: &[Key]) -> Vec<KeyringIssue> {
+    let mut missing = Vec::new();
+    let mut expired = Vec::new();
+    for fingerprint in trusted {
+        match keys.iter().find(|key| &key.fingerprint == fingerprint) {
+            None => missing.push(fingerprint.clone()),
+            Some(key) if key.expired => expired.push(fingerprint.clone()),
+            Some(_) => {}
+        }
+    }
+    let mut issues = Vec::new();
+    if !missing.is_empty() {
+        issues.push(KeyringIssue::MissingKeys(missing));
+    }
+    if !expired.is_empty() {
+        issues.push(KeyringIssue::ExpiredKeys(expired));
+    }
+    issues
+}
+
+/// Where keyring packages install their keys, as seen from outside a chroot.
+#[cfg(target_os = "linux")]
+fn keyrings_dir() -> PathBuf {
+    pacman::paths()
+        .chroot
+        .unwrap_or_else(|| PathBuf::from("/"))
+        .join("usr/share/pacman/keyrings")
+}
+
+/// The fingerprints every installed keyring package trusts.
+#[cfg(target_os = "linux")]
+fn trusted_keys() -> std::io::Result<Vec<String>> {
+    let mut trusted = Vec::new();
+    for entry in std::fs::read_dir(keyrings_dir())? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with("-trusted"))
+        {
+            trusted.extend(parse_trusted(&std::fs::read_to_string(&path)?));
+        }
+    }
+    Ok(trusted)
+}
+
+/// The keys of the keyring in `gpg_dir`, or `None` if gpg cannot read it, e.g. as a user
+/// other than root.
+#[cfg(target_os = "linux")]
+fn keyring_keys(gpg_dir: &std::path::Path) -> Option<Vec<Key>> {
+    let output = crate::run::output(
+        Command::new("gpg")
+            .arg("--homedir")
+            .arg(gpg_dir)
+            .args([
+                "--batch",
+                "--no-permission-warning",
+                "--lock-never",
+                "--no-auto-check-trustdb",
+                "--with-colons",
+                "--list-keys",
+            ])
+            .env("LC_ALL", "C"),
+    )
+    .ok()?;
+    output
+        .status
+        .success()
+        .then(|| parse_keys(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Look for the problems of the keyring of the system nixman manages.
+///
+/// # Errors
+/// Returns an error if pacman could not be queried.
+#[cfg(target_os = "linux")]
+pub fn check() -> std::io::Result<Vec<KeyringIssue>> {
+    let mut issues = Vec::new();
+    let gpg_dir = pacman::paths().gpg_dir();
+    // Without permission to look, the keyring counts as set up.
+    let initialized = gpg_dir.join("trustdb.gpg").try_exists().unwrap_or(true);
+    if !initialized {
+        issues.push(KeyringIssue::Uninitialized);
+    }
+    if let Some(installed) = pacman::pacman_query_info(KEYRING_PACKAGE)?
+        && let Some(available) = pacman::pacman_sync_infos(&[KEYRING_PACKAGE.to_string()])?.pop()
+    {
+        let (installed, available) = (installed.version.to_string(), available.version.to_string());
+        if crate::versioning::vercmp(&installed, &available).is_lt() {
+            issues.push(KeyringIssue::Outdated {
+                installed,
+                available,
+            });
+        }
+    }
+    if initialized
+        && let Ok(trusted) = trusted_keys()
+        && let Some(keys) = keyring_keys(&gpg_dir)
+    {
+        issues.extend(key_issues(&trusted, &keys));
+    }
+    Ok(issues)
+}
+
+/// `pacman-key` as root, inside the chroot if there is one.
+#[cfg(target_os = "linux")]
+fn pacman_key() -> Command {
+    pacman::paths().chroot.map_or_else(
+        || pacman::elevated("pacman-key"),
+        |dir| {
+            let mut cmd = pacman::elevated("arch-chroot");
+            cmd.arg(dir).arg("pacman-key");
+            cmd
+        },
+    )
+}
+
+/// Run one step of a keyring refresh (requires root); the keyring package is installed with
+/// pacman and recorded in the [audit log](crate::audit).
+///
+/// # Errors
+/// Returns an error if the command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn refresh(step: RefreshStep, noconfirm: bool) -> std::io::Result<ExitStatus> {
+    match step {
+        RefreshStep::Init => pacman_key().arg("--init").status(),
+        RefreshStep::Populate => pacman_key().arg("--populate").status(),
+        RefreshStep::UpdateKeyring => {
+            let packages = [KEYRING_PACKAGE.to_string()];
+            let mut cmd = pacman::install_command(&packages, false, noconfirm);
+            pacman::run_audited(&mut cmd, crate::audit::AuditAction::Install, &packages)
+                .map(|output| output.status)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Trusted keys are matched against the primary keys of the keyring, and the fixes run in
+    /// order.
+    #[test]
+    fn finds_missing_and_expired_keys() {
+        let trusted = parse_trusted("# comment\nAAAA:4:\nBBBB:4:\nCCCC:4:\n");
+        assert_eq!(trusted, ["AAAA", "BBBB", "CCCC"]);
+        let keys = parse_keys(
+            "tru::1:1700000000:0:3:1:5\n\
+             pub:-:4096:1:AA:1400000000:::-:::scSC::::::23::0:\n\
+             fpr:::::::::AAAA:\n\
+             uid:-::::1400000000::X::Alice <alice@archlinux.org>::::::::::0:\n\
+             sub:e:4096:1:CC:1400000000:1500000000:::::e::::::23:\n\
+             fpr:::::::::CCCC:\n\
+             pub:e:4096:1:BB:1400000000:1500000000::-:::sc::::::23::0:\n\
+             fpr:::::::::BBBB:\n",
+        );
+        assert_eq!(keys.len(), 2);
+        assert!(!keys[0].expired && keys[1].expired);
+        let issues = key_issues(&trusted, &keys);
+        assert_eq!(
+            issues,
+            [
+                KeyringIssue::MissingKeys(vec!["CCCC".to_string()]),
+                KeyringIssue::ExpiredKeys(vec!["BBBB".to_string()]),
+            ]
+        );
+        assert_eq!(refresh_steps(&issues), [RefreshStep::Populate]);
+        let outdated = KeyringIssue::Outdated {
+            installed: "20240101-1".to_string(),
+            available: "20241001-1".to_string(),
+        };
+        assert_eq!(
+            refresh_steps(&[outdated, KeyringIssue::Uninitialized]),
+            [
+                RefreshStep::Init,
+                RefreshStep::UpdateKeyring,
+                RefreshStep::Populate
+            ]
+        );
+        assert!(refresh_steps(&[]).is_empty());
+    }
+}
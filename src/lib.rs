@@ -2,9 +2,76 @@
 //!
 //! Provides core types and functions for synchronizing Arch Linux packages with a YAML configuration file, parsing package lists, and handling versioning.
 //!
+//! Most programs only need [`Nixman`], which bundles the config path, backend, and settings and
+//! offers `freeze`, `diff`, `status`, and `apply`. The free functions and modules below are the
+//! building blocks it uses.
+//!
+//! Everything that runs pacman or another program, [`Nixman`] included, only exists on Linux.
+//! Other platforms get the data model: [`Package`], [`PackageList`], [`Config`], [`versioning`],
+//! and reading and writing the YAML with [`read_config`] and [`write_config`], for tools that
+//! work on configs without applying them.
+//!
 //! # Modules
 //!
+//! - [`manager`]: The [`Nixman`] entry point and its builder
+//! - `nonblocking`: Async versions of the [`Nixman`] operations (feature `async`)
+//! - `tui`: Reviewing a plan and applying part of it in the terminal (feature `tui`)
 //! - [`versioning`]: Pacman version string parsing and utilities
+//! - [`pacman`]: Wrappers around `pacman` and `paru` invocations
+//! - [`run`]: Running commands with output streamed to a callback and captured
+//! - [`plan`]: The changes an apply will make, computed before executing them
+//! - [`render`]: Shared output formatting for CLI reports
+//! - [`condition`]: `when:` conditions for packages that only apply to some machines
+//! - [`flatpak`]: Flatpak as an additional package source
+//! - [`safety`]: Refusing to remove critical packages such as the kernel
+//! - [`settings`]: Default flags from a `settings:` section or `config.yml`
+//! - [`sources`]: Extra package sources (Flatpak, cargo, pipx, npm) tracked alongside pacman
+//! - [`syncdb`]: Warning about or refreshing stale sync databases before installing
+//! - [`freeze`]: Recording the installed packages in the YAML
+//! - [`explain`]: Explaining why a package is on the system and in the config
+//! - [`export`]: Exporting the package list as Nix, plain text, or a shell script
+//! - [`import`]: Importing package lists from Brewfiles, Ansible tasks, and plain text
+//! - [`local`]: Machine-local overrides merged on top of the shared YAML
+//! - [`base`]: Signed base manifests merged under the YAML on managed machines
+//! - [`lock`]: Locking to prevent concurrent nixman/pacman runs
+//! - [`archive`]: Downgrades through the Arch Linux Archive
+//! - [`names`]: Checking package names before pacman runs
+//! - [`aur`]: AUR metadata: name validation, out-of-date flags, and updates
+//! - [`outdated`]: Available upgrades compared to the YAML's version pins
+//! - [`origin`]: Recording which repository each package comes from
+//! - [`report`]: Per-step results of an apply
+//! - [`repos`]: Custom pacman repositories declared in the YAML
+//! - [`clean`]: Package cache cleanup that keeps pinned versions
+//! - [`generations`]: The log of past applies and freezes
+//! - [`snapshot`]: Filesystem snapshots (snapper, timeshift) before apply
+//! - [`lockfile`]: Exact versions resolved by the last apply or freeze
+//! - [`provides`]: Virtual packages satisfied by installed providers
+//! - [`retry`]: Retrying installs that failed because of the network
+//! - [`resume`]: Staged applies that continue after a required reboot
+//! - [`watch`]: Scheduled drift detection
+//! - [`notify`]: Desktop notifications for drift and finished runs
+//! - [`triage`]: Sorting unmanaged packages when adopting an existing system
+//! - [`verify`]: File integrity checks for managed packages
+//! - [`offline`]: Applying from the package cache without the network
+//! - [`bundle`]: Portable bundles of the YAML's packages for air-gapped machines
+//! - [`reasons`]: Install reasons that disagree with the YAML
+//! - [`schema`]: Versions of the YAML format and migrations between them
+//! - [`strict`]: Reporting YAML keys nixman does not read
+//! - [`diagnostics`]: Parse errors that point at the file, line, and entry
+//! - [`paths`]: Where nixman keeps its config, state, and cache files
+//! - [`init`]: First-time setup of the config directory
+//! - [`banned`]: Packages that must never be installed
+//! - [`applied`]: The state left by the last successful apply
+//! - [`conflicts`]: Resolving disagreements between the YAML and manual changes
+//! - [`cascade`]: What removing packages takes out with it
+//! - [`builds`]: Building AUR packages in parallel, in dependency order
+//! - [`vars`]: `${name}` variables in the YAML
+//! - [`audit`]: The log of every install, removal, and upgrade, with who ran it
+//! - [`hook`]: A pacman hook that records manual installs in the YAML
+//! - [`pacman_log`]: Reading the package history from pacman's log
+//! - [`keyring`]: Keyring problems that would fail signature checks
+//! - [`profile`]: Named configs to switch between
+//! - [`tidy`]: Normalizing the YAML
 //!
 //! # Example
 //!
@@ -26,40 +93,605 @@
     clippy::cargo,
     clippy::nursery
 )]
+// ratatui and indicatif depend on different major versions of unicode-width.
+#![cfg_attr(feature = "tui", allow(clippy::multiple_crate_versions))]
 
-use crate::versioning::FullVersion;
-use serde::Deserialize;
+#[cfg(target_os = "linux")]
+use crate::audit::AuditAction;
+use crate::condition::{Condition, Host};
+#[cfg(target_os = "linux")]
+use crate::report::Step;
+use crate::versioning::{FullVersion, VersionRelation, VersionReq};
+use indexmap::IndexMap;
 use serde::de::{self, Deserializer, MapAccess, Visitor};
 use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt;
-use std::io::Write;
 use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::time::Instant;
 
+pub mod applied;
+#[cfg(target_os = "linux")]
+pub mod archive;
+pub mod audit;
+#[cfg(target_os = "linux")]
+pub mod aur;
+pub mod banned;
+pub mod base;
+#[cfg(target_os = "linux")]
+pub mod builds;
+#[cfg(target_os = "linux")]
+pub mod bundle;
+pub mod cascade;
+#[cfg(target_os = "linux")]
+pub mod clean;
+pub mod condition;
+pub mod conflicts;
+pub mod diagnostics;
+#[cfg(target_os = "linux")]
+pub mod explain;
+pub mod export;
+#[cfg(target_os = "linux")]
+pub mod flatpak;
+#[cfg(target_os = "linux")]
+pub mod freeze;
+pub mod generations;
+pub mod hook;
+pub mod import;
+pub mod init;
+pub mod keyring;
+pub mod local;
+#[cfg(target_os = "linux")]
+pub mod lock;
+#[cfg(target_os = "linux")]
+pub mod lockfile;
+#[cfg(target_os = "linux")]
+pub mod manager;
+#[cfg(target_os = "linux")]
+pub mod names;
+pub mod notify;
+#[cfg(target_os = "linux")]
+pub mod offline;
+#[cfg(target_os = "linux")]
+pub mod origin;
+#[cfg(target_os = "linux")]
+pub mod outdated;
 pub mod pacman;
+pub mod pacman_log;
+pub mod paths;
+#[cfg(target_os = "linux")]
+pub mod plan;
+pub mod profile;
+#[cfg(target_os = "linux")]
+pub mod provides;
+#[cfg(target_os = "linux")]
+pub mod reasons;
+pub mod render;
+pub mod report;
+pub mod repos;
+#[cfg(target_os = "linux")]
+pub mod resume;
+pub mod retry;
+#[cfg(target_os = "linux")]
+pub mod run;
+#[cfg(target_os = "linux")]
+pub mod safety;
+pub mod schema;
+pub mod settings;
+pub mod snapshot;
+#[cfg(target_os = "linux")]
+pub mod sources;
+pub mod strict;
+pub mod syncdb;
+pub mod tidy;
+#[cfg(target_os = "linux")]
+pub mod triage;
+pub mod vars;
+#[cfg(target_os = "linux")]
+pub mod verify;
 pub mod versioning;
+pub mod watch;
+
+#[cfg(target_os = "linux")]
+pub use manager::{Nixman, NixmanBuilder};
+pub use report::ApplyReport;
+pub use settings::Backend;
+
+#[cfg(all(feature = "async", target_os = "linux"))]
+pub mod nonblocking;
+#[cfg(all(feature = "tui", target_os = "linux"))]
+pub mod tui;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Package {
     pub name: String,
+    /// The exact version pinned in the YAML.
     pub version: Option<FullVersion>,
+    /// A range of accepted versions (`version: ">=1.6"`), instead of an exact pin; a package
+    /// has at most one of the two.
+    pub requirement: Option<VersionReq>,
+    /// Whether the package is meant to be installed explicitly or as a dependency.
+    pub reason: InstallReason,
+    /// Only manage the package on machines matching this condition.
+    pub when: Option<Condition>,
+    /// The repository the package was installed from when frozen (`core`, `extra`, a custom
+    /// repository, or `aur`); see [`origin`].
+    pub origin: Option<String>,
+    /// When the package was last installed, as `YYYY-MM-DD HH:MM` in UTC, recorded from
+    /// pacman's log by `import --from pacman-log`; see [`pacman_log`].
+    pub installed_at: Option<String>,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// Why a package is installed, mirroring pacman's install reason.
+///
+/// `apply` only installs and removes explicit packages; packages declared as dependencies are
+/// documented in the YAML and kept, but left for pacman to manage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallReason {
+    #[default]
+    Explicit,
+    Dependency,
+}
+
+/// The packages and groups of the YAML's `packages:` list.
+///
+/// Packages are keyed by name in declaration order, so a name is declared at most once; a
+/// repeated entry is merged into the first one, with either entry's `when:` condition (see
+/// [`PackageList::insert`]).
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
 pub struct PackageList {
-    pub packages: Vec<Package>,
+    /// Packages by name.
+    pub packages: IndexMap<String, Package>,
+    /// Package groups (`- group: base-devel`), compared through their members.
+    pub groups: Vec<Group>,
+    /// Sets of packages of which one is enough (`- one-of: [vim, neovim]`).
+    pub alternatives: Vec<Alternatives>,
+    /// Entries merged into an earlier one, recorded by [`PackageList::insert`].
+    duplicates: Vec<Duplicate>,
+}
+
+/// A pacman package group declared in the YAML.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    #[serde(rename = "group")]
+    pub name: String,
+    /// Only manage the group on machines matching this condition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Condition>,
+}
+
+/// Packages of which one is enough, e.g. an editor or a JDK (`- one-of: [vim, neovim]`).
+///
+/// Whichever of them are installed are kept, and none of the others is installed; when none
+/// is installed, the first one is.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Alternatives {
+    #[serde(rename = "one-of")]
+    pub names: Vec<String>,
+    /// Only manage the alternatives on machines matching this condition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Condition>,
+}
+
+impl Alternatives {
+    /// The names wanted given the `installed` ones: those installed, or else the first.
+    #[must_use]
+    pub fn chosen(&self, installed: &[String]) -> Vec<String> {
+        self.chosen_in(&installed.iter().map(String::as_str).collect())
+    }
+
+    /// [`Alternatives::chosen`] given the set of installed names.
+    fn chosen_in(&self, installed: &HashSet<&str>) -> Vec<String> {
+        let present: Vec<String> = self
+            .names
+            .iter()
+            .filter(|name| installed.contains(name.as_str()))
+            .cloned()
+            .collect();
+        if present.is_empty() {
+            self.names.first().cloned().into_iter().collect()
+        } else {
+            present
+        }
+    }
+}
+
+/// A package declared more than once in a [`PackageList`] (see [`PackageList::duplicates`]).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Duplicate {
+    pub name: String,
+    /// The distinct versions the entries pin, in the order they appear.
+    pub versions: Vec<String>,
+}
+
+impl Duplicate {
+    /// Whether the entries pin different versions, so only one of them can be honored.
+    #[must_use]
+    pub const fn conflicts(&self) -> bool {
+        self.versions.len() > 1
+    }
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.versions.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{} ({})", self.name, self.versions.join(", "))
+        }
+    }
+}
+
+/// How the YAML writers order packages, groups, and the other name lists (`sort:` setting).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Alphabetically, ignoring case; names that differ only in case keep a fixed order.
+    #[default]
+    Insensitive,
+    /// Alphabetically by byte value, so uppercase names come first.
+    Sensitive,
+    /// In the order the packages were found or declared.
+    #[serde(rename = "none")]
+    Unsorted,
+}
+
+impl SortOrder {
+    /// Compare two names in this order; [`SortOrder::Unsorted`] considers all names equal.
+    #[must_use]
+    pub fn compare(self, a: &str, b: &str) -> std::cmp::Ordering {
+        match self {
+            // Package names are ASCII, which is compared without allocating.
+            Self::Insensitive if a.is_ascii() && b.is_ascii() => a
+                .bytes()
+                .map(|byte| byte.to_ascii_lowercase())
+                .cmp(b.bytes().map(|byte| byte.to_ascii_lowercase()))
+                .then_with(|| a.cmp(b)),
+            Self::Insensitive => a
+                .to_lowercase()
+                .cmp(&b.to_lowercase())
+                .then_with(|| a.cmp(b)),
+            Self::Sensitive => a.cmp(b),
+            Self::Unsorted => std::cmp::Ordering::Equal,
+        }
+    }
+
+    /// Sort `names` in this order. The sort is stable.
+    pub fn sort(self, names: &mut [String]) {
+        names.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+impl PackageList {
+    /// Drop the packages, groups, and alternatives whose `when:` condition does not match
+    /// `host`.
+    pub fn retain_active(&mut self, host: &Host) {
+        self.packages
+            .retain(|_, pkg| condition::applies(pkg.when.as_ref(), host));
+        self.groups
+            .retain(|group| condition::applies(group.when.as_ref(), host));
+        self.alternatives
+            .retain(|alternatives| condition::applies(alternatives.when.as_ref(), host));
+    }
+
+    /// Create a list from packages and groups, merging repeated names like
+    /// [`PackageList::insert`].
+    #[must_use]
+    pub fn new(packages: impl IntoIterator<Item = Package>, groups: Vec<Group>) -> Self {
+        let mut list = Self {
+            groups,
+            ..Self::default()
+        };
+        list.extend(packages);
+        list
+    }
+
+    /// The package declared as `name`.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Package> {
+        self.packages.get(name)
+    }
+
+    /// Whether a package named `name` is declared.
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.packages.contains_key(name)
+    }
+
+    /// The number of packages declared by name; groups and alternatives are not counted.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    /// Whether no package is declared by name.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// The packages in declaration order.
+    #[must_use]
+    pub fn iter(&self) -> indexmap::map::Values<'_, String, Package> {
+        self.packages.values()
+    }
+
+    /// Add `pkg`, or merge it into the entry already declared under its name and return
+    /// `false`.
+    ///
+    /// The first entry keeps its version, origin, install time, and reason, and only takes the
+    /// version, origin, and install time of `pkg` if it has none. It applies wherever either
+    /// entry does: it loses its `when:` condition if `pkg` has none, and otherwise matches
+    /// either condition ([`Condition::or`]). The merge is recorded in
+    /// [`PackageList::duplicates`].
+    pub fn insert(&mut self, pkg: Package) -> bool {
+        let Some(first) = self.packages.get_mut(&pkg.name) else {
+            self.packages.insert(pkg.name.clone(), pkg);
+            return true;
+        };
+        let index = self
+            .duplicates
+            .iter()
+            .position(|dup| dup.name == pkg.name)
+            .unwrap_or_else(|| {
+                self.duplicates.push(Duplicate {
+                    name: pkg.name.clone(),
+                    versions: first.version.iter().map(ToString::to_string).collect(),
+                });
+                self.duplicates.len() - 1
+            });
+        let versions = &mut self.duplicates[index].versions;
+        if let Some(version) = pkg.version.as_ref().map(ToString::to_string)
+            && !versions.contains(&version)
+        {
+            versions.push(version);
+        }
+        if first.version.is_none() && first.requirement.is_none() {
+            first.version = pkg.version;
+            first.requirement = pkg.requirement;
+        }
+        if first.origin.is_none() {
+            first.origin = pkg.origin;
+        }
+        if first.installed_at.is_none() {
+            first.installed_at = pkg.installed_at;
+        }
+        first.when = match (first.when.take(), pkg.when) {
+            (Some(when), Some(other)) => Some(when.or(other)),
+            _ => None,
+        };
+        false
+    }
+
+    /// The packages that were declared more than once, in the order they first appear.
+    #[must_use]
+    pub fn duplicates(&self) -> &[Duplicate] {
+        &self.duplicates
+    }
+
+    /// Take the record of merged duplicates, e.g. after warning about them. Repeated entries
+    /// are merged as they are added, so the packages themselves are already unique.
+    pub fn dedup(&mut self) -> Vec<Duplicate> {
+        std::mem::take(&mut self.duplicates)
+    }
+
+    /// Keep the entries of this list whose name `keep` accepts, given whether `other` declares it.
+    fn select(&self, other: &Self, keep: impl Fn(bool) -> bool) -> Self {
+        Self {
+            packages: self
+                .packages
+                .iter()
+                .filter(|(name, _)| keep(other.contains(name)))
+                .map(|(name, pkg)| (name.clone(), pkg.clone()))
+                .collect(),
+            groups: self
+                .groups
+                .iter()
+                .filter(|group| keep(other.groups.iter().any(|g| g.name == group.name)))
+                .cloned()
+                .collect(),
+            alternatives: self
+                .alternatives
+                .iter()
+                .filter(|alternatives| keep(other.alternatives.contains(alternatives)))
+                .cloned()
+                .collect(),
+            duplicates: Vec::new(),
+        }
+    }
+
+    /// Every entry of this list, followed by the entries of `other` whose name it does not
+    /// declare. Where both declare a name, this list's entries win.
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = self.clone();
+        let added = other.difference(self);
+        union.packages.extend(added.packages);
+        union.groups.extend(added.groups);
+        union.alternatives.extend(added.alternatives);
+        union
+    }
+
+    /// The entries of this list whose name `other` does not declare.
+    #[must_use]
+    pub fn difference(&self, other: &Self) -> Self {
+        self.select(other, |declared| !declared)
+    }
+
+    /// The entries of this list whose name `other` declares too.
+    #[must_use]
+    pub fn intersection(&self, other: &Self) -> Self {
+        self.select(other, |declared| declared)
+    }
+
+    /// Like [`PackageList::union`], but an entry without a version takes the version (and
+    /// origin) of `other`'s entry for the same name, so pins survive merging with an unpinned
+    /// list.
+    #[must_use]
+    pub fn merge_preferring_versions(&self, other: &Self) -> Self {
+        let mut merged = self.union(other);
+        for pkg in merged.packages.values_mut() {
+            if pkg.version.is_some() {
+                continue;
+            }
+            if let Some(pinned) = other.get(&pkg.name)
+                && pinned.version.is_some()
+            {
+                pkg.version.clone_from(&pinned.version);
+                pkg.origin.clone_from(&pinned.origin);
+            }
+        }
+        merged
+    }
+
+    /// The pinned packages of this list whose version in `installed` differs from the pin, as
+    /// `(declared, installed)` pairs. A missing epoch matches epoch 0, and packages missing
+    /// from `installed` are not included.
+    #[must_use]
+    pub fn version_mismatches<'a>(
+        &'a self,
+        installed: &'a Self,
+    ) -> Vec<(&'a Package, &'a Package)> {
+        self.packages
+            .values()
+            .filter_map(|declared| {
+                let pinned = declared.version.as_ref()?;
+                let current = installed.get(&declared.name)?;
+                let same = current
+                    .version
+                    .as_ref()
+                    .is_some_and(|version| version.relation_to(pinned) == VersionRelation::Same);
+                (!same).then_some((declared, current))
+            })
+            .collect()
+    }
+
+    /// Sort the packages and groups by name, and the alternatives by their first name.
+    pub fn sort(&mut self, order: SortOrder) {
+        self.packages.sort_by(|a, _, b, _| order.compare(a, b));
+        self.groups.sort_by(|a, b| order.compare(&a.name, &b.name));
+        self.alternatives.sort_by(|a, b| {
+            order.compare(
+                a.names.first().map_or("", String::as_str),
+                b.names.first().map_or("", String::as_str),
+            )
+        });
+    }
+}
+
+impl std::ops::Index<&str> for PackageList {
+    type Output = Package;
+
+    /// The package declared as `name`; panics if there is none, see [`PackageList::get`].
+    fn index(&self, name: &str) -> &Package {
+        self.get(name)
+            .unwrap_or_else(|| panic!("no package named `{name}` in the list"))
+    }
+}
+
+impl IntoIterator for PackageList {
+    type Item = Package;
+    type IntoIter = indexmap::map::IntoValues<String, Package>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.packages.into_values()
+    }
+}
+
+impl<'a> IntoIterator for &'a PackageList {
+    type Item = &'a Package;
+    type IntoIter = indexmap::map::Values<'a, String, Package>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Repeated names are merged as by [`PackageList::insert`].
+impl FromIterator<Package> for PackageList {
+    fn from_iter<I: IntoIterator<Item = Package>>(iter: I) -> Self {
+        Self::new(iter, Vec::new())
+    }
+}
+
+/// Repeated names are merged as by [`PackageList::insert`].
+impl Extend<Package> for PackageList {
+    fn extend<I: IntoIterator<Item = Package>>(&mut self, iter: I) {
+        for pkg in iter {
+            self.insert(pkg);
+        }
+    }
+}
+
+impl Package {
+    /// An explicit package without a version, condition, or origin.
+    #[must_use]
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: None,
+            requirement: None,
+            reason: InstallReason::Explicit,
+            when: None,
+            origin: None,
+            installed_at: None,
+        }
+    }
+
+    /// An explicit package pinned to `version`.
+    #[must_use]
+    pub fn versioned(name: impl Into<String>, version: impl Into<FullVersion>) -> Self {
+        Self {
+            version: Some(version.into()),
+            ..Self::new(name)
+        }
+    }
+
+    /// Whether `installed` satisfies this entry: it has the same name and, if this entry is
+    /// pinned, the pinned version (a missing epoch matches epoch 0), or if it has a
+    /// requirement, a version meeting it.
+    #[must_use]
+    pub fn matches(&self, installed: &Self) -> bool {
+        if installed.name != self.name {
+            return false;
+        }
+        match (&self.version, &self.requirement, &installed.version) {
+            (None, None, _) => true,
+            (Some(pinned), _, Some(version)) => {
+                version.relation_to(pinned) == VersionRelation::Same
+            }
+            (None, Some(requirement), Some(version)) => requirement.matches(&version.to_string()),
+            (_, _, None) => false,
+        }
+    }
+}
+
+/// `name`, followed by the pinned version or the requirement if there is one, as in
+/// `vim 9.1.0-1` or `vim >=9.1`.
+impl fmt::Display for Package {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if let Some(version) = &self.version {
+            write!(f, " {version}")
+        } else if let Some(requirement) = &self.requirement {
+            write!(f, " {requirement}")
+        } else {
+            Ok(())
+        }
+    }
 }
 
 impl From<&str> for Package {
     fn from(s: &str) -> Self {
         let mut parts = s.splitn(2, ' ');
-        let name = parts.next().unwrap_or("").to_string();
-        let version_str = parts.next().unwrap_or("");
-        let version = if version_str.is_empty() {
-            None
-        } else {
-            Some(FullVersion::from(version_str))
-        };
-        Self { name, version }
+        let name = parts.next().unwrap_or("");
+        parts
+            .next()
+            .filter(|version| !version.is_empty())
+            .map_or_else(|| Self::new(name), |version| Self::versioned(name, version))
     }
 }
 
@@ -68,13 +700,40 @@ impl serde::Serialize for Package {
     where
         S: Serializer,
     {
-        if self.version.is_none() {
+        let dependency = self.reason == InstallReason::Dependency;
+        let versioned = self.version.is_some() || self.requirement.is_some();
+        if !versioned
+            && !dependency
+            && self.when.is_none()
+            && self.origin.is_none()
+            && self.installed_at.is_none()
+        {
             serializer.serialize_str(&self.name)
         } else {
-            let mut state = serializer.serialize_struct("Package", 2)?;
+            let len = 1
+                + usize::from(versioned)
+                + usize::from(dependency)
+                + usize::from(self.when.is_some())
+                + usize::from(self.origin.is_some())
+                + usize::from(self.installed_at.is_some());
+            let mut state = serializer.serialize_struct("Package", len)?;
             state.serialize_field("name", &self.name)?;
             if let Some(ref v) = self.version {
                 state.serialize_field("version", v)?;
+            } else if let Some(ref requirement) = self.requirement {
+                state.serialize_field("version", requirement)?;
+            }
+            if dependency {
+                state.serialize_field("reason", &self.reason)?;
+            }
+            if let Some(ref when) = self.when {
+                state.serialize_field("when", when)?;
+            }
+            if let Some(ref origin) = self.origin {
+                state.serialize_field("origin", origin)?;
+            }
+            if let Some(ref installed_at) = self.installed_at {
+                state.serialize_field("installed_at", installed_at)?;
             }
             state.end()
         }
@@ -86,51 +745,184 @@ impl<'de> serde::Deserialize<'de> for Package {
     where
         D: Deserializer<'de>,
     {
-        struct PackageVisitor;
-        impl<'de> Visitor<'de> for PackageVisitor {
-            type Value = Package;
+        match Entry::deserialize(deserializer)? {
+            Entry::Package(package) => Ok(package),
+            Entry::Group(_) | Entry::Alternatives(_) => Err(de::Error::missing_field("name")),
+        }
+    }
+}
+
+/// One entry of the `packages:` list: a package, a group given as `group: <name>`, or
+/// alternatives given as `one-of: [<name>, ...]`.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Entry<G, P, A> {
+    Group(G),
+    Package(P),
+    Alternatives(A),
+}
+
+/// The `version:` of a package entry: an exact pin, or a requirement if it starts with an
+/// operator (see [`VersionReq::is_requirement`]).
+enum VersionField {
+    Exact(FullVersion),
+    Requirement(VersionReq),
+}
+
+impl<'de> serde::Deserialize<'de> for VersionField {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        if VersionReq::is_requirement(&text) {
+            text.parse()
+                .map(Self::Requirement)
+                .map_err(de::Error::custom)
+        } else {
+            text.parse().map(Self::Exact).map_err(de::Error::custom)
+        }
+    }
+}
+
+/// Entries are read by hand rather than as an untagged enum, so errors inside an entry keep
+/// their position in the file.
+impl<'de> serde::Deserialize<'de> for Entry<Group, Package, Alternatives> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct EntryVisitor;
+        impl<'de> Visitor<'de> for EntryVisitor {
+            type Value = Entry<Group, Package, Alternatives>;
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a string or a map with name and optional version")
+                formatter.write_str(
+                    "a string, a map with name and optional version, reason, when, origin, and \
+                     installed_at, \
+                     a map with group and optional when, or a map with one-of and optional when",
+                )
             }
-            fn visit_str<E>(self, v: &str) -> Result<Package, E>
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
             where
                 E: de::Error,
             {
-                Ok(Package {
+                Ok(Entry::Package(Package {
                     name: v.to_string(),
                     version: None,
-                })
+                    requirement: None,
+                    reason: InstallReason::Explicit,
+                    when: None,
+                    origin: None,
+                    installed_at: None,
+                }))
             }
-            fn visit_map<M>(self, mut map: M) -> Result<Package, M::Error>
+            fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
             where
                 M: MapAccess<'de>,
             {
                 let mut name = None;
+                let mut group = None;
+                let mut one_of = None;
                 let mut version = None;
+                let mut reason = InstallReason::Explicit;
+                let mut when = None;
+                let mut origin = None;
+                let mut installed_at = None;
                 while let Some(key) = map.next_key::<String>()? {
                     match key.as_str() {
                         "name" => name = Some(map.next_value()?),
-                        "version" => version = Some(map.next_value()?),
+                        "group" => group = Some(map.next_value()?),
+                        "one-of" => one_of = Some(map.next_value::<Vec<String>>()?),
+                        "version" => version = Some(map.next_value::<VersionField>()?),
+                        "reason" => reason = map.next_value()?,
+                        "when" => when = Some(map.next_value()?),
+                        "origin" => origin = Some(map.next_value()?),
+                        "installed_at" => installed_at = Some(map.next_value()?),
                         _ => {
                             let _: serde::de::IgnoredAny = map.next_value()?;
                         }
                     }
                 }
+                if let Some(name) = group {
+                    return Ok(Entry::Group(Group { name, when }));
+                }
+                if let Some(names) = one_of {
+                    if names.is_empty() {
+                        return Err(de::Error::invalid_length(0, &"at least one package"));
+                    }
+                    return Ok(Entry::Alternatives(Alternatives { names, when }));
+                }
                 let name = name.ok_or_else(|| de::Error::missing_field("name"))?;
-                Ok(Package { name, version })
+                let (version, requirement) = match version {
+                    Some(VersionField::Exact(version)) => (Some(version), None),
+                    Some(VersionField::Requirement(requirement)) => (None, Some(requirement)),
+                    None => (None, None),
+                };
+                Ok(Entry::Package(Package {
+                    name,
+                    version,
+                    requirement,
+                    reason,
+                    when,
+                    origin,
+                    installed_at,
+                }))
+            }
+        }
+        deserializer.deserialize_any(EntryVisitor)
+    }
+}
+
+impl PackageList {
+    /// The packages, then the groups, then the alternatives, as `packages:` entries.
+    fn entries(&self) -> Vec<Entry<&Group, &Package, &Alternatives>> {
+        self.packages
+            .values()
+            .map(Entry::Package)
+            .chain(self.groups.iter().map(Entry::Group))
+            .chain(self.alternatives.iter().map(Entry::Alternatives))
+            .collect()
+    }
+
+    /// A list from `packages:` entries, merging repeated names.
+    fn from_entries(entries: Vec<Entry<Group, Package, Alternatives>>) -> Self {
+        let mut list = Self::default();
+        for entry in entries {
+            match entry {
+                Entry::Group(group) if !list.groups.contains(&group) => list.groups.push(group),
+                Entry::Alternatives(alternatives) if !list.alternatives.contains(&alternatives) => {
+                    list.alternatives.push(alternatives);
+                }
+                Entry::Group(_) | Entry::Alternatives(_) => {}
+                Entry::Package(package) => {
+                    list.insert(package);
+                }
             }
         }
-        deserializer.deserialize_any(PackageVisitor)
+        list
     }
 }
 
+/// Serialize a [`PackageList`] as the value of a `packages:` key.
+fn serialize_entries<S: Serializer>(list: &PackageList, serializer: S) -> Result<S::Ok, S::Error> {
+    list.entries().serialize(serializer)
+}
+
+/// Deserialize a [`PackageList`] from the value of a `packages:` key.
+pub(crate) fn deserialize_entries<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<PackageList, D::Error> {
+    Vec::<Entry<Group, Package, Alternatives>>::deserialize(deserializer)
+        .map(PackageList::from_entries)
+}
+
 impl serde::Serialize for PackageList {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         let mut map = serializer.serialize_struct("PackageList", 1)?;
-        map.serialize_field("packages", &self.packages)?;
+        map.serialize_field("packages", &self.entries())?;
         map.end()
     }
 }
@@ -142,28 +934,229 @@ impl<'de> serde::Deserialize<'de> for PackageList {
     {
         #[derive(Deserialize)]
         struct Helper {
-            packages: Vec<Package>,
+            packages: Vec<Entry<Group, Package, Alternatives>>,
         }
         let helper = Helper::deserialize(deserializer)?;
-        Ok(Self {
-            packages: helper.packages,
-        })
+        Ok(Self::from_entries(helper.packages))
+    }
+}
+
+/// The full contents of the YAML file: pacman packages plus any additional package sources.
+///
+/// Additional sources are optional; a missing section means that source is not managed.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// The format version of the file (`schema:`); see [`schema`]. Set by [`write_config`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<u32>,
+    /// Values the rest of the file refers to as `${name}` (`vars:` section); see [`vars`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vars: Option<BTreeMap<String, String>>,
+    #[serde(
+        serialize_with = "serialize_entries",
+        deserialize_with = "deserialize_entries"
+    )]
+    pub packages: PackageList,
+    /// Flatpak application IDs (`flatpak:` section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flatpak: Option<Vec<String>>,
+    /// Crates installed with `cargo install` (`cargo:` section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cargo: Option<Vec<String>>,
+    /// Applications installed with `pipx` (`pipx:` section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipx: Option<Vec<String>>,
+    /// Global npm packages (`npm:` section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub npm: Option<Vec<String>>,
+    /// Packages `apply` must never remove, on top of [`safety::DEFAULT_CRITICAL`]
+    /// (`critical:` section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub critical: Option<Vec<String>>,
+    /// Packages `apply` neither installs nor removes (`ignore:` section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore: Option<Vec<String>>,
+    /// Packages `apply` removes and never installs (`banned:` section); see [`banned`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banned: Option<Vec<String>>,
+    /// Defaults for command-line flags (`settings:` section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<settings::Settings>,
+    /// Additional pacman repositories (`repos:` section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repos: Option<Vec<repos::Repo>>,
+}
+
+impl Config {
+    /// Create a config holding only pacman packages, with no extra sources managed.
+    #[must_use]
+    pub const fn new(packages: PackageList) -> Self {
+        Self {
+            schema: None,
+            vars: None,
+            packages,
+            flatpak: None,
+            cargo: None,
+            pipx: None,
+            npm: None,
+            critical: None,
+            ignore: None,
+            banned: None,
+            settings: None,
+            repos: None,
+        }
+    }
+
+    /// The section for an extra source (see [`sources::ExtraSource::name`]), or `None` if it is
+    /// not managed or unknown.
+    #[must_use]
+    pub fn source(&self, name: &str) -> Option<&[String]> {
+        match name {
+            "flatpak" => self.flatpak.as_deref(),
+            "cargo" => self.cargo.as_deref(),
+            "pipx" => self.pipx.as_deref(),
+            "npm" => self.npm.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Sort the packages and the extra-source, `critical:`, `ignore:`, and `banned:` lists. Repositories
+    /// keep their order, which is their priority.
+    pub fn sort(&mut self, order: SortOrder) {
+        self.packages.sort(order);
+        for names in [
+            &mut self.flatpak,
+            &mut self.cargo,
+            &mut self.pipx,
+            &mut self.npm,
+            &mut self.critical,
+            &mut self.ignore,
+            &mut self.banned,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            order.sort(names);
+        }
+    }
+
+    /// The order [`write_config`] writes this config in: its `sort` setting, or else that of the
+    /// global settings.
+    #[must_use]
+    pub fn sort_order(&self) -> SortOrder {
+        self.settings
+            .as_ref()
+            .and_then(|settings| settings.sort)
+            .unwrap_or_else(global_sort_order)
+    }
+
+    /// Mutable access to the section for an extra source, or `None` for an unknown source name.
+    pub const fn source_mut(&mut self, name: &str) -> Option<&mut Option<Vec<String>>> {
+        match name.as_bytes() {
+            b"flatpak" => Some(&mut self.flatpak),
+            b"cargo" => Some(&mut self.cargo),
+            b"pipx" => Some(&mut self.pipx),
+            b"npm" => Some(&mut self.npm),
+            _ => None,
+        }
+    }
+}
+
+/// Read and parse the YAML file into a [`Config`], upgrading files of an older [`schema`].
+///
+/// # Errors
+/// Returns an error if the file cannot be read or parsed, or its schema is newer than
+/// [`schema::CURRENT`]. Parse errors name the file and position (see [`diagnostics`]).
+pub fn read_config<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Config> {
+    let yml_content = std::fs::read_to_string(&path)?;
+    schema::parse(&yml_content).map_err(|e| diagnostics::locate(path.as_ref(), &yml_content, e))
+}
+
+/// Read the YAML with its machine-local overrides (see [`local`]) merged in, and the `ignore`
+/// lists of the [`settings`] added to its `ignore:` section.
+///
+/// This is the config `apply`, `diff`, and the other read-only commands act on; commands that
+/// rewrite the YAML use [`read_config`] so local entries never leak into the shared file.
+/// Packages declared twice are merged when read (see [`PackageList::insert`]), so apply never
+/// asks pacman for two versions of one package.
+///
+/// # Errors
+/// Returns an error if either file cannot be read or parsed.
+#[cfg(target_os = "linux")]
+pub fn read_effective_config<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Config> {
+    let (mut config, _) = local::read_merged(path)?;
+    let global = settings::Settings::load(&settings::global_path())?;
+    let ignored = config
+        .settings
+        .clone()
+        .unwrap_or_default()
+        .or(global)
+        .ignore;
+    if !ignored.is_empty() {
+        let ignore = config.ignore.get_or_insert_with(Vec::new);
+        for name in ignored {
+            if !ignore.contains(&name) {
+                ignore.push(name);
+            }
+        }
     }
+    Ok(config)
+}
+
+/// Write a [`Config`] to a YAML file at the given path, in the current [`schema`].
+///
+/// Packages and name lists are sorted by the `sort` setting of the config, or else of the
+/// global settings (case-insensitive by default), so writing the same packages twice gives the
+/// same bytes.
+///
+/// # Errors
+/// Returns an error if the config cannot be serialized, or the file cannot be created or
+/// written.
+pub fn write_config<P: AsRef<std::path::Path>>(config: &Config, path: P) -> std::io::Result<()> {
+    std::fs::write(path, render_config(config)?)
+}
+
+/// The text [`write_config`] writes for `config`.
+///
+/// # Errors
+/// Returns an error if the config cannot be serialized.
+pub fn render_config(config: &Config) -> std::io::Result<String> {
+    let mut config = config.clone();
+    config.schema = Some(schema::CURRENT);
+    config.sort(config.sort_order());
+    to_yaml(&config)
+}
+
+/// The `sort` setting of the global settings file, or the default if it is unset or unreadable.
+fn global_sort_order() -> SortOrder {
+    settings::Settings::load(&settings::global_path())
+        .ok()
+        .and_then(|settings| settings.sort)
+        .unwrap_or_default()
+}
+
+/// Serialize `value`, ending in exactly one newline.
+fn to_yaml<T: Serialize>(value: &T) -> std::io::Result<String> {
+    let mut yml = serde_yml::to_string(value).map_err(std::io::Error::other)?;
+    yml.truncate(yml.trim_end().len());
+    yml.push('\n');
+    Ok(yml)
+}
+
+/// Serialize `value` and write it to `path`, ending in exactly one newline.
+fn write_yaml<T: Serialize, P: AsRef<std::path::Path>>(value: &T, path: P) -> std::io::Result<()> {
+    std::fs::write(path, to_yaml(value)?)
 }
 
-/// Ensures the XDG-compliant YML file exists. (~/.config/nixman/packages.yaml)
+/// Ensures the YML file of the active [profile] exists ([`profile::config_file`]).
 ///
 /// # Errors
 /// Returns an error if the config directory or file cannot be created or written.
 pub fn ensure_yml() -> std::io::Result<PathBuf> {
-    let mut path = PathBuf::from(std::env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| {
-        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-        format!("{home}/.config")
-    }));
-    path.push("nixman");
-    std::fs::create_dir_all(&path)?;
-
-    path.push("packages.yml");
+    let path = profile::config_file();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
     let existed = path.exists();
     if !existed {
         std::fs::write(&path, "")?;
@@ -172,140 +1165,1073 @@ pub fn ensure_yml() -> std::io::Result<PathBuf> {
     Ok(path)
 }
 
-/// Write a package list to a YAML file at the given path.
+/// Returns the XDG-compliant state directory ([`paths::state_dir`]), creating it if needed.
 ///
 /// # Errors
-/// Returns an error if the file cannot be created or written.
+/// Returns an error if the state directory cannot be created.
+pub fn state_dir() -> std::io::Result<PathBuf> {
+    paths::ensure_state_dir()
+}
+
+/// Write a package list to a YAML file at the given path, sorted like [`write_config`] by the
+/// global `sort` setting.
 ///
-/// # Panics
-/// Panics if serialization to YAML fails (should not happen for valid data).
+/// # Errors
+/// Returns an error if the list cannot be serialized, or the file cannot be created or
+/// written.
 pub fn write_package_list_to_yaml<P: AsRef<std::path::Path>>(
     package_list: &PackageList,
     path: P,
 ) -> std::io::Result<()> {
-    let yml = serde_yml::to_string(package_list).expect("Failed to serialize to YAML");
-    let mut file = std::fs::File::create(path)?;
-    file.write_all(yml.as_bytes())?;
-    Ok(())
+    let mut package_list = package_list.clone();
+    package_list.sort(global_sort_order());
+    write_yaml(&package_list, path)
 }
 
-/// Parse the output of `pacman -Qe` into a `PackageList`, optionally versioned.
-pub fn parse_explicit_packages(output: &str, versioned: bool) -> PackageList {
-    let packages: Vec<Package> = if versioned {
-        output.lines().map(Package::from).collect()
-    } else {
-        output
-            .lines()
-            .map(|line| {
-                let name = line.split_whitespace().next().unwrap_or("").to_string();
-                Package {
-                    name,
-                    version: None,
-                }
-            })
-            .collect()
-    };
-    PackageList { packages }
+/// Parse the output of `pacman -Qe`, as text or straight from `Output::stdout`, into a
+/// `PackageList`, optionally versioned. Lines that are not `name version`, e.g. warnings, are
+/// skipped.
+#[must_use]
+pub fn parse_explicit_packages<T: AsRef<[u8]> + ?Sized>(
+    output: &T,
+    versioned: bool,
+) -> PackageList {
+    PackageList::new(
+        crate::pacman::parse_package_lines(output).map(|package| package.to_package(versioned)),
+        Vec::new(),
+    )
+}
+
+/// List the names of explicitly installed packages (`pacman -Qe`).
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn installed_explicit_packages() -> std::io::Result<Vec<String>> {
+    let output = crate::pacman::pacman_list_explicit()?;
+    Ok(crate::pacman::parse_package_lines(&output.stdout)
+        .map(|package| package.name.to_string())
+        .collect())
+}
+
+/// The explicitly installed packages with their versions (`pacman -Qe`), as
+/// [`sync_packages_from_yaml`] and [`diff_packages`] take them.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn installed_explicit_package_list() -> std::io::Result<PackageList> {
+    Ok(parse_explicit_packages(
+        &crate::pacman::pacman_list_explicit()?.stdout,
+        true,
+    ))
 }
 
 /// Synchronize installed packages with the list in the YAML file.
 ///
+/// `installed_packages` is usually [`installed_explicit_package_list`], or
+/// [`parse_explicit_packages`] of a saved `pacman -Qe`; only the names are compared here, and
+/// [`diff_packages`] compares the versions too.
+///
 /// # Returns
 /// `(to_install, to_remove)` as `Vec<String>` of package names.
 ///
 /// # Errors
 /// Returns an error if the YAML file cannot be read or parsed.
+#[cfg(target_os = "linux")]
 pub fn sync_packages_from_yaml<P: AsRef<std::path::Path>>(
     yml_path: P,
-    installed_packages: &[String],
+    installed_packages: &PackageList,
+) -> std::io::Result<(Vec<String>, Vec<String>)> {
+    sync_packages(read_effective_config(&yml_path)?, installed_packages)
+}
+
+/// [`sync_packages_from_yaml`] for an already read config.
+#[cfg(target_os = "linux")]
+fn sync_packages(
+    config: Config,
+    installed_packages: &PackageList,
 ) -> std::io::Result<(Vec<String>, Vec<String>)> {
-    let yml_content = std::fs::read_to_string(&yml_path)?;
-    let package_list: PackageList = serde_yml::from_str(&yml_content)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-    let installed: std::collections::HashSet<String> = installed_packages.iter().cloned().collect();
-    let wanted: std::collections::HashSet<String> = package_list
+    let ignored = config.ignore.unwrap_or_default();
+    let banned = config.banned.unwrap_or_default();
+    let mut package_list = config.packages;
+    package_list.retain_active(&Host::current());
+    let installed: HashSet<&str> = installed_packages
         .packages
-        .iter()
-        .map(|pkg| pkg.name.clone())
+        .keys()
+        .map(String::as_str)
         .collect();
-    let to_install: Vec<String> = wanted.difference(&installed).cloned().collect();
-    let to_remove: Vec<String> = installed.difference(&wanted).cloned().collect();
+    // Groups only need to be looked up when some declared name could be one.
+    let may_have_groups = !package_list.groups.is_empty()
+        || package_list
+            .packages
+            .keys()
+            .any(|name| !installed.contains(name.as_str()));
+    let groups = if may_have_groups {
+        available_groups().or_else(|e| {
+            if package_list.groups.is_empty() {
+                Ok(BTreeMap::new())
+            } else {
+                Err(e)
+            }
+        })?
+    } else {
+        BTreeMap::new()
+    };
+    let mut wanted = expand_groups_in(&package_list, &installed, &groups);
+    if wanted
+        .iter()
+        .any(|(name, _)| !installed.contains(name.as_str()))
+    {
+        let providers = provides::installed_providers().unwrap_or_default();
+        wanted = provides::resolve_provides_in(wanted, &installed, &providers);
+    }
+    let (mut to_install, mut to_remove) = package_changes_in(&wanted, &installed, &ignored);
+    // Banned packages are removed however they were installed, and never installed.
+    banned::enforce(
+        &banned,
+        &banned::installed(&banned)?,
+        &mut to_install,
+        &mut to_remove,
+    );
     Ok((to_install, to_remove))
 }
 
-/// Apply the YAML configuration to synchronize installed packages.
-///
-/// - `yml_path`: Path to the YAML file
-/// - `use_paru`: Use paru instead of pacman
-/// - `continue_on_error`: Continue on errors (try all packages, don't abort on first failure)
-///
+/// How an installed package differs from the YAML; see [`DiffEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    /// Declared but not installed: `apply` installs it.
+    Missing,
+    /// Installed but not declared: `apply` removes it.
+    Extra,
+    /// Installed at another version than the one pinned.
+    VersionMismatch,
+}
+
+/// One difference between the YAML and the installed packages, with the versions on both
+/// sides.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffEntry {
+    pub name: String,
+    /// The version pinned in the YAML, if any.
+    pub desired: Option<FullVersion>,
+    /// The installed version, if it is installed.
+    pub installed: Option<FullVersion>,
+    pub kind: DiffKind,
+}
+
+/// Combine the changes [`package_changes`] computed with the pins of `declared` that
+/// `installed` does not match, sorted by name.
+#[must_use]
+pub fn diff_entries(
+    declared: &PackageList,
+    installed: &PackageList,
+    to_install: &[String],
+    to_remove: &[String],
+) -> Vec<DiffEntry> {
+    let version =
+        |list: &PackageList, name: &str| list.get(name).and_then(|pkg| pkg.version.clone());
+    let mut entries: Vec<DiffEntry> = to_install
+        .iter()
+        .map(|name| DiffEntry {
+            name: name.clone(),
+            desired: version(declared, name),
+            installed: None,
+            kind: DiffKind::Missing,
+        })
+        .chain(to_remove.iter().map(|name| DiffEntry {
+            name: name.clone(),
+            desired: None,
+            installed: version(installed, name),
+            kind: DiffKind::Extra,
+        }))
+        .chain(
+            declared
+                .version_mismatches(installed)
+                .into_iter()
+                .map(|(declared, current)| DiffEntry {
+                    name: declared.name.clone(),
+                    desired: declared.version.clone(),
+                    installed: current.version.clone(),
+                    kind: DiffKind::VersionMismatch,
+                }),
+        )
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+/// Compare the YAML with the `installed` packages, with their versions.
+///
+/// The entries are what [`sync_packages_from_yaml`] would install and remove, and the pinned
+/// packages installed at another version. `installed` is usually
+/// [`installed_explicit_package_list`].
+///
 /// # Errors
-/// Returns `Err(String)` with a summary of failed packages or IO errors.
-pub fn apply_packages_from_yaml<P: AsRef<std::path::Path>>(
+/// Returns an error if the YAML file cannot be read or parsed.
+#[cfg(target_os = "linux")]
+pub fn diff_packages<P: AsRef<std::path::Path>>(
     yml_path: P,
-    use_paru: bool,
-    continue_on_error: bool,
-) -> Result<(), String> {
-    let installed_output = crate::pacman::pacman_list_explicit().map_err(|e| e.to_string())?;
-    let installed_str = String::from_utf8_lossy(&installed_output.stdout);
-    let installed: Vec<String> = installed_str
-        .lines()
-        .map(|line| line.split_whitespace().next().unwrap_or("").to_string())
+    installed: &PackageList,
+) -> std::io::Result<Vec<DiffEntry>> {
+    let config = read_effective_config(&yml_path)?;
+    let mut declared = config.packages.clone();
+    declared.retain_active(&Host::current());
+    let (to_install, to_remove) = sync_packages(config, installed)?;
+    Ok(diff_entries(&declared, installed, &to_install, &to_remove))
+}
+
+/// Compute `(to_install, to_remove)` from the [expanded](expand_groups) wanted packages.
+///
+/// Declared dependencies are kept but never installed explicitly, and `ignored` packages are
+/// left alone either way. Both lists are sorted.
+#[must_use]
+pub fn package_changes(
+    wanted: &[(String, InstallReason)],
+    installed: &[String],
+    ignored: &[String],
+) -> (Vec<String>, Vec<String>) {
+    package_changes_in(
+        wanted,
+        &installed.iter().map(String::as_str).collect(),
+        ignored,
+    )
+}
+
+/// [`package_changes`] given the set of installed names.
+fn package_changes_in(
+    wanted: &[(String, InstallReason)],
+    installed_names: &HashSet<&str>,
+    ignored: &[String],
+) -> (Vec<String>, Vec<String>) {
+    let wanted_names: HashSet<&str> = wanted.iter().map(|(name, _)| name.as_str()).collect();
+    let ignored: HashSet<&str> = ignored.iter().map(String::as_str).collect();
+    // Only the names that end up in the result are cloned.
+    let to_install: BTreeSet<&str> = wanted
+        .iter()
+        .filter(|(name, reason)| {
+            *reason == InstallReason::Explicit
+                && !installed_names.contains(name.as_str())
+                && !ignored.contains(name.as_str())
+        })
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let to_remove: BTreeSet<&str> = installed_names
+        .iter()
+        .copied()
+        .filter(|name| !wanted_names.contains(name) && !ignored.contains(name))
         .collect();
-    let (to_install, to_remove) =
-        crate::sync_packages_from_yaml(&yml_path, &installed).map_err(|e| e.to_string())?;
-    if to_install.is_empty() && to_remove.is_empty() {
-        return Ok(());
+    (
+        to_install.into_iter().map(str::to_string).collect(),
+        to_remove.into_iter().map(str::to_string).collect(),
+    )
+}
+
+/// Parse the output of `pacman -Sg` (`group package` lines) into each group's members.
+#[must_use]
+pub fn parse_groups(output: &str) -> BTreeMap<String, Vec<String>> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (group, package) in output.lines().filter_map(|line| line.split_once(' ')) {
+        groups
+            .entry(group.to_string())
+            .or_default()
+            .push(package.trim().to_string());
     }
-    let mut failed_removals = Vec::new();
-    let mut failed_installs = Vec::new();
-    if !to_remove.is_empty() {
-        if continue_on_error {
-            for pkg in &to_remove {
-                let status = if use_paru {
-                    crate::pacman::paru_remove(&[pkg.clone()]).map_err(|e| e.to_string())?
-                } else {
-                    crate::pacman::pacman_remove(&[pkg.clone()], true).map_err(|e| e.to_string())?
-                };
-                if !status.success() {
-                    failed_removals.push(pkg.clone());
-                }
-            }
+    groups
+}
+
+/// All package groups in the sync databases with their members (`pacman -Sg`).
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn available_groups() -> std::io::Result<BTreeMap<String, Vec<String>>> {
+    let output = crate::pacman::pacman_list_groups()?;
+    Ok(parse_groups(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The packages a list stands for, with the reason each is wanted.
+///
+/// Groups are replaced by their members, both `group:` entries and plain entries that name a
+/// group rather than an installed package (e.g. `- base-devel`). Unknown groups are kept as-is
+/// so pacman reports them. [`Alternatives`] stand for their installed names, or else the first
+/// one.
+#[must_use]
+pub fn expand_groups(
+    list: &PackageList,
+    installed: &[String],
+    groups: &BTreeMap<String, Vec<String>>,
+) -> Vec<(String, InstallReason)> {
+    expand_groups_in(
+        list,
+        &installed.iter().map(String::as_str).collect(),
+        groups,
+    )
+}
+
+/// [`expand_groups`] given the set of installed names.
+fn expand_groups_in(
+    list: &PackageList,
+    installed_names: &HashSet<&str>,
+    groups: &BTreeMap<String, Vec<String>>,
+) -> Vec<(String, InstallReason)> {
+    let members = |name: &str, reason: InstallReason| -> Vec<(String, InstallReason)> {
+        groups.get(name).map_or_else(
+            || vec![(name.to_string(), reason)],
+            |members| members.iter().map(|m| (m.clone(), reason)).collect(),
+        )
+    };
+    let mut wanted = Vec::with_capacity(list.packages.len());
+    for pkg in list.packages.values() {
+        if installed_names.contains(pkg.name.as_str()) {
+            wanted.push((pkg.name.clone(), pkg.reason));
         } else {
-            let status = if use_paru {
-                crate::pacman::paru_remove(&to_remove).map_err(|e| e.to_string())?
-            } else {
-                crate::pacman::pacman_remove(&to_remove, true).map_err(|e| e.to_string())?
-            };
-            if !status.success() {
-                return Err("Failed to remove some packages".to_string());
+            wanted.extend(members(&pkg.name, pkg.reason));
+        }
+    }
+    for group in &list.groups {
+        wanted.extend(members(&group.name, InstallReason::Explicit));
+    }
+    for alternatives in &list.alternatives {
+        wanted.extend(
+            alternatives
+                .chosen_in(installed_names)
+                .into_iter()
+                .map(|name| (name, InstallReason::Explicit)),
+        );
+    }
+    wanted
+}
+
+/// Record `reason` for the given packages in the YAML, adding entries for undeclared ones.
+///
+/// # Errors
+/// Returns an error if the YAML file cannot be read, parsed, or written.
+#[cfg(target_os = "linux")]
+pub fn set_install_reason<P: AsRef<std::path::Path>>(
+    yml_path: P,
+    names: &[String],
+    reason: InstallReason,
+) -> std::io::Result<()> {
+    let mut config = read_config(&yml_path)?;
+    for name in names {
+        match config.packages.packages.get_mut(name) {
+            Some(pkg) => pkg.reason = reason,
+            None => {
+                config.packages.insert(Package {
+                    reason,
+                    ..Package::new(name.clone())
+                });
             }
         }
     }
-    if !to_install.is_empty() {
-        if continue_on_error {
-            for pkg in &to_install {
-                let status = if use_paru {
-                    crate::pacman::paru_install(&[pkg.clone()]).map_err(|e| e.to_string())?
-                } else {
-                    crate::pacman::pacman_install(&[pkg.clone()], true)
-                        .map_err(|e| e.to_string())?
-                };
-                if !status.success() {
-                    failed_installs.push(pkg.clone());
-                }
+    write_config(&config, yml_path)
+}
+
+/// Compute `(to_install, to_remove)` between the wanted and installed names.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn diff_names(wanted: &[String], installed: &[String]) -> (Vec<String>, Vec<String>) {
+    // Only the names that end up in the result are cloned.
+    let missing = |names: &[String], from: &[String]| -> Vec<String> {
+        let from: HashSet<&str> = from.iter().map(String::as_str).collect();
+        let names: BTreeSet<&str> = names
+            .iter()
+            .map(String::as_str)
+            .filter(|name| !from.contains(name))
+            .collect();
+        // Sorted so results are stable across runs and comparable.
+        names.into_iter().map(str::to_string).collect()
+    };
+    (missing(wanted, installed), missing(installed, wanted))
+}
+
+/// Synchronize an extra source with its section of the YAML file.
+///
+/// Returns two empty lists when the YAML has no section for the source, since extra sources are opt-in.
+///
+/// # Returns
+/// `(to_install, to_remove)` as `Vec<String>` of package names.
+///
+/// # Errors
+/// Returns an error if the YAML file cannot be read or parsed.
+#[cfg(target_os = "linux")]
+pub fn sync_source_from_yaml<P: AsRef<std::path::Path>>(
+    yml_path: P,
+    source: &str,
+    installed: &[String],
+) -> std::io::Result<(Vec<String>, Vec<String>)> {
+    let config = read_effective_config(yml_path)?;
+    Ok(config
+        .source(source)
+        .map_or_else(Default::default, |wanted| diff_names(wanted, installed)))
+}
+
+/// The changes `apply` would make for one package source.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct SourceChanges {
+    /// `"pacman"` for pacman packages, otherwise the name of the extra source (e.g. `"flatpak"`).
+    pub source: &'static str,
+    pub to_install: Vec<String>,
+    pub to_remove: Vec<String>,
+}
+
+impl SourceChanges {
+    /// Whether this source is already in sync.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.to_install.is_empty() && self.to_remove.is_empty()
+    }
+
+    /// Only the changes an apply in `mode` would make.
+    #[must_use]
+    pub fn restricted(self, mode: ApplyMode) -> Self {
+        let (to_install, to_remove) = mode.restrict((self.to_install, self.to_remove));
+        Self {
+            to_install,
+            to_remove,
+            ..self
+        }
+    }
+}
+
+/// Compute the changes `apply` would make for pacman and every managed extra source.
+///
+/// # Errors
+/// Returns an error if the YAML file cannot be read or parsed, or a package manager could not be queried.
+#[cfg(target_os = "linux")]
+pub fn pending_changes<P: AsRef<std::path::Path>>(
+    yml_path: P,
+) -> std::io::Result<Vec<SourceChanges>> {
+    let installed = installed_explicit_package_list()?;
+    let (to_install, to_remove) = sync_packages_from_yaml(&yml_path, &installed)?;
+    let mut changes = vec![SourceChanges {
+        source: "pacman",
+        to_install,
+        to_remove,
+    }];
+    let config = read_effective_config(&yml_path)?;
+    for source in sources::builtin() {
+        if let Some(wanted) = config.source(source.name()) {
+            let (to_install, to_remove) = diff_names(wanted, &source.list()?);
+            changes.push(SourceChanges {
+                source: source.name(),
+                to_install,
+                to_remove,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// Error returned when applying the YAML fails.
+///
+/// `message` is the human-readable summary; the failed package lists are filled in when
+/// `continue_on_error` was used, so callers can report them individually. `output` holds the
+/// last lines pacman/paru printed for each failed run.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ApplyError {
+    pub message: String,
+    pub failed_removals: Vec<String>,
+    pub failed_installs: Vec<String>,
+    pub output: Vec<FailureOutput>,
+    /// Whether the apply stopped early because its [`CancelToken`] was cancelled.
+    pub cancelled: bool,
+    /// The steps run before the apply stopped, including the failed ones. Not serialized with
+    /// the error; serialize it separately where a report is wanted.
+    #[serde(skip)]
+    pub report: Box<ApplyReport>,
+}
+
+/// The trailing output of a failed pacman/paru run and the packages it was run for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailureOutput {
+    pub packages: Vec<String>,
+    pub lines: Vec<String>,
+}
+
+impl ApplyError {
+    /// Attach the output of failed runs, appending it to the message.
+    #[must_use]
+    pub fn with_output(mut self, output: Vec<FailureOutput>) -> Self {
+        use std::fmt::Write as _;
+        for failure in output.iter().filter(|f| !f.lines.is_empty()) {
+            if !self.message.ends_with('\n') {
+                self.message.push('\n');
             }
+            let _ = writeln!(self.message, "Output for {}:", failure.packages.join(", "));
+            for line in &failure.lines {
+                let _ = writeln!(self.message, "    {line}");
+            }
+        }
+        self.output.extend(output);
+        self
+    }
+
+    /// Attach the steps run before the failure, ahead of any the error already carries.
+    #[must_use]
+    pub fn with_report(mut self, mut report: ApplyReport) -> Self {
+        report.append(std::mem::take(&mut *self.report));
+        *self.report = report;
+        self
+    }
+}
+
+impl From<String> for ApplyError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            ..Self::default()
+        }
+    }
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApplyError {}
+
+/// Apply the YAML section of an extra source.
+///
+/// Does nothing when the YAML has no section for the source.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors.
+#[cfg(target_os = "linux")]
+pub fn apply_source_from_yaml<P: AsRef<std::path::Path>>(
+    yml_path: P,
+    source: &dyn sources::ExtraSource,
+    continue_on_error: bool,
+    noconfirm: bool,
+    mode: ApplyMode,
+) -> Result<ApplyReport, ApplyError> {
+    let config = read_effective_config(&yml_path).map_err(|e| e.to_string())?;
+    let Some(wanted) = config.source(source.name()) else {
+        return Ok(ApplyReport::default());
+    };
+    let installed = source.list().map_err(|e| e.to_string())?;
+    let (to_install, to_remove) = mode.restrict(diff_names(wanted, &installed));
+    execute_source_changes(
+        source,
+        &to_install,
+        &to_remove,
+        continue_on_error,
+        noconfirm,
+    )
+}
+
+/// Remove and then install the given packages of an extra source; with `noconfirm`, the
+/// source's tool is told not to ask.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors.
+#[cfg(target_os = "linux")]
+pub fn execute_source_changes(
+    source: &dyn sources::ExtraSource,
+    to_install: &[String],
+    to_remove: &[String],
+    continue_on_error: bool,
+    noconfirm: bool,
+) -> Result<ApplyReport, ApplyError> {
+    let mut report = ApplyReport::default();
+    // Extra sources install from the network, so offline they are left as they are.
+    if offline::is_offline() {
+        if !to_install.is_empty() || !to_remove.is_empty() {
+            eprintln!("Offline: leaving the {} packages alone", source.name());
+        }
+        return Ok(report);
+    }
+    let mut failed_removals = Vec::new();
+    let mut failed_installs = Vec::new();
+    let steps = batches(to_remove, continue_on_error)
+        .map(|batch| (PackageAction::Remove, batch))
+        .chain(batches(to_install, continue_on_error).map(|batch| (PackageAction::Install, batch)));
+    for (action, packages) in steps {
+        let started = Instant::now();
+        let status = match action {
+            PackageAction::Install => source.install(packages, noconfirm),
+            PackageAction::Remove => source.remove(packages, noconfirm),
+        };
+        let status = match status {
+            Ok(status) => status,
+            Err(e) => return Err(ApplyError::from(e.to_string()).with_report(report)),
+        };
+        report.steps.push(Step::finished(
+            source.name(),
+            action,
+            packages,
+            status,
+            started,
+            Vec::new(),
+        ));
+        if status.success() {
+            continue;
+        }
+        if !continue_on_error {
+            return Err(ApplyError::from(format!(
+                "Failed to {} some {} packages",
+                action.verb(),
+                source.name()
+            ))
+            .with_report(report));
+        }
+        match action {
+            PackageAction::Install => failed_installs.extend_from_slice(packages),
+            PackageAction::Remove => failed_removals.extend_from_slice(packages),
+        }
+    }
+    match failure_summary(
+        &format!("{} packages", source.name()),
+        &failed_removals,
+        &failed_installs,
+    ) {
+        Ok(()) => Ok(report),
+        Err(e) => Err(e.with_report(report)),
+    }
+}
+
+/// Install the explicit packages declared in the YAML into a new system mounted at `root` with
+/// `pacstrap -K`.
+///
+/// This is meant for provisioning a machine from the config during installation. Version pins
+/// are not enforced (pacstrap installs the current repository versions), declared dependencies
+/// are left for pacman to pull in, and extra sources are not bootstrapped.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with pacstrap's output if it fails, or an IO error message.
+#[cfg(target_os = "linux")]
+pub fn bootstrap_from_yaml<P: AsRef<std::path::Path>>(
+    yml_path: P,
+    root: &std::path::Path,
+) -> Result<(), ApplyError> {
+    let mut config = read_effective_config(&yml_path).map_err(|e| e.to_string())?;
+    config.packages.retain_active(&Host::for_root(root));
+    let packages: Vec<String> = config
+        .packages
+        .packages
+        .values()
+        .filter(|pkg| pkg.reason == InstallReason::Explicit)
+        .map(|pkg| pkg.name.clone())
+        // pacstrap installs groups by name.
+        .chain(
+            config
+                .packages
+                .groups
+                .iter()
+                .map(|group| group.name.clone()),
+        )
+        .collect();
+    if packages.is_empty() {
+        return Err(format!("{} declares no packages", yml_path.as_ref().display()).into());
+    }
+    let captured = pacman::run_audited(
+        &mut pacman::pacstrap_command(root, &packages),
+        AuditAction::Install,
+        &packages,
+    )
+    .map_err(|e| e.to_string())?;
+    if captured.status.success() {
+        Ok(())
+    } else {
+        Err(
+            ApplyError::from(format!("Failed to bootstrap {}", root.display())).with_output(vec![
+                FailureOutput {
+                    packages,
+                    lines: captured.tail,
+                },
+            ]),
+        )
+    }
+}
+
+/// Apply the YAML configuration to synchronize installed packages.
+///
+/// - `yml_path`: Path to the YAML file
+/// - `use_paru`: Use paru instead of pacman
+/// - `continue_on_error`: Continue on errors (try all packages, don't abort on first failure)
+/// - `noconfirm`: Pass `--noconfirm` so pacman/paru never stop at a prompt
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors.
+#[cfg(target_os = "linux")]
+pub fn apply_packages_from_yaml<P: AsRef<std::path::Path>>(
+    yml_path: P,
+    use_paru: bool,
+    continue_on_error: bool,
+    noconfirm: bool,
+    mode: ApplyMode,
+) -> Result<ApplyReport, ApplyError> {
+    apply_packages_from_yaml_with_progress(
+        yml_path,
+        use_paru,
+        continue_on_error,
+        noconfirm,
+        mode,
+        &mut |_| {},
+    )
+}
+
+/// Like [`apply_packages_from_yaml`], calling `progress` before each pacman/paru run.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors.
+#[cfg(target_os = "linux")]
+pub fn apply_packages_from_yaml_with_progress<P: AsRef<std::path::Path>>(
+    yml_path: P,
+    use_paru: bool,
+    continue_on_error: bool,
+    noconfirm: bool,
+    mode: ApplyMode,
+    progress: &mut dyn FnMut(Progress<'_>),
+) -> Result<ApplyReport, ApplyError> {
+    apply_packages_from_yaml_cancellable(
+        yml_path,
+        use_paru,
+        continue_on_error,
+        noconfirm,
+        mode,
+        &CancelToken::default(),
+        progress,
+    )
+}
+
+/// Like [`apply_packages_from_yaml_with_progress`], stopping before the next pacman/paru run
+/// once `cancel` is cancelled.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors, with `cancelled`
+/// set if it stopped early.
+#[cfg(target_os = "linux")]
+pub fn apply_packages_from_yaml_cancellable<P: AsRef<std::path::Path>>(
+    yml_path: P,
+    use_paru: bool,
+    continue_on_error: bool,
+    noconfirm: bool,
+    mode: ApplyMode,
+    cancel: &CancelToken,
+    progress: &mut dyn FnMut(Progress<'_>),
+) -> Result<ApplyReport, ApplyError> {
+    let plan = plan::plan_packages(&yml_path, mode).map_err(|e| e.to_string())?;
+    plan::execute(
+        &plan,
+        use_paru,
+        continue_on_error,
+        noconfirm,
+        cancel,
+        progress,
+    )
+}
+
+/// Which kinds of changes an apply makes, e.g. to converge an existing system in stages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApplyMode {
+    /// Install missing packages and remove unmanaged ones.
+    #[default]
+    Full,
+    /// Only install missing packages.
+    InstallOnly,
+    /// Only remove unmanaged packages.
+    RemoveOnly,
+}
+
+impl ApplyMode {
+    /// Drop the `(to_install, to_remove)` changes this mode does not make.
+    #[must_use]
+    pub fn restrict(self, changes: (Vec<String>, Vec<String>)) -> (Vec<String>, Vec<String>) {
+        let (to_install, to_remove) = changes;
+        match self {
+            Self::Full => (to_install, to_remove),
+            Self::InstallOnly => (to_install, Vec::new()),
+            Self::RemoveOnly => (Vec::new(), to_remove),
+        }
+    }
+}
+
+/// Whether a step of an apply installs or removes packages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageAction {
+    Install,
+    Remove,
+}
+
+impl PackageAction {
+    /// The verb for this action, e.g. `"install"`.
+    #[must_use]
+    pub const fn verb(self) -> &'static str {
+        match self {
+            Self::Install => "install",
+            Self::Remove => "remove",
+        }
+    }
+}
+
+/// A step of an apply, reported to progress callbacks before it runs.
+///
+/// With `continue_on_error` every package is its own step; otherwise all removals and all
+/// installs run as one step each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress<'a> {
+    /// 1-based index of this step.
+    pub current: usize,
+    /// Total number of steps.
+    pub total: usize,
+    pub action: PackageAction,
+    pub packages: &'a [String],
+}
+
+/// A handle for stopping an apply between pacman/paru runs, e.g. from another thread.
+///
+/// Clones share the same state; a run in progress is never interrupted.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl CancelToken {
+    /// Ask the apply to stop before its next pacman/paru run.
+    pub fn cancel(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+/// Remove and then install the given packages.
+///
+/// - `use_paru`: Use paru instead of pacman
+/// - `continue_on_error`: Continue on errors (try all packages, don't abort on first failure)
+/// - `noconfirm`: Pass `--noconfirm` so pacman/paru never stop at a prompt
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors.
+#[cfg(target_os = "linux")]
+pub fn execute_package_changes(
+    to_install: &[String],
+    to_remove: &[String],
+    use_paru: bool,
+    continue_on_error: bool,
+    noconfirm: bool,
+) -> Result<ApplyReport, ApplyError> {
+    execute_package_changes_with_progress(
+        to_install,
+        to_remove,
+        use_paru,
+        continue_on_error,
+        noconfirm,
+        &mut |_| {},
+    )
+}
+
+/// Like [`execute_package_changes`], calling `progress` before each pacman/paru run.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors.
+#[cfg(target_os = "linux")]
+pub fn execute_package_changes_with_progress(
+    to_install: &[String],
+    to_remove: &[String],
+    use_paru: bool,
+    continue_on_error: bool,
+    noconfirm: bool,
+    progress: &mut dyn FnMut(Progress<'_>),
+) -> Result<ApplyReport, ApplyError> {
+    execute_package_changes_cancellable(
+        to_install,
+        to_remove,
+        use_paru,
+        continue_on_error,
+        noconfirm,
+        &CancelToken::default(),
+        progress,
+    )
+}
+
+/// Like [`execute_package_changes_with_progress`], stopping before the next pacman/paru run
+/// once `cancel` is cancelled.
+///
+/// The names in `to_install` are checked with [`names::check_targets`] first, so unknown
+/// packages fail the apply before anything is removed.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors, with `cancelled`
+/// set if it stopped early.
+#[cfg(target_os = "linux")]
+pub fn execute_package_changes_cancellable(
+    to_install: &[String],
+    to_remove: &[String],
+    use_paru: bool,
+    continue_on_error: bool,
+    noconfirm: bool,
+    cancel: &CancelToken,
+    progress: &mut dyn FnMut(Progress<'_>),
+) -> Result<ApplyReport, ApplyError> {
+    if !to_install.is_empty() && !cancel.is_cancelled() {
+        if offline::is_offline() {
+            offline::check_cached(to_install)?;
         } else {
-            let status = if use_paru {
-                crate::pacman::paru_install(&to_install).map_err(|e| e.to_string())?
-            } else {
-                crate::pacman::pacman_install(&to_install, true).map_err(|e| e.to_string())?
-            };
-            if !status.success() {
-                return Err("Failed to install some packages".to_string());
+            names::check_targets(to_install, use_paru)?;
+        }
+    }
+    // AUR packages built in parallel are installed last, after everything paru installs.
+    let parallel = builds::parallel_targets(to_install, use_paru)
+        .map_err(|e| ApplyError::from(format!("Failed to look up AUR packages: {e}")))?;
+    let to_install: Vec<String> = to_install
+        .iter()
+        .filter(|name| !parallel.contains(name))
+        .cloned()
+        .collect();
+    let steps: Vec<(PackageAction, &[String])> = batches(to_remove, continue_on_error)
+        .map(|batch| (PackageAction::Remove, batch))
+        .chain(batches(&to_install, continue_on_error).map(|batch| (PackageAction::Install, batch)))
+        .chain((!parallel.is_empty()).then_some((PackageAction::Install, parallel.as_slice())))
+        .collect();
+    let total = steps.len();
+    let mut report = ApplyReport::default();
+    let mut removals = Vec::new();
+    let mut installs = Vec::new();
+    for (index, (action, packages)) in steps.into_iter().enumerate() {
+        if cancel.is_cancelled() {
+            return Err(ApplyError {
+                message: format!("Cancelled after {index} of {total} steps"),
+                cancelled: true,
+                ..ApplyError::default()
+            }
+            .with_output(removals.into_iter().chain(installs).collect())
+            .with_report(report));
+        }
+        progress(Progress {
+            current: index + 1,
+            total,
+            action,
+            packages,
+        });
+        // The parallel AUR builds are the last step.
+        if !parallel.is_empty() && index + 1 == total {
+            let (steps, failures) = builds::install(&parallel, builds::jobs(), noconfirm)
+                .map_err(|e| ApplyError::from(e.to_string()).with_report(report.clone()))?;
+            report.steps.extend(steps);
+            if !failures.is_empty() && !continue_on_error {
+                return Err(
+                    ApplyError::from("Failed to build some AUR packages".to_string())
+                        .with_output(failures)
+                        .with_report(report),
+                );
             }
+            installs.extend(failures);
+            continue;
+        }
+        let step = match run_step(action, packages, use_paru, noconfirm, cancel) {
+            Ok(step) => step,
+            Err(e) => return Err(ApplyError::from(e.to_string()).with_report(report)),
+        };
+        if step.outcome == report::Outcome::Succeeded {
+            report.steps.push(step);
+            continue;
         }
+        let failure = FailureOutput {
+            packages: packages.to_vec(),
+            lines: step.tail.clone(),
+        };
+        report.steps.push(step);
+        if !continue_on_error {
+            return Err(
+                ApplyError::from(format!("Failed to {} some packages", action.verb()))
+                    .with_output(vec![failure])
+                    .with_report(report),
+            );
+        }
+        match action {
+            PackageAction::Install => installs.push(failure),
+            PackageAction::Remove => removals.push(failure),
+        }
+    }
+    let failed_removals: Vec<String> = removals.iter().flat_map(|f| f.packages.clone()).collect();
+    let failed_installs: Vec<String> = installs.iter().flat_map(|f| f.packages.clone()).collect();
+    match failure_summary("packages", &failed_removals, &failed_installs) {
+        Ok(()) => Ok(report),
+        Err(e) => Err(e
+            .with_output(removals.into_iter().chain(installs).collect())
+            .with_report(report)),
     }
+}
+
+/// Run pacman/paru to carry out `action` on `packages`, retrying installs that fail because of
+/// the network as the [retry policy](retry::policy) allows, unless `cancel` is cancelled.
+#[cfg(target_os = "linux")]
+fn run_step(
+    action: PackageAction,
+    packages: &[String],
+    use_paru: bool,
+    noconfirm: bool,
+    cancel: &CancelToken,
+) -> std::io::Result<Step> {
+    let started = Instant::now();
+    let policy = retry::policy();
+    let mut attempt = 0;
+    // Only installs download anything, so only they can fail transiently.
+    let captured = loop {
+        let mut cmd = match action {
+            PackageAction::Install => pacman::install_command(packages, use_paru, noconfirm),
+            PackageAction::Remove => pacman::remove_command(packages, use_paru, noconfirm),
+        };
+        let audit_action = match action {
+            PackageAction::Install => AuditAction::Install,
+            PackageAction::Remove => AuditAction::Remove,
+        };
+        let captured = pacman::run_audited(&mut cmd, audit_action, packages)?;
+        if captured.status.success()
+            || action == PackageAction::Remove
+            || cancel.is_cancelled()
+            || !policy.should_retry(attempt, &captured.tail)
+        {
+            break captured;
+        }
+        let delay = policy.backoff(attempt);
+        attempt += 1;
+        eprintln!(
+            "Failed to install {} because of a network error; retrying in {}s ({attempt}/{})",
+            packages.join(", "),
+            delay.as_secs(),
+            policy.retries
+        );
+        std::thread::sleep(delay);
+    };
+    let mut step = Step::finished(
+        "pacman",
+        action,
+        packages,
+        captured.status,
+        started,
+        captured.tail,
+    );
+    step.attempts = attempt + 1;
+    Ok(step)
+}
+
+/// Split `packages` into the batches pacman/paru is run with: one per package when
+/// `continue_on_error` is set, otherwise a single batch (none when `packages` is empty).
+#[cfg(target_os = "linux")]
+fn batches(packages: &[String], continue_on_error: bool) -> impl Iterator<Item = &[String]> {
+    let size = if continue_on_error {
+        1
+    } else {
+        packages.len().max(1)
+    };
+    packages.chunks(size)
+}
+
+/// Build the `continue_on_error` summary of failed removals and installs of `noun` (e.g. "packages").
+#[cfg(target_os = "linux")]
+fn failure_summary(
+    noun: &str,
+    failed_removals: &[String],
+    failed_installs: &[String],
+) -> Result<(), ApplyError> {
     if failed_removals.is_empty() && failed_installs.is_empty() {
         Ok(())
     } else {
@@ -314,7 +2240,7 @@ pub fn apply_packages_from_yaml<P: AsRef<std::path::Path>>(
         if !failed_removals.is_empty() {
             let _ = write!(
                 msg,
-                "Failed to remove packages: {}",
+                "Failed to remove {noun}: {}",
                 failed_removals.join(", ")
             );
             msg.push('\n');
@@ -322,12 +2248,17 @@ pub fn apply_packages_from_yaml<P: AsRef<std::path::Path>>(
         if !failed_installs.is_empty() {
             let _ = write!(
                 msg,
-                "Failed to install packages: {}",
+                "Failed to install {noun}: {}",
                 failed_installs.join(", ")
             );
             msg.push('\n');
         }
-        Err(msg)
+        Err(ApplyError {
+            message: msg,
+            failed_removals: failed_removals.to_vec(),
+            failed_installs: failed_installs.to_vec(),
+            ..ApplyError::default()
+        })
     }
 }
 
@@ -345,13 +2276,23 @@ mod tests {
             Package {
                 name: "foo".to_string(),
                 version: Some(FullVersion::from("1.0.0-1")),
+                requirement: None,
+                reason: InstallReason::Explicit,
+                when: None,
+                origin: None,
+                installed_at: None,
             },
             Package {
                 name: "bar".to_string(),
                 version: Some(FullVersion::from("2.1.0-2")),
+                requirement: None,
+                reason: InstallReason::Explicit,
+                when: None,
+                origin: None,
+                installed_at: None,
             },
         ];
-        let package_list = PackageList { packages };
+        let package_list = PackageList::new(packages, Vec::new());
 
         let yml = serde_yml::to_string(&package_list).expect("Failed to serialize to YAML");
         let deserialized: PackageList =
@@ -362,14 +2303,37 @@ mod tests {
         );
     }
 
+    /// A version requirement is written back as the `version:` it was read from.
+    #[test]
+    fn yaml_roundtrip_version_requirement() {
+        let package_list = PackageList::new(
+            [Package {
+                requirement: Some(">=1.6, <2".parse().unwrap()),
+                ..Package::new("qux")
+            }],
+            Vec::new(),
+        );
+        let yml = serde_yml::to_string(&package_list).expect("Failed to serialize to YAML");
+        assert!(yml.contains(">=1.6, <2"));
+        let deserialized: PackageList =
+            serde_yml::from_str(&yml).expect("Failed to deserialize YAML");
+        assert_eq!(package_list, deserialized);
+        assert!(deserialized.get("qux").unwrap().version.is_none());
+    }
+
     /// Tests the conversion of a package list to a YAML file and back.
     #[test]
     fn yaml_file_roundtrip_package_list() {
         let packages = vec![Package {
             name: "baz".to_string(),
             version: Some(FullVersion::from("3.2.1-3")),
+            requirement: None,
+            reason: InstallReason::Explicit,
+            when: None,
+            origin: None,
+            installed_at: None,
         }];
-        let package_list = PackageList { packages };
+        let package_list = PackageList::new(packages, Vec::new());
         let yml = serde_yml::to_string(&package_list).expect("Failed to serialize to YAML");
         fs::write("test_packages.yml", &yml).expect("Failed to write test YAML file");
         let yml_content =
@@ -382,4 +2346,401 @@ mod tests {
         );
         let _ = fs::remove_file("test_packages.yml");
     }
+
+    /// Tests that the optional `flatpak:` section round-trips and is omitted when unmanaged.
+    #[test]
+    fn yaml_roundtrip_config_with_flatpak() {
+        let config = Config {
+            schema: None,
+            vars: None,
+            packages: PackageList::new(
+                vec![Package {
+                    name: "git".to_string(),
+                    version: None,
+                    requirement: None,
+                    reason: InstallReason::Explicit,
+                    when: None,
+                    origin: None,
+                    installed_at: None,
+                }],
+                Vec::new(),
+            ),
+            flatpak: Some(vec!["org.gimp.GIMP".to_string()]),
+            cargo: Some(vec!["ripgrep".to_string()]),
+            pipx: None,
+            npm: None,
+            critical: None,
+            ignore: None,
+            banned: None,
+            settings: None,
+            repos: None,
+        };
+        let yml = serde_yml::to_string(&config).expect("Failed to serialize to YAML");
+        assert!(yml.contains("flatpak:"));
+        let deserialized: Config = serde_yml::from_str(&yml).expect("Failed to deserialize YAML");
+        assert_eq!(config, deserialized);
+
+        let unmanaged: Config =
+            serde_yml::from_str("packages:\n  - git\n").expect("Failed to deserialize YAML");
+        assert_eq!(unmanaged.flatpak, None);
+        let yml = serde_yml::to_string(&unmanaged).expect("Failed to serialize to YAML");
+        assert!(!yml.contains("flatpak"));
+        assert_eq!(unmanaged.source("cargo"), None);
+        assert_eq!(
+            config.source("flatpak"),
+            Some(["org.gimp.GIMP".to_string()].as_slice())
+        );
+    }
+
+    /// Written YAML is sorted case-insensitively by default and byte-identical when rewritten.
+    #[test]
+    fn write_config_sorts_deterministically() {
+        let mut config = Config::new(PackageList::new(
+            ["vim", "Zoom", "alsa-utils", "base"].map(Package::from),
+            Vec::new(),
+        ));
+        config.flatpak = Some(vec!["org.gimp.GIMP".to_string(), "com.Discord".to_string()]);
+        config.settings = Some(settings::Settings {
+            sort: Some(SortOrder::Insensitive),
+            ..settings::Settings::default()
+        });
+        let path = std::env::temp_dir().join(format!("nixman-sorted-{}.yml", std::process::id()));
+        write_config(&config, &path).unwrap();
+        let first = fs::read_to_string(&path).unwrap();
+        assert!(first.starts_with("schema: 2\npackages:\n- alsa-utils\n- base\n- vim\n- Zoom\n"));
+        assert!(first.contains("flatpak:\n- com.Discord\n- org.gimp.GIMP\n"));
+        write_config(&read_config(&path).unwrap(), &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), first);
+        let _ = fs::remove_file(&path);
+
+        let mut names = vec!["b".to_string(), "B".to_string(), "a".to_string()];
+        SortOrder::Sensitive.sort(&mut names);
+        assert_eq!(names, vec!["B", "a", "b"]);
+        SortOrder::Insensitive.sort(&mut names);
+        assert_eq!(names, vec!["a", "B", "b"]);
+        SortOrder::Unsorted.sort(&mut names);
+        assert_eq!(names, vec!["a", "B", "b"]);
+    }
+
+    /// A package declared once per hostname is merged into one entry that applies to both
+    /// hosts, and written back that way.
+    #[test]
+    fn repeated_conditional_packages_keep_every_host() {
+        let list: PackageList = serde_yml::from_str(
+            "packages:\n  - name: nvidia-open\n    when: { hostname: desktop }\n  - name: nvidia-open\n    when: { hostname: workstation }\n",
+        )
+        .unwrap();
+        assert_eq!(list.len(), 1);
+        for hostname in ["desktop", "workstation"] {
+            let mut active = list.clone();
+            active.retain_active(&Host {
+                arch: "x86_64".to_string(),
+                hostname: Some(hostname.to_string()),
+            });
+            assert!(active.contains("nvidia-open"), "{hostname}");
+        }
+        let yml = serde_yml::to_string(&list).unwrap();
+        assert!(yml.contains("any:"));
+        let reread: PackageList = serde_yml::from_str(&yml).unwrap();
+        assert_eq!(reread.packages, list.packages);
+        assert!(reread.duplicates().is_empty());
+    }
+
+    /// Repeated packages merge into their first entry as they are read, and are recorded.
+    #[test]
+    fn repeated_packages_are_merged() {
+        let mut list: PackageList = serde_yml::from_str(
+            "packages:\n  - vim\n  - name: vim\n    version: 9.1.0-1\n  - name: git\n    version: 2.44.0-1\n  - name: git\n    version: 2.45.0-1\n  - name: htop\n    when:\n      hostname: laptop\n  - htop\n  - group: xorg\n  - group: xorg\n",
+        )
+        .unwrap();
+        let duplicates = list.duplicates().to_vec();
+        assert_eq!(duplicates.len(), 3);
+        assert!(!duplicates[0].conflicts());
+        assert!(duplicates[1].conflicts());
+        assert_eq!(duplicates[1].to_string(), "git (2.44.0-1, 2.45.0-1)");
+        let version = |name: &str| list.get(name)?.version.as_ref().map(ToString::to_string);
+        assert_eq!(version("vim").as_deref(), Some("9.1.0-1"));
+        assert_eq!(version("git").as_deref(), Some("2.44.0-1"));
+        assert_eq!(list.get("htop").unwrap().when, None);
+        assert_eq!(list.packages.len(), 3);
+        assert_eq!(list.groups.len(), 1);
+        assert_eq!(list.dedup(), duplicates);
+        assert!(list.duplicates().is_empty());
+
+        let mut installed = parse_explicit_packages("vim 9.0.0-1\ngit 2.44.0-1\n", true);
+        let mismatches = list.version_mismatches(&installed);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].0.name, "vim");
+        assert!(installed.insert(Package::from("tmux")));
+        assert!(!installed.insert(Package::from("tmux")));
+    }
+
+    /// Set operations go by name and keep the order and entries of the left list.
+    #[test]
+    fn package_list_set_operations() {
+        let parse = |yaml: &str| serde_yml::from_str::<PackageList>(yaml).unwrap();
+        let names = |list: &PackageList| -> Vec<String> { list.packages.keys().cloned().collect() };
+        let base = parse("packages:\n  - git\n  - vim\n  - htop\n  - group: xorg\n");
+        let other = parse(
+            "packages:\n  - name: vim\n    version: 9.1.0-1\n  - tmux\n  - group: xorg\n  - group: gnome\n",
+        );
+        let union = base.union(&other);
+        assert_eq!(names(&union), vec!["git", "vim", "htop", "tmux"]);
+        assert_eq!(union.groups.len(), 2);
+        assert!(union.packages[1].version.is_none());
+        assert_eq!(names(&base.difference(&other)), vec!["git", "htop"]);
+        assert!(base.difference(&other).groups.is_empty());
+        assert_eq!(names(&base.intersection(&other)), vec!["vim"]);
+        let merged = base.merge_preferring_versions(&other);
+        assert_eq!(names(&merged), names(&union));
+        assert_eq!(
+            merged.packages[1].version.as_ref().map(ToString::to_string),
+            Some("9.1.0-1".to_string())
+        );
+    }
+
+    /// Constructed packages display as `name version`, and match installed packages by pin or
+    /// requirement.
+    #[test]
+    fn package_constructors_and_matching() {
+        let vim = Package::versioned("vim", "9.1.0-1");
+        assert_eq!(vim.to_string(), "vim 9.1.0-1");
+        assert_eq!(vim, Package::from("vim 9.1.0-1"));
+        assert_eq!(Package::new("git").to_string(), "git");
+        let required = Package {
+            requirement: Some(">=9.1".parse().unwrap()),
+            ..Package::new("vim")
+        };
+        assert_eq!(required.to_string(), "vim >=9.1");
+        assert!(vim.matches(&Package::versioned("vim", "0:9.1.0-1")));
+        assert!(!vim.matches(&Package::versioned("vim", "9.1.0-2")));
+        assert!(!vim.matches(&Package::new("vim")));
+        assert!(required.matches(&Package::versioned("vim", "9.1.0-2")));
+        assert!(!required.matches(&Package::versioned("vim", "9.0.2167-1")));
+        assert!(Package::new("vim").matches(&Package::versioned("vim", "8.0-1")));
+        assert!(!Package::new("vim").matches(&Package::new("neovim")));
+    }
+
+    /// A list collects, extends, and iterates like a map of packages, merging repeated names.
+    #[test]
+    fn package_list_collection_traits() {
+        let mut list: PackageList = ["git", "vim"].into_iter().map(Package::from).collect();
+        list.extend([Package::from("vim 9.1.0-1"), Package::from("htop")]);
+        assert_eq!(list.len(), 3);
+        assert!(!list.is_empty() && PackageList::default().is_empty());
+        assert_eq!(list.duplicates().len(), 1);
+        assert_eq!(list["vim"].version, Some(FullVersion::from("9.1.0-1")));
+        let names: Vec<&str> = (&list).into_iter().map(|pkg| pkg.name.as_str()).collect();
+        assert_eq!(names, ["git", "vim", "htop"]);
+        let owned: Vec<Package> = list.into_iter().collect();
+        assert_eq!(owned[2].name, "htop");
+    }
+
+    /// Failure output is kept per run and appended to the message.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn apply_error_includes_failure_output() {
+        let err = failure_summary("packages", &[], &["nope".to_string()])
+            .unwrap_err()
+            .with_output(vec![FailureOutput {
+                packages: vec!["nope".to_string()],
+                lines: vec!["error: target not found: nope".to_string()],
+            }]);
+        assert_eq!(err.failed_installs, vec!["nope"]);
+        assert_eq!(
+            err.message,
+            "Failed to install packages: nope\nOutput for nope:\n    error: target not found: nope\n"
+        );
+    }
+
+    /// Each package is its own step with `continue_on_error`, otherwise there is one step.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn batches_per_package_only_when_continuing() {
+        let packages = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(batches(&packages, true).count(), 2);
+        assert_eq!(
+            batches(&packages, false).collect::<Vec<_>>(),
+            vec![&packages[..]]
+        );
+        assert_eq!(batches(&[], false).count(), 0);
+    }
+
+    /// Dependencies are written as maps with a `reason`, and plain names default to explicit.
+    #[test]
+    fn yaml_roundtrip_dependency_reason() {
+        let package_list = PackageList::new(
+            vec![
+                Package::from("git"),
+                Package {
+                    name: "perl-error".to_string(),
+                    version: None,
+                    requirement: None,
+                    reason: InstallReason::Dependency,
+                    when: None,
+                    origin: None,
+                    installed_at: None,
+                },
+            ],
+            vec![Group {
+                name: "base-devel".to_string(),
+                when: Some(Condition {
+                    arch: Some("x86_64".to_string()),
+                    ..Condition::default()
+                }),
+            }],
+        );
+        let yml = serde_yml::to_string(&package_list).expect("Failed to serialize to YAML");
+        assert!(yml.contains("reason: dependency"));
+        assert!(yml.contains("- group: base-devel\n  when:\n    arch: x86_64"));
+        let deserialized: PackageList =
+            serde_yml::from_str(&yml).expect("Failed to deserialize YAML");
+        assert_eq!(package_list, deserialized);
+    }
+
+    /// Groups expand into their members, whether declared with `group:` or as a plain name.
+    #[test]
+    fn expands_package_groups() {
+        let groups = parse_groups("base-devel make\nbase-devel gcc\nxorg xorg-server\n");
+        let list: PackageList =
+            serde_yml::from_str("packages:\n  - git\n  - xorg\n  - group: base-devel\n")
+                .expect("Failed to deserialize YAML");
+        assert_eq!(list.groups[0].name, "base-devel");
+        let names: Vec<String> = expand_groups(&list, &["git".to_string()], &groups)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["git", "xorg-server", "make", "gcc"]);
+    }
+
+    /// Declared dependencies are never installed, and ignored packages are neither installed
+    /// nor removed.
+    #[test]
+    fn computes_package_changes() {
+        let names =
+            |names: &[&str]| -> Vec<String> { names.iter().map(ToString::to_string).collect() };
+        let wanted = vec![
+            ("git".to_string(), InstallReason::Explicit),
+            ("zlib".to_string(), InstallReason::Dependency),
+            ("htop".to_string(), InstallReason::Explicit),
+            ("steam".to_string(), InstallReason::Explicit),
+            ("htop".to_string(), InstallReason::Dependency),
+        ];
+        let (to_install, to_remove) = package_changes(
+            &wanted,
+            &names(&["git", "nano", "discord", "zlib"]),
+            &names(&["steam", "discord"]),
+        );
+        assert_eq!(to_install, vec!["htop"]);
+        assert_eq!(to_remove, vec!["nano"]);
+    }
+
+    /// Diff entries carry the versions on both sides, and pins only count when installed.
+    #[test]
+    fn builds_diff_entries() {
+        let declared: PackageList = serde_yml::from_str(
+            "packages:\n  - git\n  - name: vim\n    version: 9.1.0-1\n  - name: htop\n    version: 3.3.0-1\n",
+        )
+        .unwrap();
+        let installed = parse_explicit_packages("vim 9.1.0-2\nnano 8.0-1\n", true);
+        let entries = diff_entries(
+            &declared,
+            &installed,
+            &["git".to_string(), "htop".to_string()],
+            &["nano".to_string()],
+        );
+        let kinds: Vec<(&str, DiffKind)> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("git", DiffKind::Missing),
+                ("htop", DiffKind::Missing),
+                ("nano", DiffKind::Extra),
+                ("vim", DiffKind::VersionMismatch),
+            ]
+        );
+        assert_eq!(entries[1].desired, Some(FullVersion::from("3.3.0-1")));
+        assert_eq!(entries[2].installed, Some(FullVersion::from("8.0-1")));
+        assert_eq!(entries[3].installed, Some(FullVersion::from("9.1.0-2")));
+    }
+
+    /// The installed list from `pacman -Qe` output is compared by name, so a YAML whose
+    /// packages are all installed needs no pacman queries.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn syncs_against_installed_list() {
+        let path = std::env::temp_dir().join(format!("nixman-sync-{}.yml", std::process::id()));
+        fs::write(&path, "packages:\n  - name: vim\n    version: 9.1.0-1\n").unwrap();
+        let installed = parse_explicit_packages("vim 9.1.0-2\nnano 8.0-1\n", true);
+        let (to_install, to_remove) = sync_packages_from_yaml(&path, &installed).unwrap();
+        assert!(to_install.is_empty());
+        assert_eq!(to_remove, ["nano"]);
+        let entries = diff_packages(&path, &installed).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].kind, DiffKind::VersionMismatch);
+        let _ = fs::remove_file(&path);
+    }
+
+    /// Alternatives stand for whichever of them is installed, or else the first one, and
+    /// round-trip as `one-of:` entries.
+    #[test]
+    fn expands_alternatives() {
+        let list: PackageList = serde_yml::from_str(
+            "packages:\n  - git\n  - one-of: [vim, neovim]\n  - one-of: [jdk-openjdk, jdk17-openjdk]\n",
+        )
+        .expect("Failed to deserialize YAML");
+        assert_eq!(list.alternatives.len(), 2);
+        let installed = vec!["git".to_string(), "neovim".to_string()];
+        let names: Vec<String> = expand_groups(&list, &installed, &BTreeMap::new())
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["git", "neovim", "jdk-openjdk"]);
+        let (to_install, to_remove) = diff_names(&names, &installed);
+        assert_eq!(to_install, vec!["jdk-openjdk"]);
+        assert!(to_remove.is_empty());
+        let yml = serde_yml::to_string(&list).expect("Failed to serialize to YAML");
+        assert_eq!(serde_yml::from_str::<PackageList>(&yml).unwrap(), list);
+        assert!(serde_yml::from_str::<PackageList>("packages:\n  - one-of: []\n").is_err());
+    }
+
+    /// A cancelled apply stops before running anything.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cancelled_apply_runs_nothing() {
+        let cancel = CancelToken::default();
+        let stop = cancel.clone();
+        stop.cancel();
+        let mut steps = 0;
+        let err = execute_package_changes_cancellable(
+            &["htop".to_string()],
+            &[],
+            false,
+            false,
+            true,
+            &cancel,
+            &mut |_| steps += 1,
+        )
+        .unwrap_err();
+        assert!(err.cancelled);
+        assert_eq!(steps, 0);
+    }
+
+    /// Install-only and remove-only applies drop the other kind of change.
+    #[test]
+    fn apply_mode_restricts_changes() {
+        let changes = || (vec!["vim".to_string()], vec!["nano".to_string()]);
+        assert_eq!(ApplyMode::Full.restrict(changes()), changes());
+        assert_eq!(
+            ApplyMode::InstallOnly.restrict(changes()),
+            (vec!["vim".to_string()], vec![])
+        );
+        assert_eq!(
+            ApplyMode::RemoveOnly.restrict(changes()),
+            (vec![], vec!["nano".to_string()])
+        );
+    }
 }
@@ -0,0 +1,249 @@
+//! Machine-local overrides
+//!
+//! # Overview
+//!
+//! `packages.local.yml`, next to `packages.yml`, holds changes for one machine that should not
+//! be committed to the shared file. It is merged on top of the shared config whenever nixman
+//! decides what to install or remove:
+//!
+//! ```yaml
+//! packages:
+//!   - steam
+//!   - name: git
+//!     version: 2.44.0-1
+//! exclude:
+//!   - intel-ucode
+//! ignore:
+//!   - android-studio
+//! ```
+//!
+//! Merge rules, applied in order:
+//!
+//! 1. `exclude:` drops packages, groups, and extra-source entries with these names from the
+//!    shared config; `apply` treats them like any other undeclared package.
+//! 2. `packages:` entries are added; an entry for a name the shared config already declares
+//!    replaces it (e.g. to pin a different version or change its reason).
+//! 3. `ignore:`, `critical:`, `banned:`, and the extra-source sections are added to the shared
+//!    ones.
+//! 4. `vars:` entries replace the shared [variables](crate::vars) of the same name.
+//!
+//! `freeze` never writes packages declared in the local file to the shared file.
+
+use crate::{Config, PackageList};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The local overrides belonging to a YAML file: `packages.yml` → `packages.local.yml`.
+#[must_use]
+pub fn local_path(yml_path: &Path) -> PathBuf {
+    let stem = yml_path
+        .file_stem()
+        .map_or_else(|| "packages".into(), |stem| stem.to_string_lossy());
+    yml_path.with_file_name(format!("{stem}.local.yml"))
+}
+
+/// The contents of `packages.local.yml`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LocalConfig {
+    pub packages: PackageList,
+    /// Names to drop from the shared config on this machine.
+    pub exclude: Vec<String>,
+    /// `ignore:`, `critical:`, `banned:`, and extra-source sections, merged like the shared
+    /// file's.
+    pub sections: Sections,
+}
+
+/// The list sections of a local file besides `packages:`.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Sections {
+    pub exclude: Vec<String>,
+    pub ignore: Option<Vec<String>>,
+    pub critical: Option<Vec<String>>,
+    pub banned: Option<Vec<String>>,
+    pub flatpak: Option<Vec<String>>,
+    pub cargo: Option<Vec<String>>,
+    pub pipx: Option<Vec<String>>,
+    pub npm: Option<Vec<String>>,
+    pub vars: Option<BTreeMap<String, String>>,
+}
+
+impl LocalConfig {
+    /// Parse a local file; every section, including `packages:`, is optional.
+    ///
+    /// # Errors
+    /// Returns an error if the content is not valid YAML or a section is malformed.
+    pub fn parse(content: &str) -> Result<Self, serde_yml::Error> {
+        /// Just the `packages:` section, read straight from the text so errors keep their
+        /// position.
+        #[derive(Deserialize)]
+        struct Packages {
+            #[serde(default, deserialize_with = "crate::deserialize_entries")]
+            packages: PackageList,
+        }
+        if content.trim().is_empty() {
+            return Ok(Self {
+                packages: PackageList::default(),
+                exclude: Vec::new(),
+                sections: Sections::default(),
+            });
+        }
+        let Packages { packages } = crate::schema::from_str(content)?;
+        let mut sections: Sections = crate::schema::from_str(content)?;
+        Ok(Self {
+            packages,
+            exclude: std::mem::take(&mut sections.exclude),
+            sections,
+        })
+    }
+
+    /// Read the local file at `path`, or `None` if there is none.
+    ///
+    /// # Errors
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn load(path: &Path) -> std::io::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content).map(Some).map_err(|e| {
+            crate::diagnostics::locate(
+                path,
+                &content,
+                std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+            )
+        })
+    }
+}
+
+/// What merging a local file changed, as shown by `nixman status`.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MergeReport {
+    pub path: PathBuf,
+    /// Packages and groups declared only in the local file.
+    pub added: Vec<String>,
+    /// Shared entries replaced by a local entry of the same name.
+    pub overridden: Vec<String>,
+    /// Shared entries dropped by `exclude:`.
+    pub excluded: Vec<String>,
+}
+
+/// Append the names in `extra` to `section`, creating it if needed.
+pub(crate) fn extend_section(section: &mut Option<Vec<String>>, extra: Option<Vec<String>>) {
+    for name in extra.into_iter().flatten() {
+        let section = section.get_or_insert_with(Vec::new);
+        if !section.contains(&name) {
+            section.push(name);
+        }
+    }
+}
+
+/// Merge `local` on top of `config` following the rules in the [module docs](self).
+pub fn merge(config: &mut Config, local: LocalConfig, path: PathBuf) -> MergeReport {
+    let mut report = MergeReport {
+        path,
+        ..MergeReport::default()
+    };
+    let excluded = |name: &String| local.exclude.contains(name);
+    for name in config.packages.packages.keys() {
+        if excluded(name) {
+            report.excluded.push(name.clone());
+        }
+    }
+    for group in &config.packages.groups {
+        if excluded(&group.name) {
+            report.excluded.push(group.name.clone());
+        }
+    }
+    config.packages.packages.retain(|name, _| !excluded(name));
+    config
+        .packages
+        .groups
+        .retain(|group| !excluded(&group.name));
+    for name in ["flatpak", "cargo", "pipx", "npm"] {
+        if let Some(Some(section)) = config.source_mut(name) {
+            section.retain(|entry| !excluded(entry));
+        }
+    }
+    let added = local.packages.difference(&config.packages);
+    // Replacing an entry keeps its position in the shared list.
+    for (name, pkg) in local.packages.intersection(&config.packages).packages {
+        report.overridden.push(name.clone());
+        config.packages.packages.insert(name, pkg);
+    }
+    report.added.extend(added.packages.keys().cloned());
+    report
+        .added
+        .extend(added.groups.iter().map(|group| group.name.clone()));
+    config.packages = config.packages.union(&added);
+    let sections = local.sections;
+    extend_section(&mut config.ignore, sections.ignore);
+    extend_section(&mut config.critical, sections.critical);
+    extend_section(&mut config.banned, sections.banned);
+    extend_section(&mut config.flatpak, sections.flatpak);
+    extend_section(&mut config.cargo, sections.cargo);
+    extend_section(&mut config.pipx, sections.pipx);
+    extend_section(&mut config.npm, sections.npm);
+    if let Some(vars) = sections.vars {
+        config.vars.get_or_insert_default().extend(vars);
+    }
+    report
+}
+
+/// Read the YAML with its [base manifest](crate::base) and local overrides merged in and its
+/// [variables](crate::vars) replaced.
+///
+/// Also reports what the overrides changed (`None` when there is no local file).
+///
+/// # Errors
+/// Returns an error if a file cannot be read or parsed, or the base's signature is rejected.
+#[cfg(target_os = "linux")]
+pub fn read_merged<P: AsRef<Path>>(yml_path: P) -> std::io::Result<(Config, Option<MergeReport>)> {
+    let mut config = crate::read_config(&yml_path)?;
+    if let Some(base) = crate::base::load(&yml_path)? {
+        crate::base::merge(&mut config, base);
+    }
+    let path = local_path(yml_path.as_ref());
+    let report = LocalConfig::load(&path)?.map(|local| merge(&mut config, local, path));
+    crate::vars::expand(&mut config)?;
+    Ok((config, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exclusions apply first, then local packages are added or replace shared ones.
+    #[test]
+    fn merges_local_overrides() {
+        assert_eq!(
+            local_path(Path::new("/etc/nixman/packages.yml")),
+            Path::new("/etc/nixman/packages.local.yml")
+        );
+        let mut config: Config = serde_yml::from_str(
+            "packages:\n  - git\n  - intel-ucode\n  - group: xorg\nflatpak:\n  - org.gimp.GIMP\n",
+        )
+        .unwrap();
+        let local = LocalConfig::parse(
+            "packages:\n  - steam\n  - name: git\n    version: 2.44.0-1\nexclude:\n  - intel-ucode\n  - xorg\nignore:\n  - android-studio\n",
+        )
+        .unwrap();
+        let report = merge(&mut config, local, PathBuf::from("packages.local.yml"));
+        assert_eq!(report.added, vec!["steam"]);
+        assert_eq!(report.overridden, vec!["git"]);
+        assert_eq!(report.excluded, vec!["intel-ucode", "xorg"]);
+        let names: Vec<&str> = config
+            .packages
+            .packages
+            .values()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["git", "steam"]);
+        assert!(config.packages.packages[0].version.is_some());
+        assert!(config.packages.groups.is_empty());
+        assert_eq!(config.ignore, Some(vec!["android-studio".to_string()]));
+        assert_eq!(config.flatpak, Some(vec!["org.gimp.GIMP".to_string()]));
+        assert!(LocalConfig::parse("").unwrap().packages.packages.is_empty());
+    }
+}
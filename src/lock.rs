@@ -0,0 +1,171 @@
+//! Locking to prevent concurrent nixman/pacman runs
+//!
+//! # Overview
+//!
+//! State-mutating commands take an exclusive lock on `<state dir>/lock` (by default
+//! `~/.local/state/nixman/lock`) before doing anything, and check pacman's own database lock
+//! (`/var/lib/pacman/db.lck`) before launching pacman. Instead of a second pacman failing with a
+//! cryptic "unable to lock database" error, nixman either waits or fails with a clear message.
+//!
+//! The nixman lock is an OS advisory lock, so it is released automatically when the process
+//! exits, even if it crashes.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use nixman::lock;
+//! let _guard = lock::acquire(lock::default_lock_path()?, false)?;
+//! lock::check_pacman_db(false)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fs::{File, TryLockError};
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// pacman's database lock file, present while a pacman transaction is running.
+pub const PACMAN_DB_LOCK: &str = "/var/lib/pacman/db.lck";
+
+/// The database lock of the system nixman manages: [`PACMAN_DB_LOCK`], or `db.lck` in the
+/// database directory set with [`crate::pacman::set_paths`].
+#[must_use]
+pub fn pacman_db_lock() -> PathBuf {
+    crate::pacman::paths().db_path().join("db.lck")
+}
+
+/// How often to re-check pacman's database lock while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Holds the nixman lock; it is released when this guard is dropped or the process exits.
+#[derive(Debug)]
+pub struct LockGuard {
+    file: File,
+    path: PathBuf,
+}
+
+impl LockGuard {
+    /// The path of the held lock file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Default location of the nixman lock file (`<state dir>/lock`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn default_lock_path() -> std::io::Result<PathBuf> {
+    crate::paths::lock_file()
+}
+
+/// Acquire the nixman lock at `path`.
+///
+/// With `wait`, blocks until the lock is free; otherwise fails immediately with
+/// [`ErrorKind::WouldBlock`] and a message naming the process holding it.
+///
+/// # Errors
+/// Returns an error if the lock is held by another process (and `wait` is false), or the lock
+/// file cannot be opened or written.
+pub fn acquire<P: AsRef<Path>>(path: P, wait: bool) -> std::io::Result<LockGuard> {
+    let path = path.as_ref().to_path_buf();
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if wait => {
+            eprintln!(
+                "Waiting for another nixman process ({}) to finish...",
+                holder(&mut file)
+            );
+            file.lock()?;
+        }
+        Err(TryLockError::WouldBlock) => {
+            return Err(std::io::Error::new(
+                ErrorKind::WouldBlock,
+                format!(
+                    "another nixman process ({}) is already running; wait for it to finish or pass --wait",
+                    holder(&mut file)
+                ),
+            ));
+        }
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", std::process::id())?;
+    Ok(LockGuard { file, path })
+}
+
+/// Describe the process holding the lock, from the PID it wrote into the lock file.
+fn holder(file: &mut File) -> String {
+    let mut pid = String::new();
+    if file.read_to_string(&mut pid).is_ok() && !pid.trim().is_empty() {
+        format!("pid {}", pid.trim())
+    } else {
+        "unknown pid".to_string()
+    }
+}
+
+/// Whether pacman's database is currently locked.
+#[must_use]
+pub fn pacman_db_locked() -> bool {
+    pacman_db_lock().exists()
+}
+
+/// Make sure pacman's database is not locked before running pacman.
+///
+/// With `wait`, polls until the lock disappears; otherwise fails immediately.
+///
+/// # Errors
+/// Returns [`ErrorKind::WouldBlock`] with guidance if the database is locked and `wait` is false.
+pub fn check_pacman_db(wait: bool) -> std::io::Result<()> {
+    if !pacman_db_locked() {
+        return Ok(());
+    }
+    let lock = pacman_db_lock();
+    let lock = lock.display();
+    if !wait {
+        return Err(std::io::Error::new(
+            ErrorKind::WouldBlock,
+            format!(
+                "the pacman database is locked ({lock}); another pacman is running. \
+                 Wait for it to finish or pass --wait. If no pacman is running, remove the file."
+            ),
+        ));
+    }
+    eprintln!("Waiting for another pacman process to release {lock}...");
+    while pacman_db_locked() {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A second acquisition fails while the first guard is alive and succeeds after it is dropped.
+    #[test]
+    fn lock_is_exclusive() {
+        let path = std::env::temp_dir().join(format!("nixman-lock-test-{}", std::process::id()));
+        let guard = acquire(&path, false).expect("first lock should succeed");
+        let err = acquire(&path, false).expect_err("second lock should fail");
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+        drop(guard);
+        acquire(&path, false).expect("lock should be free again");
+        let _ = std::fs::remove_file(&path);
+    }
+}
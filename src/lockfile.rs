@@ -0,0 +1,411 @@
+//! Lockfile with the exact versions resolved by the last apply or freeze
+//!
+//! # Overview
+//!
+//! `packages.yml` is the human-edited intent: which packages you want, optionally pinned.
+//! `packages.lock.yml`, written next to it by `apply` and `freeze`, records the exact version
+//! of every declared package that ended up installed, much like `Cargo.lock`:
+//!
+//! ```yaml
+//! packages:
+//!   - name: git
+//!     version: 2.45.0-1
+//!   - name: htop
+//!     version: 3.3.0-1
+//!   - name: paru
+//!     version: 2.0.4-1
+//!     aur:
+//!       base: paru
+//!       commit: 5f6d0c2a3e1b7f8e9d4c6b2a1f0e9d8c7b6a5f4e
+//! ```
+//!
+//! For packages from the AUR, the commit of the AUR git repository the installed version was
+//! built from is recorded as well ([`AurSource`]): taken from the clone paru or an earlier build
+//! left behind, or from `git ls-remote` while the installed version is the AUR's latest. When
+//! neither works, e.g. offline, the package is locked by its version only and marked
+//! `foreign: true`; `apply --locked` cannot rebuild it and refuses to run while it differs.
+//!
+//! `nixman apply --locked` reproduces the lockfile exactly, fetching versions that are no longer
+//! in the repositories from the Arch Linux Archive and building AUR packages at their recorded
+//! commit. Like `cargo --locked`, it refuses to run when the lockfile no longer matches
+//! `packages.yml`.
+
+use crate::condition::Host;
+use crate::versioning::FullVersion;
+use crate::{ApplyError, Config, InstallReason};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// A package at the exact version recorded in the lockfile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    /// The exact `epoch:version-release` string reported by pacman.
+    pub version: String,
+    /// Where a package from the AUR was built from, if it could be found.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aur: Option<AurSource>,
+    /// Whether the package is from outside the sync repositories, such as the AUR, whether or
+    /// not [`LockedPackage::aur`] could be found.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub foreign: bool,
+}
+
+/// The revision of the AUR git repository a package was built from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AurSource {
+    /// The package base, which names the repository.
+    pub base: String,
+    pub commit: String,
+}
+
+/// The contents of `packages.lock.yml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockFile {
+    pub packages: Vec<LockedPackage>,
+}
+
+impl LockFile {
+    /// Write the lockfile to `path` as YAML.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let yml = serde_yml::to_string(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, yml)
+    }
+
+    /// Read a lockfile previously written by [`LockFile::save`].
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let yml = std::fs::read_to_string(path)?;
+        crate::schema::from_str(&yml)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// The lockfile belonging to a YAML file: `packages.yml` → `packages.lock.yml`.
+#[must_use]
+pub fn lock_path(yml_path: &Path) -> PathBuf {
+    let stem = yml_path
+        .file_stem()
+        .map_or_else(|| "packages".into(), |stem| stem.to_string_lossy());
+    yml_path.with_file_name(format!("{stem}.lock.yml"))
+}
+
+/// The explicit package names declared in `config`, which the lockfile must cover.
+fn declared_names(config: &Config) -> BTreeSet<&str> {
+    config
+        .packages
+        .packages
+        .values()
+        .filter(|pkg| pkg.reason == InstallReason::Explicit)
+        .map(|pkg| pkg.name.as_str())
+        .collect()
+}
+
+/// Record the installed version of every explicit package declared in `config`.
+///
+/// `installed` holds exact `(name, version)` pairs, e.g. from `pacman -Q`; declared packages
+/// that are not installed are left out.
+#[must_use]
+pub fn resolve(config: &Config, installed: &[(String, String)]) -> LockFile {
+    let declared = declared_names(config);
+    let mut packages: Vec<LockedPackage> = installed
+        .iter()
+        .filter(|(name, _)| declared.contains(name.as_str()))
+        .map(|(name, version)| LockedPackage {
+            name: name.clone(),
+            version: version.clone(),
+            aur: None,
+            foreign: false,
+        })
+        .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    LockFile { packages }
+}
+
+/// All installed packages with their exact version strings (`pacman -Q`).
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+pub fn installed_exact_versions() -> std::io::Result<Vec<(String, String)>> {
+    let output = crate::pacman::pacman_list_installed()?;
+    Ok(crate::pacman::parse_package_lines(&output.stdout)
+        .map(|package| (package.name.to_string(), package.version.to_string()))
+        .collect())
+}
+
+/// Resolve the installed versions of the YAML's packages and write them to its lockfile.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read, pacman could not be executed, or the lockfile
+/// cannot be written.
+pub fn write_lock(yml_path: &Path) -> std::io::Result<PathBuf> {
+    let mut config = crate::read_effective_config(yml_path)?;
+    config.packages.retain_active(&Host::current());
+    let mut lock = resolve(&config, &installed_exact_versions()?);
+    let path = lock_path(yml_path);
+    let previous = LockFile::load(&path).ok();
+    record_aur_sources(&mut lock, previous.as_ref());
+    lock.save(&path)?;
+    Ok(path)
+}
+
+/// Record where the locked packages from the AUR were built from. Sources in the `previous`
+/// lockfile are kept for unchanged versions; the others are looked up, and left out if that
+/// fails.
+fn record_aur_sources(lock: &mut LockFile, previous: Option<&LockFile>) {
+    let Ok(foreign) = crate::aur::foreign_packages() else {
+        return;
+    };
+    let mut lookup = Vec::new();
+    for locked in &mut lock.packages {
+        if !foreign.iter().any(|(name, _)| *name == locked.name) {
+            continue;
+        }
+        locked.foreign = true;
+        locked.aur = previous
+            .and_then(|previous| {
+                previous.packages.iter().find(|package| {
+                    package.name == locked.name && package.version == locked.version
+                })
+            })
+            .and_then(|package| package.aur.clone());
+        if locked.aur.is_none() {
+            lookup.push(locked);
+        }
+    }
+    if lookup.is_empty() || crate::offline::is_offline() {
+        return;
+    }
+    let names: Vec<String> = lookup.iter().map(|locked| locked.name.clone()).collect();
+    let Ok(found) = crate::aur::info(&names) else {
+        return;
+    };
+    for locked in lookup {
+        let Some(package) = found.iter().find(|package| package.name == locked.name) else {
+            continue;
+        };
+        let base = package.package_base.as_deref().unwrap_or(&package.name);
+        let latest = package.version == locked.version;
+        locked.aur =
+            crate::builds::source_commit(base, &locked.version, latest).map(|commit| AurSource {
+                base: base.to_string(),
+                commit,
+            });
+    }
+}
+
+/// Packages whose installed version differs from the lockfile (or that are not installed).
+#[must_use]
+pub fn mismatched(lock: &LockFile, installed: &[(String, String)]) -> Vec<LockedPackage> {
+    lock.packages
+        .iter()
+        .filter(|locked| {
+            !installed
+                .iter()
+                .any(|(name, version)| name == &locked.name && version == &locked.version)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Where the locked packages to install come from.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LockedSources {
+    /// Repository packages, fetched from the Arch Linux Archive.
+    pub archive: Vec<LockedPackage>,
+    /// AUR packages, built at their recorded commit.
+    pub aur: Vec<LockedPackage>,
+    /// Foreign packages locked without a commit, which cannot be reproduced.
+    pub unreproducible: Vec<LockedPackage>,
+}
+
+/// Sort `packages` by where they can be installed from.
+///
+/// A package is foreign if the lockfile says so, or if it is in `foreign` (installed packages
+/// from outside the repositories, e.g. from `pacman -Qm`), which covers older lockfiles.
+#[must_use]
+pub fn split_sources(packages: Vec<LockedPackage>, foreign: &[(String, String)]) -> LockedSources {
+    let mut sources = LockedSources::default();
+    for locked in packages {
+        if locked.aur.is_some() {
+            sources.aur.push(locked);
+        } else if locked.foreign || foreign.iter().any(|(name, _)| *name == locked.name) {
+            sources.unreproducible.push(locked);
+        } else {
+            sources.archive.push(locked);
+        }
+    }
+    sources
+}
+
+/// Reproduce the lockfile exactly: remove undeclared explicit packages, then install every
+/// locked package whose installed version differs.
+///
+/// Packages come from the Arch Linux Archive or, for AUR packages with a recorded source, are
+/// built at the recorded commit. Foreign packages locked without a commit cannot be reproduced,
+/// so nothing is changed while one of them differs.
+///
+/// # Errors
+/// Returns an [`ApplyError`] if the lockfile is missing or out of date with the YAML, a foreign
+/// package that differs was locked without a commit, or removing or installing packages fails.
+pub fn apply_locked(
+    yml_path: &Path,
+    continue_on_error: bool,
+    noconfirm: bool,
+) -> Result<(), ApplyError> {
+    let path = lock_path(yml_path);
+    let lock =
+        LockFile::load(&path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    let mut config = crate::read_effective_config(yml_path).map_err(|e| e.to_string())?;
+    config.packages.retain_active(&Host::current());
+    let locked: BTreeSet<&str> = lock.packages.iter().map(|p| p.name.as_str()).collect();
+    if locked != declared_names(&config) {
+        return Err(format!(
+            "{} is out of date with {}; run `nixman apply` without --locked to update it",
+            path.display(),
+            yml_path.display()
+        )
+        .into());
+    }
+    let installed = installed_exact_versions().map_err(|e| e.to_string())?;
+    let foreign = crate::aur::foreign_packages().map_err(|e| e.to_string())?;
+    let sources = split_sources(mismatched(&lock, &installed), &foreign);
+    if !sources.unreproducible.is_empty() {
+        let names: Vec<String> = sources
+            .unreproducible
+            .into_iter()
+            .map(|locked| locked.name)
+            .collect();
+        return Err(ApplyError {
+            message: format!(
+                "Cannot reproduce {}: not from the repositories and locked without an AUR commit; \
+                 run `nixman freeze` while online to record it, or apply without --locked",
+                names.join(", ")
+            ),
+            failed_installs: names,
+            ..ApplyError::default()
+        });
+    }
+    let explicit = crate::installed_explicit_package_list().map_err(|e| e.to_string())?;
+    let (_, to_remove) =
+        crate::sync_packages_from_yaml(yml_path, &explicit).map_err(|e| e.to_string())?;
+    crate::execute_package_changes(&[], &to_remove, false, continue_on_error, noconfirm)?;
+    let to_install: Vec<(String, FullVersion)> = sources
+        .archive
+        .into_iter()
+        .map(|locked| {
+            let version: FullVersion = locked
+                .version
+                .parse()
+                .map_err(|e| format!("{} in the lockfile: {e}", locked.name))?;
+            Ok((locked.name, version))
+        })
+        .collect::<Result<_, String>>()?;
+    crate::archive::install_from_archive(&to_install, noconfirm)?;
+    crate::builds::install_at_commits(&aur_sources(&sources.aur), noconfirm)
+}
+
+/// The `(base, commit, names)` to build for the locked AUR packages, split packages of one
+/// revision together.
+fn aur_sources(packages: &[LockedPackage]) -> Vec<(String, String, Vec<String>)> {
+    let mut sources: Vec<(String, String, Vec<String>)> = Vec::new();
+    for (locked, aur) in packages
+        .iter()
+        .filter_map(|locked| Some((locked, locked.aur.as_ref()?)))
+    {
+        match sources
+            .iter_mut()
+            .find(|(base, commit, _)| *base == aur.base && *commit == aur.commit)
+        {
+            Some((_, _, names)) => names.push(locked.name.clone()),
+            None => sources.push((
+                aur.base.clone(),
+                aur.commit.clone(),
+                vec![locked.name.clone()],
+            )),
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lockfile sits next to the YAML and keeps exact versions of declared packages only.
+    #[test]
+    fn resolves_declared_packages() {
+        assert_eq!(
+            lock_path(Path::new("/home/me/.config/nixman/packages.yml")),
+            Path::new("/home/me/.config/nixman/packages.lock.yml")
+        );
+        let config: Config = serde_yml::from_str(
+            "packages:\n  - vim\n  - git\n  - name: perl-error\n    reason: dependency\n",
+        )
+        .unwrap();
+        let installed = vec![
+            ("git".to_string(), "2.45.0-1".to_string()),
+            ("perl-error".to_string(), "0.17029-5".to_string()),
+            ("vim".to_string(), "9.1.0330-1".to_string()),
+            ("zlib".to_string(), "1:1.3.1-1".to_string()),
+        ];
+        let lock = resolve(&config, &installed);
+        let names: Vec<&str> = lock.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["git", "vim"]);
+        assert_eq!(lock.packages[1].version, "9.1.0330-1");
+
+        let installed = vec![("git".to_string(), "2.46.0-1".to_string())];
+        let names: Vec<String> = mismatched(&lock, &installed)
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, vec!["git", "vim"]);
+
+        let lock: LockFile = serde_yml::from_str(
+            "packages:\n  - name: paru\n    version: 2.0.4-1\n    aur:\n      base: paru\n      commit: 5f6d0c2\n  - name: paru-debug\n    version: 2.0.4-1\n    aur:\n      base: paru\n      commit: 5f6d0c2\n  - name: git\n    version: 2.45.0-1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            aur_sources(&lock.packages),
+            vec![(
+                "paru".to_string(),
+                "5f6d0c2".to_string(),
+                vec!["paru".to_string(), "paru-debug".to_string()]
+            )]
+        );
+        let yml = serde_yml::to_string(&lock).unwrap();
+        assert_eq!(yml.matches("aur:").count(), 2);
+        assert_eq!(serde_yml::from_str::<LockFile>(&yml).unwrap(), lock);
+    }
+
+    /// Foreign packages without a commit are never sent to the archive, whether the lockfile
+    /// marks them or they are installed from outside the repositories.
+    #[test]
+    fn splits_locked_sources() {
+        let lock: LockFile = serde_yml::from_str(
+            "packages:\n  - name: paru\n    version: 2.0.4-1\n    aur:\n      base: paru\n      commit: 5f6d0c2\n  - name: yay\n    version: 12.3.5-1\n    foreign: true\n  - name: zoom\n    version: 6.0.2-1\n  - name: git\n    version: 2.45.0-1\n",
+        )
+        .unwrap();
+        let names = |packages: &[LockedPackage]| -> Vec<String> {
+            packages.iter().map(|locked| locked.name.clone()).collect()
+        };
+        let sources = split_sources(
+            lock.packages,
+            &[("zoom".to_string(), "6.0.1-1".to_string())],
+        );
+        assert_eq!(names(&sources.archive), ["git"]);
+        assert_eq!(names(&sources.aur), ["paru"]);
+        assert_eq!(names(&sources.unreproducible), ["yay", "zoom"]);
+        let yml = serde_yml::to_string(&LockFile {
+            packages: sources.unreproducible,
+        })
+        .unwrap();
+        assert_eq!(yml.matches("foreign: true").count(), 1);
+    }
+}
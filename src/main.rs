@@ -8,6 +8,20 @@
 //! - Freeze the current package state to YAML (optionally with versions)
 //! - Apply the YAML configuration to synchronize installed packages
 //! - Update all packages and update the YAML
+//! - Manage Flatpak, cargo, pipx, and npm packages alongside pacman packages
+//! - Preview the changes `apply` would make with `diff`
+//! - Audit a single package with `explain`
+//! - Screen-reader friendly output with `--plain-a11y`
+//! - Machine-readable output with `--output json`
+//!
+//! # Exit codes
+//!
+//! `diff`, `status`, and `apply --dry-run` follow a fixed contract so CI jobs and shell prompts
+//! can detect drift without parsing output:
+//!
+//! - `0`: the system is in sync with the YAML
+//! - `1`: an error occurred (including invalid command-line usage)
+//! - `2`: changes are pending
 //!
 //! # Example
 //!
@@ -28,10 +42,45 @@
     clippy::cargo,
     clippy::nursery
 )]
+// ratatui and indicatif depend on different major versions of unicode-width.
+#![cfg_attr(feature = "tui", allow(clippy::multiple_crate_versions))]
 
-use clap::{Parser, Subcommand};
-use nixman::{ensure_yml, parse_explicit_packages, write_package_list_to_yaml};
-mod pacman;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use nixman::applied::{self, Drift, DriftKind, LastApplied};
+use nixman::cascade::{self, Cascade};
+use nixman::condition::Host;
+use nixman::conflicts::{self, Conflict, Decisions, Direction, Resolution};
+use nixman::explain::{self, PlannedAction};
+use nixman::export::{self, ExportFormat};
+use nixman::flatpak::FlatpakBackend;
+use nixman::hook::{self, HookMode};
+use nixman::import::{self, ImportFormat};
+use nixman::init::{self, InitOptions};
+use nixman::keyring::{self, KeyringPolicy};
+use nixman::local;
+use nixman::plan::{self, ChangePlan, Resolved, Selection, SizeImpact};
+use nixman::render::{ChangeKind, Renderer, Style};
+use nixman::settings::{self, Settings};
+use nixman::snapshot::{self, Snapshot, SnapshotTool};
+use nixman::sources::{self, CargoSource, ExtraSource, NpmSource, PipxSource};
+use nixman::syncdb::{self, StalePolicy};
+use nixman::triage::{self, Decision, Triage};
+use nixman::{
+    ApplyMode, ApplyReport, Backend, CancelToken, InstallReason, PackageAction, Progress,
+    SourceChanges, ensure_yml,
+    pacman::{self, Elevation},
+    parse_explicit_packages, write_package_list_to_yaml,
+};
+use nixman::{
+    archive, audit, aur, banned, base, builds, bundle, clean, freeze, generations, lock, lockfile,
+    manager, names, notify, offline, origin, outdated, pacman_log, paths, profile, reasons, repos,
+    resume, retry, run, safety, schema, strict, tidy, verify, watch,
+};
+use serde_json::json;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 #[derive(Subcommand)]
 enum Commands {
@@ -41,33 +90,482 @@ enum Commands {
         packages: Vec<String>,
     },
     Update,
-    Freeze {
-        /// Include package versions in the YAML
+    /// Set up the config directory: the YAML, its ignore list, the settings, and optionally git
+    Init {
+        /// Record the installed packages, with their versions, without asking
+        #[arg(long)]
+        freeze: bool,
+        /// Put the config directory under git without asking
+        #[arg(long)]
+        git: bool,
+        /// Do not ask; only do what the flags turn on
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+    /// Write the installed packages to the YAML, after showing what would change
+    Freeze(FreezeArgs),
+    Apply(ApplyArgs),
+    /// Install the YAML's packages into a new system with pacstrap, e.g. during installation
+    ///
+    /// The new system is mounted at --root (default: /mnt).
+    Bootstrap,
+    /// Finish an apply that was deferred until after a reboot
+    Resume {
+        /// Path to the persisted plan (defaults to the one in the state directory)
+        #[arg(long)]
+        plan: Option<PathBuf>,
+    },
+    /// Show which packages `apply` would install or remove
+    Diff,
+    /// Summarize how installed packages compare to the YAML
+    Status,
+    /// List the packages declared in the YAML
+    List,
+    /// Validate the YAML file and check that every package exists in a repository or the AUR
+    Check {
+        /// Fail on packages declared twice with different versions and on unknown keys instead
+        /// of warning
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Open the YAML in $VISUAL or $EDITOR and save the edit only if it passes `check`
+    Edit,
+    /// Show available upgrades next to the versions pinned in the YAML
+    Outdated {
+        /// Only use `checkupdates`, which syncs a temporary copy of the databases, and fail
+        /// without it instead of reading the system's possibly stale ones
+        #[arg(long)]
+        safe: bool,
+    },
+    /// Upgrade the YAML to the current format version (`schema:`)
+    Migrate,
+    /// Review the planned changes in a terminal UI and apply only the staged ones
+    #[cfg(feature = "tui")]
+    Tui,
+    /// List past applies and freezes from the generation log
+    History {
+        /// List every package event in pacman's log instead, from before nixman was installed
+        #[arg(long)]
+        system: bool,
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+    /// Show the installs, removals, and upgrades nixman ran, with who ran them
+    Audit {
+        /// Only show the entries of USER
+        #[arg(long, value_name = "USER")]
+        user: Option<String>,
+        /// Only show the entries that acted on PACKAGE
+        #[arg(long, value_name = "PACKAGE")]
+        package: Option<String>,
+        /// Only show the last N entries
+        #[arg(long, value_name = "N")]
+        last: Option<usize>,
+    },
+    /// Check the files of the YAML's packages for modifications with `pacman -Qkk`
+    Verify,
+    /// Put the YAML's packages into a directory and install from it without the network
+    Bundle {
+        #[command(subcommand)]
+        action: BundleAction,
+    },
+    /// Periodically check for drift from the YAML and notify when it is found
+    Watch {
+        /// How often to check, e.g. 6h, 30m, 1d
+        #[arg(long, default_value = "6h", value_parser = watch::parse_interval)]
+        interval: Duration,
+        /// Check once and exit (used by the systemd timer)
+        #[arg(long)]
+        once: bool,
+        /// Clean the package caches after each check
+        #[arg(long)]
+        clean: bool,
+    },
+    /// Install a systemd user timer that checks for drift periodically
+    InstallTimer {
+        /// How often to check, e.g. 6h, 30m, 1d
+        #[arg(long, default_value = "6h", value_parser = watch::parse_interval)]
+        interval: Duration,
+        /// Also clean the package caches on each run
+        #[arg(long)]
+        clean: bool,
+    },
+    /// Install a pacman hook that records packages installed outside nixman in the YAML
+    InstallHook {
+        /// What the hook does: add new packages to the YAML, or only flag the missing ones
+        #[arg(long, value_name = "MODE", default_value = "add")]
+        mode: HookMode,
+        /// Remove the hook instead
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Normalize the YAML: fix key case, merge repeated entries, and sort
+    Tidy(TidyArgs),
+    /// List the named configs, or switch to another one
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Remove old package versions from the pacman and paru caches, keeping pinned ones
+    Clean {
+        /// How many versions of each package to keep (default: 3)
+        #[arg(long)]
+        keep: Option<usize>,
+        /// Only list the files that would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Mark packages as explicitly installed, in pacman's database and the YAML
+    Adopt {
+        /// The package(s) to mark as explicit
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+    /// Mark packages as installed as dependencies, in pacman's database and the YAML
+    Disown {
+        /// The package(s) to mark as dependencies
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+    /// Go through installed packages missing from the YAML and manage, ignore, or remove each
+    AdoptSystem,
+    /// Suggest packages whose pacman install reason should change to match the YAML
+    AuditReasons {
+        /// Re-mark the suggested packages with `pacman -D`
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Explain why a package is (or is not) installed and declared, and what apply would do
+    Explain {
+        /// The package to explain
+        package: String,
+    },
+    /// Show everything removing packages takes out with them, grouped per package
+    WhyRemove {
+        /// The packages to remove (default: the ones apply would remove)
+        packages: Vec<String>,
+    },
+    /// Merge packages from another manifest (Brewfile, Ansible tasks, plain list) into the YAML,
+    /// or record install times from pacman's log
+    Import {
+        /// The manifest format: brewfile, txt, ansible, or pacman-log
+        #[arg(long = "from", value_name = "FORMAT")]
+        format: ImportFormat,
+        /// The manifest to import (required, except for pacman-log: /var/log/pacman.log)
+        path: Option<PathBuf>,
+    },
+    /// Print the package list as a Nix snippet, plain list, or install script
+    Export {
+        /// The output format: nix, txt, or sh
+        #[arg(long = "to", value_name = "FORMAT")]
+        format: ExportFormat,
+        /// Use Home Manager's `home.packages` instead of `environment.systemPackages` (nix)
         #[arg(long)]
-        versioned: bool,
+        home_manager: bool,
+    },
+    /// Manage Flatpak applications (`flatpak:` section)
+    Flatpak {
+        #[command(subcommand)]
+        action: SourceAction,
+    },
+    /// Manage binaries installed with `cargo install` (`cargo:` section)
+    Cargo {
+        #[command(subcommand)]
+        action: SourceAction,
+    },
+    /// Manage applications installed with pipx (`pipx:` section)
+    Pipx {
+        #[command(subcommand)]
+        action: SourceAction,
+    },
+    /// Manage global npm packages (`npm:` section)
+    Npm {
+        #[command(subcommand)]
+        action: SourceAction,
+    },
+}
+
+/// Options for `tidy`.
+#[derive(Args)]
+struct TidyArgs {
+    /// Remove every version pin and requirement
+    #[arg(long, conflicts_with = "add_versions")]
+    strip_versions: bool,
+    /// Pin unpinned packages to their installed versions
+    #[arg(long)]
+    add_versions: bool,
+    /// Only report what would change, and exit with 2 if anything would
+    #[arg(long)]
+    check: bool,
+}
+
+impl TidyArgs {
+    /// What to do with versions.
+    const fn versions(&self) -> tidy::Versions {
+        if self.strip_versions {
+            tidy::Versions::Strip
+        } else if self.add_versions {
+            tidy::Versions::Pin
+        } else {
+            tidy::Versions::Keep
+        }
+    }
+}
+
+/// Options for `freeze`.
+#[derive(Args)]
+#[allow(clippy::struct_excessive_bools)]
+struct FreezeArgs {
+    /// Include package versions in the YAML
+    #[arg(long)]
+    versioned: bool,
+    /// Record the repository each package comes from
+    #[arg(long)]
+    origin: bool,
+    /// Only show what would change in the YAML, without writing it
+    #[arg(long)]
+    diff: bool,
+    /// Write the YAML without asking for confirmation
+    #[arg(long, short = 'y')]
+    yes: bool,
+    /// Write the packages as pacman lists them, keeping nothing of the current YAML; for very
+    /// large systems
+    #[arg(long, conflicts_with_all = ["origin", "diff"])]
+    stream: bool,
+    /// Only add the installed packages the YAML lacks, removing and changing nothing, without
+    /// asking
+    #[arg(long, conflicts_with = "stream")]
+    merge: bool,
+    /// Also record the extra sources (flatpak, cargo, pipx, npm) the YAML has no section for,
+    /// which puts them under nixman's management
+    #[arg(long, conflicts_with_all = ["stream", "merge"])]
+    add_sources: bool,
+    /// Run from the pacman hook: do nothing while nixman itself holds its lock, and never fail
+    /// the transaction over missing packages
+    #[arg(long, hide = true, requires = "merge")]
+    hook: bool,
+}
+
+/// Options for `apply`.
+#[derive(Args)]
+#[allow(clippy::struct_excessive_bools)]
+struct ApplyArgs {
+    /// Use paru instead of pacman
+    #[arg(
+        long,
+        help = "Use paru instead of pacman for applying packages from YAML"
+    )]
+    paru: bool,
+    /// Continue on errors (try all packages, don't abort on first failure)
+    #[arg(long, help = "Continue on errors when removing/installing packages")]
+    continue_on_error: bool,
+    /// Defer packages that need a reboot (e.g. DKMS modules after a kernel switch) and resume after boot
+    #[arg(
+        long,
+        help = "Defer packages that need a reboot and resume automatically after boot"
+    )]
+    resume_after_reboot: bool,
+    /// Only show what would change; exit with 2 if changes are pending
+    #[arg(
+        long,
+        help = "Show the pending changes without applying them (exit code 2 if any)"
+    )]
+    dry_run: bool,
+    /// Install older pinned versions from the Arch Linux Archive
+    #[arg(
+        long,
+        help = "Downgrade packages pinned to older versions using the Arch Linux Archive"
+    )]
+    allow_downgrade: bool,
+    /// Reproduce packages.lock.yml exactly instead of resolving the YAML
+    #[arg(
+        long,
+        conflicts_with_all = ["resume_after_reboot", "allow_downgrade"],
+        help = "Install the exact versions from packages.lock.yml; fail if it is out of date"
+    )]
+    locked: bool,
+    /// Apply only these packages of the plan
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "PACKAGES",
+        conflicts_with_all = ["locked", "resume_after_reboot"],
+        help = "Apply only these packages of the plan (comma-separated)"
+    )]
+    only: Vec<String>,
+    /// Leave these packages of the plan out
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "PACKAGES",
+        conflicts_with_all = ["locked", "resume_after_reboot"],
+        help = "Leave these packages of the plan out (comma-separated)"
+    )]
+    skip: Vec<String>,
+    /// Remove critical packages (kernel, base, systemd, ...) if the YAML says so
+    #[arg(
+        long,
+        help = "Allow removing critical packages such as the kernel, base, or systemd"
+    )]
+    force: bool,
+    /// Only install missing packages
+    #[arg(
+        long,
+        conflicts_with_all = ["remove_only", "locked"],
+        help = "Only install missing packages; leave unmanaged ones installed"
+    )]
+    install_only: bool,
+    /// Only remove unmanaged packages
+    #[arg(
+        long,
+        conflicts_with = "locked",
+        help = "Only remove unmanaged packages; don't install missing ones"
+    )]
+    remove_only: bool,
+    /// Add repositories from the `repos:` section that pacman.conf is missing
+    #[arg(
+        long,
+        help = "Write missing repos: to /etc/pacman.d/nixman-repos.conf and include it"
+    )]
+    write_pacman_conf: bool,
+    /// Show the plan and ask before changing anything
+    #[arg(
+        long,
+        conflicts_with = "dry_run",
+        help = "Ask for confirmation after showing the planned changes"
+    )]
+    confirm: bool,
+    /// Retry installs that fail because of the network up to N times
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Retry installs that fail with network errors up to N times, with backoff"
+    )]
+    retries: Option<u32>,
+    /// Build up to N AUR packages at once, in dependency order, instead of one after another
+    #[arg(
+        long,
+        value_name = "N",
+        help = "With paru, build up to N AUR packages at once in dependency order"
+    )]
+    aur_jobs: Option<usize>,
+    /// Refuse a base manifest that is not signed
+    #[arg(
+        long,
+        help = "Refuse to apply a base manifest without a valid signature"
+    )]
+    verify_signature: bool,
+    /// Snapshot tool from the `pre_apply_snapshot` setting
+    #[arg(skip)]
+    snapshot: Option<SnapshotTool>,
+}
+
+impl ApplyArgs {
+    /// The kinds of changes this apply makes.
+    const fn mode(&self) -> ApplyMode {
+        if self.install_only {
+            ApplyMode::InstallOnly
+        } else if self.remove_only {
+            ApplyMode::RemoveOnly
+        } else {
+            ApplyMode::Full
+        }
+    }
+
+    /// The packages of the plan this apply makes changes to.
+    fn selection(&self) -> Selection {
+        Selection {
+            only: self.only.clone(),
+            skip: self.skip.clone(),
+        }
+    }
+}
+
+/// Exit code: the system is in sync with the YAML.
+const EXIT_IN_SYNC: i32 = 0;
+/// Exit code: an error occurred.
+const EXIT_ERROR: i32 = 1;
+/// Exit code: changes are pending.
+const EXIT_CHANGES_PENDING: i32 = 2;
+
+/// Exit with [`EXIT_IN_SYNC`] or [`EXIT_CHANGES_PENDING`] depending on the pending changes.
+fn exit_for_changes(changes: &[SourceChanges]) -> ! {
+    if changes.iter().all(SourceChanges::is_empty) {
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    std::process::exit(EXIT_CHANGES_PENDING);
+}
+
+/// Actions available for every extra package source.
+#[derive(Subcommand)]
+enum SourceAction {
+    /// List installed packages from this source
+    List,
+    /// Install packages and add them to the YAML
+    Install {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+    /// Remove packages and drop them from the YAML
+    Remove {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+}
+
+/// Actions of `history`.
+#[derive(Subcommand, Clone, Copy)]
+enum HistoryAction {
+    /// Show the packages added and removed between two generations
+    Diff {
+        /// The earlier generation
+        a: u64,
+        /// The later generation
+        b: u64,
+    },
+}
+
+/// Actions of `profile`.
+#[derive(Subcommand)]
+enum ProfileAction {
+    /// List the profiles, marking the active one
+    List,
+    /// Make NAME the profile every later command works on (`default` for packages.yml)
+    Use { name: String },
+}
+
+/// Actions of `bundle`.
+#[derive(Subcommand)]
+enum BundleAction {
+    /// Download the YAML's packages and their dependencies into DIR, with an index
+    Create {
+        /// The directory to put the bundle in
+        dir: PathBuf,
     },
+    /// Install the packages of the bundle in DIR that are missing, without the network
     Apply {
-        /// Use paru instead of pacman
-        #[arg(
-            long,
-            help = "Use paru instead of pacman for applying packages from YAML"
-        )]
-        paru: bool,
-        /// Continue on errors (try all packages, don't abort on first failure)
-        #[arg(long, help = "Continue on errors when removing/installing packages")]
-        continue_on_error: bool,
+        /// The directory of the bundle
+        dir: PathBuf,
     },
 }
 
+/// Output formats selectable with `--output`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Human,
+    Json,
+}
+
 /// A simple CLI tool to list installed packages in Arch Linux and save them to
 /// a YAML file, mimicking the config approach of nix os.
 #[derive(Parser)]
 #[command(version, about)]
+#[allow(clippy::struct_excessive_bools)]
 struct Cli {
-    /// Run using pacstrap instead of pacman, useful for initial installs
+    /// Bootstrap a new system mounted at /mnt with pacstrap (same as `bootstrap --root /mnt`)
     #[arg(
         long,
-        help = "Run with pacstrap instead of pacman, useful for initial installs"
+        help = "Bootstrap a new system mounted at /mnt with pacstrap (same as `bootstrap --root /mnt`)"
     )]
     pacstrap: bool,
     /// Install package(s) using pacman (like pacman -S)
@@ -79,95 +577,2894 @@ struct Cli {
     /// Use paru instead of pacman for -S/--install
     #[arg(long, help = "Use paru instead of pacman for installing packages")]
     paru: bool,
+    /// Screen-reader friendly output: no color or symbols, one fact per line
+    #[arg(
+        long,
+        global = true,
+        help = "Screen-reader friendly output: explicit words, no color or symbols, one fact per line"
+    )]
+    plain_a11y: bool,
+    /// Output format for reports
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = OutputFormat::Human,
+        help = "Output format: human-readable text, or JSON on stdout with messages on stderr"
+    )]
+    output: OutputFormat,
+    /// Wait for other nixman/pacman processes instead of failing
+    #[arg(
+        long,
+        global = true,
+        help = "Wait for running nixman/pacman processes to finish instead of failing"
+    )]
+    wait: bool,
+    /// Never stop at pacman/paru prompts, for scripts, timers, and provisioning tools
+    #[arg(
+        long,
+        global = true,
+        help = "Pass --noconfirm to pacman/paru so nixman can run unattended"
+    )]
+    noconfirm: bool,
+    /// How to run pacman as root
+    #[arg(
+        long,
+        global = true,
+        value_name = "TOOL",
+        help = "Run privileged commands with sudo, doas, run0, or none (default: none as root, else the first one installed)"
+    )]
+    elevation: Option<Elevation>,
+    /// Run privileged commands directly
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "elevation",
+        help = "Run privileged commands directly, without sudo or another tool (same as --elevation none)"
+    )]
+    no_sudo: bool,
+    /// Operate on the system installed under this directory
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        help = "Pass --root DIR to every pacman/paru run, to manage a chroot or a test root (bootstrap: where the new system is mounted, default /mnt)"
+    )]
+    root: Option<PathBuf>,
+    /// Use this pacman database directory
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        help = "Pass --dbpath DIR to every pacman/paru run, e.g. a throwaway database"
+    )]
+    dbpath: Option<PathBuf>,
+    /// Run pacman inside the system mounted at this directory
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        conflicts_with_all = ["root", "dbpath"],
+        help = "Run every pacman command inside the system mounted at DIR with arch-chroot, e.g. from a live ISO"
+    )]
+    chroot: Option<PathBuf>,
+    /// Install only from the package cache and skip every network query
+    #[arg(
+        long,
+        global = true,
+        help = "Install only from /var/cache/pacman/pkg with pacman -U and skip every network query"
+    )]
+    offline: bool,
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
-fn main() {
-    let yml_path = match ensure_yml() {
-        Ok(path) => path,
+/// Turn on the flags the settings enable; flags given on the command line stay on.
+fn apply_settings(cli: &mut Cli, settings: &Settings) {
+    cli.paru |= settings.uses_paru();
+    let elevation = if cli.no_sudo {
+        Some(Elevation::None)
+    } else {
+        cli.elevation.or(settings.elevation)
+    };
+    if let Some(elevation) = elevation {
+        elevation.set_current();
+    }
+    cli.noconfirm |= settings.noconfirm.unwrap_or(false);
+    if let Some(needed) = settings.needed {
+        pacman::set_needed(needed);
+    }
+    pacman::set_programs(settings.programs());
+    match &mut cli.command {
+        Some(Commands::Freeze(args)) => {
+            args.versioned |= settings.versioned.unwrap_or(false);
+            args.origin |= settings.origins.unwrap_or(false);
+        }
+        Some(Commands::Clean { keep, .. }) => *keep = keep.or(settings.cache_keep),
+        Some(Commands::Apply(args)) => {
+            args.paru |= settings.uses_paru();
+            args.continue_on_error |= settings.continue_on_error.unwrap_or(false);
+            args.snapshot = settings.pre_apply_snapshot;
+        }
+        _ => {}
+    }
+}
+
+/// Which locks a command needs: `(nixman lock, free pacman database)`.
+const fn lock_requirements(cli: &Cli) -> (bool, bool) {
+    if cli.install.is_some() || cli.remove.is_some() {
+        return (true, true);
+    }
+    // pacstrap installs into the target's database, not the host's.
+    if cli.pacstrap {
+        return (true, false);
+    }
+    match &cli.command {
+        Some(
+            Commands::S { .. }
+            | Commands::Update
+            | Commands::Resume { .. }
+            | Commands::Adopt { .. }
+            | Commands::Disown { .. }
+            | Commands::Bundle {
+                action: BundleAction::Apply { .. },
+            },
+        ) => (true, true),
+        Some(Commands::Clean { dry_run, .. }) => (!*dry_run, !*dry_run),
+        Some(Commands::Tidy(args)) => (!args.check, false),
+        Some(Commands::AuditReasons { apply }) => (*apply, *apply),
+        #[cfg(feature = "tui")]
+        Some(Commands::Tui) => (true, true),
+        Some(Commands::Apply(args)) => (!args.dry_run, !args.dry_run),
+        Some(
+            Commands::Freeze(_)
+            | Commands::Init { .. }
+            | Commands::Bootstrap
+            | Commands::Import { .. }
+            | Commands::AdoptSystem
+            | Commands::Migrate
+            | Commands::Edit
+            | Commands::Bundle {
+                action: BundleAction::Create { .. },
+            },
+        ) => (true, false),
+        Some(
+            Commands::Flatpak { action }
+            | Commands::Cargo { action }
+            | Commands::Pipx { action }
+            | Commands::Npm { action },
+        ) => (!matches!(action, SourceAction::List), false),
+        Some(
+            Commands::Diff
+            | Commands::Status
+            | Commands::List
+            | Commands::Check { .. }
+            | Commands::Outdated { .. }
+            | Commands::History { .. }
+            | Commands::Audit { .. }
+            | Commands::Verify
+            | Commands::Explain { .. }
+            | Commands::WhyRemove { .. }
+            | Commands::Export { .. }
+            | Commands::Watch { .. }
+            | Commands::InstallTimer { .. }
+            | Commands::InstallHook { .. }
+            | Commands::Profile { .. },
+        )
+        | None => (false, false),
+    }
+}
+
+/// What a command does with the YAML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigUse {
+    /// It does not touch the YAML.
+    Unused,
+    /// It reads the YAML, which must exist.
+    Read,
+    /// It records packages in the YAML, creating it if it does not exist.
+    Write,
+}
+
+/// What the command does with the YAML.
+const fn config_use(cli: &Cli) -> ConfigUse {
+    if cli.install.is_some() {
+        return ConfigUse::Write;
+    }
+    if cli.remove.is_some() {
+        return ConfigUse::Unused;
+    }
+    if cli.pacstrap {
+        return ConfigUse::Read;
+    }
+    match &cli.command {
+        Some(Commands::Freeze(args)) if args.diff => ConfigUse::Read,
+        Some(Commands::WhyRemove { packages }) if !packages.is_empty() => ConfigUse::Unused,
+        Some(
+            Commands::Update
+            | Commands::Freeze(_)
+            | Commands::Adopt { .. }
+            | Commands::Disown { .. }
+            | Commands::AdoptSystem
+            | Commands::Import { .. },
+        ) => ConfigUse::Write,
+        Some(
+            Commands::Flatpak { action }
+            | Commands::Cargo { action }
+            | Commands::Pipx { action }
+            | Commands::Npm { action },
+        ) if !matches!(action, SourceAction::List) => ConfigUse::Write,
+        Some(
+            Commands::S { .. }
+            | Commands::Init { .. }
+            | Commands::Resume { .. }
+            | Commands::History { .. }
+            | Commands::Audit { .. }
+            | Commands::InstallTimer { .. }
+            | Commands::InstallHook { .. }
+            | Commands::Profile { .. }
+            | Commands::Bundle {
+                action: BundleAction::Apply { .. },
+            },
+        )
+        | None => ConfigUse::Unused,
+        Some(_) => ConfigUse::Read,
+    }
+}
+
+/// The YAML of the active profile. If the active profile cannot be read, commands that use the
+/// YAML fail rather than fall back to another one.
+fn active_config(renderer: Renderer, config_use: ConfigUse) -> PathBuf {
+    match profile::active() {
+        Ok(name) => profile::profile_path(&name),
+        Err(_) if config_use == ConfigUse::Unused => paths::config_file(),
+        Err(e) => fail(renderer, &format!("Failed to read the active profile: {e}")),
+    }
+}
+
+/// Take the nixman lock, or fail. From the pacman hook, a held lock means nixman started the
+/// transaction and records its own changes, so the hook exits quietly.
+fn take_lock(renderer: Renderer, cli: &Cli) -> lock::LockGuard {
+    lock::default_lock_path()
+        .and_then(|path| lock::acquire(path, cli.wait))
+        .unwrap_or_else(|e| {
+            let from_hook = matches!(&cli.command, Some(Commands::Freeze(args)) if args.hook);
+            if from_hook && e.kind() == std::io::ErrorKind::WouldBlock {
+                std::process::exit(EXIT_IN_SYNC);
+            }
+            fail(renderer, &e.to_string())
+        })
+}
+
+/// Apply `--offline`, refusing the commands that cannot work without the network.
+fn set_offline(renderer: Renderer, cli: &Cli) {
+    offline::set_offline(cli.offline);
+    if cli.offline
+        && matches!(
+            cli.command,
+            Some(Commands::Update | Commands::Outdated { .. })
+        )
+    {
+        fail(
+            renderer,
+            "update and outdated need the network; drop --offline",
+        );
+    }
+}
+
+/// The backends the command runs: pacman for everything that looks at packages, and paru as
+/// well when it installs or removes them with `--paru`. With `--chroot`, pacman runs inside
+/// the system, which [`check_chroot`] checks instead.
+fn required_backends(cli: &Cli, modifies: bool) -> Vec<Backend> {
+    if cli.chroot.is_some() {
+        return Vec::new();
+    }
+    let pacman = cli.install.is_some()
+        || cli.remove.is_some()
+        || cli.pacstrap
+        || match &cli.command {
+            Some(Commands::Init { freeze, .. }) => *freeze,
+            Some(Commands::Tidy(args)) => args.add_versions,
+            Some(
+                Commands::Edit
+                | Commands::List
+                | Commands::Migrate
+                | Commands::History { .. }
+                | Commands::Audit { .. }
+                | Commands::Import { .. }
+                | Commands::Export { .. }
+                | Commands::InstallTimer { .. }
+                | Commands::InstallHook { .. }
+                | Commands::Profile { .. }
+                | Commands::Flatpak { .. }
+                | Commands::Cargo { .. }
+                | Commands::Pipx { .. }
+                | Commands::Npm { .. },
+            ) => false,
+            _ => true,
+        };
+    let paru = pacman && cli.paru && modifies;
+    [(pacman, Backend::Pacman), (paru, Backend::Paru)]
+        .into_iter()
+        .filter_map(|(required, backend)| required.then_some(backend))
+        .collect()
+}
+
+/// With `--chroot`, check that the system can be entered, and refuse what cannot run inside it.
+fn check_chroot(renderer: Renderer, cli: &Cli) {
+    let Some(dir) = &cli.chroot else {
+        return;
+    };
+    if cli.paru || matches!(&cli.command, Some(Commands::Apply(args)) if args.paru) {
+        fail(
+            renderer,
+            "paru refuses to run as root, so it cannot run in a --chroot; install AUR packages \
+             from inside the system",
+        );
+    }
+    if cli.offline {
+        fail(
+            renderer,
+            "--offline installs from the host's package cache, not the chroot's; drop one of them",
+        );
+    }
+    if !pacman::on_path("arch-chroot") {
+        fail(
+            renderer,
+            "arch-chroot not found on PATH; install arch-install-scripts to use --chroot",
+        );
+    }
+    if !dir.join("var/lib/pacman").is_dir() {
+        fail(
+            renderer,
+            &format!(
+                "{} has no pacman database (var/lib/pacman); is the system mounted there?",
+                dir.display()
+            ),
+        );
+    }
+}
+
+/// Whether the command installs packages at the versions in the sync databases.
+const fn installs_from_sync_databases(cli: &Cli) -> bool {
+    if cli.install.is_some() {
+        return true;
+    }
+    match &cli.command {
+        Some(
+            Commands::S { .. }
+            | Commands::Bundle {
+                action: BundleAction::Create { .. },
+            },
+        ) => true,
+        Some(Commands::Apply(args)) => !args.dry_run && !args.locked && !args.remove_only,
+        _ => false,
+    }
+}
+
+/// Look for keyring problems that would fail the first signature check, and refresh the keyring
+/// first if the settings say so or the user agrees.
+fn check_keyring(renderer: Renderer, settings: &Settings, noconfirm: bool) {
+    let policy = settings.keyring_check.unwrap_or_default();
+    if policy == KeyringPolicy::Off {
+        return;
+    }
+    let issues = keyring::check().unwrap_or_else(|e| {
+        eprintln!("Warning: could not check pacman's keyring: {e}");
+        Vec::new()
+    });
+    if issues.is_empty() {
+        return;
+    }
+    for issue in &issues {
+        eprintln!("Warning: {issue}");
+    }
+    let refresh = policy == KeyringPolicy::Refresh
+        || (!noconfirm
+            && std::io::stdin().is_terminal()
+            && ask("Refresh the keyring first?", true));
+    if !refresh {
+        eprintln!(
+            "Warning: installs may fail with signature errors. Set `keyring_check: refresh` to refresh the keyring first."
+        );
+        return;
+    }
+    for step in keyring::refresh_steps(&issues) {
+        renderer.print(&format!("Refreshing the keyring: {step}"));
+        match keyring::refresh(step, noconfirm) {
+            Ok(status) if status.success() => {}
+            Ok(_) => fail(
+                renderer,
+                "Refreshing the keyring failed; not installing anything.",
+            ),
+            Err(e) => fail(renderer, &format!("Failed to refresh the keyring: {e}")),
+        }
+    }
+}
+
+/// Warn about stale sync databases, or upgrade the system first, as the settings say.
+fn check_sync_databases(renderer: Renderer, settings: &Settings, use_paru: bool, noconfirm: bool) {
+    let policy = settings.stale_sync.unwrap_or_default();
+    if policy == StalePolicy::Off {
+        return;
+    }
+    let max_age = settings
+        .max_sync_age()
+        .unwrap_or_else(|e| fail(renderer, &format!("Invalid max_sync_age: {e}")));
+    let age = match syncdb::database_age(&syncdb::sync_dir()) {
+        Ok(age) => age,
         Err(e) => {
-            eprintln!("Failed to ensure config file: {e}");
-            std::process::exit(1);
+            eprintln!("Warning: could not check the sync databases: {e}");
+            return;
         }
     };
-    println!("Using config file: {}", yml_path.display());
+    if !syncdb::is_stale(age, max_age) {
+        return;
+    }
+    let state = age.map_or_else(
+        || "missing".to_string(),
+        |age| format!("{} old", syncdb::describe_age(age)),
+    );
+    if policy == StalePolicy::Warn {
+        eprintln!(
+            "Warning: pacman's sync databases are {state}, so installs may fail with 404s. Run `nixman update` first, or set `stale_sync: upgrade`."
+        );
+        return;
+    }
+    renderer.print(&format!(
+        "pacman's sync databases are {state}; upgrading the system first."
+    ));
+    let status = if use_paru {
+        pacman::paru_update(noconfirm)
+    } else {
+        pacman::pacman_update(noconfirm)
+    };
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(_) => fail(renderer, "System upgrade failed; not installing anything."),
+        Err(e) => fail(renderer, &format!("Failed to run the system upgrade: {e}")),
+    }
+}
 
-    let cli = Cli::parse();
-    if let Some(packages) = cli.remove {
-        let use_paru = cli.paru;
-        let status = if use_paru {
-            pacman::paru_remove(&packages).expect("Failed to execute paru -Rns")
-        } else {
-            pacman::pacman_remove(&packages, true).expect("Failed to execute sudo pacman -Rns")
-        };
-        std::process::exit(status.code().unwrap_or(1));
+/// Print the pending install/remove changes through the shared renderer, the pacman ones with
+/// the side that moved since the last apply where `drift` knows it.
+fn print_changes(renderer: Renderer, changes: &[SourceChanges], drift: &[Drift]) {
+    let to_install: usize = changes.iter().map(|c| c.to_install.len()).sum();
+    let to_remove: usize = changes.iter().map(|c| c.to_remove.len()).sum();
+    if to_install == 0 && to_remove == 0 {
+        renderer.print(&renderer.in_sync());
+        return;
     }
-    if let Some(packages) = cli.install {
-        let use_paru = cli.paru;
-        let status = if use_paru {
-            pacman::paru_install(&packages).expect("Failed to execute paru -S")
-        } else {
-            pacman::pacman_install(&packages, true).expect("Failed to execute sudo pacman -S")
-        };
-        if status.success() {
-            let output = pacman::pacman_list_explicit().expect("Failed to execute pacman -Qe");
-            let output = String::from_utf8_lossy(&output.stdout);
-            let package_list = parse_explicit_packages(&output, false); // no versions by default
-            write_package_list_to_yaml(&package_list, &yml_path).expect("Failed to write to YAML");
-            println!("Updated package list written to {}", yml_path.display());
-        }
-        std::process::exit(status.code().unwrap_or(1));
-    }
-    if let Some(Commands::S { packages }) = cli.command {
-        let use_paru = cli.paru;
-        let status = if use_paru {
-            pacman::paru_install(&packages).expect("Failed to execute paru -S")
-        } else {
-            pacman::pacman_install(&packages, true).expect("Failed to execute sudo pacman -S")
-        };
-        std::process::exit(status.code().unwrap_or(1));
-    } else if matches!(cli.command, Some(Commands::Update)) {
-        let use_paru = cli.paru;
-        let status = if use_paru {
-            pacman::paru_update().expect("Failed to execute paru -Syyu")
-        } else {
-            pacman::pacman_update().expect("Failed to execute sudo pacman -Syyu")
-        };
-        if status.success() {
-            let output = pacman::pacman_list_explicit().expect("Failed to execute pacman -Qe");
-            let output = String::from_utf8_lossy(&output.stdout);
-            let package_list = parse_explicit_packages(&output, true);
-            write_package_list_to_yaml(&package_list, &yml_path).expect("Failed to write to YAML");
-            println!("Updated package list written to {}", yml_path.display());
-        }
-        std::process::exit(status.code().unwrap_or(1));
-    } else if let Some(Commands::Freeze { versioned }) = cli.command {
-        let output = pacman::pacman_list_explicit().expect("Failed to execute pacman command");
-        let output = String::from_utf8_lossy(&output.stdout);
-        let package_list = parse_explicit_packages(&output, versioned);
-        write_package_list_to_yaml(&package_list, &yml_path).expect("Failed to write to YAML");
-        println!("Frozen package list written to {}", yml_path.display());
-        std::process::exit(0);
-    } else if let Some(Commands::Apply {
-        paru,
-        continue_on_error,
-    }) = cli.command
-    {
-        match nixman::apply_packages_from_yaml(&yml_path, paru, continue_on_error) {
-            Ok(()) => {
-                println!("Apply completed successfully.");
-                std::process::exit(0);
-            }
-            Err(msg) => {
-                eprintln!("{msg}");
-                std::process::exit(1);
+    for change in changes {
+        if change.source == "pacman" && !drift.is_empty() {
+            for (kind, names) in [
+                (ChangeKind::Added, &change.to_install),
+                (ChangeKind::Removed, &change.to_remove),
+            ] {
+                let mut sorted: Vec<&String> = names.iter().collect();
+                sorted.sort();
+                for name in sorted {
+                    let detail = drift
+                        .iter()
+                        .find(|drift| drift.name == *name)
+                        .map(|drift| drift.kind.to_string());
+                    renderer.print(&renderer.change(kind, name, detail.as_deref()));
+                }
             }
+            continue;
         }
+        let detail = (change.source != "pacman").then_some(change.source);
+        renderer.print_all(renderer.changes(ChangeKind::Added, &change.to_install, detail));
+        renderer.print_all(renderer.changes(ChangeKind::Removed, &change.to_remove, detail));
+    }
+    renderer.print_all(renderer.summary(&[("to install", to_install), ("to remove", to_remove)]));
+}
+
+/// Which side moved for each pending pacman change in `changes` since the last apply, warning
+/// if its state cannot be read.
+fn drift_of(changes: &[SourceChanges]) -> Vec<Drift> {
+    applied::drift(changes).unwrap_or_else(|e| {
+        eprintln!("Warning: ignoring the state of the last apply: {e}");
+        Vec::new()
+    })
+}
+
+/// Exit with an error message (and a JSON error document in JSON mode).
+fn fail(renderer: Renderer, message: &str) -> ! {
+    if renderer.is_json() {
+        renderer.emit_json(&json!({ "success": false, "error": message }));
+    }
+    eprintln!("{message}");
+    std::process::exit(EXIT_ERROR);
+}
+
+/// `-R`: remove packages.
+fn run_remove(packages: &[String], use_paru: bool, noconfirm: bool) -> ! {
+    let status = if use_paru {
+        pacman::paru_remove(packages, noconfirm).expect("Failed to execute paru -Rns")
+    } else {
+        pacman::pacman_remove(packages, true, noconfirm).expect("Failed to execute pacman -Rns")
+    };
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// `-S`: install packages, then record the new package list (no versions by default).
+fn run_install(packages: &[String], use_paru: bool, noconfirm: bool, yml_path: &Path) -> ! {
+    let status = if use_paru {
+        pacman::paru_install(packages, noconfirm).expect("Failed to execute paru -S")
     } else {
-        let packages = pacman::pacman_list_explicit().expect("Failed to execute pacman command");
-        let output = String::from_utf8_lossy(&packages.stdout);
-        let package_list = parse_explicit_packages(&output, true);
-        write_package_list_to_yaml(&package_list, "packages.yml").expect("Failed to write to YAML");
+        pacman::pacman_install(packages, true, noconfirm).expect("Failed to execute pacman -S")
+    };
+    if status.success() {
+        freeze::write_installed(yml_path, false, false, false).unwrap_or_else(|e| {
+            eprintln!("Failed to update {}: {e}", yml_path.display());
+            std::process::exit(EXIT_ERROR);
+        });
+        println!("Updated package list written to {}", yml_path.display());
+    }
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Fail if the YAML at `yml_path`, if there is one, bans any of `packages`.
+fn refuse_banned(renderer: Renderer, yml_path: &Path, packages: &[String]) {
+    if !yml_path.exists() {
+        return;
+    }
+    let config = nixman::read_effective_config(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read YAML: {e}")));
+    let blocked = banned::blocked(&config, packages);
+    if !blocked.is_empty() {
+        fail(
+            renderer,
+            &format!(
+                "Refusing to install banned packages: {}\nRemove them from the YAML's banned: section first.",
+                blocked.join(", ")
+            ),
+        );
+    }
+}
+
+/// `s`: install packages without touching the YAML.
+fn run_s(packages: &[String], use_paru: bool, noconfirm: bool) -> ! {
+    let status = if use_paru {
+        pacman::paru_install(packages, noconfirm).expect("Failed to execute paru -S")
+    } else {
+        pacman::pacman_install(packages, true, noconfirm).expect("Failed to execute pacman -S")
+    };
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// `adopt`/`disown`: change the install reason in pacman's database, then record it in the YAML.
+fn run_mark(packages: &[String], reason: InstallReason, yml_path: &Path) -> ! {
+    let status = pacman::pacman_mark(packages, reason).expect("Failed to execute pacman -D");
+    if status.success() {
+        nixman::set_install_reason(yml_path, packages, reason).expect("Failed to write to YAML");
+        println!("Updated package list written to {}", yml_path.display());
+    }
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// `audit-reasons`: list packages whose install reason disagrees with the YAML, and re-mark
+/// them with `--apply`.
+fn run_audit_reasons(renderer: Renderer, yml_path: &Path, apply: bool) -> ! {
+    let fixes = reasons::audit(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to query pacman: {e}")));
+    let label = |reason: InstallReason| match reason {
+        InstallReason::Explicit => "explicit",
+        InstallReason::Dependency => "dependency",
+    };
+    if renderer.is_json() {
+        renderer.emit_json(&json!({ "fixes": fixes }));
+    } else if fixes.is_empty() {
+        renderer.print("All install reasons match the YAML.");
+    } else {
+        let rows: Vec<(Vec<String>, bool)> = fixes
+            .iter()
+            .map(|fix| {
+                (
+                    vec![
+                        fix.name.clone(),
+                        label(fix.installed).to_string(),
+                        label(fix.suggested).to_string(),
+                        fix.why.to_string(),
+                    ],
+                    false,
+                )
+            })
+            .collect();
+        renderer.print_all(renderer.table(&["NAME", "INSTALLED", "SUGGESTED", "WHY"], &rows));
+    }
+    if fixes.is_empty() {
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    if !apply {
+        if !renderer.is_json() {
+            renderer.print("Run with --apply to re-mark these packages.");
+        }
+        std::process::exit(EXIT_CHANGES_PENDING);
+    }
+    let status = reasons::apply(&fixes).expect("Failed to execute pacman -D");
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// `update`: upgrade the system, then record versioned packages.
+fn run_update(use_paru: bool, noconfirm: bool, yml_path: &Path) -> ! {
+    let started = Instant::now();
+    let status = if use_paru {
+        pacman::paru_update(noconfirm).expect("Failed to execute paru -Syyu")
+    } else {
+        pacman::pacman_update(noconfirm).expect("Failed to execute pacman -Syyu")
+    };
+    if status.success() {
+        freeze::write_installed(yml_path, true, false, false).unwrap_or_else(|e| {
+            eprintln!("Failed to update {}: {e}", yml_path.display());
+            std::process::exit(EXIT_ERROR);
+        });
+        println!("Updated package list written to {}", yml_path.display());
+    }
+    let _ = notify::finished("update", started.elapsed(), status.success());
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// `init`: set up the config directory, asking whether to freeze and to use git unless the
+/// flags or `yes` settle it.
+fn run_init(renderer: Renderer, freeze: bool, git: bool, yes: bool) -> ! {
+    let dir = paths::config_dir();
+    if dir.join(paths::CONFIG_FILE).exists() {
+        fail(
+            renderer,
+            &format!(
+                "{} already exists; run `nixman freeze` to update it",
+                paths::config_file().display()
+            ),
+        );
+    }
+    let options = InitOptions {
+        freeze: freeze || (!yes && ask("Record the installed packages now?", true)),
+        git: git || (!yes && ask("Put the config directory under git?", false)),
+    };
+    let report = init::init(&dir, options).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to set up {}: {e}", dir.display()),
+        )
+    });
+    renderer.print(&format!("Created {}", report.config.display()));
+    renderer.print(&format!("Ignoring {}", init::DEFAULT_IGNORE.join(", ")));
+    if report.frozen {
+        record_generation(true, None);
+        match lockfile::write_lock(&report.config) {
+            Ok(path) => renderer.print(&format!("Lockfile written to {}", path.display())),
+            Err(e) => eprintln!("Warning: failed to write the lockfile: {e}"),
+        }
+    }
+    if let Some(settings) = &report.settings {
+        renderer.print(&format!("Created {}", settings.display()));
+    }
+    if let Some(repo) = &report.git {
+        renderer.print(&format!(
+            "Initialized a git repository in {}; commit when ready",
+            repo.display()
+        ));
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `freeze`: show what would change in the YAML, then write the current package state to it
+/// once confirmed.
+fn run_freeze(renderer: Renderer, yml_path: &Path, args: &FreezeArgs, noconfirm: bool) -> ! {
+    if args.stream {
+        run_streamed_freeze(renderer, yml_path, args.versioned, args.yes || noconfirm);
+    }
+    if args.merge {
+        run_merged_freeze(renderer, yml_path, args);
+    }
+    let (config, diff) = freeze::preview(yml_path, args.versioned, args.origin, args.add_sources)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to freeze: {e}")));
+    if renderer.is_json() && args.diff {
+        renderer.emit_json(&diff);
+    } else if !diff.is_empty() {
+        print_freeze_diff(renderer, &diff);
+    }
+    if args.diff {
+        std::process::exit(if diff.is_empty() {
+            EXIT_IN_SYNC
+        } else {
+            EXIT_CHANGES_PENDING
+        });
+    }
+    if !diff.is_empty() && !args.yes && !noconfirm && !confirmed() {
+        renderer.print("Nothing was written.");
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    nixman::write_config(&config, yml_path).expect("Failed to write to YAML");
+    println!("Frozen package list written to {}", yml_path.display());
+    record_generation(true, None);
+    match lockfile::write_lock(yml_path) {
+        Ok(path) => println!("Lockfile written to {}", path.display()),
+        Err(e) => eprintln!("Warning: failed to update the lockfile: {e}"),
+    }
+    std::process::exit(0);
+}
+
+/// `freeze --stream`: replace the YAML with the packages as `pacman -Qe` lists them, once
+/// confirmed if the YAML has content.
+fn run_streamed_freeze(renderer: Renderer, yml_path: &Path, versioned: bool, yes: bool) -> ! {
+    let has_content = std::fs::metadata(yml_path).is_ok_and(|metadata| metadata.len() > 0);
+    if has_content && !yes {
+        renderer.print(&format!(
+            "--stream replaces {} with the installed packages; groups, conditions, and every \
+             other section are dropped.",
+            yml_path.display()
+        ));
+        if !confirmed() {
+            renderer.print("Nothing was written.");
+            std::process::exit(EXIT_IN_SYNC);
+        }
+    }
+    let written = freeze::write_streamed(yml_path, versioned).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to record the installed packages: {e}"),
+        )
+    });
+    println!("{written} packages written to {}", yml_path.display());
+    record_generation(true, None);
+    match lockfile::write_lock(yml_path) {
+        Ok(path) => println!("Lockfile written to {}", path.display()),
+        Err(e) => eprintln!("Warning: failed to update the lockfile: {e}"),
+    }
+    std::process::exit(0);
+}
+
+/// `freeze --merge`: add the installed packages and groups the YAML lacks, keeping every entry;
+/// with `--diff`, only list them.
+fn run_merged_freeze(renderer: Renderer, yml_path: &Path, args: &FreezeArgs) -> ! {
+    let (config, added) = freeze::preview_merge(yml_path, args.versioned, args.origin)
+        .unwrap_or_else(|e| {
+            fail(
+                renderer,
+                &format!("Failed to read the installed packages: {e}"),
+            )
+        });
+    if args.diff {
+        if renderer.is_json() {
+            renderer.emit_json(&added);
+        } else if !added.is_empty() {
+            renderer.print(&format!("Not in {}:", yml_path.display()));
+            renderer.print_all(renderer.changes(ChangeKind::Added, &added, None));
+            renderer.print("Run `nixman freeze --merge` to add them.");
+        }
+        // From the hook, pacman would report a nonzero exit as a failed transaction.
+        std::process::exit(if added.is_empty() || args.hook {
+            EXIT_IN_SYNC
+        } else {
+            EXIT_CHANGES_PENDING
+        });
+    }
+    if added.is_empty() {
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    renderer.print_all(renderer.changes(ChangeKind::Added, &added, None));
+    nixman::write_config(&config, yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to write to YAML: {e}")));
+    println!("Updated package list written to {}", yml_path.display());
+    record_generation(true, None);
+    match lockfile::write_lock(yml_path) {
+        Ok(path) => println!("Lockfile written to {}", path.display()),
+        Err(e) => eprintln!("Warning: failed to update the lockfile: {e}"),
+    }
+    std::process::exit(0);
+}
+
+/// Print the entries a freeze would add, remove, or change in the YAML.
+fn print_freeze_diff(renderer: Renderer, diff: &freeze::FreezeDiff) {
+    renderer.print_all(renderer.changes(ChangeKind::Added, &diff.added, None));
+    renderer.print_all(renderer.changes(ChangeKind::Removed, &diff.removed, None));
+    for entry in &diff.changed {
+        let detail = format!("{} -> {}", entry.before, entry.after);
+        renderer.print(&renderer.change(ChangeKind::Changed, &entry.name, Some(&detail)));
+    }
+    renderer.print_all(renderer.summary(&[
+        ("to add", diff.added.len()),
+        ("to remove", diff.removed.len()),
+        ("to change", diff.changed.len()),
+    ]));
+}
+
+/// Read the generation log, or fail.
+fn read_generations(renderer: Renderer) -> Vec<generations::Generation> {
+    generations::log_path()
+        .and_then(|path| generations::read(&path))
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read the generation log: {e}")))
+}
+
+/// `history`: list past applies and freezes with the number of packages each changed, or run
+/// `history --system` or `history diff`.
+fn run_history(renderer: Renderer, system: bool, action: Option<HistoryAction>) -> ! {
+    if system {
+        run_system_history(renderer);
+    }
+    if let Some(HistoryAction::Diff { a, b }) = action {
+        run_history_diff(renderer, a, b);
+    }
+    let generations = read_generations(renderer);
+    let history = generations::history(&generations);
+    if renderer.is_json() {
+        let entries: Vec<_> = history
+            .iter()
+            .map(|(generation, changes)| {
+                json!({
+                    "id": generation.id,
+                    "timestamp": generation.timestamp,
+                    "command": generation.command,
+                    "success": generation.success,
+                    "snapshot": generation.snapshot,
+                    "added": changes.added,
+                    "removed": changes.removed,
+                })
+            })
+            .collect();
+        renderer.emit_json(&entries);
+    } else if history.is_empty() {
+        renderer.print("No generations recorded yet.");
+    } else {
+        let rows: Vec<(Vec<String>, bool)> = history
+            .iter()
+            .map(|(generation, changes)| {
+                let cells = vec![
+                    generation.id.to_string(),
+                    generations::format_timestamp(generation.timestamp),
+                    generation.command.clone(),
+                    if generation.success { "ok" } else { "failed" }.to_string(),
+                    format!("+{} -{}", changes.added.len(), changes.removed.len()),
+                ];
+                (cells, !generation.success)
+            })
+            .collect();
+        renderer.print_all(
+            renderer.table(&["ID", "TIME (UTC)", "COMMAND", "RESULT", "CHANGES"], &rows),
+        );
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `history diff`: show the packages added and removed between generations `a` and `b`.
+fn run_history_diff(renderer: Renderer, a: u64, b: u64) -> ! {
+    let generations = read_generations(renderer);
+    let [from, to] = [a, b].map(|id| {
+        generations::find(&generations, id)
+            .unwrap_or_else(|| fail(renderer, &format!("No generation {id} in the log")))
+    });
+    let changes = generations::diff(from, to);
+    if renderer.is_json() {
+        renderer.emit_json(&changes);
+    } else if changes.is_empty() {
+        renderer.print(&format!("Generations {a} and {b} have the same packages."));
+    } else {
+        renderer.print_all(renderer.changes(ChangeKind::Added, &changes.added, None));
+        renderer.print_all(renderer.changes(ChangeKind::Removed, &changes.removed, None));
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `audit`: list the recorded installs, removals, and upgrades, oldest first, optionally only
+/// those of `user` or on `package`, and only the `last` ones.
+fn run_audit(
+    renderer: Renderer,
+    user: Option<&str>,
+    package: Option<&str>,
+    last: Option<usize>,
+) -> ! {
+    let mut entries: Vec<audit::AuditEntry> = audit::log_path()
+        .and_then(|path| audit::read(&path))
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read the audit log: {e}")))
+        .into_iter()
+        .filter(|entry| entry.matches(user, package))
+        .collect();
+    if let Some(last) = last {
+        entries.drain(..entries.len().saturating_sub(last));
+    }
+    if renderer.is_json() {
+        renderer.emit_json(&entries);
+    } else if entries.is_empty() {
+        renderer.print("No actions recorded.");
+    } else {
+        let rows: Vec<(Vec<String>, bool)> = entries
+            .iter()
+            .map(|entry| {
+                let cells = vec![
+                    generations::format_timestamp(entry.timestamp),
+                    entry.user.clone(),
+                    entry.action.as_str().to_string(),
+                    entry.packages.join(" "),
+                    entry
+                        .exit_code
+                        .map_or_else(|| "signal".to_string(), |code| code.to_string()),
+                    entry.command.clone(),
+                ];
+                (cells, !entry.success())
+            })
+            .collect();
+        renderer.print_all(renderer.table(
+            &[
+                "TIME (UTC)",
+                "USER",
+                "ACTION",
+                "PACKAGES",
+                "EXIT",
+                "COMMAND",
+            ],
+            &rows,
+        ));
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `bundle create`: put the YAML's packages and their dependencies into `dir`.
+fn run_bundle_create(renderer: Renderer, yml_path: &Path, dir: &Path) -> ! {
+    if offline::is_offline() {
+        fail(renderer, "bundle create needs the network; drop --offline");
+    }
+    let index = bundle::create(yml_path, dir).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to create a bundle in {}: {e}", dir.display()),
+        )
+    });
+    if renderer.is_json() {
+        renderer.emit_json(&index);
+    } else {
+        renderer.print(&format!(
+            "Bundled {} packages in {}",
+            index.packages.len(),
+            dir.display()
+        ));
+        if !index.missing.is_empty() {
+            renderer.print(&format!(
+                "Not bundled (build them with paru first): {}",
+                index.missing.join(", ")
+            ));
+        }
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `bundle apply`: install the packages of the bundle in `dir` that are missing.
+fn run_bundle_apply(renderer: Renderer, dir: &Path, noconfirm: bool) -> ! {
+    let installed =
+        bundle::apply(dir, noconfirm).unwrap_or_else(|e| fail(renderer, &e.to_string()));
+    if renderer.is_json() {
+        renderer.emit_json(&json!({ "success": true, "installed": installed }));
+    } else if installed.is_empty() {
+        renderer.print(&renderer.in_sync());
+    } else {
+        renderer.print(&format!("Installed: {}", installed.join(", ")));
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `import`: merge another manifest's packages into the YAML.
+fn run_import(
+    renderer: Renderer,
+    yml_path: &Path,
+    format: ImportFormat,
+    source: Option<PathBuf>,
+) -> ! {
+    let source = match (source, format) {
+        (Some(source), _) => source,
+        (None, ImportFormat::PacmanLog) => pacman_log::log_path(),
+        (None, _) => fail(renderer, "Name the manifest to import"),
+    };
+    let imported = import::import_into_yaml(yml_path, format, &source).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to import {}: {e}", source.display()),
+        )
+    });
+    if format == ImportFormat::PacmanLog {
+        report_install_times(renderer, yml_path, &imported);
+    }
+    if renderer.is_json() {
+        renderer.emit_json(&json!({
+            "added": imported.packages,
+            "skipped": imported.skipped,
+        }));
+    } else {
+        renderer.print_all(renderer.changes(ChangeKind::Added, &imported.packages, None));
+        for entry in &imported.skipped {
+            renderer.print(&format!("Skipped (no pacman equivalent): {entry}"));
+        }
+        renderer.print(&format!(
+            "Imported {} new package(s) into {}",
+            imported.packages.len(),
+            yml_path.display()
+        ));
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// Report the install times `import --from pacman-log` recorded in the YAML.
+fn report_install_times(renderer: Renderer, yml_path: &Path, imported: &import::Imported) -> ! {
+    if renderer.is_json() {
+        renderer.emit_json(&json!({
+            "recorded": imported.packages,
+            "not_in_log": imported.skipped,
+        }));
+    } else {
+        if !imported.skipped.is_empty() {
+            renderer.print(&format!(
+                "Not installed according to pacman's log: {}",
+                imported.skipped.join(", ")
+            ));
+        }
+        renderer.print(&format!(
+            "Recorded the install time of {} package(s) in {}",
+            imported.packages.len(),
+            yml_path.display()
+        ));
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `history --system`: list the package events of pacman's log, oldest first.
+fn run_system_history(renderer: Renderer) -> ! {
+    let path = pacman_log::log_path();
+    let events = pacman_log::read(&path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read {}: {e}", path.display())));
+    if renderer.is_json() {
+        renderer.emit_json(&events);
+    } else if events.is_empty() {
+        renderer.print(&format!("No package events in {}.", path.display()));
+    } else {
+        let rows: Vec<(Vec<String>, bool)> = events
+            .iter()
+            .map(|event| {
+                let cells = vec![
+                    generations::format_timestamp(event.timestamp),
+                    event.kind.as_str().to_string(),
+                    event.name.clone(),
+                    event.version.clone(),
+                ];
+                (cells, false)
+            })
+            .collect();
+        renderer.print_all(renderer.table(&["TIME (UTC)", "EVENT", "PACKAGE", "VERSION"], &rows));
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `export`: print the package list in another format on stdout.
+fn run_export(renderer: Renderer, yml_path: &Path, format: ExportFormat, home_manager: bool) -> ! {
+    let mut config = nixman::read_effective_config(yml_path).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to read {}: {e}", yml_path.display()),
+        )
+    });
+    config.packages.retain_active(&Host::current());
+    print!("{}", export::render(&config, format, home_manager));
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `diff`: show the changes `apply` would make.
+fn run_diff(renderer: Renderer, yml_path: &Path) -> ! {
+    let changes = nixman::pending_changes(yml_path).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to read {}: {e}", yml_path.display()),
+        )
+    });
+    let drift = drift_of(&changes);
+    if renderer.is_json() {
+        renderer.emit_json(&json!({
+            "in_sync": changes.iter().all(SourceChanges::is_empty),
+            "changes": changes,
+            "drift": drift,
+            "size": pacman_size_impact(&changes, None),
+        }));
+    } else {
+        print_changes(renderer, &changes, &drift);
+        if let Some(impact) = pacman_size_impact(&changes, None) {
+            print_size_impact(renderer, &impact);
+        }
+    }
+    exit_for_changes(&changes);
+}
+
+/// `status`: summarize how each package source compares to the YAML.
+fn run_status(renderer: Renderer, yml_path: &Path) -> ! {
+    let status = manager::status(yml_path).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to compare {}: {e}", yml_path.display()),
+        )
+    });
+    if renderer.is_json() {
+        renderer.emit_json(&status);
+    } else {
+        if let Some(report) = &status.local {
+            print_local_overrides(renderer, report);
+        }
+        if !status.aur_out_of_date.is_empty() {
+            renderer.print(&format!(
+                "flagged out of date in the AUR: {}",
+                status.aur_out_of_date.join(", ")
+            ));
+        }
+        for pin in &status.pin_violations {
+            renderer.print(&pin.to_string());
+        }
+        for violation in &status.requirement_violations {
+            renderer.print(&violation.to_string());
+        }
+        for source in &status.sources {
+            renderer.print(&format!("{}: {} managed", source.source, source.managed));
+            renderer.print_all(renderer.summary(&[
+                ("to install", source.to_install),
+                ("to remove", source.to_remove),
+            ]));
+        }
+        for kind in [
+            DriftKind::AddedToConfig,
+            DriftKind::RemovedFromConfig,
+            DriftKind::InstalledOutside,
+            DriftKind::RemovedOutside,
+        ] {
+            let names: Vec<&str> = status
+                .drift
+                .iter()
+                .filter(|drift| drift.kind == kind)
+                .map(|drift| drift.name.as_str())
+                .collect();
+            if !names.is_empty() {
+                renderer.print(&format!("  {kind}: {}", names.join(", ")));
+            }
+        }
+        if status.in_sync {
+            renderer.print(&renderer.in_sync());
+        }
+    }
+    std::process::exit(if status.in_sync {
+        EXIT_IN_SYNC
+    } else {
+        EXIT_CHANGES_PENDING
+    });
+}
+
+/// Show what the machine-local file changed in the shared config.
+fn print_local_overrides(renderer: Renderer, report: &local::MergeReport) {
+    renderer.print(&format!("local overrides: {}", report.path.display()));
+    for (label, names) in [
+        ("added", &report.added),
+        ("overridden", &report.overridden),
+        ("excluded", &report.excluded),
+    ] {
+        if !names.is_empty() {
+            renderer.print(&format!("  {label}: {}", names.join(", ")));
+        }
+    }
+}
+
+/// `list`: show the packages declared in the YAML.
+fn run_list(renderer: Renderer, yml_path: &Path) -> ! {
+    let config = nixman::read_effective_config(yml_path).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to read {}: {e}", yml_path.display()),
+        )
+    });
+    if renderer.is_json() {
+        renderer.emit_json(&config);
+    } else {
+        for package in &config.packages {
+            renderer.print(&package.to_string());
+        }
+        for alternatives in &config.packages.alternatives {
+            renderer.print(&format!("one of {}", alternatives.names.join(", ")));
+        }
+        for source in sources::builtin() {
+            for name in config.source(source.name()).unwrap_or_default() {
+                renderer.print(&format!("{name} ({})", source.name()));
+            }
+        }
+    }
+    std::process::exit(0);
+}
+
+/// `migrate`: rewrite the YAML in the current schema.
+fn run_migrate(renderer: Renderer, yml_path: &Path) -> ! {
+    let (from, applied) = schema::migrate_file(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to migrate YAML: {e}")));
+    if renderer.is_json() {
+        renderer.emit_json(&json!({
+            "config": yml_path,
+            "from": from,
+            "to": schema::CURRENT,
+            "migrations": applied.iter().map(|migration| migration.description).collect::<Vec<_>>(),
+        }));
+    } else if applied.is_empty() {
+        renderer.print(&format!(
+            "{} is already in schema {}.",
+            yml_path.display(),
+            schema::CURRENT
+        ));
+    } else {
+        for migration in &applied {
+            renderer.print(&format!(
+                "schema {} → {}: {}",
+                migration.from,
+                migration.from + 1,
+                migration.description
+            ));
+        }
+        renderer.print(&format!(
+            "Migrated {} from schema {from} to {}.",
+            yml_path.display(),
+            schema::CURRENT
+        ));
+    }
+    std::process::exit(0);
+}
+
+/// The settings for the YAML at `yml_path`. `tidy` is for YAMLs nixman cannot read yet, such as
+/// ones with miscased keys, so it falls back to the global settings.
+fn load_settings(renderer: Renderer, cli: &Cli, yml_path: &Path) -> Settings {
+    let global = || Settings::load(&settings::global_path());
+    let settings = if yml_path.exists() {
+        settings::resolve(yml_path).or_else(|e| {
+            if matches!(cli.command, Some(Commands::Tidy(_))) {
+                global()
+            } else {
+                Err(e)
+            }
+        })
+    } else {
+        global()
+    };
+    settings.unwrap_or_else(|e| fail(renderer, &format!("Failed to read settings: {e}")))
+}
+
+/// Packages declared twice in the YAML with different versions. Apply uses the first version.
+fn conflicting_duplicates(yml_path: &Path) -> Vec<String> {
+    nixman::read_config(yml_path)
+        .map(|config| {
+            config
+                .packages
+                .duplicates()
+                .iter()
+                .filter(|dup| dup.conflicts())
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Validate the YAML at `yml_path` as `check` does, printing warnings; with `strict`,
+/// duplicates with conflicting versions and unknown keys are errors.
+fn validate(yml_path: &Path, strict: bool) -> Result<nixman::Config, String> {
+    if let Ok(content) = std::fs::read_to_string(yml_path) {
+        let version = schema::version(&content);
+        if version < schema::CURRENT {
+            eprintln!(
+                "Warning: {} is in schema {version}; run `nixman migrate` to upgrade it",
+                yml_path.display()
+            );
+        }
+    }
+    let conflicting = conflicting_duplicates(yml_path);
+    nixman::read_effective_config(yml_path)
+        .map_err(|e| e.to_string())
+        .and_then(|config| {
+            let unknown =
+                strict::unknown_keys(&std::fs::read_to_string(yml_path).unwrap_or_default())
+                    .unwrap_or_default();
+            if unknown.is_empty() {
+                return Ok(config);
+            }
+            let lines: Vec<String> = unknown.iter().map(ToString::to_string).collect();
+            if strict {
+                Err(format!("unknown keys: {}", lines.join("; ")))
+            } else {
+                for line in lines {
+                    eprintln!("Warning: {line}; it is ignored");
+                }
+                Ok(config)
+            }
+        })
+        .and_then(|config| {
+            if conflicting.is_empty() {
+                return Ok(config);
+            }
+            let message = format!(
+                "packages declared with conflicting versions: {}",
+                conflicting.join(", ")
+            );
+            if strict {
+                Err(message)
+            } else {
+                eprintln!("Warning: {message}; the first version is used");
+                Ok(config)
+            }
+        })
+        .and_then(|config| {
+            let declared: Vec<String> = config
+                .packages
+                .packages
+                .values()
+                .map(|pkg| pkg.name.clone())
+                .chain(
+                    config
+                        .packages
+                        .alternatives
+                        .iter()
+                        .flat_map(|alternatives| alternatives.names.clone()),
+                )
+                .collect();
+            let invalid = names::invalid(&declared);
+            let banned = banned::declared(&config);
+            if !invalid.is_empty() {
+                Err(format!("invalid package names: {}", invalid.join(", ")))
+            } else if !banned.is_empty() {
+                Err(format!(
+                    "packages both declared and banned: {}",
+                    banned.join(", ")
+                ))
+            } else {
+                Ok(config)
+            }
+        })
+        .and_then(|config| match aur::unknown_packages(&config) {
+            _ if offline::is_offline() => Ok(config),
+            Ok(unknown) if !unknown.is_empty() => Err(format!(
+                "unknown packages (in no repository or the AUR): {}",
+                unknown.join(", ")
+            )),
+            Ok(_) => Ok(config),
+            Err(e) => {
+                eprintln!("Warning: could not check package names: {e}");
+                Ok(config)
+            }
+        })
+}
+
+/// `check`: validate that the YAML parses; with `strict`, duplicates with conflicting versions
+/// are an error rather than a warning.
+fn run_check(renderer: Renderer, yml_path: &Path, strict: bool) -> ! {
+    let result = validate(yml_path, strict);
+    if renderer.is_json() {
+        renderer.emit_json(&json!({
+            "config": yml_path,
+            "valid": result.is_ok(),
+            "error": result.as_ref().err().map(ToString::to_string),
+        }));
+    }
+    match result {
+        Ok(config) => {
+            renderer.print(&format!(
+                "{} is valid ({} packages)",
+                yml_path.display(),
+                config.packages.packages.len()
+            ));
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{} is invalid: {e}", yml_path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `edit`: open a copy of the YAML in the editor and save it over the YAML once it passes
+/// `check`, offering to edit it again while it does not.
+fn run_edit(renderer: Renderer, yml_path: &Path) -> ! {
+    let original = std::fs::read_to_string(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read YAML: {e}")));
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    // The copy sits next to the YAML, with a copy of its local overrides, so that relative
+    // paths and the overrides are checked as they will be read.
+    let stem = yml_path
+        .file_stem()
+        .map_or_else(|| "packages".into(), |stem| stem.to_string_lossy());
+    let copy = yml_path.with_file_name(format!(".{stem}.edit.yml"));
+    let local_copy = local::local_path(&copy);
+    let remove_copies = || {
+        let _ = std::fs::remove_file(&copy);
+        let _ = std::fs::remove_file(&local_copy);
+    };
+    let prepared = std::fs::write(&copy, &original).and_then(|()| {
+        let overrides = local::local_path(yml_path);
+        if overrides.exists() {
+            std::fs::copy(overrides, &local_copy)?;
+        }
+        Ok(())
+    });
+    if let Err(e) = prepared {
+        remove_copies();
+        fail(
+            renderer,
+            &format!("Failed to copy {}: {e}", yml_path.display()),
+        );
+    }
+    let result = loop {
+        // Through the shell, so that an editor with arguments, like `code --wait`, works.
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{editor} \"$1\""))
+            .arg("sh")
+            .arg(&copy)
+            .status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => break Err(format!("{editor} exited with {status}")),
+            Err(e) => break Err(format!("Failed to run {editor}: {e}")),
+        }
+        let edited = match std::fs::read_to_string(&copy) {
+            Ok(edited) => edited,
+            Err(e) => break Err(format!("Failed to read the edit: {e}")),
+        };
+        if edited == original {
+            break Ok(false);
+        }
+        match validate(&copy, false) {
+            Ok(_) => {
+                break std::fs::write(yml_path, &edited)
+                    .map(|()| true)
+                    .map_err(|e| format!("Failed to write {}: {e}", yml_path.display()));
+            }
+            Err(e) => {
+                // The copy goes away; name the file it stands for.
+                let e = e.replace(&copy.display().to_string(), &yml_path.display().to_string());
+                eprintln!("The edit is invalid: {e}");
+                if !std::io::stdin().is_terminal() || !ask("Edit it again?", true) {
+                    break Err(format!("{} was left unchanged", yml_path.display()));
+                }
+            }
+        }
+    };
+    remove_copies();
+    let saved = result.unwrap_or_else(|e| fail(renderer, &e));
+    if renderer.is_json() {
+        renderer.emit_json(&json!({ "config": yml_path, "saved": saved }));
+    } else if saved {
+        renderer.print(&format!("Saved {}.", yml_path.display()));
+    } else {
+        renderer.print(&format!("{} is unchanged.", yml_path.display()));
+    }
+    std::process::exit(0);
+}
+
+/// `outdated`: list available repository and AUR upgrades next to the YAML's pins, with `safe`
+/// from `checkupdates` only.
+fn run_outdated(renderer: Renderer, yml_path: &Path, safe: bool) -> ! {
+    let config = nixman::read_effective_config(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read YAML: {e}")));
+    let repo = if safe {
+        outdated::safe_repo_updates()
+    } else {
+        outdated::repo_updates()
+    }
+    .unwrap_or_else(|e| fail(renderer, &format!("Failed to check for updates: {e}")));
+    let aur = aur::foreign_packages()
+        .and_then(|installed| {
+            let names: Vec<String> = installed.iter().map(|(name, _)| name.clone()).collect();
+            Ok(aur::updates(&installed, &aur::info(&names)?))
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("Warning: could not check the AUR for updates: {e}");
+            Vec::new()
+        });
+    let upgrades = outdated::upgrades(&config, repo, aur);
+    if renderer.is_json() {
+        renderer.emit_json(&json!({ "upgrades": upgrades }));
+    } else if upgrades.is_empty() {
+        renderer.print("All packages are up to date.");
+    } else {
+        let rows: Vec<(Vec<String>, bool)> = upgrades
+            .iter()
+            .map(|upgrade| {
+                let pinned = upgrade.pinned.clone().unwrap_or_else(|| "-".to_string());
+                let note = if upgrade.violates_pin {
+                    "violates pin"
+                } else {
+                    ""
+                };
+                (
+                    vec![
+                        upgrade.name.clone(),
+                        upgrade.installed.clone(),
+                        upgrade.available.clone(),
+                        pinned,
+                        upgrade.source.to_string(),
+                        note.to_string(),
+                    ],
+                    upgrade.violates_pin,
+                )
+            })
+            .collect();
+        renderer.print_all(renderer.table(
+            &["NAME", "INSTALLED", "AVAILABLE", "PINNED", "SOURCE", "NOTE"],
+            &rows,
+        ));
+    }
+    std::process::exit(if upgrades.is_empty() {
+        EXIT_IN_SYNC
+    } else {
+        EXIT_CHANGES_PENDING
+    });
+}
+
+/// `verify`: report modified and missing files of the YAML's installed packages.
+fn run_verify(renderer: Renderer, yml_path: &Path) -> ! {
+    let config = nixman::read_effective_config(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read YAML: {e}")));
+    let names: Vec<String> = config
+        .packages
+        .packages
+        .values()
+        .map(|pkg| pkg.name.clone())
+        .collect();
+    let packages = verify::verify(&names)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to run pacman -Qkk: {e}")));
+    let intact = packages.iter().all(verify::PackageIntegrity::is_intact);
+    if renderer.is_json() {
+        renderer.emit_json(&json!({ "intact": intact, "packages": packages }));
+    } else {
+        for package in packages.iter().filter(|package| !package.is_intact()) {
+            renderer.print(&format!(
+                "{}: {} modified, {} missing of {} files",
+                package.name,
+                package.modified.len(),
+                package.missing.len(),
+                package.total_files
+            ));
+            renderer.print_all(renderer.changes(ChangeKind::Changed, &package.modified, None));
+            renderer.print_all(renderer.changes(ChangeKind::Removed, &package.missing, None));
+        }
+        if intact {
+            renderer.print(&format!(
+                "{} packages verified, no files altered.",
+                packages.len()
+            ));
+        }
+    }
+    std::process::exit(if intact {
+        EXIT_IN_SYNC
+    } else {
+        EXIT_CHANGES_PENDING
+    });
+}
+
+/// Make sure pacman knows the repositories in the `repos:` section, writing the missing ones
+/// with `--write-pacman-conf` and failing otherwise. A dry run only warns.
+fn check_repos(renderer: Renderer, yml_path: &Path, args: &ApplyArgs) {
+    let Ok(config) = nixman::read_effective_config(yml_path) else {
+        return;
+    };
+    let declared = config.repos.unwrap_or_default();
+    if declared.is_empty() {
+        return;
+    }
+    let configured = match repos::configured_repos(Path::new(repos::PACMAN_CONF)) {
+        Ok(configured) => configured,
+        Err(e) => {
+            eprintln!("Warning: could not read {}: {e}", repos::PACMAN_CONF);
+            return;
+        }
+    };
+    let missing: Vec<&str> = repos::missing(&declared, &configured)
+        .iter()
+        .map(|repo| repo.name.as_str())
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+    let message = format!(
+        "Repositories missing from {}: {}",
+        repos::PACMAN_CONF,
+        missing.join(", ")
+    );
+    if args.dry_run {
+        eprintln!("Warning: {message}");
+    } else if args.write_pacman_conf {
+        let written = repos::write_drop_in(&declared).unwrap_or_else(|e| {
+            fail(
+                renderer,
+                &format!("Failed to write {}: {e}", repos::DROP_IN),
+            )
+        });
+        renderer.print(&format!(
+            "Wrote {} to {}. Run `nixman update` to sync the new repositories.",
+            written.join(", "),
+            repos::DROP_IN
+        ));
+    } else {
+        fail(
+            renderer,
+            &format!("{message}. Add them, or rerun with --write-pacman-conf."),
+        );
+    }
+}
+
+/// Warn about packages whose recorded origin no longer matches the sync databases.
+fn warn_about_moved_packages(yml_path: &Path) {
+    let Ok(config) = nixman::read_effective_config(yml_path) else {
+        return;
+    };
+    if config
+        .packages
+        .packages
+        .values()
+        .all(|pkg| pkg.origin.is_none())
+    {
+        return;
+    }
+    match origin::repo_index() {
+        Ok(index) => {
+            for change in origin::changes(&config, &index) {
+                eprintln!("Warning: {change}");
+            }
+        }
+        Err(e) => eprintln!("Warning: could not check package origins: {e}"),
+    }
+}
+
+/// `watch`: check for drift every `interval` (or once), updating the status file and notifying
+/// when the system drifts.
+///
+/// With `clean`, the package caches are cleaned after each check, keeping that many versions.
+fn run_watch(
+    renderer: Renderer,
+    yml_path: &Path,
+    interval: Duration,
+    once: bool,
+    clean: Option<usize>,
+) -> ! {
+    let status_file = watch::status_path()
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to locate status file: {e}")));
+    let mut last_changes = None;
+    loop {
+        match watch::check(yml_path, &status_file) {
+            Ok(status) => {
+                if status.in_sync {
+                    renderer.print(&renderer.in_sync());
+                } else {
+                    let summary = status.summary();
+                    renderer.print(&format!("Drift detected: {summary}"));
+                    // Only notify again when the drift changes, not on every check.
+                    if last_changes.as_ref() != Some(&status.changes) {
+                        let _ = notify::drift(&summary);
+                    }
+                }
+                if let Some(keep) = clean {
+                    watch_clean(yml_path, keep);
+                }
+                if once {
+                    if renderer.is_json() {
+                        renderer.emit_json(&status);
+                    }
+                    exit_for_changes(&status.changes);
+                }
+                last_changes = Some(status.changes);
+            }
+            Err(e) if once => fail(renderer, &format!("Drift check failed: {e}")),
+            Err(e) => eprintln!("Drift check failed: {e}"),
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// The cleanup step of `watch --clean`, skipped while pacman is running.
+fn watch_clean(yml_path: &Path, keep: usize) {
+    if lock::check_pacman_db(false).is_err() {
+        eprintln!("Skipping cache cleanup: pacman is running");
+        return;
+    }
+    let result = nixman::read_effective_config(yml_path)
+        .and_then(|config| clean::clean(&config, keep, false));
+    match result {
+        Ok(removed) if !removed.is_empty() => {
+            eprintln!("Removed {} cached package files", removed.len());
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Cache cleanup failed: {e}"),
+    }
+}
+
+/// `clean`: apply the cache retention policy.
+fn run_clean(renderer: Renderer, yml_path: &Path, keep: usize, dry_run: bool) -> ! {
+    let config = nixman::read_effective_config(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read YAML: {e}")));
+    let files = clean::clean(&config, keep, dry_run).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to clean the package caches: {e}"),
+        )
+    });
+    if renderer.is_json() {
+        renderer.emit_json(&json!({ "dry_run": dry_run, "files": files }));
+    } else {
+        let names: Vec<String> = files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        renderer.print_all(renderer.changes(ChangeKind::Removed, &names, None));
+        let verb = if dry_run { "Would remove" } else { "Removed" };
+        renderer.print(&format!(
+            "{verb} {} files, keeping {keep} versions of each package and pinned versions.",
+            files.len()
+        ));
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `install-timer`: schedule `watch --once` with a systemd user timer.
+fn run_install_timer(renderer: Renderer, interval: Duration, clean: bool) -> ! {
+    match watch::install_timer(interval, clean) {
+        Ok(status) if status.success() => {
+            renderer.print(&format!(
+                "Enabled {} (every {}s); results are written to the nixman state directory",
+                watch::TIMER_NAME,
+                interval.as_secs()
+            ));
+            std::process::exit(EXIT_IN_SYNC);
+        }
+        Ok(status) => std::process::exit(status.code().unwrap_or(EXIT_ERROR)),
+        Err(e) => fail(
+            renderer,
+            &format!("Failed to install {}: {e}", watch::TIMER_NAME),
+        ),
+    }
+}
+
+/// `tidy`: normalize the YAML, or with `--check` only report what that would change.
+fn run_tidy(renderer: Renderer, yml_path: &Path, args: &TidyArgs) -> ! {
+    let (versions, check) = (args.versions(), args.check);
+    let installed = if versions == tidy::Versions::Pin {
+        let output = pacman::pacman_list_installed()
+            .unwrap_or_else(|e| fail(renderer, &format!("Failed to query pacman: {e}")));
+        parse_explicit_packages(&output.stdout, true)
+    } else {
+        nixman::PackageList::default()
+    };
+    let changes = tidy::tidy_file(yml_path, versions, &installed, check).unwrap_or_else(|e| {
+        fail(
+            renderer,
+            &format!("Failed to tidy {}: {e}", yml_path.display()),
+        )
+    });
+    if renderer.is_json() {
+        let changes: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        renderer.emit_json(&json!({
+            "changes": changes,
+            "written": !check && !changes.is_empty(),
+        }));
+    } else if changes.is_empty() {
+        renderer.print(&format!("{} is tidy.", yml_path.display()));
+    } else {
+        for change in &changes {
+            renderer.print(&format!("- {change}"));
+        }
+        renderer.print(&if check {
+            format!(
+                "{} is not tidy; run `nixman tidy` to fix it.",
+                yml_path.display()
+            )
+        } else {
+            format!("Tidied {}.", yml_path.display())
+        });
+    }
+    std::process::exit(if check && !changes.is_empty() {
+        EXIT_CHANGES_PENDING
+    } else {
+        EXIT_IN_SYNC
+    });
+}
+
+/// `profile list` and `profile use`.
+fn run_profile(renderer: Renderer, action: &ProfileAction) -> ! {
+    match action {
+        ProfileAction::List => {
+            let names = profile::list()
+                .unwrap_or_else(|e| fail(renderer, &format!("Failed to list the profiles: {e}")));
+            let active = profile::active().ok();
+            if renderer.is_json() {
+                let profiles: Vec<_> = names
+                    .iter()
+                    .map(|name| {
+                        json!({
+                            "name": name,
+                            "path": profile::profile_path(name),
+                            "active": active.as_deref() == Some(name.as_str()),
+                        })
+                    })
+                    .collect();
+                renderer.emit_json(&profiles);
+            } else {
+                let rows: Vec<(Vec<String>, bool)> = names
+                    .iter()
+                    .map(|name| {
+                        let is_active = active.as_deref() == Some(name.as_str());
+                        let cells = vec![
+                            if is_active { "*" } else { "" }.to_string(),
+                            name.clone(),
+                            profile::profile_path(name).display().to_string(),
+                        ];
+                        (cells, false)
+                    })
+                    .collect();
+                renderer.print_all(renderer.table(&["", "PROFILE", "FILE"], &rows));
+            }
+        }
+        ProfileAction::Use { name } => {
+            profile::set_active(name).unwrap_or_else(|e| fail(renderer, &e.to_string()));
+            renderer.print(&format!(
+                "Switched to profile {name} ({})",
+                profile::profile_path(name).display()
+            ));
+        }
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `install-hook`: write the pacman hook running in `mode`, or delete it if `remove`.
+fn run_install_hook(renderer: Renderer, mode: HookMode, remove: bool) -> ! {
+    let result = if remove {
+        hook::uninstall()
+    } else {
+        hook::install(mode)
+    };
+    match result {
+        Ok(status) if status.success() => {
+            renderer.print(&if remove {
+                format!("Removed {}", hook::HOOK_PATH)
+            } else {
+                format!(
+                    "Installed {}; pacman transactions now {} the YAML",
+                    hook::HOOK_PATH,
+                    match mode {
+                        HookMode::Add => "add new packages to",
+                        HookMode::Flag => "list new packages missing from",
+                    }
+                )
+            });
+            std::process::exit(EXIT_IN_SYNC);
+        }
+        Ok(status) => std::process::exit(status.code().unwrap_or(EXIT_ERROR)),
+        Err(e) => fail(
+            renderer,
+            &format!("Failed to install {}: {e}", hook::HOOK_PATH),
+        ),
+    }
+}
+
+/// Read triage commands from stdin until everything is decided or the user is done.
+fn triage_interactively(triage: &mut Triage) {
+    let print_undecided = |triage: &Triage| {
+        for i in triage.undecided() {
+            eprintln!("{:>4}  {}", i + 1, triage.packages[i]);
+        }
+    };
+    print_undecided(triage);
+    eprintln!(
+        "Decide with m|i|r <selection> (manage, ignore, remove), e.g. `m 1-5,8` or `i rest`; l lists, d saves."
+    );
+    while !triage.undecided().is_empty() {
+        eprint!("{} undecided> ", triage.undecided().len());
+        let mut line = String::new();
+        // Stop at end of input, saving what was decided.
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        match triage::parse_command(&line, &triage.undecided(), triage.packages.len()) {
+            Ok(triage::Command::Decide(decision, indices)) => triage.decide(decision, &indices),
+            Ok(triage::Command::List) => print_undecided(triage),
+            Ok(triage::Command::Done) => break,
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// `adopt-system`: triage the unmanaged packages interactively, then save the decisions.
+fn run_adopt_system(renderer: Renderer, yml_path: &Path) -> ! {
+    let (_, unmanaged) = nixman::installed_explicit_package_list()
+        .and_then(|installed| nixman::sync_packages_from_yaml(yml_path, &installed))
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to compute changes: {e}")));
+    if unmanaged.is_empty() {
+        renderer.print("Every installed package is already managed or ignored.");
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    let mut triage = Triage::new(unmanaged);
+    triage_interactively(&mut triage);
+    triage
+        .save(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to write to YAML: {e}")));
+    renderer.print_all(renderer.summary(&[
+        ("managed", triage.with(Decision::Manage).len()),
+        ("ignored", triage.with(Decision::Ignore).len()),
+        ("to remove", triage.with(Decision::Remove).len()),
+        ("undecided", triage.undecided().len()),
+    ]));
+    if !triage.with(Decision::Remove).is_empty() {
+        renderer
+            .print("Run `nixman apply --remove-only` to remove the packages marked for removal.");
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `explain`: report everything known about a single package.
+fn run_explain(renderer: Renderer, yml_path: &Path, package: &str) -> ! {
+    let explanation = explain::explain(yml_path, package)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to explain {package}: {e}")));
+    if renderer.is_json() {
+        renderer.emit_json(&explanation);
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    let declared = if explanation.declared_in.is_empty() {
+        "no".to_string()
+    } else {
+        let version = explanation
+            .declared_version
+            .as_ref()
+            .map_or_else(String::new, |v| format!(", version {v}"));
+        let reason = if explanation.declared_reason == Some(InstallReason::Dependency) {
+            ", as a dependency"
+        } else {
+            ""
+        };
+        format!(
+            "yes ({}{version}{reason})",
+            explanation.declared_in.join(", ")
+        )
+    };
+    let installed = match (&explanation.installed_version, explanation.installed) {
+        (Some(version), true) => format!("yes ({version})"),
+        (None, true) => "yes".to_string(),
+        (_, false) => "no".to_string(),
+    };
+    let required_by = if explanation.required_by.is_empty() {
+        "nothing".to_string()
+    } else {
+        explanation.required_by.join(", ")
+    };
+    let action = match explanation.apply_action {
+        PlannedAction::Keep => "leave it alone",
+        PlannedAction::Install => "install it",
+        PlannedAction::Remove => "remove it",
+    };
+    renderer.print(&explanation.name);
+    renderer.print(&format!("In YAML: {declared}"));
+    renderer.print(&format!("Installed: {installed}"));
+    if let Some(reason) = explanation.install_reason {
+        renderer.print(&format!(
+            "Install reason: {}",
+            match reason {
+                InstallReason::Explicit => "explicitly installed",
+                InstallReason::Dependency => "installed as a dependency",
+            }
+        ));
+    }
+    if let Some(date) = &explanation.install_date {
+        renderer.print(&format!("Installed on: {date}"));
+    }
+    if let Some(size) = explanation.installed_size {
+        renderer.print(&format!("Installed size: {}", pacman::format_size(size)));
+    }
+    renderer.print(&format!("Required by: {required_by}"));
+    renderer.print(&format!("Apply would: {action}"));
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// `why-remove`: show what removing `packages`, or the packages `apply` would remove, takes
+/// out with them.
+fn run_why_remove(renderer: Renderer, yml_path: &Path, packages: Vec<String>) -> ! {
+    let packages = if packages.is_empty() {
+        plan::plan(yml_path, ApplyMode::Full)
+            .unwrap_or_else(|e| fail(renderer, &format!("Failed to compute changes: {e}")))
+            .source_changes()
+            .into_iter()
+            .find(|changes| changes.source == "pacman")
+            .map(|changes| changes.to_remove)
+            .unwrap_or_default()
+    } else {
+        packages
+    };
+    let cascade = cascade::cascade(&packages)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to compute the removal: {e}")));
+    if renderer.is_json() {
+        renderer.emit_json(&cascade);
+    } else if packages.is_empty() {
+        renderer.print("Nothing to remove.");
+    } else {
+        print_cascade(renderer, &cascade);
+    }
+    std::process::exit(EXIT_IN_SYNC);
+}
+
+/// Print each target of a removal with the dependencies it takes out.
+fn print_cascade(renderer: Renderer, cascade: &Cascade) {
+    renderer.print(&format!(
+        "Removing {} {} takes out {} in all:",
+        cascade.targets.len(),
+        if cascade.targets.len() == 1 {
+            "package"
+        } else {
+            "packages"
+        },
+        cascade.total
+    ));
+    for target in &cascade.targets {
+        let detail = (!target.also_removes.is_empty())
+            .then(|| format!("with {}", target.also_removes.join(", ")));
+        renderer.print(&renderer.change(ChangeKind::Removed, &target.target, detail.as_deref()));
+    }
+    for name in &cascade.shared {
+        renderer.print(&renderer.change(
+            ChangeKind::Removed,
+            name,
+            Some("needed only by the packages being removed"),
+        ));
+    }
+}
+
+/// Ask whether to apply `plan`, showing first what its removals take out with them; an empty
+/// plan needs no answer.
+fn confirm_plan(renderer: Renderer, plan: Option<&ChangePlan>) -> bool {
+    let Some(plan) = plan else {
+        return confirmed();
+    };
+    if plan.is_empty() {
+        return true;
+    }
+    let to_remove = plan
+        .source_changes()
+        .into_iter()
+        .find(|changes| changes.source == "pacman")
+        .map(|changes| changes.to_remove)
+        .unwrap_or_default();
+    match cascade::cascade(&to_remove) {
+        Ok(cascade) if cascade.has_extras() => print_cascade(renderer, &cascade),
+        Ok(_) => {}
+        Err(e) => eprintln!("Warning: could not work out what the removals take with them: {e}"),
+    }
+    confirmed()
+}
+
+/// `apply --dry-run`: print the plan, with remembered conflict answers applied, and exit with
+/// whether changes are pending.
+fn run_dry_run(renderer: Renderer, yml_path: &Path, args: &ApplyArgs) -> ! {
+    let plan = plan::plan(yml_path, args.mode())
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to compute changes: {e}")));
+    let plan = select(plan, &args.selection());
+    let plan = resolve_conflicts(renderer, yml_path, plan, false, false);
+    let changes = plan.source_changes();
+    if renderer.is_json() {
+        renderer.emit_json(&json!({
+            "dry_run": true,
+            "in_sync": plan.is_empty(),
+            "changes": changes,
+            "resolved": plan.resolved,
+            "size": pacman_size_impact(&changes, plan.resolved.as_ref()),
+            "pin_violations": plan.pin_violations,
+            "requirement_violations": plan.requirement_violations,
+        }));
+    } else {
+        print_plan(renderer, &plan);
+    }
+    exit_for_changes(&changes);
+}
+
+/// Set the retry policy, the number of parallel AUR builds, and whether the base must be signed
+/// for an apply, from its flags and the settings.
+fn configure_apply(renderer: Renderer, args: &ApplyArgs, settings: &Settings) {
+    let mut policy = settings
+        .retry_policy()
+        .unwrap_or_else(|e| fail(renderer, &format!("Invalid retry_delay: {e}")));
+    policy.retries = args.retries.unwrap_or(policy.retries);
+    retry::set_policy(policy);
+    builds::set_jobs(args.aur_jobs.or(settings.aur_jobs).unwrap_or(1));
+    base::set_require_signature(args.verify_signature);
+}
+
+/// `apply`: print the pending changes, then synchronize the system with the YAML.
+fn run_apply(renderer: Renderer, yml_path: &Path, args: &ApplyArgs, noconfirm: bool) -> ! {
+    warn_about_moved_packages(yml_path);
+    check_repos(renderer, yml_path, args);
+    let selection = args.selection();
+    if args.dry_run {
+        run_dry_run(renderer, yml_path, args);
+    }
+    // A locked apply installs from the lockfile, so the YAML's plan is only shown, not executed.
+    // A partial apply must know the plan to pick from; a full one can fall back to the YAML.
+    let plan = match plan::plan(yml_path, args.mode()) {
+        Ok(plan) => Some(select(plan, &selection)),
+        Err(e) if !selection.is_all() => fail(renderer, &format!("Failed to compute changes: {e}")),
+        Err(_) => None,
+    };
+    // Locked and staged applies do not execute the plan, so there is nothing to settle.
+    let ask = !noconfirm && std::io::stdin().is_terminal() && !renderer.is_json();
+    let plan = plan.map(|plan| {
+        if args.locked || args.resume_after_reboot {
+            plan
+        } else {
+            resolve_conflicts(renderer, yml_path, plan, ask, true)
+        }
+    });
+    let changes = plan.as_ref().map(ChangePlan::source_changes);
+    if let Some(plan) = &plan
+        && !renderer.is_json()
+    {
+        print_plan(renderer, plan);
+    }
+    if args.confirm && !confirm_plan(renderer, plan.as_ref()) {
+        renderer.print("Nothing was applied.");
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    // Counted from here, so time spent at the prompt does not make a run look long.
+    let started = Instant::now();
+    // Nothing to install or remove means nothing to snapshot, unless downgrades are allowed.
+    let needs_snapshot = args.allow_downgrade || plan.as_ref().is_none_or(|plan| !plan.is_empty());
+    let snapshot = args.snapshot.filter(|_| needs_snapshot).map(|tool| {
+        renderer.print(&format!("Taking a {tool} snapshot..."));
+        snapshot::create(tool, "nixman apply").unwrap_or_else(|e| {
+            fail(
+                renderer,
+                &format!("Failed to take a snapshot, nothing was applied: {e}"),
+            )
+        })
+    });
+    let result = apply_all(renderer, yml_path, args, noconfirm, plan.as_ref());
+    record_generation(result.is_ok(), snapshot.clone());
+    if result.is_ok() {
+        record_applied(yml_path);
+        // A locked apply reproduces the lockfile, so there is nothing new to record.
+        if !args.locked
+            && let Err(e) = lockfile::write_lock(yml_path)
+        {
+            eprintln!("Warning: failed to update the lockfile: {e}");
+        }
+    }
+    let report = match &result {
+        Ok((_, report)) => report,
+        Err(e) => &*e.report,
+    };
+    if renderer.is_json() {
+        renderer.emit_json(&json!({
+            "success": result.is_ok(),
+            "reboot_pending": result.as_ref().is_ok_and(|(pending, _)| *pending),
+            "changes": changes,
+            "report": report,
+            "error": result.as_ref().err(),
+            "snapshot": snapshot,
+        }));
+    } else if !report.is_empty() {
+        renderer.print_all(renderer.table(&ApplyReport::HEADER, &report.rows()));
+    }
+    if let Some(snapshot) = &snapshot {
+        renderer.print(&format!("Taken before this apply: {snapshot}"));
+    }
+    let _ = notify::finished("apply", started.elapsed(), result.is_ok());
+    match result {
+        Ok((true, _)) => {
+            renderer.print("Some packages must wait for a reboot. Reboot to finish applying.");
+            std::process::exit(0);
+        }
+        Ok((false, _)) => {
+            renderer.print("Apply completed successfully.");
+            std::process::exit(0);
+        }
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `tui`: review the plan in a terminal UI, then apply the staged part of it.
+#[cfg(feature = "tui")]
+fn run_tui(renderer: Renderer, yml_path: &Path, use_paru: bool, noconfirm: bool) -> ! {
+    use nixman::tui::{self, Review};
+    use nixman::versioning::FullVersion;
+
+    let plan = plan::plan(yml_path, ApplyMode::Full)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to compute changes: {e}")));
+    let config = nixman::read_effective_config(yml_path)
+        .unwrap_or_else(|e| fail(renderer, &format!("Failed to read the YAML: {e}")));
+    let critical = safety::critical_packages(&config);
+    let mut review = Review::new(plan, &critical);
+    if review.is_empty() {
+        renderer.print(&renderer.in_sync());
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    let apply = tui::run(&mut review)
+        .unwrap_or_else(|e| fail(renderer, &format!("Terminal UI failed: {e}")));
+    let staged = review.staged();
+    if !apply || staged.is_empty() {
+        renderer.print("Nothing was applied.");
+        std::process::exit(EXIT_IN_SYNC);
+    }
+    print_changes(renderer, &staged.plan.source_changes(), &[]);
+    let started = Instant::now();
+    let pins: Vec<(String, FullVersion)> = staged
+        .pins
+        .iter()
+        .map(|pin| (pin.name.clone(), FullVersion::from(pin.pinned.as_str())))
+        .collect();
+    let result = plan::execute(
+        &staged.plan,
+        use_paru,
+        false,
+        noconfirm,
+        &CancelToken::default(),
+        &mut |_| {},
+    )
+    .and_then(|report| {
+        archive::install_from_archive(&pins, noconfirm)
+            .map_err(|e| e.with_report(report.clone()))?;
+        Ok(report)
+    });
+    record_generation(result.is_ok(), None);
+    if result.is_ok() {
+        record_applied(yml_path);
+    }
+    let _ = notify::finished("tui", started.elapsed(), result.is_ok());
+    match result {
+        Ok(report) => {
+            if !report.is_empty() {
+                renderer.print_all(renderer.table(&ApplyReport::HEADER, &report.rows()));
+            }
+            renderer.print("Staged changes applied successfully.");
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Settle the changes of `plan` that would undo manual changes made since the last apply: by
+/// the recorded decision, by asking when `ask`, or else by keeping the config. With `persist`,
+/// answers are recorded and config updates written; otherwise they only shape the plan.
+fn resolve_conflicts(
+    renderer: Renderer,
+    yml_path: &Path,
+    plan: ChangePlan,
+    ask: bool,
+    persist: bool,
+) -> ChangePlan {
+    let last = applied::path()
+        .and_then(|path| LastApplied::load(&path))
+        .unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring the state of the last apply: {e}");
+            None
+        });
+    let mut decisions = conflicts::decisions_path()
+        .and_then(|path| Decisions::load(&path))
+        .unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring the recorded conflict decisions: {e}");
+            Decisions::default()
+        });
+    let (to_install, to_remove) = plan.for_source("pacman");
+    decisions.retain_planned(&to_install, &to_remove);
+    let mut skip = Vec::new();
+    let mut updates = Vec::new();
+    for conflict in conflicts::find(&to_install, &to_remove, last.as_ref(), &decisions) {
+        let resolution = match decisions.get(&conflict) {
+            Some(resolution) => {
+                renderer.print(&format!("{conflict}; as decided before, {resolution}"));
+                resolution
+            }
+            None if ask => {
+                let resolution = ask_resolution(&conflict);
+                decisions.record(&conflict, resolution);
+                resolution
+            }
+            None => {
+                renderer.print(&format!("{conflict}; keeping the config"));
+                Resolution::Keep
+            }
+        };
+        match resolution {
+            Resolution::Keep => {}
+            Resolution::Skip => skip.push(conflict.name),
+            Resolution::Update => {
+                skip.push(conflict.name.clone());
+                updates.push(conflict);
+            }
+        }
+    }
+    if persist {
+        if !updates.is_empty() {
+            conflicts::update_config(yml_path, &updates)
+                .unwrap_or_else(|e| fail(renderer, &format!("Failed to write to YAML: {e}")));
+            renderer.print(&format!("Updated {}", yml_path.display()));
+        }
+        if let Err(e) = conflicts::decisions_path().and_then(|path| decisions.save(&path)) {
+            eprintln!("Warning: failed to record the conflict decisions: {e}");
+        }
+    }
+    if skip.is_empty() {
+        plan
+    } else {
+        plan.select(&Selection {
+            only: Vec::new(),
+            skip,
+        })
+    }
+}
+
+/// Ask how to settle `conflict`; end of input keeps the config.
+fn ask_resolution(conflict: &Conflict) -> Resolution {
+    let (keep, update) = match conflict.direction {
+        Direction::RemovedOutside => ("reinstall it", "drop it from the YAML"),
+        Direction::InstalledOutside => ("remove it", "add it to the YAML"),
+    };
+    eprintln!("{conflict}.");
+    loop {
+        eprint!("[k]eep the config and {keep}, [u]pdate the config and {update}, or [s]kip it? ");
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            eprintln!();
+            return Resolution::Keep;
+        }
+        if let Some(resolution) = Resolution::parse(&line) {
+            return resolution;
+        }
+    }
+}
+
+/// Remember what the YAML declared and what was installed after a successful apply, so the
+/// next one can tell manual changes from config changes.
+fn record_applied(yml_path: &Path) {
+    if let Err(e) = applied::record(yml_path) {
+        eprintln!("Warning: failed to record the state of this apply: {e}");
+    }
+}
+
+/// The part of `plan` in `selection`, warning about selected names the plan does not change.
+fn select(plan: ChangePlan, selection: &Selection) -> ChangePlan {
+    for name in selection.unmatched(&plan) {
+        eprintln!("Warning: {name} is not part of the plan");
+    }
+    plan.select(selection)
+}
+
+/// Print the planned changes, any pins the installed versions do not match, and any version
+/// requirements that cannot be met.
+fn print_plan(renderer: Renderer, plan: &ChangePlan) {
+    let changes = plan.source_changes();
+    print_changes(renderer, &changes, &drift_of(&changes));
+    if let Some(resolved) = &plan.resolved {
+        for package in &resolved.dependencies {
+            let detail = format!("dependency, {}", package.version);
+            renderer.print(&renderer.change(ChangeKind::Added, &package.name, Some(&detail)));
+        }
+        for package in &resolved.orphans {
+            let detail = format!("no longer needed, {}", package.version);
+            renderer.print(&renderer.change(ChangeKind::Removed, &package.name, Some(&detail)));
+        }
+    }
+    if let Some(impact) = pacman_size_impact(&changes, plan.resolved.as_ref()) {
+        print_size_impact(renderer, &impact);
+    }
+    for violation in &plan.pin_violations {
+        renderer.print(&violation.to_string());
+    }
+    for violation in &plan.requirement_violations {
+        renderer.print(&violation.to_string());
+    }
+}
+
+/// The size impact of the pacman side of `changes`, with the packages pacman `resolved`
+/// beyond them; `None` if it changes nothing or pacman could not be queried.
+fn pacman_size_impact(
+    changes: &[SourceChanges],
+    resolved: Option<&Resolved>,
+) -> Option<SizeImpact> {
+    let pacman = changes
+        .iter()
+        .find(|changes| changes.source == "pacman" && !changes.is_empty())?;
+    let (mut to_install, mut to_remove) = (pacman.to_install.clone(), pacman.to_remove.clone());
+    if let Some(resolved) = resolved {
+        to_install.extend(
+            resolved
+                .dependencies
+                .iter()
+                .map(|package| package.name.clone()),
+        );
+        to_remove.extend(resolved.orphans.iter().map(|package| package.name.clone()));
+    }
+    plan::size_impact(&to_install, &to_remove).ok()
+}
+
+/// Print how much the pacman changes download and how they change the installed size.
+fn print_size_impact(renderer: Renderer, impact: &SizeImpact) {
+    let sign = if impact.installed_delta < 0 { '-' } else { '+' };
+    renderer.print(&format!(
+        "Download size: {}, installed size: {sign}{}",
+        pacman::format_size(impact.download),
+        pacman::format_size(impact.installed_delta.unsigned_abs())
+    ));
+    if !impact.unknown.is_empty() {
+        renderer.print(&format!(
+            "Not counted (not in the sync databases): {}",
+            impact.unknown.join(", ")
+        ));
+    }
+}
+
+/// Ask whether to go ahead with the apply; anything but `y`/`yes` (or end of input) is a no.
+fn confirmed() -> bool {
+    ask("Proceed?", false)
+}
+
+/// Ask a yes/no `question`; an empty answer or end of input is `default`.
+fn ask(question: &str, default: bool) -> bool {
+    eprint!("{question} {} ", if default { "[Y/n]" } else { "[y/N]" });
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+        eprintln!();
+        return default;
+    }
+    match line.trim().to_ascii_lowercase().as_str() {
+        "" => default,
+        answer => matches!(answer, "y" | "yes"),
+    }
+}
+
+/// Append the running apply or freeze to the generation log, with the command line and the
+/// explicit packages it left installed.
+fn record_generation(success: bool, snapshot: Option<Snapshot>) {
+    let command = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+    let packages = pacman::pacman_list_explicit()
+        .map(|output| {
+            parse_explicit_packages(&output.stdout, false)
+                .packages
+                .into_keys()
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Err(e) = generations::log_path()
+        .and_then(|path| generations::record(&path, &command, success, snapshot, packages))
+    {
+        eprintln!("Warning: failed to record the generation: {e}");
+    }
+}
+
+/// Apply pacman packages (from the YAML or the lockfile), downgrades, and extra sources.
+///
+/// A normal apply executes `plan` when it could be computed up front, so exactly the shown
+/// changes are made. Returns whether a reboot is needed to finish, and what each pacman/paru
+/// and extra-source run did.
+fn apply_all(
+    renderer: Renderer,
+    yml_path: &Path,
+    args: &ApplyArgs,
+    noconfirm: bool,
+    plan: Option<&ChangePlan>,
+) -> Result<(bool, ApplyReport), nixman::ApplyError> {
+    let continue_on_error = args.continue_on_error;
+    // Per-package progress is only worth showing when each package is its own step.
+    let mut progress = |step: Progress<'_>| {
+        if continue_on_error {
+            report_progress(renderer, step);
+        }
+    };
+    let mode = args.mode();
+    if !args.force && mode != ApplyMode::InstallOnly {
+        // With a plan, only the removals it will actually make are checked.
+        match plan.filter(|_| !args.locked && !args.resume_after_reboot) {
+            Some(plan) => {
+                let config = nixman::read_effective_config(yml_path).map_err(|e| e.to_string())?;
+                safety::check_removal_list(&config, &plan.for_source("pacman").1)?;
+            }
+            None => safety::check_removals(yml_path)?,
+        }
+    }
+    let mut report = ApplyReport::default();
+    let reboot_pending = if args.locked {
+        lockfile::apply_locked(yml_path, continue_on_error, noconfirm)?;
+        false
+    } else if args.resume_after_reboot {
+        resume::apply_with_resume(
+            yml_path,
+            args.paru,
+            continue_on_error,
+            noconfirm,
+            mode,
+            &mut progress,
+        )?
+    } else if let Some(plan) = plan {
+        report = plan::execute(
+            plan,
+            args.paru,
+            continue_on_error,
+            noconfirm,
+            &CancelToken::default(),
+            &mut progress,
+        )?;
+        false
+    } else {
+        report = nixman::apply_packages_from_yaml_with_progress(
+            yml_path,
+            args.paru,
+            continue_on_error,
+            noconfirm,
+            mode,
+            &mut progress,
+        )?;
+        false
+    };
+    // Downgrades are installs, so a removal-only apply skips them.
+    if !args.locked
+        && mode != ApplyMode::RemoveOnly
+        && let Err(e) = apply_downgrades(renderer, yml_path, args, noconfirm)
+    {
+        return Err(e.with_report(report));
+    }
+    // The plan already covered the extra sources.
+    if args.locked || args.resume_after_reboot || plan.is_none() {
+        for source in sources::builtin() {
+            match nixman::apply_source_from_yaml(
+                yml_path,
+                source.as_ref(),
+                continue_on_error,
+                noconfirm,
+                mode,
+            ) {
+                Ok(source_report) => report.append(source_report),
+                Err(e) => return Err(e.with_report(report)),
+            }
+        }
+    }
+    Ok((reboot_pending, report))
+}
+
+/// Show a step of an apply as `[12/48] installing foo...`, with a progress bar when stderr is a
+/// terminal and human output is used.
+fn report_progress(renderer: Renderer, step: Progress<'_>) {
+    let verb = match step.action {
+        PackageAction::Install => "installing",
+        PackageAction::Remove => "removing",
+    };
+    let message = format!("{verb} {}...", step.packages.join(" "));
+    let bar = if renderer.style() == Style::Human {
+        ProgressDrawTarget::stderr()
+    } else {
+        ProgressDrawTarget::hidden()
+    };
+    let bar = ProgressBar::with_draw_target(Some(step.total as u64), bar);
+    if bar.is_hidden() {
+        renderer.print(&format!("[{}/{}] {message}", step.current, step.total));
+        return;
+    }
+    if let Ok(style) = ProgressStyle::with_template("[{pos}/{len}] {wide_bar} {msg}") {
+        bar.set_style(style);
+    }
+    bar.set_position(step.current as u64);
+    bar.set_message(message);
+    // Leave the line in place: pacman's own output follows below it.
+    bar.abandon();
+}
+
+/// `bootstrap`/`--pacstrap`: pacstrap the YAML's packages into a new system.
+fn run_bootstrap(renderer: Renderer, yml_path: &Path, root: &Path) -> ! {
+    match nixman::bootstrap_from_yaml(yml_path, root) {
+        Ok(()) => {
+            renderer.print(&format!(
+                "Bootstrapped {} from {}",
+                root.display(),
+                yml_path.display()
+            ));
+            std::process::exit(0);
+        }
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Downgrade packages pinned to older versions when allowed, otherwise point out that they exist.
+fn apply_downgrades(
+    renderer: Renderer,
+    yml_path: &Path,
+    args: &ApplyArgs,
+    noconfirm: bool,
+) -> Result<(), nixman::ApplyError> {
+    let selection = args.selection();
+    if args.allow_downgrade {
+        let downgraded = archive::downgrade_from_yaml(yml_path, noconfirm, &selection)?;
+        if !downgraded.is_empty() {
+            renderer.print(&format!("Downgraded: {}", downgraded.join(", ")));
+        }
+        return Ok(());
+    }
+    let pending = nixman::read_effective_config(yml_path)
+        .and_then(|config| {
+            archive::installed_versions()
+                .map(|installed| archive::downgrades_needed(&config, &installed))
+        })
+        .unwrap_or_default();
+    let names: Vec<&str> = pending
+        .iter()
+        .map(|d| d.name.as_str())
+        .filter(|name| selection.includes(name))
+        .collect();
+    if !names.is_empty() {
+        renderer.print(&format!(
+            "{} pinned to older versions than installed; pass --allow-downgrade to downgrade them",
+            names.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// `resume`: finish an apply that was deferred until after a reboot.
+fn run_resume(plan: Option<PathBuf>) -> ! {
+    let plan = plan.map_or_else(resume::plan_path, Ok).unwrap_or_else(|e| {
+        eprintln!("Failed to locate resume plan: {e}");
+        std::process::exit(1);
+    });
+    match resume::resume(&plan) {
+        Ok(()) => {
+            println!("Resumed apply completed successfully.");
+            std::process::exit(0);
+        }
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `flatpak`/`cargo`/`pipx`/`npm`: list, install, or remove packages of an extra source,
+/// keeping its YAML section up to date.
+fn run_source(
+    source: &dyn ExtraSource,
+    action: SourceAction,
+    yml_path: &Path,
+    noconfirm: bool,
+) -> ! {
+    let (names, install) = match action {
+        SourceAction::List => {
+            let installed = source.list().unwrap_or_else(|e| {
+                eprintln!("Failed to list {} packages: {e}", source.name());
+                std::process::exit(1);
+            });
+            for name in installed {
+                println!("{name}");
+            }
+            std::process::exit(0);
+        }
+        SourceAction::Install { names } => (names, true),
+        SourceAction::Remove { names } => (names, false),
+    };
+    let status = if install {
+        source.install(&names, noconfirm)
+    } else {
+        source.remove(&names, noconfirm)
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to execute {}: {e}", source.name());
+        std::process::exit(1);
+    });
+    if status.success() {
+        let mut config = nixman::read_config(yml_path).expect("Failed to read YAML");
+        if let Some(slot) = config.source_mut(source.name()) {
+            let section = slot.get_or_insert_with(Vec::new);
+            if install {
+                for name in names {
+                    if !section.contains(&name) {
+                        section.push(name);
+                    }
+                }
+            } else {
+                section.retain(|name| !names.contains(name));
+            }
+        }
+        nixman::write_config(&config, yml_path).expect("Failed to write to YAML");
+        println!("Updated package list written to {}", yml_path.display());
+    }
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+fn main() {
+    // clap exits with 2 on usage errors, which would read as "changes pending".
+    let mut cli = Cli::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        std::process::exit(if e.use_stderr() {
+            EXIT_ERROR
+        } else {
+            EXIT_IN_SYNC
+        });
+    });
+    let renderer = Renderer::new(if cli.plain_a11y {
+        Style::Plain
+    } else {
+        Style::Human
+    })
+    .with_json(cli.output == OutputFormat::Json);
+    run::set_stdout_to_stderr(renderer.is_json());
+    let config_use = config_use(&cli);
+    let mut yml_path = active_config(renderer, config_use);
+    if !yml_path.exists() {
+        match config_use {
+            ConfigUse::Write => {
+                yml_path = ensure_yml().unwrap_or_else(|e| {
+                    fail(renderer, &format!("Failed to create the config file: {e}"))
+                });
+            }
+            ConfigUse::Read => fail(
+                renderer,
+                &format!(
+                    "{} does not exist; run `nixman init` to set it up",
+                    yml_path.display()
+                ),
+            ),
+            ConfigUse::Unused => {}
+        }
+    }
+    if config_use != ConfigUse::Unused {
+        renderer.print(&format!("Using config file: {}", yml_path.display()));
+    }
+    let settings = load_settings(renderer, &cli, &yml_path);
+    apply_settings(&mut cli, &settings);
+    notify::set_policy(
+        settings
+            .notify_policy()
+            .unwrap_or_else(|e| fail(renderer, &format!("Invalid notify_after: {e}"))),
+    );
+    if !matches!(cli.command, Some(Commands::Check { .. })) {
+        for duplicate in conflicting_duplicates(&yml_path) {
+            eprintln!(
+                "Warning: {duplicate} is declared with conflicting versions; the first is used"
+            );
+        }
+    }
+    // For bootstrap, --root is where pacstrap installs, not a system pacman manages.
+    if !cli.pacstrap && !matches!(cli.command, Some(Commands::Bootstrap)) {
+        pacman::set_paths(pacman::Paths {
+            root: cli.root.clone(),
+            dbpath: cli.dbpath.clone(),
+            chroot: cli.chroot.clone(),
+        });
+        check_chroot(renderer, &cli);
+    }
+
+    let (needs_lock, needs_pacman) = lock_requirements(&cli);
+    // Held until the process exits; the OS releases it even on `process::exit`.
+    let _lock = needs_lock.then(|| take_lock(renderer, &cli));
+    for backend in required_backends(&cli, needs_lock) {
+        backend
+            .require()
+            .unwrap_or_else(|e| fail(renderer, &e.to_string()));
+    }
+    if needs_pacman {
+        lock::check_pacman_db(cli.wait).unwrap_or_else(|e| fail(renderer, &e.to_string()));
+    }
+    set_offline(renderer, &cli);
+    // Offline, the cache is all there is; refreshing the databases would need the network.
+    if installs_from_sync_databases(&cli) && !cli.offline {
+        check_keyring(renderer, &settings, cli.noconfirm);
+        check_sync_databases(renderer, &settings, cli.paru, cli.noconfirm);
+    }
+
+    if cli.pacstrap {
+        run_bootstrap(renderer, &yml_path, Path::new("/mnt"));
+    }
+    if let Some(packages) = cli.remove {
+        run_remove(&packages, cli.paru, cli.noconfirm);
+    }
+    if let Some(packages) = cli.install {
+        refuse_banned(renderer, &yml_path, &packages);
+        run_install(&packages, cli.paru, cli.noconfirm, &yml_path);
+    }
+    run_command(renderer, cli, &yml_path, &settings);
+}
+
+/// Run the subcommand, or without one write the installed packages to `./packages.yml`.
+fn run_command(renderer: Renderer, cli: Cli, yml_path: &Path, settings: &Settings) {
+    match cli.command {
+        Some(Commands::S { packages }) => {
+            refuse_banned(renderer, yml_path, &packages);
+            run_s(&packages, cli.paru, cli.noconfirm);
+        }
+        Some(Commands::Update) => run_update(cli.paru, cli.noconfirm, yml_path),
+        Some(Commands::Init { freeze, git, yes }) => {
+            run_init(renderer, freeze, git, yes || cli.noconfirm);
+        }
+        Some(Commands::Freeze(args)) => run_freeze(renderer, yml_path, &args, cli.noconfirm),
+        Some(Commands::Diff) => run_diff(renderer, yml_path),
+        Some(Commands::Status) => run_status(renderer, yml_path),
+        Some(Commands::List) => run_list(renderer, yml_path),
+        Some(Commands::Check { strict }) => run_check(renderer, yml_path, strict),
+        Some(Commands::Edit) => run_edit(renderer, yml_path),
+        Some(Commands::Outdated { safe }) => run_outdated(renderer, yml_path, safe),
+        #[cfg(feature = "tui")]
+        Some(Commands::Tui) => run_tui(renderer, yml_path, cli.paru, cli.noconfirm),
+        Some(Commands::History { system, action }) => run_history(renderer, system, action),
+        Some(Commands::Audit {
+            user,
+            package,
+            last,
+        }) => run_audit(renderer, user.as_deref(), package.as_deref(), last),
+        Some(Commands::Verify) => run_verify(renderer, yml_path),
+        Some(Commands::Bundle {
+            action: BundleAction::Create { dir },
+        }) => run_bundle_create(renderer, yml_path, &dir),
+        Some(Commands::Bundle {
+            action: BundleAction::Apply { dir },
+        }) => run_bundle_apply(renderer, &dir, cli.noconfirm),
+        Some(Commands::Adopt { packages }) => {
+            run_mark(&packages, InstallReason::Explicit, yml_path)
+        }
+        Some(Commands::Disown { packages }) => {
+            run_mark(&packages, InstallReason::Dependency, yml_path)
+        }
+        Some(Commands::Watch {
+            interval,
+            once,
+            clean,
+        }) => {
+            let keep = settings.cache_keep.unwrap_or(clean::DEFAULT_KEEP);
+            run_watch(renderer, yml_path, interval, once, clean.then_some(keep));
+        }
+        Some(Commands::InstallHook { mode, remove }) => run_install_hook(renderer, mode, remove),
+        Some(Commands::Profile { action }) => run_profile(renderer, &action),
+        Some(Commands::Tidy(args)) => run_tidy(renderer, yml_path, &args),
+        Some(Commands::InstallTimer { interval, clean }) => {
+            run_install_timer(renderer, interval, clean);
+        }
+        Some(Commands::Clean { keep, dry_run }) => run_clean(
+            renderer,
+            yml_path,
+            keep.unwrap_or(clean::DEFAULT_KEEP),
+            dry_run,
+        ),
+        Some(Commands::Explain { package }) => run_explain(renderer, yml_path, &package),
+        Some(Commands::WhyRemove { packages }) => run_why_remove(renderer, yml_path, packages),
+        Some(Commands::AdoptSystem) => run_adopt_system(renderer, yml_path),
+        Some(Commands::Migrate) => run_migrate(renderer, yml_path),
+        Some(Commands::AuditReasons { apply }) => run_audit_reasons(renderer, yml_path, apply),
+        Some(Commands::Export {
+            format,
+            home_manager,
+        }) => run_export(renderer, yml_path, format, home_manager),
+        Some(Commands::Import { format, path }) => run_import(renderer, yml_path, format, path),
+        Some(Commands::Apply(args)) => {
+            configure_apply(renderer, &args, settings);
+            run_apply(renderer, yml_path, &args, cli.noconfirm);
+        }
+        Some(Commands::Bootstrap) => run_bootstrap(
+            renderer,
+            yml_path,
+            cli.root.as_deref().unwrap_or_else(|| Path::new("/mnt")),
+        ),
+        Some(Commands::Resume { plan }) => run_resume(plan),
+        Some(Commands::Flatpak { action }) => {
+            run_source(&FlatpakBackend, action, yml_path, cli.noconfirm)
+        }
+        Some(Commands::Cargo { action }) => {
+            run_source(&CargoSource, action, yml_path, cli.noconfirm)
+        }
+        Some(Commands::Pipx { action }) => run_source(&PipxSource, action, yml_path, cli.noconfirm),
+        Some(Commands::Npm { action }) => run_source(&NpmSource, action, yml_path, cli.noconfirm),
+        None if !yml_path.exists() => fail(
+            renderer,
+            "nixman is not set up yet; run `nixman init`, or `nixman --help` for the commands",
+        ),
+        None => {
+            let packages =
+                pacman::pacman_list_explicit().expect("Failed to execute pacman command");
+            let package_list = parse_explicit_packages(&packages.stdout, true);
+            write_package_list_to_yaml(&package_list, "packages.yml")
+                .expect("Failed to write to YAML");
+        }
     }
 }
@@ -0,0 +1,397 @@
+//! The high-level library entry point
+//!
+//! # Overview
+//!
+//! [`Nixman`] bundles what every operation needs — the YAML path, the backend, and the
+//! [settings](crate::settings) — so it is resolved once instead of being passed to each free
+//! function:
+//!
+//! ```rust,no_run
+//! use nixman::{Backend, Nixman};
+//!
+//! let nixman = Nixman::builder()
+//!     .config("/etc/nixman/packages.yml")
+//!     .backend(Backend::Paru)
+//!     .noconfirm(true)
+//!     .build()?;
+//! for change in nixman.diff()? {
+//!     println!("{}: +{:?} -{:?}", change.source, change.to_install, change.to_remove);
+//! }
+//! nixman.apply()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Options left unset on the builder fall back to the settings, then to nixman's defaults.
+
+use crate::applied::{self, Drift};
+use crate::aur;
+use crate::condition::Host;
+use crate::local::{self, MergeReport};
+use crate::plan::{self, ChangePlan, PinViolation, RequirementViolation};
+use crate::settings::{self, Settings};
+use crate::{
+    ApplyError, ApplyMode, ApplyReport, CancelToken, SourceChanges, builds, freeze, lockfile,
+    pacman, retry, safety,
+};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+pub use crate::settings::Backend;
+
+/// How one package source compares to the YAML.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceStatus {
+    /// `"pacman"` or the name of an extra source.
+    pub source: &'static str,
+    /// Number of entries the YAML declares for this source.
+    pub managed: usize,
+    pub to_install: usize,
+    pub to_remove: usize,
+}
+
+/// The summary shown by `nixman status`.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Status {
+    pub config: PathBuf,
+    pub in_sync: bool,
+    pub sources: Vec<SourceStatus>,
+    /// What the machine-local overrides changed, if there are any.
+    pub local: Option<MergeReport>,
+    /// Declared AUR packages flagged out of date (empty if the AUR could not be reached).
+    pub aur_out_of_date: Vec<String>,
+    /// Pinned packages installed at another version.
+    pub pin_violations: Vec<PinViolation>,
+    /// Packages installed at a version outside their version requirement.
+    pub requirement_violations: Vec<RequirementViolation>,
+    /// Which side moved for each pending pacman change, since the last apply (empty if none
+    /// was recorded).
+    pub drift: Vec<Drift>,
+}
+
+/// Summarize how each package source compares to the YAML at `yml_path`.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read or parsed, or a package manager could not be
+/// queried.
+pub fn status<P: AsRef<Path>>(yml_path: P) -> std::io::Result<Status> {
+    let yml_path = yml_path.as_ref();
+    let (config, local) = local::read_merged(yml_path)?;
+    let changes = crate::pending_changes(yml_path)?;
+    let mut declared = config.packages.clone();
+    declared.retain_active(&Host::current());
+    let sources = changes
+        .iter()
+        .map(|change| SourceStatus {
+            source: change.source,
+            managed: if change.source == "pacman" {
+                config.packages.packages.len()
+            } else {
+                config.source(change.source).map_or(0, <[String]>::len)
+            },
+            to_install: change.to_install.len(),
+            to_remove: change.to_remove.len(),
+        })
+        .collect();
+    let (mut requirement_violations, unmet) = plan::requirement_checks(&declared)?;
+    requirement_violations.extend(unmet.into_iter().filter(|unmet| unmet.installed.is_some()));
+    Ok(Status {
+        config: yml_path.to_path_buf(),
+        in_sync: changes.iter().all(SourceChanges::is_empty) && requirement_violations.is_empty(),
+        sources,
+        local,
+        aur_out_of_date: if crate::offline::is_offline() {
+            Vec::new()
+        } else {
+            aur::declared_out_of_date(&config).unwrap_or_default()
+        },
+        pin_violations: plan::pin_violations(&declared)?,
+        requirement_violations,
+        drift: applied::drift(&changes).unwrap_or_default(),
+    })
+}
+
+/// Builds a [`Nixman`]; see [`Nixman::builder`].
+#[derive(Debug, Default)]
+#[must_use]
+pub struct NixmanBuilder {
+    config: Option<PathBuf>,
+    backend: Option<Backend>,
+    settings: Option<Settings>,
+    noconfirm: Option<bool>,
+    continue_on_error: Option<bool>,
+    force: bool,
+    mode: ApplyMode,
+    paths: Option<pacman::Paths>,
+    strict: bool,
+}
+
+impl NixmanBuilder {
+    /// The YAML to manage (default: `~/.config/nixman/packages.yml`, created if missing).
+    pub fn config<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config = Some(path.into());
+        self
+    }
+
+    /// Install and remove through pacman or paru (default: the `helper` setting, else pacman).
+    pub const fn backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Use these settings instead of reading them from the YAML and `config.yml`.
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Pass `--noconfirm` to pacman/paru.
+    pub const fn noconfirm(mut self, noconfirm: bool) -> Self {
+        self.noconfirm = Some(noconfirm);
+        self
+    }
+
+    /// Try every package instead of stopping at the first failure.
+    pub const fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.continue_on_error = Some(continue_on_error);
+        self
+    }
+
+    /// Allow [`Nixman::apply`] to remove critical packages (see [`crate::safety`]).
+    pub const fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Restrict [`Nixman::apply`] to installs or removals.
+    pub const fn mode(mut self, mode: ApplyMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Manage the system under another root or database instead of the running one, e.g. a
+    /// chroot or a throwaway database in tests (see [`pacman::set_paths`]).
+    pub fn paths(mut self, paths: pacman::Paths) -> Self {
+        self.paths = Some(paths);
+        self
+    }
+
+    /// Refuse to build if the YAML has keys nixman does not read, such as a misspelled
+    /// `version:` (see [`crate::strict`]).
+    pub const fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Resolve the config path and settings.
+    ///
+    /// The settings' `elevation`, `needed`, programs, and retry policy, and the builder's paths,
+    /// apply to the whole process (see [`pacman::Elevation::set_current`],
+    /// [`pacman::set_needed`], [`pacman::set_programs`], [`retry::set_policy`], and
+    /// [`pacman::set_paths`]).
+    ///
+    /// # Errors
+    /// Returns an error if the default YAML cannot be created, the settings cannot be read or
+    /// have a malformed `retry_delay`, or, in strict mode, the YAML has unknown keys.
+    pub fn build(self) -> std::io::Result<Nixman> {
+        let config = match self.config {
+            Some(path) => path,
+            None => crate::ensure_yml()?,
+        };
+        if self.strict {
+            crate::strict::check(&config)?;
+        }
+        let settings = match self.settings {
+            Some(settings) => settings,
+            None => settings::resolve(&config)?,
+        };
+        if let Some(elevation) = settings.elevation {
+            elevation.set_current();
+        }
+        if let Some(needed) = settings.needed {
+            pacman::set_needed(needed);
+        }
+        pacman::set_programs(settings.programs());
+        if let Some(paths) = self.paths {
+            pacman::set_paths(paths);
+        }
+        if settings.retries.is_some() || settings.retry_delay.is_some() {
+            retry::set_policy(settings.retry_policy().map_err(std::io::Error::other)?);
+        }
+        if let Some(jobs) = settings.aur_jobs {
+            builds::set_jobs(jobs);
+        }
+        Ok(Nixman {
+            backend: self.backend.or(settings.helper).unwrap_or_default(),
+            noconfirm: self.noconfirm.or(settings.noconfirm).unwrap_or(false),
+            continue_on_error: self
+                .continue_on_error
+                .or(settings.continue_on_error)
+                .unwrap_or(false),
+            force: self.force,
+            mode: self.mode,
+            config,
+            settings,
+        })
+    }
+}
+
+/// A configured nixman: one YAML, one backend, and its settings.
+#[derive(Debug, Clone)]
+pub struct Nixman {
+    config: PathBuf,
+    backend: Backend,
+    settings: Settings,
+    noconfirm: bool,
+    continue_on_error: bool,
+    force: bool,
+    mode: ApplyMode,
+}
+
+impl Nixman {
+    /// Start configuring a [`Nixman`].
+    pub fn builder() -> NixmanBuilder {
+        NixmanBuilder::default()
+    }
+
+    /// The managed YAML.
+    #[must_use]
+    pub fn config_path(&self) -> &Path {
+        &self.config
+    }
+
+    #[must_use]
+    pub const fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// The YAML with machine-local overrides merged in.
+    ///
+    /// # Errors
+    /// Returns an error if the YAML cannot be read or parsed.
+    pub fn config(&self) -> std::io::Result<crate::Config> {
+        crate::read_effective_config(&self.config)
+    }
+
+    /// Record the installed packages in the YAML (with versions and origins per the `versioned`
+    /// and `origins` settings) and update the lockfile, like `nixman freeze`.
+    ///
+    /// # Errors
+    /// Returns an error if pacman cannot be run, the YAML cannot be parsed, or the YAML or
+    /// lockfile cannot be written.
+    pub fn freeze(&self) -> std::io::Result<()> {
+        freeze::write_installed(
+            &self.config,
+            self.settings.versioned.unwrap_or(false),
+            self.settings.origins.unwrap_or(false),
+            false,
+        )?;
+        lockfile::write_lock(&self.config).map(|_| ())
+    }
+
+    /// The changes [`Nixman::apply`] would make, like `nixman diff`.
+    ///
+    /// # Errors
+    /// Returns an error if the YAML cannot be read or a package manager could not be queried.
+    pub fn diff(&self) -> std::io::Result<Vec<SourceChanges>> {
+        Ok(crate::pending_changes(&self.config)?
+            .into_iter()
+            .map(|change| change.restricted(self.mode))
+            .collect())
+    }
+
+    /// Every install and removal [`Nixman::apply`] would make, plus pins the installed versions
+    /// do not match.
+    ///
+    /// # Errors
+    /// Returns an error if the YAML cannot be read or a package manager could not be queried.
+    pub fn plan(&self) -> std::io::Result<ChangePlan> {
+        plan::plan(&self.config, self.mode)
+    }
+
+    /// How each package source compares to the YAML, like `nixman status`.
+    ///
+    /// # Errors
+    /// Returns an error if the YAML cannot be read or a package manager could not be queried.
+    pub fn status(&self) -> std::io::Result<Status> {
+        status(&self.config)
+    }
+
+    /// Install and remove packages until the system matches the YAML, then update the
+    /// lockfile, like `nixman apply`.
+    ///
+    /// # Returns
+    /// What each pacman/paru and extra-source run did.
+    ///
+    /// # Errors
+    /// Returns an [`ApplyError`] if pacman or the backend is not installed (see
+    /// [`Backend::available`]), a critical package would be removed without
+    /// [`NixmanBuilder::force`], or installing or removing fails.
+    pub fn apply(&self) -> Result<ApplyReport, ApplyError> {
+        self.apply_cancellable(&CancelToken::default())
+    }
+
+    /// Like [`Nixman::apply`], stopping before the next pacman/paru run or extra source once
+    /// `cancel` is cancelled.
+    ///
+    /// # Errors
+    /// As [`Nixman::apply`]; a cancelled apply returns an [`ApplyError`] with `cancelled` set.
+    pub fn apply_cancellable(&self, cancel: &CancelToken) -> Result<ApplyReport, ApplyError> {
+        for backend in [Backend::Pacman, self.backend] {
+            backend.require().map_err(|e| e.to_string())?;
+        }
+        if !self.force && self.mode != ApplyMode::InstallOnly {
+            safety::check_removals(&self.config)?;
+        }
+        let plan = self.plan().map_err(|e| e.to_string())?;
+        let report = plan::execute(
+            &plan,
+            self.backend == Backend::Paru,
+            self.continue_on_error,
+            self.noconfirm,
+            cancel,
+            &mut |_| {},
+        )?;
+        match lockfile::write_lock(&self.config) {
+            Ok(_) => Ok(report),
+            Err(e) => Err(ApplyError::from(format!(
+                "Applied, but failed to update the lockfile: {e}"
+            ))
+            .with_report(report)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builder options win over settings, and settings over defaults.
+    #[test]
+    fn builder_falls_back_to_settings() {
+        let settings: Settings = serde_yml::from_str("helper: paru\nnoconfirm: true\n").unwrap();
+        let nixman = Nixman::builder()
+            .config("packages.yml")
+            .settings(settings.clone())
+            .build()
+            .unwrap();
+        assert_eq!(nixman.backend(), Backend::Paru);
+        assert!(nixman.noconfirm);
+        assert!(!nixman.continue_on_error);
+        assert_eq!(nixman.config_path(), Path::new("packages.yml"));
+
+        let nixman = Nixman::builder()
+            .config("packages.yml")
+            .settings(settings)
+            .backend(Backend::Pacman)
+            .noconfirm(false)
+            .build()
+            .unwrap();
+        assert_eq!(nixman.backend(), Backend::Pacman);
+        assert!(!nixman.noconfirm);
+    }
+}
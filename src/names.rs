@@ -0,0 +1,159 @@
+//! Package name validation before pacman runs
+//!
+//! # Overview
+//!
+//! pacman checks its targets only once a transaction starts, so a typo in the YAML used to fail
+//! an apply with `target not found` after the removals had already happened. [`check_targets`]
+//! runs before anything is changed and reports every bad name at once:
+//!
+//! - names pacman would reject outright ([`is_valid`]),
+//! - names that are no package, group, or provided name in the sync databases (checked with
+//!   `pacman -Slq`, `pacman -Sg`, and for the rest `pacman -Sp`),
+//! - with paru, names that are not in the AUR either.
+
+use crate::{ApplyError, aur, pacman, run};
+use std::process::Stdio;
+
+/// Whether `name` is a name pacman accepts: letters, digits, and `@._+-`, not
+/// starting with `-` or `.`, optionally prefixed with a repository (`extra/vim`).
+#[must_use]
+pub fn is_valid(name: &str) -> bool {
+    let (repo, name) = name.split_once('/').unwrap_or(("", name));
+    let allowed = |part: &str| {
+        part.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '_' | '+' | '-'))
+    };
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && allowed(name)
+        && (repo.is_empty() || allowed(repo))
+}
+
+/// The names in `names` pacman would reject.
+#[must_use]
+pub fn invalid(names: &[String]) -> Vec<String> {
+    names
+        .iter()
+        .filter(|name| !is_valid(name))
+        .cloned()
+        .collect()
+}
+
+/// The targets pacman reported as `target not found` in `output`.
+#[must_use]
+pub fn parse_not_found(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("error: target not found: "))
+        .map(|name| name.trim().to_string())
+        .collect()
+}
+
+/// The names in `names` that no sync database knows as a package, group, or provided name.
+///
+/// # Errors
+/// Returns an error if pacman could not be executed.
+pub fn unresolved(names: &[String]) -> std::io::Result<Vec<String>> {
+    let repo = aur::repo_packages()?;
+    let groups = crate::available_groups()?;
+    let candidates: Vec<&String> = names
+        .iter()
+        .filter(|name| !repo.contains(name) && !groups.contains_key(*name))
+        .collect();
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+    // -Sp resolves provides and repo prefixes without touching the system; stdin is closed so
+    // provider selection prompts take their default.
+    let output = run::output(
+        pacman::pacman_command()
+            .args(["-Sp", "--print-format", "%n"])
+            .args(&candidates)
+            .stdin(Stdio::null()),
+    )?;
+    Ok(parse_not_found(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// The names in `names` that cannot be installed: unresolved in the sync databases and, with
+/// `use_paru`, missing from the AUR.
+///
+/// # Errors
+/// Returns an error if pacman could not be executed or the AUR could not be reached.
+pub fn unknown_targets(names: &[String], use_paru: bool) -> std::io::Result<Vec<String>> {
+    let unresolved = unresolved(names)?;
+    if !use_paru || unresolved.is_empty() {
+        return Ok(unresolved);
+    }
+    let found = aur::info(&unresolved)?;
+    Ok(unresolved
+        .into_iter()
+        .filter(|name| !found.iter().any(|package| &package.name == name))
+        .collect())
+}
+
+/// Make sure every name in `to_install` can be installed before anything runs.
+///
+/// If pacman or the AUR cannot be queried, the names are left for pacman to check.
+///
+/// # Errors
+/// Returns an [`ApplyError`] listing every invalid and every unknown name.
+pub fn check_targets(to_install: &[String], use_paru: bool) -> Result<(), ApplyError> {
+    let invalid = invalid(to_install);
+    if !invalid.is_empty() {
+        return Err(format!("Invalid package names: {}", invalid.join(", ")).into());
+    }
+    let unknown = unknown_targets(to_install, use_paru).unwrap_or_default();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let hint = if use_paru {
+        "in no repository or the AUR"
+    } else {
+        "in no repository; use --paru for AUR packages"
+    };
+    Err(format!(
+        "Unknown packages ({hint}): {}. Nothing was changed.",
+        unknown.join(", ")
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// pacman's character set is enforced, and not-found targets are collected.
+    #[test]
+    fn validates_names_and_parses_missing_targets() {
+        for name in [
+            "vim",
+            "gtk2+extra",
+            "python-pip",
+            "lib32-glibc",
+            "r8168-dkms@x",
+            "extra/vim",
+        ] {
+            assert!(is_valid(name), "{name}");
+        }
+        for name in [
+            "",
+            "vim!",
+            "-vim",
+            ".vim",
+            "vim htop",
+            "vim;rm",
+            "extra/",
+            "ex tra/vim",
+        ] {
+            assert!(!is_valid(name), "{name}");
+        }
+        assert_eq!(
+            invalid(&["vim".to_string(), "htop?".to_string()]),
+            vec!["htop?"]
+        );
+        assert_eq!(
+            parse_not_found("error: target not found: vimm\nerror: target not found: htpo\n"),
+            vec!["vimm", "htpo"]
+        );
+    }
+}
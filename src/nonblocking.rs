@@ -0,0 +1,86 @@
+//! Async API (feature `async`)
+//!
+//! # Overview
+//!
+//! Applying can take minutes and diffing shells out to several package managers, which would
+//! block a GUI event loop or an async daemon. These methods run the [`Nixman`] operations and
+//! AUR queries on tokio's blocking thread pool and must be called from within a tokio runtime:
+//!
+//! ```rust,no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use nixman::{CancelToken, Nixman};
+//!
+//! let nixman = Nixman::builder().noconfirm(true).build()?;
+//! let changes = nixman.diff_async().await?;
+//! let cancel = CancelToken::default();
+//! // e.g. hand a clone to a "Stop" button that calls `cancel.cancel()`.
+//! nixman.apply_async(cancel.clone()).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Dropping a future does not stop an operation that already started; cancel its
+//! [`CancelToken`] instead, which stops the apply cleanly before the next pacman/paru run.
+
+use crate::aur::{self, AurPackage};
+use crate::manager::Status;
+use crate::{ApplyError, ApplyReport, CancelToken, Nixman, SourceChanges};
+
+/// Run `f` on the blocking thread pool, turning a panic into an error.
+async fn blocking<T, F>(f: F) -> std::io::Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(std::io::Error::other)
+}
+
+impl Nixman {
+    /// [`Nixman::diff`] without blocking the runtime.
+    ///
+    /// # Errors
+    /// As [`Nixman::diff`].
+    pub async fn diff_async(&self) -> std::io::Result<Vec<SourceChanges>> {
+        let nixman = self.clone();
+        blocking(move || nixman.diff()).await?
+    }
+
+    /// [`Nixman::status`] without blocking the runtime.
+    ///
+    /// # Errors
+    /// As [`Nixman::status`].
+    pub async fn status_async(&self) -> std::io::Result<Status> {
+        let nixman = self.clone();
+        blocking(move || nixman.status()).await?
+    }
+
+    /// [`Nixman::freeze`] without blocking the runtime.
+    ///
+    /// # Errors
+    /// As [`Nixman::freeze`].
+    pub async fn freeze_async(&self) -> std::io::Result<()> {
+        let nixman = self.clone();
+        blocking(move || nixman.freeze()).await?
+    }
+
+    /// [`Nixman::apply_cancellable`] without blocking the runtime.
+    ///
+    /// # Errors
+    /// As [`Nixman::apply_cancellable`].
+    pub async fn apply_async(&self, cancel: CancelToken) -> Result<ApplyReport, ApplyError> {
+        let nixman = self.clone();
+        blocking(move || nixman.apply_cancellable(&cancel))
+            .await
+            .map_err(|e| ApplyError::from(e.to_string()))?
+    }
+}
+
+/// [`aur::info`] without blocking the runtime.
+///
+/// # Errors
+/// As [`aur::info`].
+pub async fn aur_info(names: Vec<String>) -> std::io::Result<Vec<AurPackage>> {
+    blocking(move || aur::info(&names)).await?
+}
@@ -0,0 +1,178 @@
+//! Desktop notifications
+//!
+//! # Overview
+//!
+//! nixman talks to the desktop's notification daemon over D-Bus (through `notify-rust`), so a
+//! run started from a script, a keybinding, or a timer can be followed without a terminal.
+//! What is announced depends on the `notify` setting ([`NotifyOn`]):
+//!
+//! - `drift` (default): `watch` and the drift timer notify when the system drifts,
+//! - `all`: also `apply` and `update` when they finish, if they took at least `notify_after`
+//!   ([`DEFAULT_AFTER`]),
+//! - `off`: never.
+//!
+//! Set the policy for the whole process with [`set_policy`]. Failing to notify, e.g. on a
+//! machine without a notification daemon, never fails the command itself.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// How long an apply or update must take before its completion is announced.
+pub const DEFAULT_AFTER: Duration = Duration::from_secs(30);
+
+/// Which events send a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyOn {
+    Off,
+    /// Drift found by `watch`.
+    #[default]
+    Drift,
+    /// Drift, and long `apply`/`update` runs finishing.
+    All,
+}
+
+/// When to send notifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotifyPolicy {
+    pub on: NotifyOn,
+    /// The shortest run whose completion is announced.
+    pub after: Duration,
+}
+
+impl Default for NotifyPolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl NotifyPolicy {
+    /// Drift only.
+    pub const DEFAULT: Self = Self {
+        on: NotifyOn::Drift,
+        after: DEFAULT_AFTER,
+    };
+
+    /// Whether drift found by `watch` is announced.
+    #[must_use]
+    pub fn notifies_drift(self) -> bool {
+        self.on != NotifyOn::Off
+    }
+
+    /// Whether a run that took `elapsed` is announced when it finishes.
+    #[must_use]
+    pub fn notifies_finished(self, elapsed: Duration) -> bool {
+        self.on == NotifyOn::All && elapsed >= self.after
+    }
+}
+
+static POLICY: Mutex<NotifyPolicy> = Mutex::new(NotifyPolicy::DEFAULT);
+
+/// The notification policy of the process.
+#[must_use]
+pub fn policy() -> NotifyPolicy {
+    *POLICY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Use `policy` for every notification of the process.
+pub fn set_policy(policy: NotifyPolicy) {
+    *POLICY.lock().unwrap_or_else(PoisonError::into_inner) = policy;
+}
+
+/// Show a desktop notification, whatever the policy.
+///
+/// # Errors
+/// Returns an error if the notification daemon could not be reached.
+#[cfg(target_os = "linux")]
+pub fn send(summary: &str, body: &str) -> std::io::Result<()> {
+    notify_rust::Notification::new()
+        .appname("nixman")
+        .summary(summary)
+        .body(body)
+        .show()
+        .map(drop)
+        .map_err(std::io::Error::other)
+}
+
+/// Announce drift described by `summary`, if the policy asks for it.
+///
+/// # Errors
+/// Returns an error if the notification daemon could not be reached.
+#[cfg(target_os = "linux")]
+pub fn drift(summary: &str) -> std::io::Result<()> {
+    if !policy().notifies_drift() {
+        return Ok(());
+    }
+    send("nixman: drift detected", summary)
+}
+
+/// Announce that `command` finished after `elapsed`, if the policy asks for it.
+///
+/// # Errors
+/// Returns an error if the notification daemon could not be reached.
+#[cfg(target_os = "linux")]
+pub fn finished(command: &str, elapsed: Duration, success: bool) -> std::io::Result<()> {
+    if !policy().notifies_finished(elapsed) {
+        return Ok(());
+    }
+    let (summary, body) = finished_message(command, elapsed, success);
+    send(&summary, &body)
+}
+
+/// The summary and body announcing that `command` finished after `elapsed`.
+#[must_use]
+pub fn finished_message(command: &str, elapsed: Duration, success: bool) -> (String, String) {
+    let secs = elapsed.as_secs();
+    let took = if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    };
+    if success {
+        (
+            format!("nixman {command} finished"),
+            format!("Completed in {took}."),
+        )
+    } else {
+        (
+            format!("nixman {command} failed"),
+            format!("Failed after {took}; see the terminal or log for details."),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only long runs are announced, and only with `notify: all`.
+    #[test]
+    fn announces_long_runs_only_when_asked() {
+        let all = NotifyPolicy {
+            on: serde_yml::from_str("all").unwrap(),
+            after: Duration::from_secs(30),
+        };
+        assert!(all.notifies_finished(Duration::from_secs(95)));
+        assert!(!all.notifies_finished(Duration::from_secs(5)));
+        assert!(all.notifies_drift());
+        assert!(!NotifyPolicy::DEFAULT.notifies_finished(Duration::from_hours(1)));
+        assert!(NotifyPolicy::DEFAULT.notifies_drift());
+        let off = NotifyPolicy {
+            on: NotifyOn::Off,
+            ..all
+        };
+        assert!(!off.notifies_drift());
+        assert_eq!(
+            finished_message("apply", Duration::from_secs(95), true),
+            (
+                "nixman apply finished".to_string(),
+                "Completed in 1m 35s.".to_string()
+            )
+        );
+        assert_eq!(
+            finished_message("update", Duration::from_secs(42), false).0,
+            "nixman update failed"
+        );
+    }
+}
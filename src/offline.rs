@@ -0,0 +1,139 @@
+//! Offline applies from the package cache
+//!
+//! # Overview
+//!
+//! With `--offline` ([`set_offline`]), nixman does not touch the network, so a config can be
+//! reproduced on an air-gapped machine from a package cache synced from another one:
+//!
+//! - installs run `pacman -U` on the newest file of each package in
+//!   [`PACMAN_CACHE`](crate::clean::PACMAN_CACHE), and fail before anything changes if a
+//!   package is not cached,
+//! - pinned versions, including `--allow-downgrade`, come from the cache instead of the
+//!   [Arch Linux Archive](crate::archive),
+//! - `diff` and `status` skip the AUR, and stale sync databases are not refreshed,
+//! - the extra sources (Flatpak, cargo, pipx, npm) are left alone, and `update` and `outdated`
+//!   refuse to run.
+//!
+//! Dependencies that are not installed yet must be cached as well; pacman resolves them from
+//! its sync databases and then finds their files in the cache.
+
+use crate::ApplyError;
+use crate::clean::{self, CachedFile};
+use crate::versioning::{FullVersion, vercmp};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the process runs offline.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Whether the process runs offline.
+#[must_use]
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Run the rest of the process offline, or not.
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+/// The newest package file of `name` in `files`, or the one at exactly `version` if given.
+/// Signature files are skipped.
+#[must_use]
+pub fn find<'a>(
+    files: &'a [CachedFile],
+    name: &str,
+    version: Option<&FullVersion>,
+) -> Option<&'a CachedFile> {
+    files
+        .iter()
+        .filter(|file| file.name == name && file.path.extension().is_none_or(|ext| ext != "sig"))
+        .filter(|file| {
+            version.is_none_or(|version| FullVersion::from(file.version.as_str()) == *version)
+        })
+        .max_by(|a, b| vercmp(&a.version, &b.version))
+}
+
+/// The cached files for `packages`, each at its version if one is given, and the packages
+/// that are not cached.
+///
+/// # Errors
+/// Returns an error if the cache exists but cannot be read.
+pub fn cached_files(
+    packages: &[(String, Option<FullVersion>)],
+) -> std::io::Result<(Vec<PathBuf>, Vec<String>)> {
+    let files = clean::cache_files(Path::new(clean::PACMAN_CACHE))?;
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for (name, version) in packages {
+        match find(&files, name, version.as_ref()) {
+            Some(file) => found.push(file.path.clone()),
+            None => missing.push(
+                version
+                    .as_ref()
+                    .map_or_else(|| name.clone(), |version| format!("{name} {version}")),
+            ),
+        }
+    }
+    Ok((found, missing))
+}
+
+/// The newest cached file of each of `names`, and the names that are not cached.
+///
+/// # Errors
+/// Returns an error if the cache exists but cannot be read.
+pub fn cached_packages(names: &[String]) -> std::io::Result<(Vec<PathBuf>, Vec<String>)> {
+    let packages: Vec<(String, Option<FullVersion>)> =
+        names.iter().map(|name| (name.clone(), None)).collect();
+    cached_files(&packages)
+}
+
+/// Fail unless every one of `names` is in the cache.
+///
+/// # Errors
+/// Returns an [`ApplyError`] listing the packages that are not cached, or if the cache cannot
+/// be read.
+pub fn check_cached(names: &[String]) -> Result<(), ApplyError> {
+    let (_, missing) = cached_packages(names).map_err(|e| e.to_string())?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "Not in {}, so they cannot be installed offline: {}. Nothing was changed.",
+        clean::PACMAN_CACHE,
+        missing.join(", ")
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The newest version is picked unless one is pinned, and signatures are never picked.
+    #[test]
+    fn finds_cached_packages() {
+        let files: Vec<CachedFile> = [
+            "htop-3.2.2-1-x86_64.pkg.tar.zst",
+            "htop-3.3.0-3-x86_64.pkg.tar.zst",
+            "htop-3.3.0-3-x86_64.pkg.tar.zst.sig",
+            "htop-3.10.0-1-x86_64.pkg.tar.zst.sig",
+            "ffmpeg-2:7.0.2-3-x86_64.pkg.tar.zst",
+        ]
+        .iter()
+        .filter_map(|name| clean::parse_file_name(Path::new(name)))
+        .collect();
+        let path = |file: Option<&CachedFile>| file.map(|file| file.path.clone());
+        assert_eq!(
+            path(find(&files, "htop", None)),
+            Some(PathBuf::from("htop-3.3.0-3-x86_64.pkg.tar.zst"))
+        );
+        assert_eq!(
+            path(find(&files, "htop", Some(&FullVersion::from("3.2.2-1")))),
+            Some(PathBuf::from("htop-3.2.2-1-x86_64.pkg.tar.zst"))
+        );
+        assert!(find(&files, "ffmpeg", Some(&FullVersion::from("2:7.0.2-3"))).is_some());
+        assert!(find(&files, "htop", Some(&FullVersion::from("3.10.0-1"))).is_none());
+        assert!(find(&files, "git", None).is_none());
+    }
+}
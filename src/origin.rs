@@ -0,0 +1,139 @@
+//! Which repository each package comes from
+//!
+//! # Overview
+//!
+//! With `freeze --origin` (or `origins: true` in the [settings](crate::settings)), every frozen
+//! package records the repository it was installed from:
+//!
+//! ```yaml
+//! packages:
+//!   - name: htop
+//!     origin: extra
+//!   - name: paru
+//!     origin: aur
+//! ```
+//!
+//! Packages in no sync repository are recorded as `aur`. Once a YAML records origins, later
+//! freezes keep them up to date. `apply` compares the recorded origins with the current sync
+//! databases and warns about packages that moved to another repository or disappeared, which
+//! usually means they were dropped to the AUR.
+
+use crate::Config;
+use crate::pacman;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The origin recorded for packages that are in no sync repository.
+pub const AUR: &str = "aur";
+
+/// A package whose recorded origin no longer matches the sync databases.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OriginChange {
+    pub name: String,
+    pub recorded: String,
+    /// The repository now providing the package, or `None` if no repository does.
+    pub current: Option<String>,
+}
+
+impl std::fmt::Display for OriginChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.current {
+            Some(current) => write!(f, "{} moved from {} to {current}", self.name, self.recorded),
+            None => write!(
+                f,
+                "{} is no longer in {} or any other repository (dropped to the AUR?)",
+                self.name, self.recorded
+            ),
+        }
+    }
+}
+
+/// Parse `pacman -Sl` output (`repo name version [installed]`) into the repository of each
+/// package. A package in several repositories belongs to the first, as pacman would pick it.
+#[must_use]
+pub fn parse_repo_list(output: &str) -> BTreeMap<String, String> {
+    let mut repos = BTreeMap::new();
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        if let (Some(repo), Some(name)) = (fields.next(), fields.next()) {
+            repos
+                .entry(name.to_string())
+                .or_insert_with(|| repo.to_string());
+        }
+    }
+    repos
+}
+
+/// The repository of every package in the sync databases (`pacman -Sl`).
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+pub fn repo_index() -> std::io::Result<BTreeMap<String, String>> {
+    let output = crate::run::output(pacman::pacman_command().arg("-Sl"))?;
+    Ok(parse_repo_list(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The origin to record for `name`: its repository, or [`AUR`].
+#[must_use]
+pub fn origin_of(name: &str, index: &BTreeMap<String, String>) -> String {
+    index
+        .get(name)
+        .map_or_else(|| AUR.to_string(), Clone::clone)
+}
+
+/// The packages in `config` whose recorded origin differs from `index`.
+///
+/// Packages recorded as [`AUR`] only count as moved once a repository provides them.
+#[must_use]
+pub fn changes(config: &Config, index: &BTreeMap<String, String>) -> Vec<OriginChange> {
+    config
+        .packages
+        .packages
+        .values()
+        .filter_map(|pkg| {
+            let recorded = pkg.origin.as_ref()?;
+            let current = index.get(&pkg.name);
+            let moved = if recorded == AUR {
+                current.is_some()
+            } else {
+                current != Some(recorded)
+            };
+            moved.then(|| OriginChange {
+                name: pkg.name.clone(),
+                recorded: recorded.clone(),
+                current: current.cloned(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Moves between repositories and to or from the AUR are reported; unchanged origins are not.
+    #[test]
+    fn reports_moved_packages() {
+        let index = parse_repo_list(
+            "core linux 6.9.2.arch1-1 [installed]\nextra htop 3.3.0-1\nmultilib steam 1.0.0.81-1\nchaotic-aur htop 3.3.0-2\nextra yay 12.3.5-1\n",
+        );
+        assert_eq!(index["htop"], "extra");
+        assert_eq!(origin_of("paru", &index), AUR);
+        let config: Config = serde_yml::from_str(
+            "packages:\n  - name: linux\n    origin: core\n  - name: steam\n    origin: extra\n  - name: zoom\n    origin: extra\n  - name: paru\n    origin: aur\n  - name: yay\n    origin: aur\n  - htop\n",
+        )
+        .unwrap();
+        let moved: Vec<String> = changes(&config, &index)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            moved,
+            vec![
+                "steam moved from extra to multilib",
+                "zoom is no longer in extra or any other repository (dropped to the AUR?)",
+                "yay moved from aur to extra",
+            ]
+        );
+    }
+}
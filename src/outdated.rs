@@ -0,0 +1,205 @@
+//! Available upgrades compared to the YAML's version pins
+//!
+//! # Overview
+//!
+//! `nixman outdated` lists every package with a newer version available: repository packages
+//! from `checkupdates` (or `pacman -Qu` when pacman-contrib is not installed) and AUR packages
+//! from the [AUR RPC](crate::aur). Each upgrade is shown next to the version pinned in the YAML,
+//! if any, so upgrades that would move a package off its pin stand out before `nixman update`
+//! applies them.
+//!
+//! `nixman outdated --safe` insists on `checkupdates` ([`safe_repo_updates`]): it syncs a
+//! temporary copy of the databases, so the preview is current without the `pacman -Sy` that
+//! would leave the system half-upgraded. Without pacman-contrib, or when `checkupdates` fails,
+//! it reports an error instead of falling back to the possibly stale databases.
+
+use crate::Config;
+use crate::aur::AurUpdate;
+use crate::pacman;
+use crate::versioning::FullVersion;
+use serde::Serialize;
+use std::process::Command;
+
+/// A package with a newer version available.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Upgrade {
+    pub name: String,
+    pub installed: String,
+    pub available: String,
+    /// The version pinned in the YAML, or its version requirement, if any.
+    pub pinned: Option<String>,
+    /// `"repo"` or `"aur"`.
+    pub source: &'static str,
+    /// Whether upgrading would leave the pinned version or the version requirement.
+    pub violates_pin: bool,
+}
+
+/// Parse `name old -> new` lines as printed by `checkupdates` and `pacman -Qu`.
+#[must_use]
+pub fn parse_updates(output: &str) -> Vec<(String, String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let installed = fields.next()?;
+            (fields.next()? == "->").then_some(())?;
+            let available = fields.next()?;
+            Some((
+                name.to_string(),
+                installed.to_string(),
+                available.to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Upgrades available from the sync repositories.
+///
+/// `checkupdates` is preferred because it checks against fresh databases without touching the
+/// system's; `pacman -Qu` compares against the current sync databases. `checkupdates` only knows
+/// the running system, so `pacman -Qu` is used when [`pacman::set_paths`] picked another one.
+///
+/// # Errors
+/// Returns an error if neither command can be executed.
+pub fn repo_updates() -> std::io::Result<Vec<(String, String, String)>> {
+    if !pacman::paths().is_default() {
+        let output = crate::run::output(pacman::pacman_command().arg("-Qu"))?;
+        return Ok(parse_updates(&String::from_utf8_lossy(&output.stdout)));
+    }
+    let output = match crate::run::output(&mut Command::new("checkupdates")) {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            crate::run::output(pacman::pacman_command().arg("-Qu"))?
+        }
+        Err(e) => return Err(e),
+    };
+    Ok(parse_updates(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Upgrades available from the sync repositories, from `checkupdates` only.
+///
+/// # Errors
+/// Returns an error if `checkupdates` is not installed or fails, or [`pacman::set_paths`]
+/// picked another system than the running one, which `checkupdates` cannot check.
+pub fn safe_repo_updates() -> std::io::Result<Vec<(String, String, String)>> {
+    if !pacman::paths().is_default() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "checkupdates only checks the running system; drop --root and --dbpath",
+        ));
+    }
+    let output = crate::run::output(&mut Command::new("checkupdates")).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            std::io::Error::new(
+                e.kind(),
+                "checkupdates is not installed; install pacman-contrib",
+            )
+        } else {
+            e
+        }
+    })?;
+    checkupdates_result(
+        output.status.code(),
+        &String::from_utf8_lossy(&output.stdout),
+        &String::from_utf8_lossy(&output.stderr),
+    )
+}
+
+/// Interpret how `checkupdates` exited: `0` with updates, `2` without, anything else failed.
+fn checkupdates_result(
+    code: Option<i32>,
+    stdout: &str,
+    stderr: &str,
+) -> std::io::Result<Vec<(String, String, String)>> {
+    match code {
+        Some(0) => Ok(parse_updates(stdout)),
+        Some(2) => Ok(Vec::new()),
+        _ => Err(std::io::Error::other(format!(
+            "checkupdates failed: {}",
+            stderr.trim()
+        ))),
+    }
+}
+
+/// Combine repository and AUR upgrades with the pins in `config`, sorted by name.
+#[must_use]
+pub fn upgrades(
+    config: &Config,
+    repo: Vec<(String, String, String)>,
+    aur: Vec<AurUpdate>,
+) -> Vec<Upgrade> {
+    let aur = aur
+        .into_iter()
+        .map(|update| (update.name, update.installed, update.available, "aur"));
+    let mut upgrades: Vec<Upgrade> = repo
+        .into_iter()
+        .map(|(name, installed, available)| (name, installed, available, "repo"))
+        .chain(aur)
+        .map(|(name, installed, available, source)| {
+            let declared = config.packages.get(&name);
+            let pin = declared.and_then(|pkg| pkg.version.as_ref());
+            let requirement = declared.and_then(|pkg| pkg.requirement.as_ref());
+            Upgrade {
+                violates_pin: pin.is_some_and(|pin| *pin != FullVersion::from(available.as_str()))
+                    || requirement.is_some_and(|requirement| !requirement.matches(&available)),
+                pinned: pin
+                    .map(ToString::to_string)
+                    .or_else(|| requirement.map(ToString::to_string)),
+                name,
+                installed,
+                available,
+                source,
+            }
+        })
+        .collect();
+    upgrades.sort_by(|a, b| a.name.cmp(&b.name));
+    upgrades
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Upgrades away from a pinned version are flagged; unpinned ones are not.
+    #[test]
+    fn flags_upgrades_past_pins() {
+        let repo = parse_updates(
+            "linux 6.9.1.arch1-1 -> 6.9.2.arch1-1\nvim 9.1.0330-1 -> 9.1.0400-1 [ignored]\nnot an update\n",
+        );
+        assert_eq!(repo.len(), 2);
+        let config: Config =
+            serde_yml::from_str(
+                "packages:\n  - name: linux\n    version: \"~6.9\"\n  - name: vim\n    version: 9.1.0330-1\n",
+            )
+            .unwrap();
+        let aur = vec![AurUpdate {
+            name: "paru".to_string(),
+            installed: "2.0.3-1".to_string(),
+            available: "2.0.4-1".to_string(),
+        }];
+        let upgrades = upgrades(&config, repo, aur);
+        let names: Vec<&str> = upgrades.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, vec!["linux", "paru", "vim"]);
+        assert!(!upgrades[0].violates_pin);
+        assert_eq!(upgrades[0].pinned.as_deref(), Some("~6.9"));
+        assert_eq!(upgrades[1].source, "aur");
+        assert_eq!(upgrades[2].pinned.as_deref(), Some("9.1.0330-1"));
+        assert!(upgrades[2].violates_pin);
+    }
+
+    /// `checkupdates` reports "no updates" with its exit code, and failures are not mistaken for
+    /// it.
+    #[test]
+    fn reads_checkupdates_status() {
+        let found = checkupdates_result(Some(0), "vim 9.1.0330-1 -> 9.1.0400-1\n", "").unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(checkupdates_result(Some(2), "", "").unwrap().is_empty());
+        let err =
+            checkupdates_result(Some(1), "", "==> ERROR: Cannot fetch updates\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "checkupdates failed: ==> ERROR: Cannot fetch updates"
+        );
+    }
+}
@@ -3,49 +3,463 @@
 //! # Overview
 //!
 //! This module provides simple Rust wrappers for common `pacman` and `paru` package manager operations on Arch Linux systems.
-//! It allows programmatic installation, removal, and listing of packages, with optional sudo support for privileged operations.
+//! It allows programmatic installation, removal, and listing of packages, with privilege elevation through sudo, doas, or
+//! run0 (see [`Elevation`]) and `--noconfirm` for unattended runs.
 //!
 //! # Functions
 //!
-//! - [`pacman_install`]: Install packages using pacman, optionally with sudo.
+//! - [`Elevation`]: The tool used to run commands as root.
+//! - [`on_path`]: Whether a program such as pacman or paru is installed.
+//! - [`pacman_install`]: Install packages using pacman, optionally elevated.
 //! - [`pacman_list_explicit`]: List explicitly installed packages.
 //! - [`paru_install`]: Install packages using paru (AUR helper).
-//! - [`pacman_remove`]: Remove packages using pacman, optionally with sudo.
+//! - [`pacman_remove`]: Remove packages using pacman, optionally elevated.
 //! - [`paru_remove`]: Remove packages using paru.
-//! - [`pacman_update`]: Update the system using pacman, optionally with sudo.
+//! - [`pacman_update`]: Update the system using pacman, elevated.
 //! - [`paru_update`]: Update the system using paru.
+//! - [`pacman_list_installed`]: List all installed packages with their versions.
+//! - [`pacman_list_dependencies`]: List packages installed as dependencies.
+//! - [`pacman_list_groups`]: List package groups and their members.
+//! - [`parse_package_lines`]: Read the `name version` lines of a listing as borrowed [`PackageRef`]s, skipping anything else.
+//! - [`pacman_query_info`] / [`pacman_query_infos`]: What `pacman -Qi` reports about installed
+//!   packages, as [`PackageInfo`].
+//! - [`pacman_sync_infos`]: What `pacman -Si` reports about packages in the sync databases, as
+//!   [`SyncInfo`].
+//! - [`pacman_mark`]: Change the install reason of packages.
+//! - [`install_command`] / [`remove_command`]: Build the commands used by `apply`.
+//! - [`set_needed`]: Turn off `--needed`, which installs pass by default.
+//! - [`set_paths`]: Operate on another root or database (`--root`, `--dbpath`).
+//! - [`set_programs`]: Run wrappers or binaries elsewhere in place of pacman and paru, with extra
+//!   options for transactions.
+//! - [`pacstrap_command`]: Build the `pacstrap` command used to bootstrap a new system.
+//! - [`run_captured`]: Run a command, teeing its output while keeping the last lines for error reports.
+//! - [`run_audited`]: [`run_captured`] for commands that change the system, recorded in the
+//!   [audit log](crate::audit).
+//!
+//! The functions that build or run commands only exist on Linux; the types and the parsers of
+//! pacman's output are available everywhere.
 //!
 //! # Example
 //!
-//! ```rust
+//! ```rust,no_run
+//! # #[cfg(target_os = "linux")] {
 //! use nixman::pacman::{pacman_install, pacman_list_explicit};
-//! let status = pacman_install(&["htop".to_string()], true)?;
+//! let status = pacman_install(&["htop".to_string()], true, false)?;
 //! let output = pacman_list_explicit()?;
+//! # }
+//! # Ok::<(), std::io::Error>(())
 //! ```
 
+use crate::InstallReason;
+#[cfg(target_os = "linux")]
+use crate::audit::{self, AuditAction};
+#[cfg(target_os = "linux")]
+use crate::run;
+use crate::versioning::FullVersion;
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
 use std::process::{Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// Number of trailing output lines kept by [`run_captured`].
+pub const TAIL_LINES: usize = 10;
+
+/// The tool used to run pacman and other privileged commands as root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Elevation {
+    Sudo,
+    Doas,
+    /// systemd's `run0`.
+    Run0,
+    /// Run commands directly, e.g. when nixman itself runs as root.
+    None,
+}
+
+/// Whether `program` is a file in one of the directories on `PATH`.
+#[must_use]
+pub fn on_path(program: &str) -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+}
+
+/// Whether the process runs as root, e.g. in a container or chroot, judged by the owner of
+/// `/proc/self`.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn is_root() -> bool {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata("/proc/self").is_ok_and(|metadata| metadata.uid() == 0)
+}
+
+/// Whether the process runs as root; never, off Linux.
+#[cfg(not(target_os = "linux"))]
+#[must_use]
+pub const fn is_root() -> bool {
+    false
+}
+
+/// Whether `program` can be run: a path to an existing file, or a name [`on_path`].
+#[must_use]
+pub fn installed(program: &OsStr) -> bool {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file();
+    }
+    program.to_str().is_some_and(on_path)
+}
+
+/// The elevation used by every privileged command, chosen once per process.
+static CURRENT_ELEVATION: OnceLock<Elevation> = OnceLock::new();
+
+impl Elevation {
+    /// The program that elevates, or `None` to run commands directly.
+    #[must_use]
+    pub const fn program(self) -> Option<&'static str> {
+        match self {
+            Self::Sudo => Some("sudo"),
+            Self::Doas => Some("doas"),
+            Self::Run0 => Some("run0"),
+            Self::None => None,
+        }
+    }
+
+    /// [`Elevation::None`] when the process already runs as root, as in containers, which often
+    /// have no sudo; otherwise the first of sudo, doas, and run0 found on `PATH`, or sudo if
+    /// none is.
+    #[must_use]
+    pub fn detect() -> Self {
+        if is_root() {
+            return Self::None;
+        }
+        [Self::Sudo, Self::Doas, Self::Run0]
+            .into_iter()
+            .find(|elevation| elevation.program().is_some_and(on_path))
+            .unwrap_or(Self::Sudo)
+    }
+
+    /// The elevation privileged commands use: the one passed to [`Elevation::set_current`],
+    /// or [`Elevation::detect`]ed on first use.
+    #[must_use]
+    pub fn current() -> Self {
+        *CURRENT_ELEVATION.get_or_init(Self::detect)
+    }
+
+    /// Use this elevation for every privileged command of the process. Only the first call, made
+    /// before any privileged command is built, takes effect; returns whether it did.
+    pub fn set_current(self) -> bool {
+        CURRENT_ELEVATION.set(self).is_ok()
+    }
+
+    /// A command running `program` with this elevation, e.g. `doas pacman`.
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub fn command(self, program: impl AsRef<OsStr>) -> Command {
+        let program = program.as_ref();
+        self.program().map_or_else(
+            || Command::new(program),
+            |elevate| {
+                let mut cmd = Command::new(elevate);
+                cmd.arg(program);
+                cmd
+            },
+        )
+    }
+}
+
+impl std::str::FromStr for Elevation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sudo" => Ok(Self::Sudo),
+            "doas" => Ok(Self::Doas),
+            "run0" => Ok(Self::Run0),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "unknown elevation `{s}` (expected sudo, doas, run0, or none)"
+            )),
+        }
+    }
+}
+
+/// The system pacman operates on, for chroots and throwaway databases in tests.
+///
+/// `root` and `dbpath` are passed to every pacman and paru invocation (`--root`, `--dbpath`).
+/// With `chroot`, pacman runs inside the system instead, through `arch-chroot`, so its hooks
+/// and install scriptlets run there too.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Paths {
+    /// The installation root instead of `/`.
+    pub root: Option<PathBuf>,
+    /// The database directory instead of `<root>/var/lib/pacman`.
+    pub dbpath: Option<PathBuf>,
+    /// A mounted system every pacman invocation enters with `arch-chroot`, as root. paru is
+    /// not run there, since it refuses to run as root.
+    pub chroot: Option<PathBuf>,
+}
+
+impl Paths {
+    /// The command-line options selecting these paths.
+    #[must_use]
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(root) = &self.root {
+            args.extend([OsString::from("--root"), root.into()]);
+        }
+        if let Some(dbpath) = &self.dbpath {
+            args.extend([OsString::from("--dbpath"), dbpath.into()]);
+        }
+        args
+    }
+
+    /// The database directory pacman uses with these paths, as seen from outside a chroot.
+    #[must_use]
+    pub fn db_path(&self) -> PathBuf {
+        self.dbpath.clone().unwrap_or_else(|| {
+            self.chroot
+                .as_deref()
+                .or(self.root.as_deref())
+                .unwrap_or_else(|| Path::new("/"))
+                .join("var/lib/pacman")
+        })
+    }
+
+    /// The log file pacman writes with these paths, as seen from outside a chroot.
+    #[must_use]
+    pub fn log_file(&self) -> PathBuf {
+        self.chroot
+            .as_deref()
+            .or(self.root.as_deref())
+            .unwrap_or_else(|| Path::new("/"))
+            .join("var/log/pacman.log")
+    }
+
+    /// pacman's keyring with these paths, as seen from outside a chroot. Unlike the database,
+    /// it does not follow `--root`: pacman checks signatures with the running system's keys.
+    #[must_use]
+    pub fn gpg_dir(&self) -> PathBuf {
+        self.chroot
+            .as_deref()
+            .unwrap_or_else(|| Path::new("/"))
+            .join("etc/pacman.d/gnupg")
+    }
+
+    /// Whether these are the defaults, i.e. pacman manages the running system.
+    #[must_use]
+    pub const fn is_default(&self) -> bool {
+        self.root.is_none() && self.dbpath.is_none() && self.chroot.is_none()
+    }
+}
+
+static PATHS: Mutex<Paths> = Mutex::new(Paths {
+    root: None,
+    dbpath: None,
+    chroot: None,
+});
+
+/// The paths every pacman and paru invocation of the process uses.
+#[must_use]
+pub fn paths() -> Paths {
+    PATHS.lock().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// Run every later pacman and paru invocation of the process against `paths`.
+pub fn set_paths(paths: Paths) {
+    *PATHS.lock().unwrap_or_else(PoisonError::into_inner) = paths;
+}
+
+/// The programs run as pacman and paru (`pacman_path`, `paru_path`, and `extra_args`
+/// settings), for wrappers such as powerpill or binaries outside `PATH`, e.g. in containers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Programs {
+    /// Run instead of `pacman`: a name looked up on `PATH`, or a path.
+    pub pacman: Option<PathBuf>,
+    /// Run instead of `paru`.
+    pub paru: Option<PathBuf>,
+    /// Passed to every command that installs, removes, or upgrades packages, e.g.
+    /// `--color=always`. Queries whose output nixman reads never get them, so they cannot
+    /// change what nixman parses.
+    pub extra_args: Vec<String>,
+}
+
+impl Programs {
+    /// The program run as pacman.
+    #[must_use]
+    pub fn pacman(&self) -> &OsStr {
+        self.pacman
+            .as_deref()
+            .map_or_else(|| OsStr::new("pacman"), Path::as_os_str)
+    }
+
+    /// The program run as paru.
+    #[must_use]
+    pub fn paru(&self) -> &OsStr {
+        self.paru
+            .as_deref()
+            .map_or_else(|| OsStr::new("paru"), Path::as_os_str)
+    }
+}
+
+static PROGRAMS: Mutex<Programs> = Mutex::new(Programs {
+    pacman: None,
+    paru: None,
+    extra_args: Vec::new(),
+});
+
+/// The programs every pacman and paru invocation of the process runs.
+#[must_use]
+pub fn programs() -> Programs {
+    PROGRAMS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Run `programs` for every later pacman and paru invocation of the process.
+pub fn set_programs(programs: Programs) {
+    *PROGRAMS.lock().unwrap_or_else(PoisonError::into_inner) = programs;
+}
+
+/// `pacman` with the [`paths`] of the process, as root if `elevate`; in a chroot always as
+/// root, since entering it needs root.
+#[cfg(target_os = "linux")]
+fn pacman_in_paths(elevate: bool) -> Command {
+    let paths = paths();
+    let pacman = programs().pacman().to_owned();
+    let mut cmd = match &paths.chroot {
+        Some(dir) => {
+            let mut cmd = elevated("arch-chroot");
+            cmd.arg(dir).arg(pacman);
+            cmd
+        }
+        None if elevate => elevated(pacman),
+        None => Command::new(pacman),
+    };
+    cmd.args(paths.args());
+    cmd
+}
+
+/// `pacman` with the [`paths`] of the process, in the C locale so that its output can be
+/// parsed whatever the user's language.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn pacman_command() -> Command {
+    let mut cmd = pacman_in_paths(false);
+    cmd.env("LC_ALL", "C");
+    cmd
+}
+
+/// `pacman` as root with the [current](Elevation::current) elevation, the [`paths`] of the
+/// process, and the [extra arguments](Programs::extra_args) for transactions.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn elevated_pacman() -> Command {
+    let mut cmd = pacman_in_paths(true);
+    cmd.args(&programs().extra_args);
+    cmd
+}
+
+/// `pacman` for a transaction: [`elevated_pacman`] if `elevate`, else [`pacman_command`] with
+/// the extra arguments.
+#[cfg(target_os = "linux")]
+fn pacman_transaction(elevate: bool) -> Command {
+    if elevate {
+        return elevated_pacman();
+    }
+    let mut cmd = pacman_command();
+    cmd.args(&programs().extra_args);
+    cmd
+}
+
+/// `paru` with the [`paths`] of the process, which it passes on to pacman, and the
+/// [extra arguments](Programs::extra_args); nixman only runs paru for transactions.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn paru_command() -> Command {
+    let programs = programs();
+    let mut cmd = Command::new(programs.paru());
+    cmd.args(paths().args()).args(&programs.extra_args);
+    cmd
+}
+
+/// Whether installs pass `--needed`, skipping packages that are already up to date.
+static NEEDED: AtomicBool = AtomicBool::new(true);
+
+/// Whether installs pass `--needed` (the default) so already up-to-date packages are not
+/// reinstalled.
+#[must_use]
+pub fn needed() -> bool {
+    NEEDED.load(Ordering::Relaxed)
+}
+
+/// Turn `--needed` on or off for every install of the process.
+pub fn set_needed(needed: bool) {
+    NEEDED.store(needed, Ordering::Relaxed);
+}
+
+/// Add `-S`, `--needed` if enabled, and the packages to an install command. Offline, `-U`
+/// and the packages' files in the cache are added instead; packages that are not cached are
+/// left for pacman to report.
+#[cfg(target_os = "linux")]
+fn install_args<'a>(cmd: &'a mut Command, packages: &[String]) -> &'a mut Command {
+    let offline = crate::offline::is_offline();
+    cmd.arg(if offline { "-U" } else { "-S" });
+    if needed() {
+        cmd.arg("--needed");
+    }
+    if offline {
+        let (files, missing) = crate::offline::cached_packages(packages)
+            .unwrap_or_else(|_| (Vec::new(), packages.to_vec()));
+        return cmd.args(files).args(missing);
+    }
+    cmd.args(packages)
+}
+
+/// Run `program` as root with the [current](Elevation::current) elevation.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn elevated(program: impl AsRef<OsStr>) -> Command {
+    Elevation::current().command(program)
+}
+
+/// Exit status and trailing output of a command run with [`run_captured`].
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub struct CapturedOutput {
+    /// The exit status of the command.
+    pub status: ExitStatus,
+    /// The last non-empty lines the command wrote to stdout and stderr, oldest first.
+    pub tail: Vec<String>,
+}
 
 /// Installs the given packages using pacman.
 ///
 /// # Arguments
 /// * `packages` - A slice of package names to install.
-/// * `use_sudo` - Whether to run pacman with sudo.
+/// * `elevate` - Whether to run pacman as root with the [current](Elevation::current) elevation.
+/// * `noconfirm` - Whether to pass `--noconfirm`, skipping interactive prompts.
 ///
 /// # Returns
 /// * `std::io::Result<ExitStatus>` - The exit status of the pacman command.
 ///
 /// # Errors
 /// Returns an error if the pacman command could not be executed.
-pub fn pacman_install(packages: &[String], use_sudo: bool) -> std::io::Result<ExitStatus> {
-    let mut cmd = if use_sudo {
-        let mut c = Command::new("sudo");
-        c.arg("pacman");
-        c
-    } else {
-        Command::new("pacman")
-    };
-    cmd.arg("-S").args(packages);
-    cmd.status()
+#[cfg(target_os = "linux")]
+pub fn pacman_install(
+    packages: &[String],
+    elevate: bool,
+    noconfirm: bool,
+) -> std::io::Result<ExitStatus> {
+    let mut cmd = pacman_transaction(elevate);
+    install_args(&mut cmd, packages);
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    audited_status(&mut cmd, AuditAction::Install, packages)
 }
 
 /// Lists explicitly installed packages using `pacman -Qe`.
@@ -55,81 +469,771 @@ pub fn pacman_install(packages: &[String], use_sudo: bool) -> std::io::Result<Ex
 ///
 /// # Errors
 /// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
 pub fn pacman_list_explicit() -> std::io::Result<Output> {
-    Command::new("pacman").arg("-Qe").output()
+    run::output(pacman_command().arg("-Qe"))
+}
+
+/// Lists all installed packages with their versions using `pacman -Q`.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn pacman_list_installed() -> std::io::Result<Output> {
+    run::output(pacman_command().arg("-Q"))
+}
+
+/// Lists packages installed as dependencies using `pacman -Qd`.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn pacman_list_dependencies() -> std::io::Result<Output> {
+    run::output(pacman_command().arg("-Qd"))
+}
+
+/// Lists the members of every package group in the sync databases using `pacman -Sg`.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn pacman_list_groups() -> std::io::Result<Output> {
+    run::output(pacman_command().arg("-Sg"))
+}
+
+/// What `pacman -Qi` reports about an installed package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: FullVersion,
+    pub reason: InstallReason,
+    /// Dependencies as declared, with any version constraint, e.g. `glibc>=2.38`.
+    pub depends: Vec<String>,
+    /// Virtual packages it provides, with any version, e.g. `java-runtime=17`.
+    pub provides: Vec<String>,
+    /// Installed packages that depend on it.
+    pub required_by: Vec<String>,
+    /// The installed size in bytes.
+    pub installed_size: u64,
+    /// When it was installed, as pacman prints it.
+    pub install_date: String,
+}
+
+/// Query `pacman -Qi` about the installed package `name`; `None` if it is not installed.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn pacman_query_info(name: &str) -> std::io::Result<Option<PackageInfo>> {
+    Ok(pacman_query_infos(&[name.to_string()])?.pop())
+}
+
+/// Query `pacman -Qi` about each installed package in `names`, skipping those that are not
+/// installed.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn pacman_query_infos(names: &[String]) -> std::io::Result<Vec<PackageInfo>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let output = run::output(pacman_command().arg("-Qi").args(names))?;
+    Ok(parse_package_infos(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Parse `pacman -Qi` output for one or more packages, skipping blocks that lack a name,
+/// version, or install reason.
+#[must_use]
+pub fn parse_package_infos(output: &str) -> Vec<PackageInfo> {
+    output
+        .split("\n\n")
+        .filter_map(parse_package_info)
+        .collect()
+}
+
+/// Parse the `pacman -Qi` block of one package.
+#[must_use]
+pub fn parse_package_info(info: &str) -> Option<PackageInfo> {
+    let field = |key| parse_info_field(info, key);
+    let list = |key| {
+        field(key)
+            .filter(|value| value != "None")
+            .map(|value| value.split_whitespace().map(ToString::to_string).collect())
+            .unwrap_or_default()
+    };
+    let reason = if field("Install Reason")?.starts_with("Explicitly") {
+        InstallReason::Explicit
+    } else {
+        InstallReason::Dependency
+    };
+    Some(PackageInfo {
+        name: field("Name")?,
+        version: FullVersion::from(field("Version")?.as_str()),
+        reason,
+        depends: list("Depends On"),
+        provides: list("Provides"),
+        required_by: list("Required By"),
+        installed_size: field("Installed Size")
+            .and_then(|size| parse_size(&size))
+            .unwrap_or_default(),
+        install_date: field("Install Date").unwrap_or_default(),
+    })
+}
+
+/// What `pacman -Si` reports about a package in the sync databases.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncInfo {
+    pub name: String,
+    pub repository: String,
+    pub version: FullVersion,
+    /// The size of the package file in bytes.
+    pub download_size: u64,
+    /// The installed size in bytes.
+    pub installed_size: u64,
+}
+
+/// A package in a transaction pacman printed instead of carrying out (`--print`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PrintedPackage {
+    pub name: String,
+    pub version: String,
+    /// The download size in bytes for installs (0 if the package is cached), the installed size
+    /// for removals.
+    pub size: u64,
+}
+
+/// Run `pacman <operation> --print` on `targets`, which resolves the transaction, its
+/// dependencies included, without changing anything or needing root.
+///
+/// # Errors
+/// Returns an error if pacman could not be executed.
+#[cfg(target_os = "linux")]
+pub fn print_transaction(operation: &[&str], targets: &[String]) -> std::io::Result<Output> {
+    run::output(
+        pacman_command()
+            .args(operation)
+            .args(["--print", "--print-format", "%n %v %s"])
+            .args(targets)
+            .stdin(std::process::Stdio::null()),
+    )
+}
+
+/// Parse the output of [`print_transaction`], skipping lines that are not packages.
+#[must_use]
+pub fn parse_printed(output: &str) -> Vec<PrintedPackage> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (name, version, size) = (fields.next()?, fields.next()?, fields.next()?);
+            Some(PrintedPackage {
+                name: name.to_string(),
+                version: version.to_string(),
+                size: size.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Query `pacman -Si` about each package in `names`, skipping those that are not in the sync
+/// databases, such as AUR packages.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn pacman_sync_infos(names: &[String]) -> std::io::Result<Vec<SyncInfo>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let output = run::output(pacman_command().arg("-Si").args(names))?;
+    Ok(parse_sync_infos(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse `pacman -Si` output, skipping blocks that lack a repository, name, or version.
+#[must_use]
+pub fn parse_sync_infos(output: &str) -> Vec<SyncInfo> {
+    output
+        .split("\n\n")
+        .filter_map(|info| {
+            let field = |key| parse_info_field(info, key);
+            let size = |key| {
+                field(key)
+                    .and_then(|size| parse_size(&size))
+                    .unwrap_or_default()
+            };
+            Some(SyncInfo {
+                name: field("Name")?,
+                repository: field("Repository")?,
+                version: FullVersion::from(field("Version")?.as_str()),
+                download_size: size("Download Size"),
+                installed_size: size("Installed Size"),
+            })
+        })
+        .collect()
+}
+
+/// Extract a field from `pacman -Qi` or `-Si` output.
+///
+/// Fields are `Key : value` lines; long values continue on indented lines, which are joined.
+#[must_use]
+pub fn parse_info_field(info: &str, key: &str) -> Option<String> {
+    let mut lines = info.lines().skip_while(|line| {
+        line.split_once(" : ")
+            .is_none_or(|(k, _)| line.starts_with(char::is_whitespace) || k.trim() != key)
+    });
+    let first = lines.next()?.split_once(" : ")?.1.trim().to_string();
+    let rest = lines
+        .take_while(|line| line.starts_with(char::is_whitespace))
+        .map(str::trim);
+    Some(
+        std::iter::once(first.as_str())
+            .chain(rest)
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// The binary size units pacman prints sizes in.
+const SIZE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// Parse a size as pacman prints it, e.g. `10.52 MiB`, into bytes.
+#[must_use]
+pub fn parse_size(size: &str) -> Option<u64> {
+    let (number, unit) = size.trim().split_once(' ')?;
+    let power = SIZE_UNITS.iter().position(|u| *u == unit.trim())?;
+    let unit = 1u64 << (10 * power);
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let whole: u64 = whole.parse().ok()?;
+    let fraction_value: u64 = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse().ok()?
+    };
+    let scale = 10u64.checked_pow(u32::try_from(fraction.len()).ok()?)?;
+    whole
+        .checked_mul(unit)?
+        .checked_add(fraction_value.checked_mul(unit)? / scale)
+}
+
+/// Format `bytes` in the largest binary unit below it, with one decimal, e.g. `10.5 MiB`.
+#[must_use]
+pub fn format_size(bytes: u64) -> String {
+    let power = (0..SIZE_UNITS.len())
+        .rev()
+        .find(|power| bytes >> (10 * power) > 0)
+        .unwrap_or(0);
+    if power == 0 {
+        return format!("{bytes} B");
+    }
+    let tenths = u128::from(bytes) * 10 / (1u128 << (10 * power));
+    format!("{}.{} {}", tenths / 10, tenths % 10, SIZE_UNITS[power])
+}
+
+/// A `name version` line of a `pacman -Q` listing, borrowed from the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackageRef<'a> {
+    pub name: &'a str,
+    /// A valid [`FullVersion`], not parsed yet.
+    pub version: &'a str,
+}
+
+impl PackageRef<'_> {
+    /// An explicit [`Package`](crate::Package) entry for the line, pinned to its version if
+    /// `versioned`.
+    #[must_use]
+    pub fn to_package(self, versioned: bool) -> crate::Package {
+        if versioned {
+            crate::Package::versioned(self.name, self.version)
+        } else {
+            crate::Package::new(self.name)
+        }
+    }
+}
+
+/// Parse a `name version` line of a `pacman -Q` listing; `None` for anything else, such as
+/// warnings or translated messages that ended up in the output.
+#[must_use]
+pub fn parse_package_line(line: &str) -> Option<PackageRef<'_>> {
+    let mut fields = line.split_whitespace();
+    let (name, version) = (fields.next()?, fields.next()?);
+    if fields.next().is_some() || !is_package_name(name) || !FullVersion::is_valid(version) {
+        return None;
+    }
+    Some(PackageRef { name, version })
+}
+
+/// The `name version` lines of a `pacman -Q` listing, skipping every other line.
+///
+/// The output is read as it is, e.g. `Output::stdout`, so nothing is copied; lines that are not
+/// UTF-8 are skipped like any other noise.
+pub fn parse_package_lines<T: AsRef<[u8]> + ?Sized>(
+    output: &T,
+) -> impl Iterator<Item = PackageRef<'_>> {
+    output
+        .as_ref()
+        .split(|&byte| byte == b'\n')
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .filter_map(parse_package_line)
+}
+
+/// Whether `name` only has the characters makepkg allows in package names.
+fn is_package_name(name: &str) -> bool {
+    !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@._+-".contains(c))
+}
+
+/// Marks the given packages as explicitly installed or as dependencies
+/// (`pacman -D --asexplicit` / `--asdeps`, elevated).
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn pacman_mark(packages: &[String], reason: InstallReason) -> std::io::Result<ExitStatus> {
+    let flag = match reason {
+        InstallReason::Explicit => "--asexplicit",
+        InstallReason::Dependency => "--asdeps",
+    };
+    let mut cmd = elevated_pacman();
+    cmd.args(["-D", flag]).args(packages);
+    audited_status(&mut cmd, AuditAction::Mark, packages)
 }
 
 /// Installs the given packages using paru (AUR helper).
 ///
 /// # Arguments
 /// * `packages` - A slice of package names to install.
+/// * `noconfirm` - Whether to pass `--noconfirm`, skipping interactive prompts.
 ///
 /// # Returns
 /// * `std::io::Result<ExitStatus>` - The exit status of the paru command.
 ///
 /// # Errors
 /// Returns an error if the paru command could not be executed.
-pub fn paru_install(packages: &[String]) -> std::io::Result<ExitStatus> {
-    let mut cmd = Command::new("paru");
-    cmd.arg("-S").args(packages);
-    cmd.status()
+#[cfg(target_os = "linux")]
+pub fn paru_install(packages: &[String], noconfirm: bool) -> std::io::Result<ExitStatus> {
+    let mut cmd = paru_command();
+    install_args(&mut cmd, packages);
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    audited_status(&mut cmd, AuditAction::Install, packages)
 }
 
 /// Removes the given packages using pacman.
 ///
 /// # Arguments
 /// * `packages` - A slice of package names to remove.
-/// * `use_sudo` - Whether to run pacman with sudo.
+/// * `elevate` - Whether to run pacman as root with the [current](Elevation::current) elevation.
+/// * `noconfirm` - Whether to pass `--noconfirm`, skipping interactive prompts.
 ///
 /// # Returns
 /// * `std::io::Result<ExitStatus>` - The exit status of the pacman command.
 ///
 /// # Errors
 /// Returns an error if the pacman command could not be executed.
-pub fn pacman_remove(packages: &[String], use_sudo: bool) -> std::io::Result<ExitStatus> {
-    let mut cmd = if use_sudo {
-        let mut c = Command::new("sudo");
-        c.arg("pacman");
-        c
-    } else {
-        Command::new("pacman")
-    };
+#[cfg(target_os = "linux")]
+pub fn pacman_remove(
+    packages: &[String],
+    elevate: bool,
+    noconfirm: bool,
+) -> std::io::Result<ExitStatus> {
+    let mut cmd = pacman_transaction(elevate);
     cmd.arg("-Rns").args(packages);
-    cmd.status()
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    audited_status(&mut cmd, AuditAction::Remove, packages)
 }
 
 /// Removes the given packages using paru.
 ///
 /// # Arguments
 /// * `packages` - A slice of package names to remove.
+/// * `noconfirm` - Whether to pass `--noconfirm`, skipping interactive prompts.
 ///
 /// # Returns
 /// * `std::io::Result<ExitStatus>` - The exit status of the paru command.
 ///
 /// # Errors
 /// Returns an error if the paru command could not be executed.
-pub fn paru_remove(packages: &[String]) -> std::io::Result<ExitStatus> {
-    let mut cmd = Command::new("paru");
+#[cfg(target_os = "linux")]
+pub fn paru_remove(packages: &[String], noconfirm: bool) -> std::io::Result<ExitStatus> {
+    let mut cmd = paru_command();
     cmd.arg("-Rns").args(packages);
-    cmd.status()
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    audited_status(&mut cmd, AuditAction::Remove, packages)
 }
 
-/// Runs a full system upgrade using pacman (sudo pacman -Syyu).
+/// Runs a full system upgrade using pacman (`pacman -Syyu`, elevated), with `--noconfirm` if
+/// `noconfirm`.
 ///
 /// # Errors
 /// Returns an error if the pacman command could not be executed.
-pub fn pacman_update() -> std::io::Result<std::process::ExitStatus> {
-    let mut cmd = Command::new("sudo");
-    cmd.arg("pacman").arg("-Syyu");
-    cmd.status()
+#[cfg(target_os = "linux")]
+pub fn pacman_update(noconfirm: bool) -> std::io::Result<std::process::ExitStatus> {
+    let mut cmd = elevated_pacman();
+    cmd.arg("-Syyu");
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    audited_status(&mut cmd, AuditAction::Update, &[])
 }
 
-/// Runs a full system upgrade using paru (paru -Syyu).
+/// Runs a full system upgrade using paru (paru -Syyu), with `--noconfirm` if `noconfirm`.
 ///
 /// # Errors
 /// Returns an error if the paru command could not be executed.
-pub fn paru_update() -> std::io::Result<std::process::ExitStatus> {
-    let mut cmd = Command::new("paru");
+#[cfg(target_os = "linux")]
+pub fn paru_update(noconfirm: bool) -> std::io::Result<std::process::ExitStatus> {
+    let mut cmd = paru_command();
     cmd.arg("-Syyu");
-    cmd.status()
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    audited_status(&mut cmd, AuditAction::Update, &[])
+}
+
+/// Builds the install command used by `apply`: `paru -S` or elevated `pacman -S`, with
+/// `--noconfirm` if `noconfirm` and `--needed` unless turned off with [`set_needed`].
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn install_command(packages: &[String], use_paru: bool, noconfirm: bool) -> Command {
+    let mut cmd = if use_paru {
+        paru_command()
+    } else {
+        elevated_pacman()
+    };
+    install_args(&mut cmd, packages);
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    cmd
+}
+
+/// Builds the removal command used by `apply`: `paru -Rns` or elevated `pacman -Rns`, with
+/// `--noconfirm` if `noconfirm`.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn remove_command(packages: &[String], use_paru: bool, noconfirm: bool) -> Command {
+    let mut cmd = if use_paru {
+        paru_command()
+    } else {
+        elevated_pacman()
+    };
+    cmd.arg("-Rns").args(packages);
+    if noconfirm {
+        cmd.arg("--noconfirm");
+    }
+    cmd
+}
+
+/// Builds `pacstrap -K <root> <packages>` (elevated), which installs into a new system mounted at `root`
+/// and initializes its pacman keyring.
+#[cfg(target_os = "linux")]
+#[must_use]
+pub fn pacstrap_command(root: &Path, packages: &[String]) -> Command {
+    let mut cmd = elevated("pacstrap");
+    cmd.arg("-K").arg(root).args(packages);
+    cmd
+}
+
+/// Runs `cmd`, passing its output through to the terminal while keeping the last
+/// [`TAIL_LINES`] lines, so a failure can be reported with pacman's own explanation.
+///
+/// stdin is inherited, so confirmation prompts keep working. Lines also reach the
+/// [`run`](crate::run) hook.
+///
+/// # Errors
+/// Returns an error if the command could not be executed.
+#[cfg(target_os = "linux")]
+pub fn run_captured(cmd: &mut Command) -> std::io::Result<CapturedOutput> {
+    let output = run::run_command(cmd, true, &mut |_, _| {})?;
+    Ok(CapturedOutput {
+        status: output.status,
+        tail: output.tail,
+    })
+}
+
+/// [`run_captured`], recording in the [audit log](crate::audit) that `cmd` carried out
+/// `action` on `packages`.
+///
+/// # Errors
+/// Returns an error if the command could not be executed; nothing is recorded then.
+#[cfg(target_os = "linux")]
+pub fn run_audited(
+    cmd: &mut Command,
+    action: AuditAction,
+    packages: &[String],
+) -> std::io::Result<CapturedOutput> {
+    let captured = run_captured(cmd)?;
+    audit::record(action, packages, cmd, captured.status);
+    Ok(captured)
+}
+
+/// Run `cmd` with its output going to the terminal, recording it like [`run_audited`].
+#[cfg(target_os = "linux")]
+fn audited_status(
+    cmd: &mut Command,
+    action: AuditAction,
+    packages: &[String],
+) -> std::io::Result<ExitStatus> {
+    let status = run::status(cmd)?;
+    audit::record(action, packages, cmd, status);
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the last `TAIL_LINES` lines are kept, and stderr is captured as well as stdout.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn captures_tail_of_output() {
+        let captured = run_captured(
+            Command::new("sh").args(["-c", "for i in $(seq 1 20); do echo line$i; done"]),
+        )
+        .expect("sh should run");
+        assert!(captured.status.success());
+        let expected: Vec<String> = (11..=20).map(|i| format!("line{i}")).collect();
+        assert_eq!(captured.tail, expected);
+
+        let captured = run_captured(
+            Command::new("sh").args(["-c", "echo 'error: target not found: nope' >&2; exit 3"]),
+        )
+        .expect("sh should run");
+        assert_eq!(captured.status.code(), Some(3));
+        assert_eq!(captured.tail, vec!["error: target not found: nope"]);
+    }
+
+    /// `--root` and `--dbpath` are only passed when set, and the database and log follow the
+    /// root, but the keyring only a chroot.
+    #[test]
+    fn paths_select_root_and_database() {
+        assert!(Paths::default().args().is_empty());
+        assert_eq!(Paths::default().db_path(), Path::new("/var/lib/pacman"));
+        let chroot = Paths {
+            root: Some(PathBuf::from("/tmp/chroot")),
+            ..Paths::default()
+        };
+        assert_eq!(chroot.args(), vec!["--root", "/tmp/chroot"]);
+        assert_eq!(chroot.db_path(), Path::new("/tmp/chroot/var/lib/pacman"));
+        let throwaway = Paths {
+            dbpath: Some(PathBuf::from("/tmp/db")),
+            ..chroot
+        };
+        assert_eq!(
+            throwaway.args(),
+            vec!["--root", "/tmp/chroot", "--dbpath", "/tmp/db"]
+        );
+        assert_eq!(throwaway.db_path(), Path::new("/tmp/db"));
+        assert_eq!(
+            throwaway.log_file(),
+            Path::new("/tmp/chroot/var/log/pacman.log")
+        );
+        assert_eq!(throwaway.gpg_dir(), Path::new("/etc/pacman.d/gnupg"));
+        assert!(!throwaway.is_default());
+        let entered = Paths {
+            chroot: Some(PathBuf::from("/mnt")),
+            ..Paths::default()
+        };
+        assert!(entered.args().is_empty());
+        assert_eq!(entered.db_path(), Path::new("/mnt/var/lib/pacman"));
+        assert_eq!(entered.gpg_dir(), Path::new("/mnt/etc/pacman.d/gnupg"));
+        assert!(!entered.is_default());
+    }
+
+    /// pacstrap targets the given root and initializes its keyring.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pacstrap_targets_root() {
+        let cmd = pacstrap_command(Path::new("/mnt"), &["base".to_string(), "git".to_string()]);
+        // Whatever the elevation, including none when the tests run as root.
+        let args: Vec<_> = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert!(args.ends_with(&["pacstrap", "-K", "/mnt", "base", "git"].map(String::from)));
+    }
+
+    /// Elevation wraps the program, except for `none`, which runs it directly, and programs
+    /// are found on `PATH`.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn elevates_with_configured_tool() {
+        let program = |cmd: &Command| {
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            program(&Elevation::Doas.command("pacman")),
+            vec!["doas", "pacman"]
+        );
+        assert_eq!(program(&Elevation::None.command("pacman")), vec!["pacman"]);
+        assert_eq!("run0".parse(), Ok(Elevation::Run0));
+        assert!("su".parse::<Elevation>().is_err());
+        assert!(on_path("sh"));
+        assert!(!on_path("nixman-no-such-program"));
+        assert_eq!(Elevation::detect() == Elevation::None, is_root());
+    }
+
+    /// Installs skip up-to-date packages with `--needed`.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn installs_with_needed() {
+        let cmd = install_command(&["git".to_string()], true, false);
+        let args: Vec<_> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(args, vec!["-S", "--needed", "git"]);
+    }
+
+    const GIT_INFO: &str = "\
+Name            : git
+Version         : 2.45.0-1
+Description     : the fast distributed version control system
+Architecture    : x86_64
+URL             : https://git-scm.com/
+Licenses        : GPL-2.0-only
+Groups          : None
+Provides        : None
+Depends On      : curl  expat  perl  perl-error  perl-mailtools  openssl
+                  pcre2  grep  shadow  zlib
+Optional Deps   : tk: gitk and git gui
+                  openssh: ssh transport and crypto [installed]
+Required By     : github-cli  lazygit
+Optional For    : None
+Conflicts With  : None
+Replaces        : None
+Installed Size  : 29.38 MiB
+Packager        : Christian Hesse <eworm@archlinux.org>
+Build Date      : Mon 29 Apr 2024 08:45:11 PM UTC
+Install Date    : Tue 07 May 2024 09:12:40 AM UTC
+Install Reason  : Explicitly installed
+Install Script  : Yes
+Validated By    : Signature
+
+Name            : jre-openjdk-headless
+Version         : 22.0.1.u8-1
+Provides        : java-runtime-headless=22  jre22-openjdk-headless
+Depends On      : java-runtime-common>=3  ca-certificates-utils  nss
+Required By     : jre-openjdk
+Installed Size  : 178.30 MiB
+Install Date    : Tue 07 May 2024 09:13:02 AM UTC
+Install Reason  : Installed as a dependency for another package
+";
+
+    /// Every block of `pacman -Qi` becomes a `PackageInfo`, with wrapped values joined.
+    #[test]
+    fn parses_package_info() {
+        let infos = parse_package_infos(GIT_INFO);
+        assert_eq!(infos.len(), 2);
+        let git = &infos[0];
+        assert_eq!(git.version, FullVersion::from("2.45.0-1"));
+        assert_eq!(git.reason, InstallReason::Explicit);
+        assert_eq!(git.depends.len(), 10);
+        assert_eq!(git.depends[9], "zlib");
+        assert!(git.provides.is_empty());
+        assert_eq!(git.required_by, vec!["github-cli", "lazygit"]);
+        assert_eq!(git.installed_size, 30_807_162);
+        assert_eq!(git.install_date, "Tue 07 May 2024 09:12:40 AM UTC");
+        let jre = &infos[1];
+        assert_eq!(jre.reason, InstallReason::Dependency);
+        assert_eq!(jre.provides[0], "java-runtime-headless=22");
+        assert_eq!(
+            parse_info_field(GIT_INFO, "Licenses").as_deref(),
+            Some("GPL-2.0-only")
+        );
+        assert_eq!(
+            parse_package_info("error: package 'nope' was not found\n"),
+            None
+        );
+
+        assert_eq!(parse_size("512.00 B"), Some(512));
+        assert_eq!(parse_size("1.50 GiB"), Some(1_610_612_736));
+        assert_eq!(parse_size("3 parsecs"), None);
+        assert_eq!(format_size(30_807_162), "29.3 MiB");
+        assert_eq!(format_size(900), "900 B");
+    }
+
+    /// Configured programs replace pacman and paru, as names on `PATH` or as paths.
+    #[test]
+    fn resolves_configured_programs() {
+        assert_eq!(Programs::default().pacman(), "pacman");
+        let programs = Programs {
+            pacman: Some(PathBuf::from("powerpill")),
+            paru: Some(PathBuf::from("/opt/paru/bin/paru")),
+            extra_args: vec!["--color=always".to_string()],
+        };
+        assert_eq!(programs.pacman(), "powerpill");
+        assert_eq!(programs.paru(), "/opt/paru/bin/paru");
+        assert!(installed(OsStr::new("sh")));
+        assert!(installed(OsStr::new("/bin/sh")));
+        assert!(!installed(OsStr::new("/opt/paru/bin/paru")));
+        assert!(!installed(OsStr::new("nixman-no-such-program")));
+    }
+
+    /// Listings are read from raw bytes, skipping lines that are not UTF-8, and become owned
+    /// packages only when asked.
+    #[test]
+    fn reads_listings_from_bytes() {
+        let output = b"htop 3.3.0-3\r\nwarnung: \xff\xfe kaputt\nffmpeg 2:7.0.2-3\n".to_vec();
+        let packages: Vec<_> = parse_package_lines(&output).collect();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "htop");
+        let package = packages[1].to_package(true);
+        assert_eq!(package.name, "ffmpeg");
+        assert_eq!(package.version.unwrap().to_string(), "2:7.0.2-3");
+        assert_eq!(packages[1].to_package(false).version, None);
+        assert!(!FullVersion::is_valid("3.3.0"));
+    }
+
+    /// Warnings and translated messages mixed into a listing are skipped.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn skips_noise_in_listings() {
+        const NOISY: &str = "\
+warning: database file for 'extra' does not exist (use '-Sy' to download)
+htop 3.3.0-3
+Warnung: Die Datenbank 'core' existiert nicht
+python-pip 24.2-1
+:: Synchronisiere Paketdatenbanken...
+linux-zen 6.10.9.zen1-1
+  gcc-libs 14.2.1+r134+gab884fffe3fc-1  
+avertissement : impossible de lire la base
+ffmpeg 2:7.0.2-3
+Keine Aktualisierungen
+error: failed to init transaction
+";
+        let packages: Vec<_> = parse_package_lines(NOISY)
+            .map(|package| (package.name, package.version))
+            .collect();
+        assert_eq!(
+            packages,
+            vec![
+                ("htop", "3.3.0-3"),
+                ("python-pip", "24.2-1"),
+                ("linux-zen", "6.10.9.zen1-1"),
+                ("gcc-libs", "14.2.1+r134+gab884fffe3fc-1"),
+                ("ffmpeg", "2:7.0.2-3"),
+            ]
+        );
+        assert_eq!(parse_package_line("vim 9.1.0-1 extra"), None);
+        assert_eq!(parse_package_line("-bad 1.0-1"), None);
+        let envs: Vec<_> = pacman_command()
+            .get_envs()
+            .map(|(key, value)| (key.to_owned(), value.map(ToOwned::to_owned)))
+            .collect();
+        assert_eq!(envs, vec![("LC_ALL".into(), Some("C".into()))]);
+    }
 }
@@ -0,0 +1,220 @@
+//! pacman's own history
+//!
+//! # Overview
+//!
+//! pacman logs every transaction to `/var/log/pacman.log` (under `--root` or `--chroot` if
+//! set), from the day the system was installed, long before nixman was:
+//!
+//! ```text
+//! [2024-05-01T10:00:00+0200] [ALPM] installed htop (3.3.0-1)
+//! [2024-06-02T18:30:12+0200] [ALPM] upgraded htop (3.3.0-1 -> 3.3.0-2)
+//! ```
+//!
+//! [`parse`] reads the installs, removals, upgrades, downgrades, and reinstalls from it as
+//! [`LogEvent`]s, in UTC; logs from before pacman 5.1, which wrote local times without a zone
+//! (`[2019-01-01 12:00]`), are read as UTC. Other lines, such as scriptlet output and the
+//! commands pacman was run with, are skipped.
+//!
+//! `nixman import --from pacman-log` records when each declared package was last installed as
+//! its `installed_at:` ([`install_times`]), and `nixman history --system` lists the events.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// What happened to a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Installed,
+    Removed,
+    Upgraded,
+    Downgraded,
+    Reinstalled,
+}
+
+impl EventKind {
+    /// The verb pacman logs, e.g. `installed`.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Installed => "installed",
+            Self::Removed => "removed",
+            Self::Upgraded => "upgraded",
+            Self::Downgraded => "downgraded",
+            Self::Reinstalled => "reinstalled",
+        }
+    }
+}
+
+/// One package event of pacman's log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogEvent {
+    /// When it happened, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub kind: EventKind,
+    pub name: String,
+    /// The version, or `old -> new` for upgrades and downgrades.
+    pub version: String,
+}
+
+/// The log of the system nixman manages (see [`crate::pacman::Paths::log_file`]).
+#[must_use]
+pub fn log_path() -> PathBuf {
+    crate::pacman::paths().log_file()
+}
+
+/// Days since 1970-01-01 of a civil date (Howard Hinnant's algorithm).
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Parse a log timestamp, `2024-05-01T10:00:00+0200` or the older `2024-05-01 10:00`, into
+/// seconds since the Unix epoch.
+#[must_use]
+pub fn parse_timestamp(text: &str) -> Option<u64> {
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = text.get(range)?;
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())?
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':')];
+    if !separators
+        .iter()
+        .all(|&(i, separator)| text.as_bytes().get(i) == Some(&separator))
+        || !matches!(text.as_bytes().get(10), Some(b'T' | b' '))
+    {
+        return None;
+    }
+    let time = text.get(11..)?;
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute) = (number(11..13)?, number(14..16)?);
+    let (second, offset) = match time.len() {
+        5 => (0, 0),
+        // `HH:MM:SS+HHMM`
+        13 => {
+            let sign = match time.as_bytes()[8] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            (
+                number(17..19)?,
+                sign * (number(20..22)? * 3_600 + number(22..24)? * 60),
+            )
+        }
+        _ => return None,
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second - offset;
+    u64::try_from(seconds).ok()
+}
+
+/// Parse one line of the log, or `None` if it is not a package event.
+#[must_use]
+pub fn parse_line(line: &str) -> Option<LogEvent> {
+    let (timestamp, rest) = line.strip_prefix('[')?.split_once("] ")?;
+    let timestamp = parse_timestamp(timestamp)?;
+    // Events are tagged `[ALPM]`, or `[PACMAN]` in old logs, or not at all in older ones.
+    let rest = rest
+        .strip_prefix("[ALPM] ")
+        .or_else(|| rest.strip_prefix("[PACMAN] "))
+        .unwrap_or(rest);
+    let (verb, rest) = rest.split_once(' ')?;
+    let kind = match verb {
+        "installed" => EventKind::Installed,
+        "removed" => EventKind::Removed,
+        "upgraded" => EventKind::Upgraded,
+        "downgraded" => EventKind::Downgraded,
+        "reinstalled" => EventKind::Reinstalled,
+        _ => return None,
+    };
+    let (name, version) = rest.split_once(' ')?;
+    let version = version.trim_end().strip_prefix('(')?.strip_suffix(')')?;
+    Some(LogEvent {
+        timestamp,
+        kind,
+        name: name.to_string(),
+        version: version.to_string(),
+    })
+}
+
+/// The package events of a log, in order.
+#[must_use]
+pub fn parse(content: &str) -> Vec<LogEvent> {
+    content.lines().filter_map(parse_line).collect()
+}
+
+/// Read the package events of the log at `path`; lines that are not UTF-8, as scriptlets
+/// sometimes write, are read lossily.
+///
+/// # Errors
+/// Returns an error if the log cannot be read.
+pub fn read(path: &Path) -> std::io::Result<Vec<LogEvent>> {
+    Ok(parse(&String::from_utf8_lossy(&std::fs::read(path)?)))
+}
+
+/// When each package the log shows as installed was last installed: removing a package
+/// forgets it, and upgrades and reinstalls keep the time.
+#[must_use]
+pub fn install_times(events: &[LogEvent]) -> HashMap<&str, u64> {
+    let mut times = HashMap::new();
+    for event in events {
+        match event.kind {
+            EventKind::Installed => {
+                times.insert(event.name.as_str(), event.timestamp);
+            }
+            EventKind::Removed => {
+                times.remove(event.name.as_str());
+            }
+            EventKind::Upgraded | EventKind::Downgraded | EventKind::Reinstalled => {}
+        }
+    }
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Events are read in UTC from old and new logs, other lines are skipped, and a reinstall
+    /// after a removal counts from the new install.
+    #[test]
+    fn reads_install_times() {
+        let log = "\
+[2019-01-01 12:00] [PACMAN] installed vim (8.1.0-1)
+[2024-05-01T10:00:00+0200] [PACMAN] Running 'pacman -S htop'
+[2024-05-01T10:00:00+0200] [ALPM] installed htop (3.3.0-1)
+[2024-05-01T10:00:01+0200] [ALPM-SCRIPTLET] installed by hand (yes)
+[2024-06-02T18:30:12+0200] [ALPM] upgraded htop (3.3.0-1 -> 3.3.0-2)
+[2024-06-03T09:00:00-0100] [ALPM] removed vim (8.1.0-1)
+[2024-06-03T09:05:00+0000] [ALPM] installed vim (9.1.0-1)
+not a log line
+";
+        let events = parse(log);
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0].timestamp, 1_546_344_000);
+        assert_eq!(events[1].timestamp, 1_714_550_400);
+        assert_eq!(events[2].kind, EventKind::Upgraded);
+        assert_eq!(events[2].version, "3.3.0-1 -> 3.3.0-2");
+        assert_eq!(events[3].timestamp, 1_717_408_800);
+        let times = install_times(&events);
+        assert_eq!(times["htop"], 1_714_550_400);
+        assert_eq!(
+            crate::generations::format_timestamp(times["vim"]),
+            "2024-06-03 09:05"
+        );
+        assert_eq!(parse_timestamp("2024-13-01 10:00"), None);
+        assert_eq!(parse_timestamp("2024-05-01T10:00:00Z"), None);
+    }
+}
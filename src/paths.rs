@@ -0,0 +1,267 @@
+//! Where nixman keeps its files
+//!
+//! # Overview
+//!
+//! Every file nixman reads or writes outside the YAML's own directory is resolved here,
+//! following the [XDG Base Directory](https://specifications.freedesktop.org/basedir-spec/latest/)
+//! specification:
+//!
+//! | Kind | Directory | Override | Files |
+//! |------|-----------|----------|-------|
+//! | Config | `$XDG_CONFIG_HOME/nixman` (`~/.config/nixman`) | `NIXMAN_CONFIG_DIR` | `packages.yml`, `config.yml`, `profiles/` |
+//! | State | `$XDG_STATE_HOME/nixman` (`~/.local/state/nixman`) | `NIXMAN_STATE_DIR` | the lock, the active profile, the generation log, the audit log, the resume plan, the drift status, the last apply, conflict decisions |
+//! | Cache | `$XDG_CACHE_HOME/nixman` (`~/.cache/nixman`) | `NIXMAN_CACHE_DIR` | packages downloaded from the archive, scratch databases for bundles, parallel AUR builds |
+//!
+//! An `XDG_*` variable that is unset, empty, or not an absolute path is ignored, as the
+//! specification requires, and the default under `$HOME` is used. The `NIXMAN_*` overrides
+//! replace the whole directory, e.g. to keep a test run's state apart.
+//!
+//! The functions only resolve paths; the `ensure_*` variants create the directory too.
+//!
+//! Other programs' directories that nixman reads, such as paru's build cache
+//! ([`paru_clone_dir`]) and systemd's user units ([`systemd_user_dir`]), are resolved here as
+//! well.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// The name of nixman's directory within each XDG base directory.
+const APP: &str = "nixman";
+
+/// The YAML's file name in the config directory.
+pub const CONFIG_FILE: &str = "packages.yml";
+
+/// The global settings' file name in the config directory.
+pub const SETTINGS_FILE: &str = "config.yml";
+
+/// An XDG base directory: `value` if it is an absolute path, else `fallback` under `home`
+/// (or the working directory without a home).
+fn base_dir(value: Option<OsString>, home: Option<OsString>, fallback: &str) -> PathBuf {
+    value
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(|| {
+            home.filter(|home| !home.is_empty())
+                .map_or_else(|| PathBuf::from("."), PathBuf::from)
+                .join(fallback)
+        })
+}
+
+/// The XDG base directory named by `variable`, defaulting to `fallback` under `$HOME`.
+fn xdg(variable: &str, fallback: &str) -> PathBuf {
+    base_dir(
+        std::env::var_os(variable),
+        std::env::var_os("HOME"),
+        fallback,
+    )
+}
+
+/// nixman's directory within the XDG base directory named by `variable`, unless the
+/// `override_variable` names another one.
+fn app_dir(override_variable: &str, variable: &str, fallback: &str) -> PathBuf {
+    std::env::var_os(override_variable)
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(|| xdg(variable, fallback).join(APP), PathBuf::from)
+}
+
+/// `$XDG_CONFIG_HOME`, by default `~/.config`.
+#[must_use]
+pub fn config_home() -> PathBuf {
+    xdg("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_STATE_HOME`, by default `~/.local/state`.
+#[must_use]
+pub fn state_home() -> PathBuf {
+    xdg("XDG_STATE_HOME", ".local/state")
+}
+
+/// `$XDG_CACHE_HOME`, by default `~/.cache`.
+#[must_use]
+pub fn cache_home() -> PathBuf {
+    xdg("XDG_CACHE_HOME", ".cache")
+}
+
+/// nixman's config directory (`$XDG_CONFIG_HOME/nixman`, or `$NIXMAN_CONFIG_DIR`).
+#[must_use]
+pub fn config_dir() -> PathBuf {
+    app_dir("NIXMAN_CONFIG_DIR", "XDG_CONFIG_HOME", ".config")
+}
+
+/// nixman's state directory (`$XDG_STATE_HOME/nixman`, or `$NIXMAN_STATE_DIR`).
+#[must_use]
+pub fn state_dir() -> PathBuf {
+    app_dir("NIXMAN_STATE_DIR", "XDG_STATE_HOME", ".local/state")
+}
+
+/// nixman's cache directory (`$XDG_CACHE_HOME/nixman`, or `$NIXMAN_CACHE_DIR`).
+#[must_use]
+pub fn cache_dir() -> PathBuf {
+    app_dir("NIXMAN_CACHE_DIR", "XDG_CACHE_HOME", ".cache")
+}
+
+/// Create `dir` if needed and return it.
+fn ensure(dir: PathBuf) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// [`state_dir`], created if needed.
+///
+/// # Errors
+/// Returns an error if the directory cannot be created.
+pub fn ensure_state_dir() -> std::io::Result<PathBuf> {
+    ensure(state_dir())
+}
+
+/// The subdirectory `name` of [`cache_dir`], created if needed.
+///
+/// # Errors
+/// Returns an error if the directory cannot be created.
+pub fn ensure_cache_subdir(name: &str) -> std::io::Result<PathBuf> {
+    ensure(cache_dir().join(name))
+}
+
+/// The default YAML (`<config dir>/packages.yml`).
+#[must_use]
+pub fn config_file() -> PathBuf {
+    config_dir().join(CONFIG_FILE)
+}
+
+/// The global settings file (`<config dir>/config.yml`).
+#[must_use]
+pub fn settings_file() -> PathBuf {
+    config_dir().join(SETTINGS_FILE)
+}
+
+/// The named YAMLs of [profiles](crate::profile) (`<config dir>/profiles`).
+#[must_use]
+pub fn profiles_dir() -> PathBuf {
+    config_dir().join("profiles")
+}
+
+/// The name of the active [profile](crate::profile) (`<state dir>/profile`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn profile_file() -> std::io::Result<PathBuf> {
+    Ok(ensure_state_dir()?.join("profile"))
+}
+
+/// The nixman lock (`<state dir>/lock`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn lock_file() -> std::io::Result<PathBuf> {
+    Ok(ensure_state_dir()?.join("lock"))
+}
+
+/// The generation log (`<state dir>/generations.jsonl`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn generations_file() -> std::io::Result<PathBuf> {
+    Ok(ensure_state_dir()?.join("generations.jsonl"))
+}
+
+/// The [audit log](crate::audit) (`<state dir>/audit.log`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn audit_file() -> std::io::Result<PathBuf> {
+    Ok(ensure_state_dir()?.join("audit.log"))
+}
+
+/// The plan persisted for `resume` (`<state dir>/resume.yml`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn resume_file() -> std::io::Result<PathBuf> {
+    Ok(ensure_state_dir()?.join("resume.yml"))
+}
+
+/// The drift status written by `watch` (`<state dir>/drift.json`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn drift_file() -> std::io::Result<PathBuf> {
+    Ok(ensure_state_dir()?.join("drift.json"))
+}
+
+/// The state of the last successful apply (`<state dir>/last-applied.json`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn applied_file() -> std::io::Result<PathBuf> {
+    Ok(ensure_state_dir()?.join("last-applied.json"))
+}
+
+/// The recorded answers to apply conflicts (`<state dir>/conflicts.json`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn conflicts_file() -> std::io::Result<PathBuf> {
+    Ok(ensure_state_dir()?.join("conflicts.json"))
+}
+
+/// Where packages downloaded from the [archive](crate::archive) are kept
+/// (`<cache dir>/archive`).
+///
+/// # Errors
+/// Returns an error if the directory cannot be created.
+pub fn archive_dir() -> std::io::Result<PathBuf> {
+    ensure_cache_subdir("archive")
+}
+
+/// The scratch pacman database used to create [bundles](crate::bundle)
+/// (`<cache dir>/bundle-db`); it is not created.
+#[must_use]
+pub fn bundle_db() -> PathBuf {
+    cache_dir().join("bundle-db")
+}
+
+/// paru's build directory (`$XDG_CACHE_HOME/paru/clone`).
+#[must_use]
+pub fn paru_clone_dir() -> PathBuf {
+    cache_home().join("paru/clone")
+}
+
+/// The directory of systemd user units (`$XDG_CONFIG_HOME/systemd/user`).
+#[must_use]
+pub fn systemd_user_dir() -> PathBuf {
+    config_home().join("systemd/user")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Absolute XDG values are used; unset, empty, and relative ones fall back under `$HOME`.
+    #[test]
+    fn resolves_base_dirs() {
+        let resolve = |value: Option<&str>, home: Option<&str>| {
+            base_dir(
+                value.map(OsString::from),
+                home.map(OsString::from),
+                ".cache",
+            )
+        };
+        assert_eq!(
+            resolve(Some("/tmp/cache"), Some("/home/me")),
+            PathBuf::from("/tmp/cache")
+        );
+        assert_eq!(
+            resolve(None, Some("/home/me")),
+            PathBuf::from("/home/me/.cache")
+        );
+        assert_eq!(
+            resolve(Some(""), Some("/home/me")),
+            PathBuf::from("/home/me/.cache")
+        );
+        assert_eq!(
+            resolve(Some("relative/cache"), Some("/home/me")),
+            PathBuf::from("/home/me/.cache")
+        );
+        assert_eq!(resolve(None, None), PathBuf::from("./.cache"));
+    }
+}
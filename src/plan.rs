@@ -0,0 +1,757 @@
+//! Change plans: what an apply will do, computed before anything runs
+//!
+//! # Overview
+//!
+//! An apply is split in two steps. [`plan`] compares the system with the YAML and returns a
+//! [`ChangePlan`] listing every install and removal, plus pinned packages whose installed
+//! version differs from the pin and packages whose version requirement cannot be met. [`execute`] then carries out exactly that plan. In between,
+//! a caller can show the plan, ask for confirmation, or drop it:
+//!
+//! ```rust,no_run
+//! use nixman::{ApplyMode, CancelToken, plan};
+//!
+//! let plan = plan::plan("packages.yml", ApplyMode::Full)?;
+//! for change in &plan.install {
+//!     println!("install {} ({})", change.name, change.source);
+//! }
+//! if !plan.is_empty() {
+//!     plan::execute(&plan, false, false, true, &CancelToken::default(), &mut |_| {})?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! A [`Selection`] (`apply --only`/`--skip`) narrows a plan to some of its packages with
+//! [`ChangePlan::select`], e.g. to apply everything but a long AUR build now.
+//!
+//! Packages declared with a version requirement (`version: ">=1.6"`) that are installed at a
+//! version outside it are planned as upgrades when the sync databases have a version that
+//! satisfies it; otherwise they become a [`RequirementViolation`].
+//!
+//! The names alone understate what pacman does: installs pull in dependencies, and `-Rns`
+//! removals take the dependencies nothing else needs with them. [`resolve`] asks pacman's own
+//! resolver (`pacman -S --print` and `-Rns --print`) for them, and the plan keeps the answer
+//! as [`ChangePlan::resolved`]. Installs pacman cannot resolve, such as AUR packages, are left
+//! out of it; when pacman refuses a removal, the plan has no resolution and is name-only.
+//!
+//! [`size_impact`] estimates how much the pacman side of a plan downloads and how it changes
+//! the installed size, from `pacman -Si` and `-Qi`.
+
+use crate::condition::Host;
+use crate::pacman::{PackageInfo, PrintedPackage, SyncInfo};
+use crate::versioning::VersionRelation;
+use crate::{
+    ApplyError, ApplyMode, ApplyReport, CancelToken, Package, PackageList, Progress, SourceChanges,
+    pacman, read_effective_config, sources,
+};
+use serde::Serialize;
+use std::path::Path;
+
+/// One package to install or remove.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedChange {
+    /// `"pacman"` or the name of an extra source.
+    pub source: &'static str,
+    pub name: String,
+    /// The version pinned in the YAML, or its version requirement, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<String>,
+}
+
+/// A pinned package whose installed version is not the pinned one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PinViolation {
+    pub name: String,
+    pub pinned: String,
+    pub installed: String,
+    /// How the installed version relates to the pin, e.g. newer.
+    pub relation: VersionRelation,
+}
+
+impl std::fmt::Display for PinViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is pinned to {} but {} is installed, which is {}",
+            self.name, self.pinned, self.installed, self.relation
+        )
+    }
+}
+
+/// A declared package whose version requirement the installed version does not meet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RequirementViolation {
+    pub name: String,
+    /// The requirement from the YAML, e.g. `>=1.6`.
+    pub required: String,
+    /// The installed version, if it is installed.
+    pub installed: Option<String>,
+    /// The version in the sync databases, if it is there.
+    pub available: Option<String>,
+}
+
+impl std::fmt::Display for RequirementViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} requires {}", self.name, self.required)?;
+        match (&self.installed, &self.available) {
+            (Some(installed), _) => write!(f, " but {installed} is installed"),
+            (None, Some(available)) => write!(f, " but {available} is available"),
+            (None, None) => f.write_str(" but it is not available"),
+        }
+    }
+}
+
+/// Everything an apply will change, in the order it will change it: removals first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ChangePlan {
+    pub install: Vec<PlannedChange>,
+    pub remove: Vec<PlannedChange>,
+    /// Pins the plan does not fix; see [`crate::archive`] for downgrading to them.
+    pub pin_violations: Vec<PinViolation>,
+    /// Version requirements the plan cannot meet.
+    pub requirement_violations: Vec<RequirementViolation>,
+    /// What pacman's resolver adds to the pacman changes, if it could be asked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<Resolved>,
+}
+
+/// The packages pacman changes besides the ones a plan names.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Resolved {
+    /// Dependencies installed along with the planned installs.
+    pub dependencies: Vec<PrintedPackage>,
+    /// Packages removed along with the planned removals because nothing else needs them.
+    pub orphans: Vec<PrintedPackage>,
+    /// Planned installs pacman cannot resolve, such as AUR packages, so their dependencies are
+    /// unknown.
+    pub unresolved: Vec<String>,
+}
+
+impl Resolved {
+    /// Keep the packages pacman would install (`installed`) and remove (`removed`) beyond
+    /// `to_install` and `to_remove`.
+    #[must_use]
+    pub fn new(
+        to_install: &[String],
+        to_remove: &[String],
+        installed: Vec<PrintedPackage>,
+        removed: Vec<PrintedPackage>,
+        unresolved: Vec<String>,
+    ) -> Self {
+        Self {
+            dependencies: installed
+                .into_iter()
+                .filter(|package| !to_install.contains(&package.name))
+                .collect(),
+            orphans: removed
+                .into_iter()
+                .filter(|package| !to_remove.contains(&package.name))
+                .collect(),
+            unresolved,
+        }
+    }
+}
+
+/// Ask pacman what installing `to_install` and removing `to_remove` changes beyond them.
+///
+/// # Errors
+/// Returns an error if pacman could not be executed or refuses the transaction for another
+/// reason than an unknown install, e.g. because a removal would break a dependency.
+pub fn resolve(to_install: &[String], to_remove: &[String]) -> std::io::Result<Resolved> {
+    let (installed, unresolved) = resolve_installs(to_install)?;
+    let removed = if to_remove.is_empty() {
+        Vec::new()
+    } else {
+        let output = pacman::print_transaction(&["-Rns"], to_remove)?;
+        if !output.status.success() {
+            return Err(refused(&output));
+        }
+        pacman::parse_printed(&String::from_utf8_lossy(&output.stdout))
+    };
+    Ok(Resolved::new(
+        to_install, to_remove, installed, removed, unresolved,
+    ))
+}
+
+/// What pacman would install for `to_install`, and the names it does not know.
+fn resolve_installs(to_install: &[String]) -> std::io::Result<(Vec<PrintedPackage>, Vec<String>)> {
+    if to_install.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let output = pacman::print_transaction(&["-S", "--needed"], to_install)?;
+    if output.status.success() {
+        let installed = pacman::parse_printed(&String::from_utf8_lossy(&output.stdout));
+        return Ok((installed, Vec::new()));
+    }
+    // AUR packages are not in the sync databases; resolve the rest without them.
+    let unresolved = crate::names::parse_not_found(&String::from_utf8_lossy(&output.stderr));
+    if unresolved.is_empty() {
+        return Err(refused(&output));
+    }
+    let known: Vec<String> = to_install
+        .iter()
+        .filter(|name| !unresolved.contains(name))
+        .cloned()
+        .collect();
+    let (installed, _) = resolve_installs(&known)?;
+    Ok((installed, unresolved))
+}
+
+/// The error for a transaction pacman refused, with its reasons.
+fn refused(output: &std::process::Output) -> std::io::Error {
+    std::io::Error::other(format!(
+        "pacman refuses the transaction: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))
+}
+
+/// How much a set of pacman changes downloads and how it changes the installed size.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SizeImpact {
+    /// Bytes to download for the installs, before the package cache is checked.
+    pub download: u64,
+    /// How much the installed size grows, in bytes; negative if it shrinks.
+    pub installed_delta: i64,
+    /// Installs that are not in the sync databases, such as AUR packages, so their size is
+    /// unknown.
+    pub unknown: Vec<String>,
+}
+
+impl SizeImpact {
+    /// The impact of installing `to_install`, found in `available`, and removing `removed`.
+    #[must_use]
+    pub fn new(to_install: &[String], available: &[SyncInfo], removed: &[PackageInfo]) -> Self {
+        let bytes = |size: u64| i64::try_from(size).unwrap_or(i64::MAX);
+        let mut impact = Self::default();
+        for name in to_install {
+            match available.iter().find(|info| info.name == *name) {
+                Some(info) => {
+                    impact.download += info.download_size;
+                    impact.installed_delta += bytes(info.installed_size);
+                }
+                None => impact.unknown.push(name.clone()),
+            }
+        }
+        for info in removed {
+            impact.installed_delta -= bytes(info.installed_size);
+        }
+        impact
+    }
+}
+
+/// Estimate the [`SizeImpact`] of installing `to_install` and removing `to_remove` with pacman.
+///
+/// # Errors
+/// Returns an error if pacman could not be queried.
+pub fn size_impact(to_install: &[String], to_remove: &[String]) -> std::io::Result<SizeImpact> {
+    Ok(SizeImpact::new(
+        to_install,
+        &pacman::pacman_sync_infos(to_install)?,
+        &pacman::pacman_query_infos(to_remove)?,
+    ))
+}
+
+/// The packages of a plan to apply: those named in `only` (all if empty), minus those in `skip`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    pub only: Vec<String>,
+    pub skip: Vec<String>,
+}
+
+impl Selection {
+    /// Whether every package is selected.
+    #[must_use]
+    pub const fn is_all(&self) -> bool {
+        self.only.is_empty() && self.skip.is_empty()
+    }
+
+    /// Whether the package `name` is selected.
+    #[must_use]
+    pub fn includes(&self, name: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|only| only == name))
+            && !self.skip.iter().any(|skip| skip == name)
+    }
+
+    /// The names in `only` or `skip` that `plan` does not change, most likely typos.
+    #[must_use]
+    pub fn unmatched(&self, plan: &ChangePlan) -> Vec<&str> {
+        self.only
+            .iter()
+            .chain(&self.skip)
+            .map(String::as_str)
+            .filter(|name| {
+                !plan
+                    .install
+                    .iter()
+                    .chain(&plan.remove)
+                    .any(|change| change.name == *name)
+                    && !plan.pin_violations.iter().any(|pin| pin.name == *name)
+                    && !plan
+                        .requirement_violations
+                        .iter()
+                        .any(|violation| violation.name == *name)
+            })
+            .collect()
+    }
+}
+
+impl ChangePlan {
+    /// The part of the plan `selection` includes, with the [resolution](Self::resolved)
+    /// asked for again if it drops a pacman change.
+    #[must_use]
+    pub fn select(mut self, selection: &Selection) -> Self {
+        let before = self.install.len() + self.remove.len();
+        self.install
+            .retain(|change| selection.includes(&change.name));
+        self.remove
+            .retain(|change| selection.includes(&change.name));
+        if self.resolved.is_some() && self.install.len() + self.remove.len() != before {
+            self.resolve();
+        }
+        self.pin_violations
+            .retain(|pin| selection.includes(&pin.name));
+        self.requirement_violations
+            .retain(|violation| selection.includes(&violation.name));
+        self
+    }
+
+    /// Ask pacman's resolver about the pacman changes again; see [`resolve`].
+    pub fn resolve(&mut self) {
+        let (to_install, to_remove) = self.for_source("pacman");
+        self.resolved = resolve(&to_install, &to_remove).ok();
+    }
+
+    /// Whether there is nothing to install or remove.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.install.is_empty() && self.remove.is_empty()
+    }
+
+    /// The names to install and remove from `source`.
+    #[must_use]
+    pub fn for_source(&self, source: &str) -> (Vec<String>, Vec<String>) {
+        let names = |changes: &[PlannedChange]| {
+            changes
+                .iter()
+                .filter(|change| change.source == source)
+                .map(|change| change.name.clone())
+                .collect()
+        };
+        (names(&self.install), names(&self.remove))
+    }
+
+    /// The plan grouped by source, pacman first, as reported by `diff`.
+    #[must_use]
+    pub fn source_changes(&self) -> Vec<SourceChanges> {
+        let mut names = vec!["pacman"];
+        for change in self.install.iter().chain(&self.remove) {
+            if !names.contains(&change.source) {
+                names.push(change.source);
+            }
+        }
+        names
+            .into_iter()
+            .map(|source| {
+                let (to_install, to_remove) = self.for_source(source);
+                SourceChanges {
+                    source,
+                    to_install,
+                    to_remove,
+                }
+            })
+            .collect()
+    }
+
+    fn push(
+        &mut self,
+        source: &'static str,
+        changes: (Vec<String>, Vec<String>),
+        pins: &[(String, String)],
+    ) {
+        let planned = |name: String| PlannedChange {
+            source,
+            pinned: pins
+                .iter()
+                .find(|(pinned, _)| *pinned == name)
+                .map(|(_, version)| version.clone()),
+            name,
+        };
+        let (to_install, to_remove) = changes;
+        self.install.extend(to_install.into_iter().map(planned));
+        self.remove.extend(to_remove.into_iter().map(planned));
+    }
+}
+
+/// Plan the pacman changes an apply in `mode` would make.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read or parsed, or pacman could not be queried.
+pub fn plan_packages<P: AsRef<Path>>(yml_path: P, mode: ApplyMode) -> std::io::Result<ChangePlan> {
+    let config = read_effective_config(&yml_path)?;
+    let installed = crate::installed_explicit_package_list()?;
+    let (mut to_install, to_remove) = crate::sync_packages_from_yaml(&yml_path, &installed)?;
+    let mut declared = config.packages;
+    declared.retain_active(&Host::current());
+    let (upgrades, requirement_violations) = requirement_checks(&declared)?;
+    for upgrade in upgrades {
+        if !to_install.contains(&upgrade.name) {
+            to_install.push(upgrade.name);
+        }
+    }
+    let pins: Vec<(String, String)> = declared
+        .packages
+        .values()
+        .filter_map(|pkg| {
+            let pin = pkg.version.as_ref().map(ToString::to_string);
+            let pin = pin.or_else(|| pkg.requirement.as_ref().map(ToString::to_string))?;
+            Some((pkg.name.clone(), pin))
+        })
+        .collect();
+    let mut plan = ChangePlan::default();
+    plan.push("pacman", mode.restrict((to_install, to_remove)), &pins);
+    plan.pin_violations = pin_violations(&declared)?;
+    plan.requirement_violations = requirement_violations;
+    plan.resolve();
+    Ok(plan)
+}
+
+/// Check the version requirements of `declared` against pacman's `installed` and `available`
+/// versions.
+///
+/// Returns the installed packages that an upgrade brings within their requirement, and the
+/// requirements no version at hand meets.
+#[must_use]
+pub fn check_requirements(
+    declared: &PackageList,
+    installed: &[PackageInfo],
+    available: &[SyncInfo],
+) -> (Vec<RequirementViolation>, Vec<RequirementViolation>) {
+    let mut upgrades = Vec::new();
+    let mut violations = Vec::new();
+    for pkg in declared.packages.values() {
+        let Some(requirement) = &pkg.requirement else {
+            continue;
+        };
+        let installed = installed
+            .iter()
+            .find(|info| info.name == pkg.name)
+            .map(|info| info.version.to_string());
+        let available = available
+            .iter()
+            .find(|info| info.name == pkg.name)
+            .map(|info| info.version.to_string());
+        let met = |version: &Option<String>| {
+            version
+                .as_deref()
+                .is_some_and(|version| requirement.matches(version))
+        };
+        if met(&installed) || (installed.is_none() && met(&available)) {
+            continue;
+        }
+        let upgradable = met(&available);
+        let violation = RequirementViolation {
+            name: pkg.name.clone(),
+            required: requirement.to_string(),
+            installed,
+            available,
+        };
+        if upgradable {
+            upgrades.push(violation);
+        } else {
+            violations.push(violation);
+        }
+    }
+    (upgrades, violations)
+}
+
+/// [`check_requirements`] for the installed and available versions pacman reports.
+///
+/// # Errors
+/// Returns an error if pacman could not be queried.
+pub fn requirement_checks(
+    declared: &PackageList,
+) -> std::io::Result<(Vec<RequirementViolation>, Vec<RequirementViolation>)> {
+    let names: Vec<String> = declared
+        .packages
+        .values()
+        .filter(|pkg| pkg.requirement.is_some())
+        .map(|pkg| pkg.name.clone())
+        .collect();
+    if names.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    Ok(check_requirements(
+        declared,
+        &pacman::pacman_query_infos(&names)?,
+        &pacman::pacman_sync_infos(&names)?,
+    ))
+}
+
+/// The pinned packages of `declared` installed at another version, whether they were
+/// installed explicitly or as dependencies.
+///
+/// # Errors
+/// Returns an error if pacman could not be queried.
+pub fn pin_violations(declared: &PackageList) -> std::io::Result<Vec<PinViolation>> {
+    let pinned: Vec<String> = declared
+        .packages
+        .values()
+        .filter(|pkg| pkg.version.is_some())
+        .map(|pkg| pkg.name.clone())
+        .collect();
+    let installed = PackageList::new(
+        pacman::pacman_query_infos(&pinned)?
+            .into_iter()
+            .map(|info| Package {
+                reason: info.reason,
+                ..Package::versioned(info.name, info.version)
+            }),
+        Vec::new(),
+    );
+    Ok(declared
+        .version_mismatches(&installed)
+        .into_iter()
+        .filter_map(|(declared, current)| {
+            let (pinned, installed) = (declared.version.as_ref()?, current.version.as_ref()?);
+            Some(PinViolation {
+                name: declared.name.clone(),
+                pinned: pinned.to_string(),
+                installed: installed.to_string(),
+                relation: installed.relation_to(pinned),
+            })
+        })
+        .collect())
+}
+
+/// Plan every change an apply in `mode` would make, for pacman and each managed extra source.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read or parsed, or a package manager could not be
+/// queried.
+pub fn plan<P: AsRef<Path>>(yml_path: P, mode: ApplyMode) -> std::io::Result<ChangePlan> {
+    let mut plan = plan_packages(&yml_path, mode)?;
+    let config = read_effective_config(&yml_path)?;
+    for source in sources::builtin() {
+        if let Some(wanted) = config.source(source.name()) {
+            let changes = mode.restrict(crate::diff_names(wanted, &source.list()?));
+            plan.push(source.name(), changes, &[]);
+        }
+    }
+    Ok(plan)
+}
+
+/// Carry out `plan`: pacman removals and installs (reporting each run to `progress`), then
+/// each extra source's. Stops before the next pacman/paru run or source once `cancel` is
+/// cancelled.
+///
+/// # Returns
+/// What each pacman/paru and extra-source run did.
+///
+/// # Errors
+/// Returns an [`ApplyError`] with a summary of failed packages or IO errors, with `cancelled`
+/// set if it stopped early.
+pub fn execute(
+    plan: &ChangePlan,
+    use_paru: bool,
+    continue_on_error: bool,
+    noconfirm: bool,
+    cancel: &CancelToken,
+    progress: &mut dyn FnMut(Progress<'_>),
+) -> Result<ApplyReport, ApplyError> {
+    let (to_install, to_remove) = plan.for_source("pacman");
+    let mut report = crate::execute_package_changes_cancellable(
+        &to_install,
+        &to_remove,
+        use_paru,
+        continue_on_error,
+        noconfirm,
+        cancel,
+        progress,
+    )?;
+    for source in sources::builtin() {
+        let (to_install, to_remove) = plan.for_source(source.name());
+        if to_install.is_empty() && to_remove.is_empty() {
+            continue;
+        }
+        if cancel.is_cancelled() {
+            return Err(ApplyError {
+                message: format!("Cancelled before applying {}", source.name()),
+                cancelled: true,
+                report: Box::new(report),
+                ..ApplyError::default()
+            });
+        }
+        match crate::execute_source_changes(
+            source.as_ref(),
+            &to_install,
+            &to_remove,
+            continue_on_error,
+            noconfirm,
+        ) {
+            Ok(source_report) => report.append(source_report),
+            Err(e) => return Err(e.with_report(report)),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Changes are attributed to their source and carry their pins.
+    #[test]
+    fn groups_changes_by_source() {
+        let mut plan = ChangePlan::default();
+        let pins = vec![("vim".to_string(), "9.1.0-1".to_string())];
+        plan.push(
+            "pacman",
+            (
+                vec!["vim".to_string(), "htop".to_string()],
+                vec!["nano".to_string()],
+            ),
+            &pins,
+        );
+        plan.push(
+            "flatpak",
+            (vec!["org.gimp.GIMP".to_string()], Vec::new()),
+            &[],
+        );
+        assert_eq!(plan.install[0].pinned.as_deref(), Some("9.1.0-1"));
+        assert_eq!(plan.install[1].pinned, None);
+        assert_eq!(
+            plan.for_source("pacman"),
+            (
+                vec!["vim".to_string(), "htop".to_string()],
+                vec!["nano".to_string()]
+            )
+        );
+        let changes = plan.source_changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].source, "flatpak");
+        assert_eq!(changes[1].to_install, vec!["org.gimp.GIMP"]);
+        assert!(!plan.is_empty());
+        assert!(ChangePlan::default().source_changes()[0].is_empty());
+    }
+
+    /// Installed packages outside their requirement are upgraded when the repositories meet
+    /// it, and reported otherwise.
+    #[test]
+    fn checks_version_requirements() {
+        let declared = PackageList::new(
+            [
+                Package::from("git"),
+                Package::from("htop"),
+                Package::from("linux"),
+                Package::from("zig"),
+            ]
+            .map(|mut pkg| {
+                let requirement = match pkg.name.as_str() {
+                    "git" => ">=2.40",
+                    "htop" => ">=3.3",
+                    "linux" => "~6.6",
+                    _ => ">=0.12",
+                };
+                pkg.requirement = Some(requirement.parse().unwrap());
+                pkg
+            }),
+            Vec::new(),
+        );
+        let reason = "Install Reason  : Explicitly installed";
+        let installed = pacman::parse_package_infos(&format!(
+            "Name            : git\nVersion         : 2.45.1-1\n{reason}\n\n\
+             Name            : htop\nVersion         : 3.2.2-1\n{reason}\n\n\
+             Name            : linux\nVersion         : 6.6.30-1\n{reason}\n"
+        ));
+        let available = pacman::parse_sync_infos(
+            "Repository      : extra\nName            : htop\nVersion         : 3.3.0-3\n\n\
+             Repository      : core\nName            : linux\nVersion         : 6.9.1-1\n\n\
+             Repository      : extra\nName            : zig\nVersion         : 0.11.0-1\n",
+        );
+        let (upgrades, violations) = check_requirements(&declared, &installed, &available);
+        assert_eq!(
+            upgrades[0].to_string(),
+            "htop requires >=3.3 but 3.2.2-1 is installed"
+        );
+        assert_eq!(upgrades.len(), 1);
+        let shown: Vec<String> = violations.iter().map(ToString::to_string).collect();
+        assert_eq!(shown, vec!["zig requires >=0.12 but 0.11.0-1 is available"]);
+    }
+
+    /// Installs add their sizes, removals subtract theirs, and AUR packages are unknown.
+    #[test]
+    fn estimates_size_impact() {
+        let available = pacman::parse_sync_infos(
+            "Repository      : extra\nName            : htop\nVersion         : 3.3.0-3\n\
+             Download Size   : 165.06 KiB\nInstalled Size  : 423.73 KiB\n\n\
+             Repository      : extra\nName            : gimp\nVersion         : 2.10.38-1\n\
+             Download Size   : 20.00 MiB\nInstalled Size  : 100.00 MiB\n",
+        );
+        let removed = pacman::parse_package_infos(
+            "Name            : libreoffice-fresh\nVersion         : 24.2.3-1\n\
+             Installed Size  : 500.00 MiB\nInstall Reason  : Explicitly installed\n",
+        );
+        let names = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let impact = SizeImpact::new(&names(&["gimp", "paru-bin"]), &available, &removed);
+        assert_eq!(impact.download, 20 << 20);
+        assert_eq!(impact.installed_delta, -(400 << 20));
+        assert_eq!(impact.unknown, vec!["paru-bin"]);
+        assert_eq!(available[0].download_size, 169_021);
+    }
+
+    /// pacman's printed transactions keep only the packages beyond the planned ones.
+    #[test]
+    fn resolves_beyond_the_plan() {
+        let installed = pacman::parse_printed(
+            "warning: htop-3.3.0-3 is up to date -- skipping\n\
+             gimp 2.10.38-1 20971520\nbabl 0.1.108-1 0\n",
+        );
+        let removed = pacman::parse_printed("krita 5.2.2-1 314572800\nquazip 1.4-1 1048576\n");
+        let names = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let resolved = Resolved::new(
+            &names(&["htop", "gimp", "paru-bin"]),
+            &names(&["krita"]),
+            installed,
+            removed,
+            names(&["paru-bin"]),
+        );
+        assert_eq!(resolved.dependencies.len(), 1);
+        assert_eq!(resolved.dependencies[0].name, "babl");
+        assert_eq!(resolved.dependencies[0].size, 0);
+        assert_eq!(resolved.orphans[0].name, "quazip");
+        assert_eq!(resolved.orphans[0].size, 1 << 20);
+        assert_eq!(resolved.unresolved, vec!["paru-bin"]);
+    }
+
+    /// `--only` keeps just the named packages and `--skip` drops them.
+    #[test]
+    fn selects_part_of_a_plan() {
+        let mut plan = ChangePlan::default();
+        plan.push(
+            "pacman",
+            (
+                vec!["htop".to_string(), "zoom".to_string()],
+                vec!["nano".to_string()],
+            ),
+            &[],
+        );
+        let skip = Selection {
+            skip: vec!["zoom".to_string()],
+            ..Selection::default()
+        };
+        assert_eq!(
+            plan.clone().select(&skip).for_source("pacman"),
+            (vec!["htop".to_string()], vec!["nano".to_string()])
+        );
+        let only = Selection {
+            only: vec!["nano".to_string(), "vim".to_string()],
+            ..Selection::default()
+        };
+        assert_eq!(only.unmatched(&plan), vec!["vim"]);
+        assert_eq!(
+            plan.clone().select(&only).for_source("pacman"),
+            (Vec::new(), vec!["nano".to_string()])
+        );
+        assert!(Selection::default().is_all());
+        assert_eq!(plan.clone().select(&Selection::default()), plan);
+    }
+}
@@ -0,0 +1,151 @@
+//! Named configs to switch between
+//!
+//! # Overview
+//!
+//! Besides `packages.yml`, the config directory can hold named YAMLs under `profiles/`, such as
+//! `profiles/minimal.yml` and `profiles/full.yml`, for machines that switch between setups.
+//! `nixman profile use <name>` records the active profile in `<state dir>/profile`; from then
+//! on `apply`, `freeze`, `diff`, and every other command work on its YAML ([`config_file`]),
+//! with its own lockfile and local overrides next to it. The profile named [`DEFAULT`] is
+//! `packages.yml` itself, and is active until another one is chosen.
+//!
+//! Profiles are created by hand, e.g. by copying `packages.yml`; `nixman profile list` lists
+//! them.
+
+use crate::paths;
+use std::path::{Path, PathBuf};
+
+/// The profile that stands for `packages.yml`.
+pub const DEFAULT: &str = "default";
+
+/// Whether `name` can name a profile: letters, digits, `-`, and `_` only, so that it cannot
+/// leave the profiles directory or clash with a lockfile such as `minimal.lock.yml`.
+#[must_use]
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The YAML of the profile `name`.
+#[must_use]
+pub fn profile_path(name: &str) -> PathBuf {
+    if name == DEFAULT {
+        paths::config_file()
+    } else {
+        paths::profiles_dir().join(format!("{name}.yml"))
+    }
+}
+
+/// The profiles in `dir`, sorted; files that are not profiles, such as lockfiles, are skipped.
+///
+/// # Errors
+/// Returns an error if the directory exists but cannot be read.
+pub fn names_in(dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "yml")
+            && let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+            && is_valid_name(name)
+            && name != DEFAULT
+        {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Every profile: [`DEFAULT`] first, then those in the profiles directory.
+///
+/// # Errors
+/// Returns an error if the profiles directory cannot be read.
+pub fn list() -> std::io::Result<Vec<String>> {
+    let mut names = vec![DEFAULT.to_string()];
+    names.extend(names_in(&paths::profiles_dir())?);
+    Ok(names)
+}
+
+/// The active profile, [`DEFAULT`] unless another one was chosen.
+///
+/// # Errors
+/// Returns an error if the state file cannot be read or holds an invalid name.
+pub fn active() -> std::io::Result<String> {
+    let path = paths::profile_file()?;
+    let name = match std::fs::read_to_string(&path) {
+        Ok(name) => name,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(DEFAULT.to_string()),
+        Err(e) => return Err(e),
+    };
+    match name.trim() {
+        "" => Ok(DEFAULT.to_string()),
+        name if is_valid_name(name) => Ok(name.to_string()),
+        name => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid profile name `{name}` in {}", path.display()),
+        )),
+    }
+}
+
+/// Make `name` the active profile.
+///
+/// # Errors
+/// Returns an error if the name is invalid, the profile does not exist, or the state file
+/// cannot be written.
+pub fn set_active(name: &str) -> std::io::Result<()> {
+    if !is_valid_name(name) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid profile name `{name}`: use letters, digits, `-`, and `_`"),
+        ));
+    }
+    let path = profile_path(name);
+    if name != DEFAULT && !path.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no profile `{name}`: create {} first", path.display()),
+        ));
+    }
+    std::fs::write(paths::profile_file()?, format!("{name}\n"))
+}
+
+/// The YAML of the active profile; `packages.yml` if the active profile cannot be read.
+#[must_use]
+pub fn config_file() -> PathBuf {
+    active().map_or_else(|_| paths::config_file(), |name| profile_path(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only `.yml` files with valid names are profiles.
+    #[test]
+    fn lists_profiles() {
+        let dir = std::env::temp_dir().join(format!("nixman-profiles-{}", std::process::id()));
+        assert!(names_in(&dir).unwrap().is_empty());
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in [
+            "minimal.yml",
+            "full.yml",
+            "full.lock.yml",
+            "full.local.yml",
+            "notes.txt",
+            "default.yml",
+        ] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        assert_eq!(names_in(&dir).unwrap(), ["full", "minimal"]);
+        assert!(is_valid_name("work-laptop_2"));
+        assert!(!is_valid_name("../etc"));
+        assert!(!is_valid_name(""));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,130 @@
+//! Virtual packages satisfied by providers
+//!
+//! # Overview
+//!
+//! Some packages are virtual: `cron` or `java-runtime` are not packages themselves but are
+//! *provided* by others (`cronie`, `jre-openjdk`). When the YAML declares a virtual name, the
+//! installed provider stands in for it, so the diff neither reports the virtual name as missing
+//! nor the provider as undeclared.
+//!
+//! Providers are read from the `Provides` field of `pacman -Qei`.
+
+use crate::InstallReason;
+use crate::pacman::{self, parse_info_field};
+use std::collections::{BTreeMap, HashSet};
+
+/// Parse `pacman -Qi` output for several packages into `virtual name → providers`.
+///
+/// Versions in provisions (`java-runtime=17`) are dropped.
+#[must_use]
+pub fn parse_provides(info: &str) -> BTreeMap<String, Vec<String>> {
+    let mut providers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for block in info.split("\n\n") {
+        let (Some(name), Some(provisions)) = (
+            parse_info_field(block, "Name"),
+            parse_info_field(block, "Provides"),
+        ) else {
+            continue;
+        };
+        if provisions == "None" {
+            continue;
+        }
+        for provision in provisions.split_whitespace() {
+            let virtual_name = provision.split(['=', '<', '>']).next().unwrap_or(provision);
+            providers
+                .entry(virtual_name.to_string())
+                .or_default()
+                .push(name.clone());
+        }
+    }
+    providers
+}
+
+/// What each explicitly installed package provides, keyed by virtual name.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+pub fn installed_providers() -> std::io::Result<BTreeMap<String, Vec<String>>> {
+    let output = crate::run::output(pacman::pacman_command().arg("-Qei"))?;
+    Ok(parse_provides(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Replace wanted names that are not installed but provided by an installed package with that
+/// provider.
+#[must_use]
+pub fn resolve_provides(
+    wanted: Vec<(String, InstallReason)>,
+    installed: &[String],
+    providers: &BTreeMap<String, Vec<String>>,
+) -> Vec<(String, InstallReason)> {
+    resolve_provides_in(
+        wanted,
+        &installed.iter().map(String::as_str).collect(),
+        providers,
+    )
+}
+
+/// [`resolve_provides`] given the set of installed names.
+pub(crate) fn resolve_provides_in(
+    wanted: Vec<(String, InstallReason)>,
+    installed: &HashSet<&str>,
+    providers: &BTreeMap<String, Vec<String>>,
+) -> Vec<(String, InstallReason)> {
+    wanted
+        .into_iter()
+        .map(|(name, reason)| {
+            if installed.contains(name.as_str()) {
+                return (name, reason);
+            }
+            let provider = providers.get(&name).and_then(|providers| {
+                providers
+                    .iter()
+                    .find(|provider| installed.contains(provider.as_str()))
+            });
+            (provider.cloned().unwrap_or(name), reason)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = "\
+Name            : cronie
+Version         : 1.7.2-1
+Provides        : cron
+Install Reason  : Explicitly installed
+
+Name            : jre-openjdk
+Version         : 22.0.1.u8-1
+Provides        : java-runtime=22  java-runtime-openjdk=22  jre-openjdk-headless=22.0.1.u8
+                  java-environment=22
+Install Reason  : Explicitly installed
+
+Name            : git
+Version         : 2.45.0-1
+Provides        : None
+";
+
+    /// Declared virtual names are satisfied by the installed provider instead.
+    #[test]
+    fn resolves_virtual_packages() {
+        let providers = parse_provides(INFO);
+        assert_eq!(providers["cron"], vec!["cronie"]);
+        assert_eq!(providers["java-environment"], vec!["jre-openjdk"]);
+        assert!(!providers.contains_key("None"));
+
+        let installed = vec!["cronie".to_string(), "git".to_string()];
+        let wanted = vec![
+            ("cron".to_string(), InstallReason::Explicit),
+            ("git".to_string(), InstallReason::Explicit),
+            ("java-runtime".to_string(), InstallReason::Explicit),
+        ];
+        let names: Vec<String> = resolve_provides(wanted, &installed, &providers)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["cronie", "git", "java-runtime"]);
+    }
+}
@@ -0,0 +1,202 @@
+//! Auditing pacman install reasons against the YAML
+//!
+//! # Overview
+//!
+//! Packages often end up with the wrong install reason: installing a group with `pacman -S`
+//! marks every member explicit, and a package first installed by hand stays explicit after
+//! something starts depending on it. Such packages show up as drift although nothing needs to
+//! be installed or removed. `nixman audit-reasons` compares pacman's reasons with the YAML and
+//! suggests re-marking:
+//!
+//! - packages the YAML declares with `reason: dependency` that pacman has as explicit,
+//! - undeclared explicit packages that other installed packages require, which are most
+//!   likely dependencies that were installed by hand,
+//! - packages the YAML declares as explicit that pacman has as dependencies.
+//!
+//! `--apply` carries the suggestions out with `pacman -D --asdeps`/`--asexplicit`. Ignored
+//! packages are left alone.
+
+use crate::pacman::{self, PackageInfo};
+use crate::{Config, InstallReason};
+use serde::Serialize;
+use std::path::Path;
+use std::process::ExitStatus;
+
+/// Why a package's install reason should change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "packages")]
+pub enum Why {
+    /// The YAML declares it as a dependency.
+    DeclaredDependency,
+    /// It is not declared, and these installed packages require it.
+    RequiredBy(Vec<String>),
+    /// The YAML declares it as explicit.
+    DeclaredExplicit,
+}
+
+impl std::fmt::Display for Why {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeclaredDependency => f.write_str("declared as a dependency"),
+            Self::RequiredBy(packages) => write!(f, "required by {}", packages.join(", ")),
+            Self::DeclaredExplicit => f.write_str("declared as explicit"),
+        }
+    }
+}
+
+/// A package whose install reason should change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReasonFix {
+    pub name: String,
+    /// The reason pacman has now.
+    pub installed: InstallReason,
+    /// The reason to mark it with.
+    pub suggested: InstallReason,
+    pub why: Why,
+}
+
+/// The fixes for `infos`, the installed packages pacman reports, given `wanted`, the YAML's
+/// packages with their reasons (groups expanded), and the `ignored` names.
+#[must_use]
+pub fn suggest(
+    wanted: &[(String, InstallReason)],
+    ignored: &[String],
+    infos: &[PackageInfo],
+) -> Vec<ReasonFix> {
+    infos
+        .iter()
+        .filter(|info| !ignored.contains(&info.name))
+        .filter_map(|info| {
+            let declared = wanted
+                .iter()
+                .find(|(name, _)| *name == info.name)
+                .map(|(_, reason)| *reason);
+            let (suggested, why) = match (declared, info.reason) {
+                (Some(InstallReason::Dependency), InstallReason::Explicit) => {
+                    (InstallReason::Dependency, Why::DeclaredDependency)
+                }
+                (None, InstallReason::Explicit) if !info.required_by.is_empty() => (
+                    InstallReason::Dependency,
+                    Why::RequiredBy(info.required_by.clone()),
+                ),
+                (Some(InstallReason::Explicit), InstallReason::Dependency) => {
+                    (InstallReason::Explicit, Why::DeclaredExplicit)
+                }
+                _ => return None,
+            };
+            Some(ReasonFix {
+                name: info.name.clone(),
+                installed: info.reason,
+                suggested,
+                why,
+            })
+        })
+        .collect()
+}
+
+/// Compare pacman's install reasons with the YAML at `yml_path`.
+///
+/// # Errors
+/// Returns an error if the YAML cannot be read or pacman could not be queried.
+pub fn audit<P: AsRef<Path>>(yml_path: P) -> std::io::Result<Vec<ReasonFix>> {
+    let config: Config = crate::read_effective_config(&yml_path)?;
+    let mut list = config.packages;
+    list.retain_active(&crate::condition::Host::current());
+    let explicit = crate::installed_explicit_packages()?;
+    let groups = crate::available_groups().unwrap_or_default();
+    let wanted = crate::expand_groups(&list, &explicit, &groups);
+    let mut names = explicit;
+    for (name, _) in &wanted {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    let infos = pacman::pacman_query_infos(&names)?;
+    Ok(suggest(&wanted, &config.ignore.unwrap_or_default(), &infos))
+}
+
+/// Re-mark the packages of `fixes` with `pacman -D`, dependencies first.
+///
+/// # Errors
+/// Returns an error if pacman could not be executed.
+pub fn apply(fixes: &[ReasonFix]) -> std::io::Result<ExitStatus> {
+    let mut status = None;
+    for reason in [InstallReason::Dependency, InstallReason::Explicit] {
+        let names: Vec<String> = fixes
+            .iter()
+            .filter(|fix| fix.suggested == reason)
+            .map(|fix| fix.name.clone())
+            .collect();
+        if names.is_empty() {
+            continue;
+        }
+        let marked = pacman::pacman_mark(&names, reason)?;
+        if !marked.success() {
+            return Ok(marked);
+        }
+        status = Some(marked);
+    }
+    Ok(status.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, reason: InstallReason, required_by: &[&str]) -> PackageInfo {
+        PackageInfo {
+            name: name.to_string(),
+            version: "1.0-1".into(),
+            reason,
+            depends: Vec::new(),
+            provides: Vec::new(),
+            required_by: required_by.iter().map(ToString::to_string).collect(),
+            installed_size: 0,
+            install_date: String::new(),
+        }
+    }
+
+    /// Mismatches with the YAML and required undeclared packages are suggested; ignored and
+    /// consistent packages are not.
+    #[test]
+    fn suggests_reason_fixes() {
+        let wanted = vec![
+            ("git".to_string(), InstallReason::Explicit),
+            ("perl".to_string(), InstallReason::Dependency),
+            ("htop".to_string(), InstallReason::Explicit),
+        ];
+        let infos = vec![
+            info("git", InstallReason::Dependency, &["lazygit"]),
+            info("perl", InstallReason::Explicit, &["git"]),
+            info("htop", InstallReason::Explicit, &[]),
+            info("zlib", InstallReason::Explicit, &["curl", "git"]),
+            info("neofetch", InstallReason::Explicit, &[]),
+            info("vim", InstallReason::Explicit, &["vim-plug"]),
+        ];
+        let fixes = suggest(&wanted, &["vim".to_string()], &infos);
+        let summary: Vec<(&str, InstallReason, String)> = fixes
+            .iter()
+            .map(|fix| (fix.name.as_str(), fix.suggested, fix.why.to_string()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "git",
+                    InstallReason::Explicit,
+                    "declared as explicit".to_string()
+                ),
+                (
+                    "perl",
+                    InstallReason::Dependency,
+                    "declared as a dependency".to_string()
+                ),
+                (
+                    "zlib",
+                    InstallReason::Dependency,
+                    "required by curl, git".to_string()
+                ),
+            ]
+        );
+    }
+}
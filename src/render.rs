@@ -0,0 +1,309 @@
+//! Shared output rendering for CLI reports
+//!
+//! Every reporting command (`diff`, `apply`, `freeze`, ...) formats its results through a [`Renderer`]
+//! so that presentation choices live in one place. Two styles are supported for human output:
+//!
+//! - [`Style::Human`]: compact symbols (`+`, `-`, `~`) with ANSI color when writing to a terminal.
+//! - [`Style::Plain`]: screen-reader friendly output. No color, no symbols, no tables or spinners;
+//!   every line states one fact and starts with an explicit word such as `ADDED` or `REMOVED`.
+//!
+//! In JSON mode (`--output json`) commands emit a single structured document on stdout with
+//! [`Renderer::emit_json`], and human-readable lines printed with [`Renderer::print`] move to stderr
+//! so scripts can parse stdout directly.
+//!
+//! # Example
+//!
+//! ```rust
+//! use nixman::render::{ChangeKind, Renderer, Style};
+//! let renderer = Renderer::new(Style::Plain);
+//! assert_eq!(renderer.change(ChangeKind::Added, "htop", None), "ADDED: htop");
+//! ```
+
+use serde::Serialize;
+use std::io::IsTerminal;
+
+/// The presentation style used for CLI output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Style {
+    /// Symbols and color, intended for sighted terminal users.
+    #[default]
+    Human,
+    /// One fact per line with explicit words, intended for screen readers (`--plain-a11y`).
+    Plain,
+}
+
+/// The kind of change a reported entry represents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The package is (or will be) added to the system or the config.
+    Added,
+    /// The package is (or will be) removed from the system or the config.
+    Removed,
+    /// The package stays but something about it (e.g. its version) differs.
+    Changed,
+}
+
+impl ChangeKind {
+    /// The explicit word used for this change in plain output.
+    #[must_use]
+    pub const fn word(self) -> &'static str {
+        match self {
+            Self::Added => "ADDED",
+            Self::Removed => "REMOVED",
+            Self::Changed => "CHANGED",
+        }
+    }
+
+    const fn symbol(self) -> char {
+        match self {
+            Self::Added => '+',
+            Self::Removed => '-',
+            Self::Changed => '~',
+        }
+    }
+
+    const fn color(self) -> &'static str {
+        match self {
+            Self::Added => "\x1b[32m",
+            Self::Removed => "\x1b[31m",
+            Self::Changed => "\x1b[33m",
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
+/// Formats report lines according to the selected [`Style`] and routes output to the right stream.
+#[derive(Debug, Clone, Copy)]
+pub struct Renderer {
+    style: Style,
+    color: bool,
+    json: bool,
+}
+
+impl Renderer {
+    /// Creates a renderer for the given style. Color is only enabled for [`Style::Human`]
+    /// when stdout is a terminal and `NO_COLOR` is not set.
+    #[must_use]
+    pub fn new(style: Style) -> Self {
+        let color = style == Style::Human
+            && std::io::stdout().is_terminal()
+            && std::env::var_os("NO_COLOR").is_none();
+        Self {
+            style,
+            color,
+            json: false,
+        }
+    }
+
+    /// Enables or disables JSON mode.
+    #[must_use]
+    pub const fn with_json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    /// The style this renderer was created with.
+    #[must_use]
+    pub const fn style(&self) -> Style {
+        self.style
+    }
+
+    /// Whether JSON mode is enabled.
+    #[must_use]
+    pub const fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// Prints a human-readable line: to stdout normally, to stderr in JSON mode.
+    pub fn print(&self, line: &str) {
+        if self.json {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+    }
+
+    /// Prints several human-readable lines with [`Renderer::print`].
+    pub fn print_all<I: IntoIterator<Item = String>>(&self, lines: I) {
+        for line in lines {
+            self.print(&line);
+        }
+    }
+
+    /// Prints a structured result as pretty JSON on stdout, or an `{"error": ...}` object if it
+    /// cannot be serialized.
+    pub fn emit_json<T: Serialize + ?Sized>(&self, value: &T) {
+        let json = serde_json::to_string_pretty(value).unwrap_or_else(|e| {
+            serde_json::json!({ "error": format!("cannot serialize the result: {e}") }).to_string()
+        });
+        println!("{json}");
+    }
+
+    /// Formats a single change entry, e.g. `+ htop` or `ADDED: htop`.
+    ///
+    /// `detail` is appended as extra context, such as a version.
+    #[must_use]
+    pub fn change(&self, kind: ChangeKind, name: &str, detail: Option<&str>) -> String {
+        match self.style {
+            Style::Plain => detail.map_or_else(
+                || format!("{}: {name}", kind.word()),
+                |detail| format!("{}: {name}, {detail}", kind.word()),
+            ),
+            Style::Human => {
+                let line = detail.map_or_else(
+                    || format!("{} {name}", kind.symbol()),
+                    |detail| format!("{} {name} ({detail})", kind.symbol()),
+                );
+                if self.color {
+                    format!("{}{line}{RESET}", kind.color())
+                } else {
+                    line
+                }
+            }
+        }
+    }
+
+    /// Formats a list of changes of the same kind, one per line, sorted by name.
+    ///
+    /// `detail` is attached to every entry, e.g. the package source.
+    #[must_use]
+    pub fn changes(&self, kind: ChangeKind, names: &[String], detail: Option<&str>) -> Vec<String> {
+        let mut sorted: Vec<&String> = names.iter().collect();
+        sorted.sort();
+        sorted
+            .into_iter()
+            .map(|name| self.change(kind, name, detail))
+            .collect()
+    }
+
+    /// Formats a summary line, e.g. `2 to install, 1 to remove` or, in plain style,
+    /// one line per count.
+    #[must_use]
+    pub fn summary(&self, counts: &[(&str, usize)]) -> Vec<String> {
+        match self.style {
+            Style::Plain => counts
+                .iter()
+                .map(|(label, count)| format!("TOTAL {label}: {count}"))
+                .collect(),
+            Style::Human => vec![
+                counts
+                    .iter()
+                    .map(|(label, count)| format!("{count} {label}"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ],
+        }
+    }
+
+    /// Formats rows as aligned columns under `header`; rows flagged `true` are colored as
+    /// warnings. In plain style there is no table: each row is one line of `column: value`
+    /// pairs (empty cells are left out), with flagged rows starting with `WARNING`.
+    #[must_use]
+    pub fn table(&self, header: &[&str], rows: &[(Vec<String>, bool)]) -> Vec<String> {
+        if self.style == Style::Plain {
+            return rows
+                .iter()
+                .map(|(cells, flagged)| {
+                    let pairs: Vec<String> = header
+                        .iter()
+                        .zip(cells)
+                        .filter(|(_, cell)| !cell.is_empty())
+                        .map(|(column, cell)| format!("{}: {cell}", column.to_lowercase()))
+                        .collect();
+                    let prefix = if *flagged { "WARNING: " } else { "" };
+                    format!("{prefix}{}", pairs.join(", "))
+                })
+                .collect();
+        }
+        let mut widths: Vec<usize> = header.iter().map(|column| column.len()).collect();
+        for (cells, _) in rows {
+            for (width, cell) in widths.iter_mut().zip(cells) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        let line = |cells: Vec<&str>| {
+            cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+        let mut lines = vec![line(header.to_vec())];
+        for (cells, flagged) in rows {
+            let row = line(cells.iter().map(String::as_str).collect());
+            lines.push(if *flagged && self.color {
+                format!("{}{row}{RESET}", ChangeKind::Removed.color())
+            } else {
+                row
+            });
+        }
+        lines
+    }
+
+    /// Formats a message stating that nothing needs to change.
+    #[must_use]
+    pub fn in_sync(&self) -> String {
+        match self.style {
+            Style::Plain => "IN SYNC: installed packages match the config".to_string(),
+            Style::Human => "Everything is in sync.".to_string(),
+        }
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new(Style::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plain output spells out the change kind and never uses symbols.
+    #[test]
+    fn plain_changes_use_words() {
+        let renderer = Renderer::new(Style::Plain);
+        let lines = renderer.changes(
+            ChangeKind::Removed,
+            &["zsh".to_string(), "bash".to_string()],
+            None,
+        );
+        assert_eq!(lines, vec!["REMOVED: bash", "REMOVED: zsh"]);
+        assert_eq!(
+            renderer.change(ChangeKind::Changed, "htop", Some("3.2.2-1 to 3.3.0-1")),
+            "CHANGED: htop, 3.2.2-1 to 3.3.0-1"
+        );
+    }
+
+    /// Plain tables spell out each column; human tables are aligned.
+    #[test]
+    fn tables_align_or_spell_out_columns() {
+        let rows = vec![(vec!["vim".to_string(), "9.1-1".to_string()], true)];
+        assert_eq!(
+            Renderer::new(Style::Plain).table(&["NAME", "PINNED"], &rows),
+            vec!["WARNING: name: vim, pinned: 9.1-1"]
+        );
+        let human = Renderer {
+            style: Style::Human,
+            color: false,
+            json: false,
+        };
+        assert_eq!(
+            human.table(&["NAME", "PINNED"], &rows),
+            vec!["NAME  PINNED", "vim   9.1-1"]
+        );
+    }
+
+    /// Plain summaries put one count on each line.
+    #[test]
+    fn plain_summary_is_one_fact_per_line() {
+        let renderer = Renderer::new(Style::Plain);
+        let lines = renderer.summary(&[("to install", 2), ("to remove", 1)]);
+        assert_eq!(lines, vec!["TOTAL to install: 2", "TOTAL to remove: 1"]);
+    }
+}
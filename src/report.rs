@@ -0,0 +1,191 @@
+//! What an apply did, step by step
+//!
+//! # Overview
+//!
+//! Every pacman/paru run (and every extra-source run) of an apply becomes a [`Step`]: what it
+//! did to which packages, whether it succeeded, its exit code, how long it took, and the last
+//! lines it printed. A successful apply returns the steps as an [`ApplyReport`]; a failed one
+//! carries the steps it got through in [`ApplyError::report`](crate::ApplyError::report).
+//!
+//! With `continue_on_error` each package is its own step; otherwise all removals and all
+//! installs of a source run as one step each.
+
+use crate::PackageAction;
+use serde::{Serialize, Serializer};
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+/// How a step ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+}
+
+/// One pacman/paru or extra-source run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Step {
+    /// `"pacman"` or the name of an extra source.
+    pub source: &'static str,
+    pub action: PackageAction,
+    pub packages: Vec<String>,
+    pub outcome: Outcome,
+    /// The exit code, if the run was not killed by a signal.
+    pub exit_code: Option<i32>,
+    /// How often the run was tried; more than 1 when a transient failure was retried (see
+    /// [`crate::retry`]).
+    pub attempts: u32,
+    /// The time from the first attempt to the end of the last one.
+    #[serde(rename = "duration_ms", serialize_with = "as_millis")]
+    pub duration: Duration,
+    /// The last lines the run printed (empty for extra sources, whose output is not captured).
+    pub tail: Vec<String>,
+}
+
+impl Step {
+    /// Record a finished run that started at `started`.
+    #[must_use]
+    pub fn finished(
+        source: &'static str,
+        action: PackageAction,
+        packages: &[String],
+        status: ExitStatus,
+        started: Instant,
+        tail: Vec<String>,
+    ) -> Self {
+        Self {
+            source,
+            action,
+            packages: packages.to_vec(),
+            outcome: if status.success() {
+                Outcome::Succeeded
+            } else {
+                Outcome::Failed
+            },
+            exit_code: status.code(),
+            attempts: 1,
+            duration: started.elapsed(),
+            tail,
+        }
+    }
+}
+
+fn as_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+/// The steps of an apply, in the order they ran.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ApplyReport {
+    pub steps: Vec<Step>,
+}
+
+impl ApplyReport {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Add the steps of a later part of the apply.
+    pub fn append(&mut self, mut later: Self) {
+        self.steps.append(&mut later.steps);
+    }
+
+    /// The steps that failed.
+    pub fn failed(&self) -> impl Iterator<Item = &Step> {
+        self.steps
+            .iter()
+            .filter(|step| step.outcome == Outcome::Failed)
+    }
+
+    /// One row per package for [`Renderer::table`](crate::render::Renderer::table) under
+    /// [`ApplyReport::HEADER`], with failed packages flagged.
+    #[must_use]
+    pub fn rows(&self) -> Vec<(Vec<String>, bool)> {
+        self.steps
+            .iter()
+            .flat_map(|step| {
+                step.packages.iter().map(move |name| {
+                    let failed = step.outcome == Outcome::Failed;
+                    (
+                        vec![
+                            name.clone(),
+                            step.source.to_string(),
+                            step.action.verb().to_string(),
+                            if failed { "failed" } else { "ok" }.to_string(),
+                            step.exit_code
+                                .map_or_else(|| "-".to_string(), |code| code.to_string()),
+                            format!("{:.1}s", step.duration.as_secs_f64()),
+                        ],
+                        failed,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// The columns of [`ApplyReport::rows`].
+    pub const HEADER: [&str; 6] = ["PACKAGE", "SOURCE", "ACTION", "RESULT", "EXIT", "TIME"];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A status with exit code 1.
+    fn failure() -> ExitStatus {
+        #[cfg(unix)]
+        let status = std::os::unix::process::ExitStatusExt::from_raw(1 << 8);
+        #[cfg(windows)]
+        let status = std::os::windows::process::ExitStatusExt::from_raw(1);
+        status
+    }
+
+    /// Steps become one row per package, and failures are flagged and counted.
+    #[test]
+    fn reports_each_package() {
+        let mut report = ApplyReport {
+            steps: vec![Step {
+                source: "pacman",
+                action: PackageAction::Remove,
+                packages: vec!["nano".to_string()],
+                outcome: Outcome::Succeeded,
+                exit_code: Some(0),
+                attempts: 1,
+                duration: Duration::from_millis(1200),
+                tail: Vec::new(),
+            }],
+        };
+        let started = Instant::now();
+        report.append(ApplyReport {
+            steps: vec![Step::finished(
+                "pacman",
+                PackageAction::Install,
+                &["vim".to_string(), "htop".to_string()],
+                failure(),
+                started,
+                vec!["error: target not found: htop".to_string()],
+            )],
+        });
+        assert_eq!(report.failed().count(), 1);
+        assert_eq!(report.steps[1].exit_code, Some(1));
+        let rows = report.rows();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[0],
+            (
+                vec!["nano", "pacman", "remove", "ok", "0", "1.2s"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                false
+            )
+        );
+        assert!(rows[2].1);
+        assert_eq!(rows[2].0[0], "htop");
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["steps"][0]["duration_ms"], 1200);
+        assert_eq!(json["steps"][1]["outcome"], "failed");
+    }
+}
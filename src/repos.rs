@@ -0,0 +1,226 @@
+//! Custom pacman repositories declared in the YAML
+//!
+//! # Overview
+//!
+//! Packages from third-party repositories can only be installed once pacman knows about the
+//! repository. A `repos:` section declares them next to the packages:
+//!
+//! ```yaml
+//! repos:
+//!   - name: chaotic-aur
+//!     server: https://cdn-mirror.chaotic.cx/$repo/$arch
+//!     sig_level: Required DatabaseOptional
+//! ```
+//!
+//! Before installing, `apply` checks that every declared repository has a section in
+//! `/etc/pacman.conf` (following its `Include` directives). With `--write-pacman-conf`, missing
+//! repositories are written to a drop-in file, [`DROP_IN`], which is included from
+//! `pacman.conf` if it is not already.
+
+#[cfg(target_os = "linux")]
+use crate::pacman;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+#[cfg(target_os = "linux")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::process::Stdio;
+
+/// pacman's configuration file.
+pub const PACMAN_CONF: &str = "/etc/pacman.conf";
+
+/// The file nixman writes declared repositories to.
+pub const DROP_IN: &str = "/etc/pacman.d/nixman-repos.conf";
+
+/// A pacman repository declared in the YAML.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Repo {
+    pub name: String,
+    /// The `Server` URL; pacman expands `$repo` and `$arch`.
+    pub server: String,
+    /// The `SigLevel`, e.g. `Required DatabaseOptional` (default: pacman's global level).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig_level: Option<String>,
+}
+
+/// The files an `Include` value refers to; a `*` in the file name matches any text.
+fn expand_include(value: &str) -> Vec<PathBuf> {
+    let path = Path::new(value);
+    let Some(pattern) = path.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    let Some((prefix, suffix)) = pattern.split_once('*') else {
+        return vec![path.to_path_buf()];
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(prefix) && name.ends_with(suffix))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// The repository sections in a pacman configuration, and the files it includes.
+fn parse_conf(content: &str) -> (Vec<String>, Vec<PathBuf>) {
+    let mut sections = Vec::new();
+    let mut includes = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if let Some(section) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            if section != "options" {
+                sections.push(section.to_string());
+            }
+        } else if let Some((key, value)) = line.split_once('=')
+            && key.trim() == "Include"
+        {
+            includes.extend(expand_include(value.trim()));
+        }
+    }
+    (sections, includes)
+}
+
+/// The repositories configured in the pacman configuration at `path`, including those in the
+/// files it includes. Unreadable included files (such as missing mirror lists) are skipped.
+///
+/// # Errors
+/// Returns an error if `path` itself cannot be read.
+pub fn configured_repos(path: &Path) -> std::io::Result<Vec<String>> {
+    let (mut repos, mut pending) = parse_conf(&std::fs::read_to_string(path)?);
+    let mut visited = vec![path.to_path_buf()];
+    while let Some(include) = pending.pop() {
+        if visited.contains(&include) {
+            continue;
+        }
+        if let Ok(content) = std::fs::read_to_string(&include) {
+            let (sections, includes) = parse_conf(&content);
+            repos.extend(sections);
+            pending.extend(includes);
+        }
+        visited.push(include);
+    }
+    Ok(repos)
+}
+
+/// The declared repositories missing from `configured`.
+#[must_use]
+pub fn missing<'a>(declared: &'a [Repo], configured: &[String]) -> Vec<&'a Repo> {
+    declared
+        .iter()
+        .filter(|repo| !configured.contains(&repo.name))
+        .collect()
+}
+
+/// The contents of the drop-in file for `repos`.
+#[must_use]
+pub fn render_drop_in(repos: &[&Repo]) -> String {
+    let mut out = String::from("# Generated by nixman from the repos: section; do not edit.\n");
+    for repo in repos {
+        let _ = write!(out, "\n[{}]\n", repo.name);
+        if let Some(sig_level) = &repo.sig_level {
+            let _ = writeln!(out, "SigLevel = {sig_level}");
+        }
+        let _ = writeln!(out, "Server = {}", repo.server);
+    }
+    out
+}
+
+/// Write `content` to the root-owned file at `path` through the configured elevation tool.
+#[cfg(target_os = "linux")]
+fn write_privileged(path: &str, content: &str, append: bool) -> std::io::Result<()> {
+    let mut command = pacman::elevated("tee");
+    if append {
+        command.arg("-a");
+    }
+    let mut child = command
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(content.as_bytes())?;
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!("failed to write {path}")))
+    }
+}
+
+/// Write the declared repositories that are not configured elsewhere to [`DROP_IN`], and
+/// include it from [`PACMAN_CONF`] unless it already is. Returns the repositories written.
+///
+/// # Errors
+/// Returns an error if either file cannot be read or written.
+#[cfg(target_os = "linux")]
+pub fn write_drop_in(declared: &[Repo]) -> std::io::Result<Vec<String>> {
+    let (in_drop_in, _) = parse_conf(&std::fs::read_to_string(DROP_IN).unwrap_or_default());
+    let configured = configured_repos(Path::new(PACMAN_CONF))?;
+    // The drop-in is replaced as a whole, so it must keep the repositories it already holds.
+    let repos: Vec<&Repo> = declared
+        .iter()
+        .filter(|repo| !configured.contains(&repo.name) || in_drop_in.contains(&repo.name))
+        .collect();
+    write_privileged(DROP_IN, &render_drop_in(&repos), false)?;
+    let (_, includes) = parse_conf(&std::fs::read_to_string(PACMAN_CONF)?);
+    if !includes.iter().any(|include| include == Path::new(DROP_IN)) {
+        write_privileged(
+            PACMAN_CONF,
+            &format!("\n# Repositories declared in nixman's YAML\nInclude = {DROP_IN}\n"),
+            true,
+        )?;
+    }
+    Ok(repos.iter().map(|repo| repo.name.clone()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Repositories in included files count as configured; declared ones that are not are
+    /// rendered into the drop-in.
+    #[test]
+    fn finds_missing_repos_through_includes() {
+        let dir = std::env::temp_dir().join(format!("nixman-repos-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let conf = dir.join("pacman.conf");
+        std::fs::write(
+            &conf,
+            format!(
+                "[options]\nArchitecture = auto\n\n[core]\nInclude = /nonexistent/mirrorlist\n\n[extra]\nInclude = {}/conf.d/*.conf\n",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("conf.d/custom.conf"),
+            "[custom]\nServer = file:///srv/repo\n",
+        )
+        .unwrap();
+        let configured = configured_repos(&conf).unwrap();
+        assert_eq!(configured, vec!["core", "extra", "custom"]);
+
+        let declared: Vec<Repo> = serde_yml::from_str(
+            "- name: custom\n  server: file:///srv/repo\n- name: chaotic-aur\n  server: https://cdn-mirror.chaotic.cx/$repo/$arch\n  sig_level: Required DatabaseOptional\n",
+        )
+        .unwrap();
+        let missing = missing(&declared, &configured);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(
+            render_drop_in(&missing),
+            "# Generated by nixman from the repos: section; do not edit.\n\n[chaotic-aur]\nSigLevel = Required DatabaseOptional\nServer = https://cdn-mirror.chaotic.cx/$repo/$arch\n"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,272 @@
+//! Staged applies that continue after a required reboot
+//!
+//! # Overview
+//!
+//! Some changes can only be completed once the system has booted into a new kernel. The classic
+//! example is switching kernels while also installing `*-dkms` modules: the modules are built
+//! against the running kernel, so they should be installed after rebooting.
+//!
+//! When `nixman apply --resume-after-reboot` detects this situation it:
+//!
+//! 1. applies everything that can be done now,
+//! 2. persists the remaining packages as a [`ResumePlan`] in the state directory,
+//! 3. registers a one-shot systemd unit ([`UNIT_NAME`]) that runs `nixman resume` on the next boot.
+//!
+//! `nixman resume` installs the remaining packages with pacman (the unit runs as root, so paru is
+//! not used) and `--noconfirm` (nobody is there to answer prompts), then removes the plan and the
+//! unit.
+
+use crate::pacman;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+
+/// Name of the systemd unit that resumes a staged apply after boot.
+pub const UNIT_NAME: &str = "nixman-resume.service";
+
+/// Kernel packages whose installation requires a reboot before DKMS modules can be built against them.
+pub const KERNEL_PACKAGES: &[&str] = &[
+    "linux",
+    "linux-lts",
+    "linux-zen",
+    "linux-hardened",
+    "linux-rt",
+    "linux-rt-lts",
+];
+
+/// The remaining work of an apply that was interrupted by a required reboot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumePlan {
+    /// The YAML file the apply was started from.
+    pub config: PathBuf,
+    /// Whether to keep going when a package fails.
+    pub continue_on_error: bool,
+    /// Packages still to be installed after the reboot.
+    pub remaining: Vec<String>,
+}
+
+impl ResumePlan {
+    /// Write the plan to `path` as YAML.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let yml = serde_yml::to_string(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, yml)
+    }
+
+    /// Read a plan previously written by [`ResumePlan::save`].
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let yml = std::fs::read_to_string(path)?;
+        crate::schema::from_str(&yml)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Default location of the persisted plan (`<state dir>/resume.yml`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn plan_path() -> std::io::Result<PathBuf> {
+    crate::paths::resume_file()
+}
+
+/// Split the packages to install into what can be installed now and what must wait for a reboot.
+///
+/// Returns `None` when no reboot is needed, i.e. unless a kernel package and at least one
+/// `*-dkms` package are installed together.
+#[must_use]
+pub fn split_for_reboot(to_install: &[String]) -> Option<(Vec<String>, Vec<String>)> {
+    let switches_kernel = to_install
+        .iter()
+        .any(|pkg| KERNEL_PACKAGES.contains(&pkg.as_str()));
+    if !switches_kernel {
+        return None;
+    }
+    let (after, now): (Vec<String>, Vec<String>) = to_install
+        .iter()
+        .cloned()
+        .partition(|pkg| pkg.ends_with("-dkms"));
+    if after.is_empty() {
+        None
+    } else {
+        Some((now, after))
+    }
+}
+
+/// Render the systemd unit that runs `<exe> resume --plan <plan>` once on the next boot.
+#[must_use]
+pub fn unit_contents(exe: &Path, plan: &Path) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Resume nixman apply after reboot\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         ConditionPathExists={plan}\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={exe} resume --plan {plan}\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        exe = exe.display(),
+        plan = plan.display(),
+    )
+}
+
+fn unit_path() -> PathBuf {
+    Path::new("/etc/systemd/system").join(UNIT_NAME)
+}
+
+/// Install and enable the resume unit (requires root).
+///
+/// # Errors
+/// Returns an error if the current executable cannot be resolved or a command could not be executed.
+pub fn register_unit(plan: &Path) -> std::io::Result<ExitStatus> {
+    let exe = std::env::current_exe()?;
+    let mut tee = pacman::elevated("tee")
+        .arg(unit_path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = tee.stdin.take() {
+        stdin.write_all(unit_contents(&exe, plan).as_bytes())?;
+    }
+    let status = tee.wait()?;
+    if !status.success() {
+        return Ok(status);
+    }
+    pacman::elevated("systemctl")
+        .args(["enable", UNIT_NAME])
+        .status()
+}
+
+/// Disable and delete the resume unit (requires root).
+///
+/// # Errors
+/// Returns an error if a command could not be executed.
+pub fn unregister_unit() -> std::io::Result<ExitStatus> {
+    let status = pacman::elevated("systemctl")
+        .args(["disable", UNIT_NAME])
+        .status()?;
+    if !status.success() {
+        return Ok(status);
+    }
+    pacman::elevated("rm").arg(unit_path()).status()
+}
+
+/// Apply the YAML like [`crate::apply_packages_from_yaml_with_progress`], but defer packages that
+/// need a reboot.
+///
+/// # Returns
+/// `true` when packages were deferred and a reboot is required to finish the apply.
+///
+/// # Errors
+/// Returns an [`ApplyError`](crate::ApplyError) with a summary of failed packages or IO errors.
+pub fn apply_with_resume<P: AsRef<Path>>(
+    yml_path: P,
+    use_paru: bool,
+    continue_on_error: bool,
+    noconfirm: bool,
+    mode: crate::ApplyMode,
+    progress: &mut dyn FnMut(crate::Progress<'_>),
+) -> Result<bool, crate::ApplyError> {
+    let installed = crate::installed_explicit_package_list().map_err(|e| e.to_string())?;
+    let (to_install, to_remove) = mode.restrict(
+        crate::sync_packages_from_yaml(&yml_path, &installed).map_err(|e| e.to_string())?,
+    );
+    let Some((now, after)) = split_for_reboot(&to_install) else {
+        crate::execute_package_changes_with_progress(
+            &to_install,
+            &to_remove,
+            use_paru,
+            continue_on_error,
+            noconfirm,
+            progress,
+        )?;
+        return Ok(false);
+    };
+    crate::execute_package_changes_with_progress(
+        &now,
+        &to_remove,
+        use_paru,
+        continue_on_error,
+        noconfirm,
+        progress,
+    )?;
+    let config = std::fs::canonicalize(&yml_path).map_err(|e| e.to_string())?;
+    let path = plan_path().map_err(|e| e.to_string())?;
+    ResumePlan {
+        config,
+        continue_on_error,
+        remaining: after,
+    }
+    .save(&path)
+    .map_err(|e| e.to_string())?;
+    let status = register_unit(&path).map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(true)
+    } else {
+        Err(
+            format!("Failed to register {UNIT_NAME}; run `nixman resume` manually after rebooting")
+                .into(),
+        )
+    }
+}
+
+/// Finish a staged apply: install the remaining packages, then remove the plan and the unit.
+///
+/// # Errors
+/// Returns an [`ApplyError`](crate::ApplyError) with a summary of failed packages or IO errors. The plan and unit are
+/// kept when installation fails, so the next boot tries again.
+pub fn resume<P: AsRef<Path>>(plan_path: P) -> Result<(), crate::ApplyError> {
+    let plan = ResumePlan::load(&plan_path).map_err(|e| e.to_string())?;
+    // Resuming runs unattended from systemd, so there is nobody to answer prompts.
+    crate::execute_package_changes(&plan.remaining, &[], false, plan.continue_on_error, true)?;
+    std::fs::remove_file(&plan_path).map_err(|e| e.to_string())?;
+    let status = unregister_unit().map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Failed to remove {UNIT_NAME}").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// DKMS packages are deferred only when a kernel is installed in the same apply.
+    #[test]
+    fn splits_dkms_after_kernel_switch() {
+        let to_install = vec![
+            "linux-lts".to_string(),
+            "nvidia-dkms".to_string(),
+            "htop".to_string(),
+        ];
+        let (now, after) = split_for_reboot(&to_install).expect("expected a reboot split");
+        assert_eq!(now, vec!["linux-lts", "htop"]);
+        assert_eq!(after, vec!["nvidia-dkms"]);
+
+        assert_eq!(split_for_reboot(&["nvidia-dkms".to_string()]), None);
+        assert_eq!(split_for_reboot(&["linux".to_string()]), None);
+    }
+
+    /// The unit passes the plan path explicitly, since it runs as root with a different home.
+    #[test]
+    fn unit_resumes_with_explicit_plan() {
+        let unit = unit_contents(
+            Path::new("/usr/bin/nixman"),
+            Path::new("/home/me/.local/state/nixman/resume.yml"),
+        );
+        assert!(unit.contains(
+            "ExecStart=/usr/bin/nixman resume --plan /home/me/.local/state/nixman/resume.yml"
+        ));
+        assert!(unit.contains("Type=oneshot"));
+    }
+}
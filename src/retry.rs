@@ -0,0 +1,159 @@
+//! Retrying installs that failed for transient reasons
+//!
+//! # Overview
+//!
+//! An unattended apply should not give up because a mirror timed out or an AUR clone hit a
+//! network hiccup. When an install fails, its last lines of output are checked with
+//! [`is_transient`]: download and connection errors are retried after a delay that doubles
+//! each time ([`RetryPolicy::backoff`]), while errors that would fail again, such as
+//! `target not found` or unresolvable dependencies, are reported at once.
+//!
+//! Retries are off by default. Turn them on for the whole process with [`set_policy`], which
+//! `apply --retries` and the `retries`/`retry_delay` settings do.
+
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// The first delay when none is configured.
+pub const DEFAULT_DELAY: Duration = Duration::from_secs(10);
+
+/// The longest delay between two attempts, however many retries came before.
+pub const MAX_DELAY: Duration = Duration::from_mins(5);
+
+/// Output that means the install would fail the same way again.
+const PERMANENT: &[&str] = &[
+    "target not found",
+    "could not satisfy dependencies",
+    "unable to satisfy dependency",
+    "conflicting files",
+    "are in conflict",
+    "invalid or corrupted package",
+    "is not a valid package",
+];
+
+/// Output that points at the network or a mirror.
+const TRANSIENT: &[&str] = &[
+    "failed retrieving file",
+    "failed to retrieve some files",
+    "download library error",
+    "failed to synchronize",
+    "operation timed out",
+    "connection timed out",
+    "connection refused",
+    "connection reset",
+    "could not resolve host",
+    "temporary failure in name resolution",
+    "network is unreachable",
+    "the requested url returned error: 5",
+    "unable to access",
+    "failed to clone",
+    "failed to download sources",
+    "early eof",
+];
+
+/// How often and how patiently to retry transient install failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 turns retrying off.
+    pub retries: u32,
+    /// The delay before the first retry.
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::OFF
+    }
+}
+
+impl RetryPolicy {
+    /// No retries: every failure is reported at once.
+    pub const OFF: Self = Self {
+        retries: 0,
+        delay: DEFAULT_DELAY,
+    };
+
+    /// The delay before retry number `retry` (0-based): the initial delay, doubled each time,
+    /// at most [`MAX_DELAY`].
+    #[must_use]
+    pub fn backoff(self, retry: u32) -> Duration {
+        self.delay
+            .checked_mul(2u32.saturating_pow(retry))
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY))
+    }
+
+    /// Whether a run that failed on attempt `attempt` (0-based) with output `tail` is worth
+    /// another try.
+    #[must_use]
+    pub fn should_retry(self, attempt: u32, tail: &[String]) -> bool {
+        attempt < self.retries && is_transient(tail)
+    }
+}
+
+static POLICY: Mutex<RetryPolicy> = Mutex::new(RetryPolicy::OFF);
+
+/// The retry policy of the process.
+#[must_use]
+pub fn policy() -> RetryPolicy {
+    *POLICY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Use `policy` for every install of the process.
+pub fn set_policy(policy: RetryPolicy) {
+    *POLICY.lock().unwrap_or_else(PoisonError::into_inner) = policy;
+}
+
+/// Whether output ending in `tail` describes a network or mirror failure rather than one that
+/// would happen again.
+#[must_use]
+pub fn is_transient(tail: &[String]) -> bool {
+    let lines: Vec<String> = tail.iter().map(|line| line.to_lowercase()).collect();
+    let mentions = |patterns: &[&str]| {
+        lines
+            .iter()
+            .any(|line| patterns.iter().any(|pattern| line.contains(pattern)))
+    };
+    !mentions(PERMANENT) && mentions(TRANSIENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    /// Mirror and clone failures are retried, missing targets are not, and delays double up to
+    /// the cap.
+    #[test]
+    fn classifies_failures_and_backs_off() {
+        assert!(is_transient(&lines(
+            "error: failed retrieving file 'vim-9.1.0-1-x86_64.pkg.tar.zst' from mirror: Operation timed out after 10001 milliseconds\n\
+             error: failed to commit transaction (failed to retrieve some files)"
+        )));
+        assert!(is_transient(&lines(
+            "fatal: unable to access 'https://aur.archlinux.org/paru.git/': Could not resolve host: aur.archlinux.org\n\
+             error: failed to download sources for 'paru-2.0.4-1'"
+        )));
+        assert!(!is_transient(&lines("error: target not found: vimm")));
+        assert!(!is_transient(&lines(
+            "error: failed to prepare transaction (could not satisfy dependencies)\n\
+             :: unable to satisfy dependency 'foo' required by bar"
+        )));
+        assert!(!is_transient(&[]));
+
+        let policy = RetryPolicy {
+            retries: 2,
+            delay: Duration::from_secs(10),
+        };
+        assert_eq!(policy.backoff(0), Duration::from_secs(10));
+        assert_eq!(policy.backoff(2), Duration::from_secs(40));
+        assert_eq!(policy.backoff(10), MAX_DELAY);
+        assert_eq!(policy.backoff(u32::MAX), MAX_DELAY);
+        let timeout = lines("error: failed retrieving file 'x': Connection timed out");
+        assert!(policy.should_retry(1, &timeout));
+        assert!(!policy.should_retry(2, &timeout));
+        assert!(!RetryPolicy::default().should_retry(0, &timeout));
+    }
+}
@@ -0,0 +1,380 @@
+//! Running commands with streamed and captured output
+//!
+//! # Overview
+//!
+//! [`run_command`] is how nixman runs pacman, paru, and the other package tools (`pactree`,
+//! `checkupdates`, flatpak, cargo, pipx, npm, and the snapshot tools) when it needs their
+//! output. Downloads with `curl` and signature checks are run directly. Each line is handed to a callback as soon as it is written, for logging or progress, while
+//! the full output and its last lines are kept for parsing and error reports. With `echo`, the
+//! output is passed through to the terminal as well, as `apply` does.
+//!
+//! With `--output json`, stdout is reserved for nixman's JSON: after
+//! [`set_stdout_to_stderr`], echoed stdout and the stdout of commands run with [`status`] go to
+//! stderr instead.
+//!
+//! A hook set with [`set_hook`] sees every line of every command run this way, including the
+//! pacman and extra source queries behind `diff` and `status`, so frontends can log what the
+//! tools printed.
+//!
+//! [`stream_stdout`] hands stdout over line by line without keeping it, for output too large to
+//! hold, such as a streamed freeze.
+//!
+//! ```rust,no_run
+//! use nixman::run::{run_command, Stream};
+//! use std::process::Command;
+//! let output = run_command(Command::new("pacman").arg("-Qe"), false, &mut |stream, line| {
+//!     if stream == Stream::Stderr {
+//!         eprintln!("pacman: {line}");
+//!     }
+//! })?;
+//! println!("{} packages", output.stdout_lines().len());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::pacman::TAIL_LINES;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, PoisonError};
+use std::thread::JoinHandle;
+
+/// Which output stream a line came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// A callback that receives every line of every command run with [`run_command`].
+pub type Hook = Box<dyn Fn(Stream, &str) + Send + Sync>;
+
+static HOOK: Mutex<Option<Hook>> = Mutex::new(None);
+
+/// Pass every line of every command run with [`run_command`] to `hook`, or stop with `None`.
+pub fn set_hook(hook: Option<Hook>) {
+    *HOOK.lock().unwrap_or_else(PoisonError::into_inner) = hook;
+}
+
+static STDOUT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Send what commands print to stdout to stderr instead, so stdout holds only nixman's own
+/// output, as `--output json` needs.
+pub fn set_stdout_to_stderr(enabled: bool) {
+    STDOUT_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Run `cmd` with its output going to the terminal, like [`Command::status`], except that its
+/// stdout goes to stderr after [`set_stdout_to_stderr`].
+///
+/// # Errors
+/// Returns an error if the command could not be executed.
+pub fn status(cmd: &mut Command) -> std::io::Result<ExitStatus> {
+    if STDOUT_TO_STDERR.load(Ordering::Relaxed) {
+        cmd.stdout(std::io::stderr());
+    }
+    cmd.status()
+}
+
+/// Everything a command run with [`run_command`] wrote.
+#[derive(Debug, Clone)]
+pub struct RunOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// The last [`TAIL_LINES`] non-empty lines of both streams, oldest first.
+    pub tail: Vec<String>,
+}
+
+impl RunOutput {
+    /// The lines of stdout, lossily decoded.
+    #[must_use]
+    pub fn stdout_lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl From<RunOutput> for Output {
+    fn from(output: RunOutput) -> Self {
+        Self {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+        }
+    }
+}
+
+/// Run `cmd` and capture its output, calling `on_line` with each line as it arrives.
+///
+/// Lines of stdout and stderr also go to the [hook](set_hook). With `echo`, the output is
+/// copied to the terminal unchanged as well, stdout to stderr after [`set_stdout_to_stderr`].
+/// stdin is left as configured on `cmd`.
+///
+/// # Errors
+/// Returns an error if the command could not be executed.
+pub fn run_command(
+    cmd: &mut Command,
+    echo: bool,
+    on_line: &mut dyn FnMut(Stream, &str),
+) -> std::io::Result<RunOutput> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let (sender, lines) = mpsc::channel();
+    let stdout = child.stdout.take().map(|out| {
+        let terminal: Option<Box<dyn Write + Send>> = echo.then(|| {
+            if STDOUT_TO_STDERR.load(Ordering::Relaxed) {
+                Box::new(std::io::stderr()) as _
+            } else {
+                Box::new(std::io::stdout()) as _
+            }
+        });
+        read_lines(out, Stream::Stdout, terminal, sender.clone())
+    });
+    let stderr = child.stderr.take().map(|err| {
+        let terminal: Option<Box<dyn Write + Send>> =
+            echo.then(|| Box::new(std::io::stderr()) as _);
+        read_lines(err, Stream::Stderr, terminal, sender.clone())
+    });
+    // The loop below ends once both readers have dropped their senders.
+    drop(sender);
+    let mut tail = VecDeque::with_capacity(TAIL_LINES);
+    for (stream, line) in lines {
+        if let Some(hook) = HOOK.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+            hook(stream, &line);
+        }
+        on_line(stream, &line);
+        if !line.trim().is_empty() {
+            if tail.len() == TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line.trim_end().to_string());
+        }
+    }
+    let status = child.wait()?;
+    let collect = |reader: Option<JoinHandle<Vec<u8>>>| {
+        reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default()
+    };
+    Ok(RunOutput {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+        tail: tail.into(),
+    })
+}
+
+/// Run `cmd` quietly, like [`Command::output`], with its lines still going to the
+/// [hook](set_hook).
+///
+/// # Errors
+/// Returns an error if the command could not be executed.
+pub fn output(cmd: &mut Command) -> std::io::Result<Output> {
+    run_command(cmd, false, &mut |_, _| {}).map(Output::from)
+}
+
+/// Run `cmd` and hand each line of its stdout to `on_line` as it arrives, without keeping it, so
+/// memory stays bounded however much the command prints. stderr is captured as by
+/// [`run_command`].
+///
+/// The returned stdout is empty, and the tail only holds stderr. Lines of both streams go to the
+/// [hook](set_hook).
+///
+/// # Errors
+/// Returns an error if the command could not be executed or its output could not be read, or
+/// the first error of `on_line`, after which the command is killed.
+pub fn stream_stdout(
+    cmd: &mut Command,
+    on_line: &mut dyn FnMut(&[u8]) -> std::io::Result<()>,
+) -> std::io::Result<RunOutput> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let (sender, lines) = mpsc::channel();
+    let stderr = child
+        .stderr
+        .take()
+        .map(|err| read_lines(err, Stream::Stderr, None, sender));
+    let streamed = child
+        .stdout
+        .take()
+        .map_or(Ok(()), |out| for_each_line(out, on_line));
+    if streamed.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    streamed?;
+    let mut tail = VecDeque::with_capacity(TAIL_LINES);
+    for (stream, line) in lines {
+        if let Some(hook) = HOOK.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+            hook(stream, &line);
+        }
+        if !line.trim().is_empty() {
+            if tail.len() == TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line.trim_end().to_string());
+        }
+    }
+    Ok(RunOutput {
+        status,
+        stdout: Vec::new(),
+        stderr: stderr
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default(),
+        tail: tail.into(),
+    })
+}
+
+/// Hand each line of `reader` to `on_line`, without its newline, through one reused buffer.
+fn for_each_line<R: Read>(
+    reader: R,
+    on_line: &mut dyn FnMut(&[u8]) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        if let Some(hook) = HOOK.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+            hook(Stream::Stdout, &String::from_utf8_lossy(&line));
+        }
+        on_line(&line)?;
+    }
+}
+
+/// Read `reader` on a background thread, copying it to `terminal` if given and sending each
+/// complete line to `lines`. Returns everything that was read.
+fn read_lines<R: Read + Send + 'static>(
+    mut reader: R,
+    stream: Stream,
+    mut terminal: Option<Box<dyn Write + Send>>,
+    lines: Sender<(Stream, String)>,
+) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        let mut all = Vec::new();
+        let mut line_start = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if let Some(terminal) = terminal.as_mut() {
+                let _ = terminal.write_all(&buf[..n]);
+                let _ = terminal.flush();
+            }
+            all.extend_from_slice(&buf[..n]);
+            while let Some(end) = all[line_start..].iter().position(|&byte| byte == b'\n') {
+                let line = String::from_utf8_lossy(&all[line_start..line_start + end]);
+                let _ = lines.send((stream, line.into_owned()));
+                line_start += end + 1;
+            }
+        }
+        if line_start < all.len() {
+            let line = String::from_utf8_lossy(&all[line_start..]).into_owned();
+            let _ = lines.send((stream, line));
+        }
+        all
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of both streams reach the callback as they are written and are captured in full.
+    #[test]
+    fn streams_and_captures_lines() {
+        let mut seen = Vec::new();
+        let output = run_command(
+            Command::new("sh").args(["-c", "echo one; echo oops >&2; printf two"]),
+            false,
+            &mut |stream, line| seen.push((stream, line.to_string())),
+        )
+        .expect("sh should run");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"one\ntwo");
+        assert_eq!(output.stderr, b"oops\n");
+        assert_eq!(output.stdout_lines(), vec!["one", "two"]);
+        seen.sort_by_key(|(stream, _)| *stream == Stream::Stderr);
+        assert_eq!(
+            seen,
+            vec![
+                (Stream::Stdout, "one".to_string()),
+                (Stream::Stdout, "two".to_string()),
+                (Stream::Stderr, "oops".to_string()),
+            ]
+        );
+        assert_eq!(output.tail.len(), 3);
+    }
+
+    /// Streamed stdout reaches the callback line by line without being kept, and an error
+    /// from the callback stops the command.
+    #[test]
+    fn streams_stdout_without_keeping_it() {
+        let mut seen = Vec::new();
+        let output = stream_stdout(
+            Command::new("sh").args(["-c", "echo one; echo oops >&2; printf two"]),
+            &mut |line| {
+                seen.push(String::from_utf8_lossy(line).into_owned());
+                Ok(())
+            },
+        )
+        .expect("sh should run");
+        assert!(output.status.success());
+        assert_eq!(seen, vec!["one", "two"]);
+        assert!(output.stdout.is_empty());
+        assert_eq!(output.tail, vec!["oops"]);
+
+        let err = stream_stdout(&mut Command::new("yes"), &mut |_| {
+            Err(std::io::Error::other("full"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "full");
+    }
+
+    /// With stdout sent to stderr, nothing the commands print reaches stdout, so it holds only
+    /// the JSON. The test runs itself in a child process to see the real stdout.
+    #[test]
+    fn keeps_stdout_for_json() {
+        if std::env::var_os("NIXMAN_TEST_JSON_CHILD").is_some() {
+            set_stdout_to_stderr(true);
+            run_command(
+                Command::new("sh").args(["-c", "echo echoed"]),
+                true,
+                &mut |_, _| {},
+            )
+            .expect("sh should run");
+            status(Command::new("sh").args(["-c", "echo inherited"])).expect("sh should run");
+            println!("{{\"success\":true}}");
+            return;
+        }
+        let output = Command::new(std::env::current_exe().expect("test binary"))
+            .args([
+                "run::tests::keeps_stdout_for_json",
+                "--exact",
+                "--nocapture",
+            ])
+            .env("NIXMAN_TEST_JSON_CHILD", "1")
+            .output()
+            .expect("test binary should run");
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stdout.contains("{\"success\":true}"));
+        assert!(!stdout.contains("echoed") && !stdout.contains("inherited"));
+        assert!(stderr.contains("echoed") && stderr.contains("inherited"));
+    }
+}
@@ -0,0 +1,134 @@
+//! Refusing to remove critical packages
+//!
+//! # Overview
+//!
+//! `apply` removes every explicit package that is not in the YAML, so deleting the wrong line
+//! could uninstall the kernel or `systemd`. Before removing anything, `apply` checks the removals
+//! against the critical packages and aborts unless `--force` is given.
+//!
+//! The critical packages are:
+//!
+//! - [`DEFAULT_CRITICAL`],
+//! - anything listed in the YAML's `critical:` section,
+//! - and everything those packages depend on, according to `pactree` (from `pacman-contrib`;
+//!   skipped when it is not installed).
+
+use crate::{ApplyError, Config};
+use std::collections::BTreeSet;
+use std::process::Command;
+
+/// Packages that are always critical.
+pub const DEFAULT_CRITICAL: &[&str] = &[
+    "base",
+    "linux",
+    "linux-lts",
+    "linux-zen",
+    "linux-hardened",
+    "linux-firmware",
+    "systemd",
+    "glibc",
+    "pacman",
+    "sudo",
+];
+
+/// The dependency tree of `package`, including itself (`pactree -lu`), in the database set
+/// with [`crate::pacman::set_paths`] if any.
+///
+/// # Errors
+/// Returns an error if `pactree` could not be executed.
+pub fn dependency_tree(package: &str) -> std::io::Result<Vec<String>> {
+    let mut cmd = Command::new("pactree");
+    let paths = crate::pacman::paths();
+    if !paths.is_default() {
+        cmd.arg("--dbpath").arg(paths.db_path());
+    }
+    let output = crate::run::output(cmd.args(["-lu", package]))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// The critical package names for `config`, with the dependency trees of the installed ones.
+#[must_use]
+pub fn critical_packages(config: &Config) -> BTreeSet<String> {
+    let roots: Vec<String> = DEFAULT_CRITICAL
+        .iter()
+        .map(ToString::to_string)
+        .chain(config.critical.iter().flatten().cloned())
+        .collect();
+    let mut critical: BTreeSet<String> = roots.iter().cloned().collect();
+    for root in &roots {
+        // pactree prints nothing for packages that are not installed.
+        critical.extend(dependency_tree(root).unwrap_or_default());
+    }
+    critical
+}
+
+/// The packages in `to_remove` that are critical.
+#[must_use]
+pub fn blocked_removals(to_remove: &[String], critical: &BTreeSet<String>) -> Vec<String> {
+    to_remove
+        .iter()
+        .filter(|name| critical.contains(*name))
+        .cloned()
+        .collect()
+}
+
+/// Fail if applying the YAML would remove a critical package.
+///
+/// # Errors
+/// Returns an [`ApplyError`] naming the critical packages that would be removed, or if the YAML
+/// or installed packages cannot be read.
+pub fn check_removals<P: AsRef<std::path::Path>>(yml_path: P) -> Result<(), ApplyError> {
+    let config = crate::read_effective_config(&yml_path).map_err(|e| e.to_string())?;
+    let installed = crate::installed_explicit_package_list().map_err(|e| e.to_string())?;
+    let (_, to_remove) =
+        crate::sync_packages_from_yaml(&yml_path, &installed).map_err(|e| e.to_string())?;
+    check_removal_list(&config, &to_remove)
+}
+
+/// Fail if `to_remove`, e.g. the pacman removals of a [plan](crate::plan), holds a critical
+/// package of `config`.
+///
+/// # Errors
+/// Returns an [`ApplyError`] naming the critical packages that would be removed.
+pub fn check_removal_list(config: &Config, to_remove: &[String]) -> Result<(), ApplyError> {
+    let blocked = blocked_removals(to_remove, &critical_packages(config));
+    if blocked.is_empty() {
+        return Ok(());
+    }
+    Err(ApplyError {
+        message: format!(
+            "Refusing to remove critical packages: {}\nAdd them to the YAML, or use --force to remove them anyway.",
+            blocked.join(", ")
+        ),
+        failed_removals: blocked,
+        ..ApplyError::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the critical packages of a removal set are blocked.
+    #[test]
+    fn blocks_critical_removals() {
+        let critical: BTreeSet<String> = ["base", "systemd", "util-linux"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let to_remove = vec![
+            "htop".to_string(),
+            "systemd".to_string(),
+            "util-linux".to_string(),
+        ];
+        assert_eq!(
+            blocked_removals(&to_remove, &critical),
+            vec!["systemd", "util-linux"]
+        );
+        assert!(blocked_removals(&["htop".to_string()], &critical).is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+//! Versions of the YAML format and migrations between them
+//!
+//! # Overview
+//!
+//! The YAML records the format it is written in with a top-level `schema:` field, which every
+//! writer sets to [`CURRENT`]. Files of an older schema are still read: they are upgraded in
+//! memory, one [`Migration`] at a time, before they are parsed, so a format change never
+//! breaks existing files. `nixman migrate` writes the upgraded file back.
+//!
+//! | Schema | Format |
+//! |--------|--------|
+//! | 1 | Any file without `schema:`, written before the field existed |
+//! | 2 | Schema 1 with `schema: 2` |
+//!
+//! A file of a schema newer than [`CURRENT`] is refused instead of being misread.
+//!
+//! A format change adds a step to [`MIGRATIONS`] that rewrites the parsed YAML of the previous
+//! schema into the new one, and bumps [`CURRENT`].
+//!
+//! Every file nixman reads is parsed with [`from_str`], which reports YAML the parser chokes on
+//! as an error instead of panicking.
+
+use crate::Config;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_yml::{Mapping, Value};
+use std::io;
+use std::path::Path;
+
+/// The schema nixman writes.
+pub const CURRENT: u32 = 2;
+
+/// The schema of files without a `schema:` field.
+pub const UNVERSIONED: u32 = 1;
+
+/// One step of upgrading the YAML, from schema `from` to `from + 1`.
+pub struct Migration {
+    pub from: u32,
+    /// What the step changes, as shown by `nixman migrate`.
+    pub description: &'static str,
+    /// Rewrite the top-level mapping of a file in schema `from`.
+    pub apply: fn(&mut Mapping),
+}
+
+/// The migrations, in order.
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "record the schema in the file",
+    apply: |_| {},
+}];
+
+/// Deserialize YAML `content`, like `serde_yml::from_str`.
+///
+/// libyml, the parser behind `serde_yml`, panics on some malformed input instead of reporting
+/// it; such a panic is returned as an error.
+///
+/// # Errors
+/// Returns an error if `content` is not valid YAML for `T`.
+pub fn from_str<T: DeserializeOwned>(content: &str) -> Result<T, serde_yml::Error> {
+    std::panic::catch_unwind(|| serde_yml::from_str(content)).unwrap_or_else(|_| {
+        Err(serde::de::Error::custom(
+            "the YAML is malformed and could not be parsed",
+        ))
+    })
+}
+
+/// Just the `schema:` field of a file; the rest is ignored.
+#[derive(Default, Deserialize)]
+struct Header {
+    #[serde(default)]
+    schema: Option<u32>,
+}
+
+/// The schema of YAML `content`, or [`UNVERSIONED`] if it has none. Content that cannot be
+/// parsed counts as current, so the error is reported when it is read as a [`Config`].
+#[must_use]
+pub fn version(content: &str) -> u32 {
+    from_str::<Header>(content)
+        .ok()
+        .map_or(CURRENT, |header| header.schema.unwrap_or(UNVERSIONED))
+}
+
+/// Whether `content` has a top-level `schema:` line naming [`CURRENT`], found without parsing
+/// it.
+fn declares_current(content: &str) -> bool {
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix("schema:"))
+        .any(|value| value.split('#').next().unwrap_or("").trim().parse() == Ok(CURRENT))
+}
+
+/// The migrations that upgrade a file in schema `from` to [`CURRENT`].
+#[must_use]
+pub fn pending(from: u32) -> Vec<&'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(|migration| migration.from >= from)
+        .collect()
+}
+
+/// Upgrade the top-level mapping of a file in schema `from` to [`CURRENT`]. Returns the
+/// migrations that were applied.
+///
+/// # Errors
+/// Returns an error if `from` is newer than [`CURRENT`].
+pub fn migrate(mapping: &mut Mapping, from: u32) -> io::Result<Vec<&'static Migration>> {
+    if from > CURRENT {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the YAML is in schema {from}, but this nixman only reads up to schema {CURRENT}; \
+                 upgrade nixman"
+            ),
+        ));
+    }
+    let applied = pending(from);
+    for migration in &applied {
+        (migration.apply)(mapping);
+    }
+    mapping.insert(Value::from("schema"), Value::from(CURRENT));
+    Ok(applied)
+}
+
+/// Parse YAML `content` of any known schema into a [`Config`] of the current one.
+///
+/// A migrated file that does not parse is parsed once more as it is written, so the error
+/// keeps its position in the file where the old format allows it.
+///
+/// # Errors
+/// Returns an error if the content is not a valid config, or its schema is newer than
+/// [`CURRENT`].
+pub fn parse(content: &str) -> io::Result<Config> {
+    let invalid = |e: serde_yml::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+    // Current files, the common case, are parsed once rather than once more for the version.
+    if declares_current(content)
+        && let Ok(config) = from_str::<Config>(content)
+        && config.schema == Some(CURRENT)
+    {
+        return Ok(config);
+    }
+    let from = version(content);
+    if from == CURRENT {
+        return from_str(content).map_err(invalid);
+    }
+    let mut mapping: Mapping = from_str(content).map_err(invalid)?;
+    migrate(&mut mapping, from)?;
+    serde_yml::from_value(Value::Mapping(mapping)).map_err(|e| {
+        from_str::<Config>(content)
+            .err()
+            .filter(|located| located.location().is_some())
+            .map_or_else(|| invalid(e), invalid)
+    })
+}
+
+/// Upgrade the YAML at `path` to [`CURRENT`] and write it back. Returns the schema it was in
+/// and the migrations that were applied, which are none if it was current already.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, parsed, or written.
+pub fn migrate_file(path: &Path) -> io::Result<(u32, Vec<&'static Migration>)> {
+    let content = std::fs::read_to_string(path)?;
+    let from = version(&content);
+    if from == CURRENT {
+        return Ok((from, Vec::new()));
+    }
+    let config = parse(&content)?;
+    crate::write_config(&config, path)?;
+    Ok((from, pending(from)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unversioned files are read and upgraded; files of a newer schema are refused.
+    #[test]
+    fn reads_every_schema() {
+        let old = "packages:\n  - git\n  - name: vim\n    version: 9.1.0330-1\n";
+        assert_eq!(version(old), UNVERSIONED);
+        let config = parse(old).unwrap();
+        assert_eq!(config.schema, Some(CURRENT));
+        assert_eq!(config.packages.packages.len(), 2);
+
+        let current = format!("schema: {CURRENT}\npackages:\n  - git\n");
+        assert_eq!(version(&current), CURRENT);
+        assert_eq!(parse(&current).unwrap().schema, Some(CURRENT));
+
+        let newer = format!("schema: {}\npackages:\n  - git\n", CURRENT + 1);
+        let err = parse(&newer).unwrap_err();
+        assert!(err.to_string().contains("upgrade nixman"));
+
+        assert_eq!(pending(UNVERSIONED).len(), (CURRENT - UNVERSIONED) as usize);
+        assert!(pending(CURRENT).is_empty());
+    }
+
+    /// Pieces of YAML, nixman's keys, and odd characters that random files are made of.
+    const FRAGMENTS: &[&str] = &[
+        "packages:",
+        "vars:",
+        "settings:",
+        "when:",
+        "name: ",
+        "version: ",
+        "one-of: ",
+        "\n",
+        "  ",
+        "- ",
+        "\t",
+        "[",
+        "]",
+        "{",
+        "}",
+        ",",
+        ":",
+        "?",
+        "&a ",
+        "*a",
+        "!!str ",
+        "\"",
+        "#",
+        "|",
+        "${",
+        "1:",
+        "-1",
+        "99999999999999999999",
+        "git",
+        "日",
+    ];
+
+    proptest::proptest! {
+        /// Whatever the YAML, reading it never panics; configs that parse can be written back.
+        #[test]
+        fn arbitrary_yaml_never_panics(
+            fragments in proptest::collection::vec(proptest::sample::select(FRAGMENTS), 0..48)
+        ) {
+            let content = fragments.concat();
+            if let Ok(mut config) = parse(&content) {
+                let _ = crate::vars::expand(&mut config);
+                proptest::prop_assert!(serde_yml::to_string(&config).is_ok());
+            }
+            let _ = crate::local::LocalConfig::parse(&content);
+            let _ = crate::strict::unknown_keys(&content);
+            let _ = crate::import::parse_ansible(&content);
+        }
+    }
+
+    /// Input libyml panics on, and nesting too deep to follow, are errors.
+    #[test]
+    fn malformed_yaml_is_an_error() {
+        let panics = "ny:,<         \t  h";
+        assert!(parse(panics).is_err());
+        assert!(crate::strict::unknown_keys(panics).is_err());
+        assert!(crate::local::LocalConfig::parse(panics).is_err());
+        let nested = "[".repeat(10_000);
+        assert!(parse(&nested).is_err());
+        assert!(crate::strict::unknown_keys(&nested).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Default flags and behavior settings
+//!
+//! # Overview
+//!
+//! Flags that are passed on every run can be set once instead, either in a `settings:` section
+//! of `packages.yml` or in `~/.config/nixman/config.yml` (the whole file is the settings):
+//!
+//! ```yaml
+//! settings:
+//!   helper: paru          # pacman (default) or paru
+//!   elevation: doas       # sudo, doas, run0, or none (default: none as root, else the first one installed)
+//!   noconfirm: true       # --noconfirm
+//!   needed: false         # reinstall packages that are already up to date (default: true)
+//!   versioned: true       # freeze --versioned
+//!   origins: true         # freeze --origin
+//!   continue_on_error: true
+//!   stale_sync: upgrade   # warn (default), upgrade, or off; see [`crate::syncdb`]
+//!   max_sync_age: 2d      # when the sync databases count as stale (default: 1d)
+//!   keyring_check: refresh  # ask (default), refresh, or off; see [`crate::keyring`]
+//!   cache_keep: 2         # versions of each package `clean` keeps (default: 3)
+//!   pre_apply_snapshot: snapper  # or timeshift; see [`crate::snapshot`]
+//!   retries: 3            # retry installs that hit network errors; see [`crate::retry`]
+//!   retry_delay: 30s      # before the first retry, doubling each time (default: 10s)
+//!   aur_jobs: 4           # AUR packages built at once with paru; see [`crate::builds`]
+//!   sort: sensitive       # how written YAML is ordered: insensitive (default), sensitive, or none
+//!   notify: all           # desktop notifications: drift (default), all, or off; see [`crate::notify`]
+//!   notify_after: 1m      # with `all`, announce applies and updates that take this long (default: 30s)
+//!   base: /etc/nixman/base.yml  # a base manifest merged under the YAML; see [`crate::base`]
+//!   base_key: /etc/nixman/minisign.pub  # verifies the base's signature
+//!   pacman_path: powerpill  # run instead of pacman; see [`crate::pacman::Programs`]
+//!   paru_path: /opt/paru/bin/paru
+//!   extra_args: ["--color=always"]  # added to installs, removals, and upgrades
+//!   ignore:               # added to every YAML's ignore: section
+//!     - steam
+//! ```
+//!
+//! Command-line flags always win. Otherwise `packages.yml` takes precedence over `config.yml`,
+//! key by key, and unset keys keep nixman's defaults.
+
+use crate::SortOrder;
+use crate::keyring::KeyringPolicy;
+use crate::notify::{self, NotifyOn, NotifyPolicy};
+use crate::pacman::{Elevation, Programs};
+use crate::retry::{self, RetryPolicy};
+use crate::snapshot::SnapshotTool;
+use crate::syncdb::{self, StalePolicy};
+use crate::watch::parse_interval;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Which program installs and removes pacman packages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Pacman,
+    /// paru, which also builds AUR packages.
+    Paru,
+}
+
+impl Backend {
+    /// The program this backend runs.
+    #[must_use]
+    pub const fn program(self) -> &'static str {
+        match self {
+            Self::Pacman => "pacman",
+            Self::Paru => "paru",
+        }
+    }
+
+    /// The program run for this backend, which the `pacman_path` and `paru_path` settings
+    /// replace (see [`crate::pacman::set_programs`]).
+    #[must_use]
+    pub fn configured(self, programs: &Programs) -> Option<&Path> {
+        match self {
+            Self::Pacman => programs.pacman.as_deref(),
+            Self::Paru => programs.paru.as_deref(),
+        }
+    }
+
+    /// Whether the program is installed: on `PATH`, or where the settings point.
+    #[must_use]
+    pub fn available(self) -> bool {
+        let programs = crate::pacman::programs();
+        self.configured(&programs).map_or_else(
+            || crate::pacman::on_path(self.program()),
+            |program| crate::pacman::installed(program.as_os_str()),
+        )
+    }
+
+    /// Check that the program is installed, with an error that says what to do if not.
+    ///
+    /// # Errors
+    /// Returns a [`NotFound`](std::io::ErrorKind::NotFound) error if it is not on `PATH`, or
+    /// the program the settings name does not exist.
+    pub fn require(self) -> std::io::Result<()> {
+        if self.available() {
+            return Ok(());
+        }
+        if let Some(program) = self.configured(&crate::pacman::programs()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "{} (the {}_path setting) not found",
+                    program.display(),
+                    self.program()
+                ),
+            ));
+        }
+        let message = match self {
+            Self::Pacman => {
+                "pacman not found on PATH; nixman manages Arch Linux systems and needs pacman"
+            }
+            Self::Paru => {
+                "paru not found on PATH; install it, or drop --paru (and `helper: paru` from the \
+                 settings) to use pacman"
+            }
+        };
+        Err(std::io::Error::new(std::io::ErrorKind::NotFound, message))
+    }
+}
+
+/// Defaults for command-line flags; `None` means unset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub helper: Option<Backend>,
+    /// How to run privileged commands.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<Elevation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noconfirm: Option<bool>,
+    /// Whether installs pass `--needed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub needed: Option<bool>,
+    /// Whether `freeze` records versions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub versioned: Option<bool>,
+    /// Whether `freeze` records each package's repository.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origins: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continue_on_error: Option<bool>,
+    /// What to do before installing when the sync databases are stale.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_sync: Option<StalePolicy>,
+    /// An interval such as `1d` or `12h` after which the sync databases count as stale.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sync_age: Option<String>,
+    /// What to do before installing when pacman's keyring has problems.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyring_check: Option<KeyringPolicy>,
+    /// How many versions of each package `clean` keeps in the package caches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_keep: Option<usize>,
+    /// Take a filesystem snapshot with this tool before applying.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_apply_snapshot: Option<SnapshotTool>,
+    /// How often `apply` retries installs that failed because of the network.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// An interval such as `30s` before the first retry; it doubles with each retry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<String>,
+    /// How many AUR packages `apply` builds at once when it uses paru.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aur_jobs: Option<usize>,
+    /// How packages are ordered when nixman writes a YAML.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortOrder>,
+    /// Which events send a desktop notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<NotifyOn>,
+    /// An interval such as `1m`; shorter applies and updates finish without a notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_after: Option<String>,
+    /// A base manifest merged under the YAML, relative to the YAML's directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base: Option<PathBuf>,
+    /// The minisign public key or SSH `allowed_signers` file that verifies the base.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_key: Option<PathBuf>,
+    /// The program run instead of `pacman`, e.g. `powerpill` or an absolute path.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pacman_path: Option<PathBuf>,
+    /// The program run instead of `paru`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paru_path: Option<PathBuf>,
+    /// Options added to every pacman and paru command that changes packages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_args: Option<Vec<String>>,
+    /// Packages ignored by `apply` in addition to the YAML's `ignore:` section.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+}
+
+impl Settings {
+    /// These settings, with unset keys taken from `fallback`; ignore lists are combined.
+    #[must_use]
+    pub fn or(self, fallback: Self) -> Self {
+        let mut ignore = self.ignore;
+        for name in fallback.ignore {
+            if !ignore.contains(&name) {
+                ignore.push(name);
+            }
+        }
+        Self {
+            helper: self.helper.or(fallback.helper),
+            elevation: self.elevation.or(fallback.elevation),
+            noconfirm: self.noconfirm.or(fallback.noconfirm),
+            needed: self.needed.or(fallback.needed),
+            versioned: self.versioned.or(fallback.versioned),
+            origins: self.origins.or(fallback.origins),
+            continue_on_error: self.continue_on_error.or(fallback.continue_on_error),
+            stale_sync: self.stale_sync.or(fallback.stale_sync),
+            max_sync_age: self.max_sync_age.or(fallback.max_sync_age),
+            keyring_check: self.keyring_check.or(fallback.keyring_check),
+            cache_keep: self.cache_keep.or(fallback.cache_keep),
+            pre_apply_snapshot: self.pre_apply_snapshot.or(fallback.pre_apply_snapshot),
+            retries: self.retries.or(fallback.retries),
+            retry_delay: self.retry_delay.or(fallback.retry_delay),
+            aur_jobs: self.aur_jobs.or(fallback.aur_jobs),
+            sort: self.sort.or(fallback.sort),
+            notify: self.notify.or(fallback.notify),
+            notify_after: self.notify_after.or(fallback.notify_after),
+            base: self.base.or(fallback.base),
+            base_key: self.base_key.or(fallback.base_key),
+            pacman_path: self.pacman_path.or(fallback.pacman_path),
+            paru_path: self.paru_path.or(fallback.paru_path),
+            extra_args: self.extra_args.or(fallback.extra_args),
+            ignore,
+        }
+    }
+
+    /// Whether paru should be used instead of pacman.
+    #[must_use]
+    pub fn uses_paru(&self) -> bool {
+        self.helper == Some(Backend::Paru)
+    }
+
+    /// The `pacman_path`, `paru_path`, and `extra_args` settings.
+    #[must_use]
+    pub fn programs(&self) -> Programs {
+        Programs {
+            pacman: self.pacman_path.clone(),
+            paru: self.paru_path.clone(),
+            extra_args: self.extra_args.clone().unwrap_or_default(),
+        }
+    }
+
+    /// The `max_sync_age` setting, or [`syncdb::DEFAULT_MAX_AGE`].
+    ///
+    /// # Errors
+    /// Returns a message if the interval is malformed.
+    pub fn max_sync_age(&self) -> Result<Duration, String> {
+        self.max_sync_age
+            .as_deref()
+            .map_or(Ok(syncdb::DEFAULT_MAX_AGE), parse_interval)
+    }
+
+    /// The `retries` and `retry_delay` settings (default: no retries, [`retry::DEFAULT_DELAY`]).
+    ///
+    /// # Errors
+    /// Returns a message if the delay is malformed.
+    pub fn retry_policy(&self) -> Result<RetryPolicy, String> {
+        Ok(RetryPolicy {
+            retries: self.retries.unwrap_or(0),
+            delay: self
+                .retry_delay
+                .as_deref()
+                .map_or(Ok(retry::DEFAULT_DELAY), parse_interval)?,
+        })
+    }
+
+    /// The `notify` and `notify_after` settings (default: drift only, [`notify::DEFAULT_AFTER`]).
+    ///
+    /// # Errors
+    /// Returns a message if the interval is malformed.
+    pub fn notify_policy(&self) -> Result<NotifyPolicy, String> {
+        Ok(NotifyPolicy {
+            on: self.notify.unwrap_or_default(),
+            after: self
+                .notify_after
+                .as_deref()
+                .map_or(Ok(notify::DEFAULT_AFTER), parse_interval)?,
+        })
+    }
+
+    /// Read the settings file at `path`; a missing or empty file means no settings.
+    ///
+    /// # Errors
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        if content.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        crate::schema::from_str(&content).map_err(|e| {
+            crate::diagnostics::locate(
+                path,
+                &content,
+                std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+            )
+        })
+    }
+}
+
+/// The global settings file (`$XDG_CONFIG_HOME/nixman/config.yml`).
+#[must_use]
+pub fn global_path() -> PathBuf {
+    crate::paths::settings_file()
+}
+
+/// The settings in effect for the YAML at `yml_path`: its `settings:` section on top of the
+/// global settings file.
+///
+/// # Errors
+/// Returns an error if either file cannot be read or parsed. An empty YAML has no settings.
+pub fn resolve<P: AsRef<Path>>(yml_path: P) -> std::io::Result<Settings> {
+    let global = Settings::load(&global_path())?;
+    let content = std::fs::read_to_string(&yml_path)?;
+    if content.trim().is_empty() {
+        return Ok(global);
+    }
+    let config = crate::schema::parse(&content)
+        .map_err(|e| crate::diagnostics::locate(yml_path.as_ref(), &content, e))?;
+    Ok(config.settings.unwrap_or_default().or(global))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keys set in the YAML win over the global file, and ignore lists are combined.
+    #[test]
+    fn yaml_settings_take_precedence() {
+        let yaml: Settings =
+            serde_yml::from_str("helper: paru\nignore:\n  - steam\n").expect("valid settings");
+        let global: Settings =
+            serde_yml::from_str("helper: pacman\nnoconfirm: true\nignore:\n  - steam\n  - zoom\n")
+                .expect("valid settings");
+        let settings = yaml.or(global);
+        assert!(settings.uses_paru());
+        assert_eq!(settings.noconfirm, Some(true));
+        assert_eq!(settings.programs(), Programs::default());
+        assert_eq!(settings.versioned, None);
+        assert_eq!(settings.ignore, vec!["steam", "zoom"]);
+        assert!(serde_yml::from_str::<Settings>("helper: yay\n").is_err());
+    }
+}
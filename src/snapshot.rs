@@ -0,0 +1,123 @@
+//! Filesystem snapshots before apply
+//!
+//! # Overview
+//!
+//! With `pre_apply_snapshot: snapper` (or `timeshift`) in the [settings](crate::settings),
+//! `nixman apply` takes a filesystem snapshot before installing or removing anything and
+//! records its ID in the [generation log](crate::generations). If an apply breaks the system,
+//! the snapshot restores the files as well as the package set:
+//!
+//! ```sh
+//! sudo snapper rollback 42
+//! sudo timeshift --restore --snapshot '2024-05-01_10-00-00'
+//! ```
+//!
+//! If the snapshot cannot be created, the apply is aborted.
+
+#[cfg(target_os = "linux")]
+use crate::pacman;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The program that takes snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotTool {
+    /// `snapper create`, for Btrfs (or LVM thin) snapper configurations.
+    Snapper,
+    /// `timeshift --create`, in whichever mode timeshift is configured for.
+    Timeshift,
+}
+
+impl fmt::Display for SnapshotTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Snapper => "snapper",
+            Self::Timeshift => "timeshift",
+        })
+    }
+}
+
+/// A snapshot taken before an apply.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub tool: SnapshotTool,
+    /// The snapper number or timeshift snapshot name.
+    pub id: String,
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} snapshot {}", self.tool, self.id)
+    }
+}
+
+/// The snapshot name in timeshift's output (`Tagged snapshot '2024-05-01_10-00-00': ondemand`).
+#[must_use]
+pub fn parse_timeshift_output(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let rest = &line[line.find("Tagged snapshot '")? + "Tagged snapshot '".len()..];
+        Some(rest[..rest.find('\'')?].to_string())
+    })
+}
+
+/// Take a snapshot described as `description`, through the configured elevation tool.
+///
+/// # Errors
+/// Returns an error if the tool cannot be run, fails, or does not report the snapshot's ID.
+#[cfg(target_os = "linux")]
+pub fn create(tool: SnapshotTool, description: &str) -> std::io::Result<Snapshot> {
+    let mut command = pacman::elevated(tool.to_string());
+    match tool {
+        SnapshotTool::Snapper => command.args([
+            "create",
+            "--type",
+            "single",
+            "--cleanup-algorithm",
+            "number",
+            "--print-number",
+            "--description",
+            description,
+        ]),
+        SnapshotTool::Timeshift => {
+            command.args(["--create", "--scripted", "--comments", description])
+        }
+    };
+    let output = crate::run::output(&mut command)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "{tool} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let id = match tool {
+        SnapshotTool::Snapper => Some(stdout.trim().to_string()).filter(|id| !id.is_empty()),
+        SnapshotTool::Timeshift => parse_timeshift_output(&stdout),
+    };
+    id.map(|id| Snapshot { tool, id }).ok_or_else(|| {
+        std::io::Error::other(format!("{tool} did not report the snapshot it created"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The snapshot name is taken from timeshift's tagging message.
+    #[test]
+    fn parses_timeshift_snapshot_name() {
+        let output = "Using system disk as snapshot device for creating snapshots in RSYNC mode\n\
+                      Creating new snapshot...(RSYNC)\n\
+                      Saving to device: /dev/sda2, mounted at path: /run/timeshift/backup\n\
+                      RSYNC Snapshot saved successfully (12s)\n\
+                      Tagged snapshot '2024-05-01_10-00-00': ondemand\n";
+        assert_eq!(
+            parse_timeshift_output(output).as_deref(),
+            Some("2024-05-01_10-00-00")
+        );
+        assert_eq!(parse_timeshift_output("Error: no space left\n"), None);
+        let setting: SnapshotTool = serde_yml::from_str("snapper").unwrap();
+        assert_eq!(setting, SnapshotTool::Snapper);
+    }
+}
@@ -0,0 +1,238 @@
+//! Extra package sources tracked alongside pacman packages
+//!
+//! # Overview
+//!
+//! Besides system packages, users often install software through other package managers:
+//! Flatpak applications, `cargo install` binaries, `pipx` applications, and global `npm` packages.
+//! Each of these is an [`ExtraSource`] with its own section in the YAML:
+//!
+//! ```yaml
+//! packages:
+//!   - git
+//! cargo:
+//!   - ripgrep
+//! pipx:
+//!   - black
+//! npm:
+//!   - typescript
+//! ```
+//!
+//! Management of a source is opt-in: when its section is absent, nixman leaves it alone.
+//!
+//! # Adding a source
+//!
+//! Implement [`ExtraSource`], add a matching field to [`crate::Config`], and register the source
+//! in [`builtin`].
+
+use crate::flatpak::FlatpakBackend;
+use std::process::{Command, ExitStatus};
+
+/// A package manager whose installed packages can be listed, installed, and removed by name.
+pub trait ExtraSource {
+    /// The YAML section key for this source, e.g. `"cargo"`.
+    fn name(&self) -> &'static str;
+
+    /// Lists the names of installed packages.
+    ///
+    /// # Errors
+    /// Returns an error if the underlying command could not be executed or failed.
+    fn list(&self) -> std::io::Result<Vec<String>>;
+
+    /// Installs the given packages; with `noconfirm`, without asking any questions.
+    ///
+    /// # Errors
+    /// Returns an error if the underlying command could not be executed.
+    fn install(&self, names: &[String], noconfirm: bool) -> std::io::Result<ExitStatus>;
+
+    /// Removes the given packages; with `noconfirm`, without asking any questions.
+    ///
+    /// # Errors
+    /// Returns an error if the underlying command could not be executed.
+    fn remove(&self, names: &[String], noconfirm: bool) -> std::io::Result<ExitStatus>;
+}
+
+/// All built-in extra sources, in the order they are applied.
+#[must_use]
+pub fn builtin() -> Vec<Box<dyn ExtraSource>> {
+    vec![
+        Box::new(FlatpakBackend),
+        Box::new(CargoSource),
+        Box::new(PipxSource),
+        Box::new(NpmSource),
+    ]
+}
+
+/// Looks up a built-in source by its YAML section key.
+#[must_use]
+pub fn by_name(name: &str) -> Option<Box<dyn ExtraSource>> {
+    builtin().into_iter().find(|source| source.name() == name)
+}
+
+/// Run a listing command and return its stdout, turning a non-zero exit into an error.
+fn list_output(cmd: &mut Command) -> std::io::Result<String> {
+    let output = crate::run::output(cmd)?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "{} failed: {}",
+            cmd.get_program().display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl ExtraSource for FlatpakBackend {
+    fn name(&self) -> &'static str {
+        "flatpak"
+    }
+
+    fn list(&self) -> std::io::Result<Vec<String>> {
+        Self::list(*self)
+    }
+
+    fn install(&self, names: &[String], noconfirm: bool) -> std::io::Result<ExitStatus> {
+        Self::install(*self, names, noconfirm)
+    }
+
+    fn remove(&self, names: &[String], noconfirm: bool) -> std::io::Result<ExitStatus> {
+        Self::remove(*self, names, noconfirm)
+    }
+}
+
+/// Binaries installed with `cargo install`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CargoSource;
+
+impl ExtraSource for CargoSource {
+    fn name(&self) -> &'static str {
+        "cargo"
+    }
+
+    fn list(&self) -> std::io::Result<Vec<String>> {
+        list_output(Command::new("cargo").args(["install", "--list"]))
+            .map(|output| parse_cargo_list(&output))
+    }
+
+    // cargo, pipx, and npm never prompt, so `noconfirm` changes nothing for them.
+    fn install(&self, names: &[String], _noconfirm: bool) -> std::io::Result<ExitStatus> {
+        crate::run::status(Command::new("cargo").arg("install").args(names))
+    }
+
+    fn remove(&self, names: &[String], _noconfirm: bool) -> std::io::Result<ExitStatus> {
+        crate::run::status(Command::new("cargo").arg("uninstall").args(names))
+    }
+}
+
+/// Python applications installed with `pipx`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipxSource;
+
+impl ExtraSource for PipxSource {
+    fn name(&self) -> &'static str {
+        "pipx"
+    }
+
+    fn list(&self) -> std::io::Result<Vec<String>> {
+        list_output(Command::new("pipx").args(["list", "--short"]))
+            .map(|output| parse_pipx_list(&output))
+    }
+
+    fn install(&self, names: &[String], _noconfirm: bool) -> std::io::Result<ExitStatus> {
+        crate::run::status(Command::new("pipx").arg("install").args(names))
+    }
+
+    fn remove(&self, names: &[String], _noconfirm: bool) -> std::io::Result<ExitStatus> {
+        // `pipx uninstall` only accepts a single package per invocation.
+        for name in names {
+            let status = crate::run::status(Command::new("pipx").arg("uninstall").arg(name))?;
+            if !status.success() {
+                return Ok(status);
+            }
+        }
+        Ok(ExitStatus::default())
+    }
+}
+
+/// Global packages installed with `npm install -g`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NpmSource;
+
+impl ExtraSource for NpmSource {
+    fn name(&self) -> &'static str {
+        "npm"
+    }
+
+    fn list(&self) -> std::io::Result<Vec<String>> {
+        list_output(Command::new("npm").args(["ls", "-g", "--depth=0", "--parseable"]))
+            .map(|output| parse_npm_list(&output))
+    }
+
+    fn install(&self, names: &[String], _noconfirm: bool) -> std::io::Result<ExitStatus> {
+        crate::run::status(Command::new("npm").args(["install", "-g"]).args(names))
+    }
+
+    fn remove(&self, names: &[String], _noconfirm: bool) -> std::io::Result<ExitStatus> {
+        crate::run::status(Command::new("npm").args(["uninstall", "-g"]).args(names))
+    }
+}
+
+/// Parse `cargo install --list`: unindented `name vX.Y.Z:` lines name a crate, indented lines
+/// list its binaries.
+#[must_use]
+pub fn parse_cargo_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Parse `pipx list --short`: one `name version` pair per line.
+#[must_use]
+pub fn parse_pipx_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Parse `npm ls -g --depth=0 --parseable`: the first line is the global prefix, every other
+/// line is the path of a package inside `node_modules` (scoped packages keep their `@scope/`).
+#[must_use]
+pub fn parse_npm_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.rsplit_once("node_modules/").map(|(_, name)| name))
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests parsing of each provider's listing format.
+    #[test]
+    fn parses_provider_listings() {
+        let cargo = "cargo-edit v0.12.2:\n    cargo-add\n    cargo-rm\nripgrep v14.1.0:\n    rg\n";
+        assert_eq!(parse_cargo_list(cargo), vec!["cargo-edit", "ripgrep"]);
+
+        let pipx = "black 24.1.0\npoetry 1.7.1\n";
+        assert_eq!(parse_pipx_list(pipx), vec!["black", "poetry"]);
+
+        let npm = "/usr/lib\n/usr/lib/node_modules/typescript\n/usr/lib/node_modules/@vue/cli\n";
+        assert_eq!(parse_npm_list(npm), vec!["typescript", "@vue/cli"]);
+    }
+
+    /// Every built-in source is reachable by its section name.
+    #[test]
+    fn builtin_sources_by_name() {
+        for name in ["flatpak", "cargo", "pipx", "npm"] {
+            assert_eq!(by_name(name).map(|source| source.name()), Some(name));
+        }
+        assert!(by_name("brew").is_none());
+    }
+}
@@ -0,0 +1,376 @@
+//! Strict checking of the YAML's keys
+//!
+//! # Overview
+//!
+//! serde skips keys it does not know, so a typo such as `verion:` silently drops a pin.
+//! [`unknown_keys`] walks the YAML and reports every key that nixman does not read, with its
+//! line and column:
+//!
+//! - top-level keys other than the [`Config`](crate::Config) sections,
+//! - keys of `packages:` entries other than `name`, `version`, `reason`, `when`, `origin`, and
+//!   `installed_at` (`group` and `when` for groups, `one-of` and `when` for alternatives),
+//! - keys of `settings:`, `repos:` entries, and `when:` conditions that their types do not
+//!   have.
+//!
+//! `nixman check --strict` fails on them, and [`NixmanBuilder::strict`] refuses to build with
+//! them.
+//!
+//! [`NixmanBuilder::strict`]: crate::manager::NixmanBuilder::strict
+
+use crate::condition::Condition;
+use crate::repos::Repo;
+use crate::settings::Settings;
+use serde::Serialize;
+use serde::de::{self, DeserializeOwned, Visitor};
+use serde_yml::libyml::parser::{Event, Parser};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+
+/// The top-level sections of the YAML.
+pub(crate) const CONFIG_KEYS: &[&str] = &[
+    "schema", "vars", "packages", "flatpak", "cargo", "pipx", "npm", "critical", "ignore",
+    "banned", "settings", "repos",
+];
+
+/// The keys of a package entry.
+pub(crate) const PACKAGE_KEYS: &[&str] = &[
+    "name",
+    "version",
+    "reason",
+    "when",
+    "origin",
+    "installed_at",
+];
+
+/// The keys of a group entry.
+pub(crate) const GROUP_KEYS: &[&str] = &["group", "when"];
+
+/// The keys of an alternatives entry.
+pub(crate) const ALTERNATIVES_KEYS: &[&str] = &["one-of", "when"];
+
+/// A key nixman does not read.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnknownKey {
+    pub key: String,
+    /// Where the key is, e.g. `packages[2]` or `settings`; empty at the top level.
+    pub path: String,
+    /// 1-based line and column of the key.
+    pub line: u64,
+    pub column: u64,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {} column {}: unknown key `",
+            self.line, self.column
+        )?;
+        if !self.path.is_empty() {
+            write!(f, "{}.", self.path)?;
+        }
+        write!(f, "{}`", self.key)
+    }
+}
+
+/// What a mapping in the YAML holds, which decides the keys it may have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Config,
+    /// A package or group entry.
+    Entry,
+    Settings,
+    Repo,
+    Condition,
+    /// Anything nixman does not check.
+    Free,
+}
+
+impl Context {
+    /// What the value of `key` in a mapping of this context holds.
+    fn child(self, key: &str) -> Self {
+        match (self, key) {
+            (Self::Config, "packages") => Self::Entry,
+            (Self::Config, "settings") => Self::Settings,
+            (Self::Config, "repos") => Self::Repo,
+            (Self::Entry, "when") | (Self::Condition, "any") => Self::Condition,
+            _ => Self::Free,
+        }
+    }
+
+    /// The keys a mapping of this context may have, given the keys it has.
+    fn known(self, keys: &[&str]) -> Option<&'static [&'static str]> {
+        match self {
+            Self::Config => Some(CONFIG_KEYS),
+            Self::Entry if keys.contains(&"group") => Some(GROUP_KEYS),
+            Self::Entry if keys.contains(&"one-of") => Some(ALTERNATIVES_KEYS),
+            Self::Entry => Some(PACKAGE_KEYS),
+            Self::Settings => Some(fields_of::<Settings>()),
+            Self::Repo => Some(fields_of::<Repo>()),
+            Self::Condition => Some(fields_of::<Condition>()),
+            Self::Free => None,
+        }
+    }
+}
+
+/// A mapping or sequence being walked.
+enum Frame {
+    Mapping {
+        context: Context,
+        path: String,
+        keys: Vec<(String, u64, u64)>,
+        /// The key whose value comes next, or `None` if a key comes next.
+        key: Option<String>,
+    },
+    Sequence {
+        context: Context,
+        path: String,
+        index: usize,
+    },
+}
+
+impl Frame {
+    /// The context and path of the next value in this frame.
+    fn next_value(&self) -> (Context, String) {
+        match self {
+            Self::Mapping {
+                context, path, key, ..
+            } => {
+                let key = key.as_deref().unwrap_or_default();
+                let path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{path}.{key}")
+                };
+                (context.child(key), path)
+            }
+            Self::Sequence {
+                context,
+                path,
+                index,
+            } => (*context, format!("{path}[{index}]")),
+        }
+    }
+
+    /// Move past a value that ended.
+    fn finish_value(&mut self) {
+        match self {
+            Self::Mapping { key, .. } => *key = None,
+            Self::Sequence { index, .. } => *index += 1,
+        }
+    }
+}
+
+/// How deeply mappings and sequences may nest, as in `serde_yml`; each level keeps its path.
+const MAX_DEPTH: usize = 128;
+
+/// The keys of the YAML `content` that nixman does not read, in the order they appear.
+///
+/// # Errors
+/// Returns an error if the content is not valid YAML.
+pub fn unknown_keys(content: &str) -> std::io::Result<Vec<UnknownKey>> {
+    // libyml panics on some malformed input; see `schema::from_str`.
+    std::panic::catch_unwind(|| scan_keys(content)).unwrap_or_else(|_| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the YAML is malformed and could not be parsed",
+        ))
+    })
+}
+
+/// [`unknown_keys`], as reported by the parser's events.
+fn scan_keys(content: &str) -> std::io::Result<Vec<UnknownKey>> {
+    let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{e}"));
+    let mut parser = Parser::new(Cow::Borrowed(content.as_bytes()));
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut unknown = Vec::new();
+    loop {
+        let (event, mark) = parser.parse_next_event().map_err(invalid)?;
+        if matches!(event, Event::MappingStart(_) | Event::SequenceStart(_))
+            && stack.len() >= MAX_DEPTH
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "line {}: nested more than {MAX_DEPTH} levels deep",
+                    mark.line() + 1
+                ),
+            ));
+        }
+        let (value_context, value_path) = stack
+            .last()
+            .map_or((Context::Config, String::new()), Frame::next_value);
+        match event {
+            Event::StreamEnd | Event::DocumentEnd => break,
+            Event::StreamStart | Event::DocumentStart => {}
+            Event::Scalar(scalar) => match stack.last_mut() {
+                Some(Frame::Mapping { keys, key, .. }) if key.is_none() => {
+                    let name = String::from_utf8_lossy(&scalar.value).into_owned();
+                    keys.push((name.clone(), mark.line() + 1, mark.column() + 1));
+                    *key = Some(name);
+                }
+                Some(frame) => frame.finish_value(),
+                None => {}
+            },
+            Event::Alias(_) => {
+                if let Some(frame) = stack.last_mut() {
+                    frame.finish_value();
+                }
+            }
+            Event::MappingStart(_) => stack.push(Frame::Mapping {
+                context: value_context,
+                path: value_path,
+                keys: Vec::new(),
+                key: None,
+            }),
+            Event::SequenceStart(_) => stack.push(Frame::Sequence {
+                context: value_context,
+                path: value_path,
+                index: 0,
+            }),
+            Event::MappingEnd | Event::SequenceEnd => {
+                if let Some(Frame::Mapping {
+                    context,
+                    path,
+                    keys,
+                    ..
+                }) = stack.pop()
+                {
+                    let names: Vec<&str> = keys.iter().map(|(name, ..)| name.as_str()).collect();
+                    if let Some(known) = context.known(&names) {
+                        unknown.extend(
+                            keys.iter()
+                                .filter(|(name, ..)| !known.contains(&name.as_str()))
+                                .map(|(name, line, column)| UnknownKey {
+                                    key: name.clone(),
+                                    path: path.clone(),
+                                    line: *line,
+                                    column: *column,
+                                }),
+                        );
+                    }
+                }
+                if let Some(frame) = stack.last_mut() {
+                    frame.finish_value();
+                }
+            }
+        }
+    }
+    Ok(unknown)
+}
+
+/// Fail if the YAML at `path` has keys nixman does not read.
+///
+/// # Errors
+/// Returns an error listing the unknown keys, or if the file cannot be read or is not valid
+/// YAML.
+pub fn check(path: &Path) -> std::io::Result<()> {
+    let unknown = unknown_keys(&std::fs::read_to_string(path)?)?;
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let lines: Vec<String> = unknown.iter().map(ToString::to_string).collect();
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), lines.join("; ")),
+    ))
+}
+
+/// The field names of a struct that derives `Deserialize`.
+fn fields_of<T: DeserializeOwned>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// A deserializer that only records the field names a struct asks for.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> de::Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("only the field names are needed"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Typos are found with their position anywhere nixman checks keys, and nowhere else.
+    #[test]
+    fn finds_unknown_keys() {
+        let yaml = "\
+schema: 2
+packages:
+  - git
+  - name: vim
+    verion: 9.1.0330-1
+  - group: base-devel
+    when:
+      hostnme: laptop
+  - name: nvidia-open
+    when:
+      any:
+        - hostname: desktop
+        - arhc: x86_64
+settings:
+  noconfrim: true
+repos:
+  - name: custom
+    server: https://example.com/$arch
+ignroe:
+  - steam
+";
+        let found: Vec<String> = unknown_keys(yaml)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                "line 5 column 5: unknown key `packages[1].verion`",
+                "line 8 column 7: unknown key `packages[2].when.hostnme`",
+                "line 13 column 11: unknown key `packages[3].when.any[1].arhc`",
+                "line 15 column 3: unknown key `settings.noconfrim`",
+                "line 19 column 1: unknown key `ignroe`",
+            ]
+        );
+
+        let mut config = crate::Config::new(crate::PackageList::new(
+            [crate::Package::from("vim 9.1.0330-1")],
+            Vec::new(),
+        ));
+        config.schema = Some(crate::schema::CURRENT);
+        config.flatpak = Some(vec!["org.gimp.GIMP".to_string()]);
+        config.cargo = Some(Vec::new());
+        config.pipx = Some(Vec::new());
+        config.npm = Some(Vec::new());
+        config.critical = Some(Vec::new());
+        config.ignore = Some(Vec::new());
+        config.banned = Some(Vec::new());
+        config.settings = Some(Settings::default());
+        config.repos = Some(Vec::new());
+        let written = serde_yml::to_string(&config).unwrap();
+        assert_eq!(unknown_keys(&written).unwrap(), Vec::new());
+    }
+}
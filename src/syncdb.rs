@@ -0,0 +1,108 @@
+//! Stale sync database detection
+//!
+//! # Overview
+//!
+//! pacman installs packages at the versions listed in its sync databases
+//! (`/var/lib/pacman/sync/*.db`). Once those are a few days old, mirrors have often dropped the
+//! listed versions and installs fail with 404s. Before installing, nixman checks how old the
+//! databases are and, depending on the `stale_sync` setting:
+//!
+//! - `warn` (default): prints a warning,
+//! - `upgrade`: runs a full `pacman -Syu` first. Refreshing the databases with `-Sy` alone
+//!   would leave a partial upgrade, which Arch does not support,
+//! - `off`: does nothing.
+//!
+//! Databases count as stale after `max_sync_age` (default one day).
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Where pacman keeps its sync databases.
+pub const SYNC_DIR: &str = "/var/lib/pacman/sync";
+
+/// The sync database directory of the system nixman manages: [`SYNC_DIR`], or `sync` in the
+/// database directory set with [`crate::pacman::set_paths`].
+#[must_use]
+pub fn sync_dir() -> PathBuf {
+    crate::pacman::paths().db_path().join("sync")
+}
+
+/// How old the sync databases may be before they count as stale.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_hours(24);
+
+/// What to do before installing when the sync databases are stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StalePolicy {
+    #[default]
+    Warn,
+    /// Run a full system upgrade first.
+    Upgrade,
+    Off,
+}
+
+/// The age of the oldest sync database in `dir`, or `None` if there are none.
+///
+/// # Errors
+/// Returns an error if the directory or a database's modification time cannot be read.
+pub fn database_age(dir: &Path) -> std::io::Result<Option<Duration>> {
+    let mut oldest: Option<SystemTime> = None;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "db") {
+            continue;
+        }
+        let modified = std::fs::metadata(&path)?.modified()?;
+        oldest = Some(oldest.map_or(modified, |oldest| oldest.min(modified)));
+    }
+    // A database modified "in the future" (clock skew) counts as fresh.
+    Ok(oldest.map(|oldest| oldest.elapsed().unwrap_or_default()))
+}
+
+/// Whether databases of the given age are older than `max_age`. Missing databases are stale.
+#[must_use]
+pub fn is_stale(age: Option<Duration>, max_age: Duration) -> bool {
+    age.is_none_or(|age| age > max_age)
+}
+
+/// A human-readable age such as `3 days` or `5 hours`.
+#[must_use]
+pub fn describe_age(age: Duration) -> String {
+    let hours = age.as_secs() / 3600;
+    match hours {
+        0..=1 => format!("{} minutes", age.as_secs() / 60),
+        2..=47 => format!("{hours} hours"),
+        _ => format!("{} days", hours / 24),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The oldest `.db` file decides the age; other files are ignored.
+    #[test]
+    fn oldest_database_decides_age() {
+        let dir = std::env::temp_dir().join(format!("nixman-syncdb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(database_age(&dir).unwrap(), None);
+        assert!(is_stale(None, DEFAULT_MAX_AGE));
+
+        let three_days_ago = SystemTime::now() - Duration::from_hours(72);
+        for (name, modified) in [
+            ("core.db", SystemTime::now()),
+            ("extra.db", three_days_ago),
+            ("extra.files", SystemTime::UNIX_EPOCH),
+        ] {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(modified).unwrap();
+        }
+        let age = database_age(&dir).unwrap().expect("databases exist");
+        assert!(age >= Duration::from_hours(72) && age < Duration::from_hours(73));
+        assert!(is_stale(Some(age), DEFAULT_MAX_AGE));
+        assert!(!is_stale(Some(age), Duration::from_hours(96)));
+        assert_eq!(describe_age(age), "3 days");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,371 @@
+//! Normalizing the YAML
+//!
+//! # Overview
+//!
+//! `nixman tidy` rewrites the YAML the way nixman writes it, like `cargo fmt` for the manifest,
+//! and reports each [`Change`] it made:
+//!
+//! - keys written in another case, such as `Packages:` or `Version:`, are renamed; serde would
+//!   otherwise skip them, or refuse a file without `packages:`,
+//! - packages declared more than once are merged (see [`PackageList::insert`]), and repeated
+//!   groups and `one-of` entries removed,
+//! - groups without a name are dropped, and a `one-of` with a single package becomes a plain
+//!   entry for it,
+//! - repeated names in the extra-source, `critical:`, `ignore:`, and `banned:` lists are
+//!   removed,
+//! - entries are sorted by the `sort` setting,
+//! - with [`Versions::Strip`] every pin and requirement is removed, and with [`Versions::Pin`]
+//!   every unpinned package that is installed is pinned to its installed version.
+//!
+//! `${name}` references are kept as written. `nixman tidy --check` only reports the changes.
+
+use crate::strict::{ALTERNATIVES_KEYS, CONFIG_KEYS, GROUP_KEYS, PACKAGE_KEYS};
+use crate::{Config, Package, PackageList, schema};
+use serde_yml::{Mapping, Value};
+use std::fmt;
+use std::path::Path;
+
+/// What `tidy` does with package versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Versions {
+    /// Leave them as they are.
+    #[default]
+    Keep,
+    /// Remove every pin and requirement.
+    Strip,
+    /// Pin unpinned packages to the installed version.
+    Pin,
+}
+
+/// One thing `tidy` changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A key written in another case was renamed.
+    RenamedKey { from: String, to: String },
+    /// The entries of a package declared more than once were merged.
+    MergedDuplicate(String),
+    /// Repeated group and `one-of` entries were removed.
+    RemovedRepeats(usize),
+    /// A group without a name was dropped.
+    DroppedEmptyGroup,
+    /// A `one-of` with a single package was replaced by the package.
+    CollapsedAlternatives(String),
+    /// A repeated name was removed from a list section.
+    RemovedListRepeat { section: &'static str, name: String },
+    /// A package's version or requirement was removed.
+    Unpinned(String),
+    /// A package was pinned to its installed version.
+    Pinned { name: String, version: String },
+    /// The entries were put in order.
+    Sorted,
+    /// Only the layout of the file changed, e.g. its indentation or quoting.
+    Reformatted,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RenamedKey { from, to } => write!(f, "renamed `{from}:` to `{to}:`"),
+            Self::MergedDuplicate(name) => write!(f, "merged the repeated entries of {name}"),
+            Self::RemovedRepeats(count) => {
+                write!(f, "removed {count} repeated group or one-of entries")
+            }
+            Self::DroppedEmptyGroup => write!(f, "dropped a group without a name"),
+            Self::CollapsedAlternatives(name) => {
+                write!(f, "replaced `one-of: [{name}]` with {name}")
+            }
+            Self::RemovedListRepeat { section, name } => {
+                write!(f, "removed the repeated {name} from {section}:")
+            }
+            Self::Unpinned(name) => write!(f, "unpinned {name}"),
+            Self::Pinned { name, version } => write!(f, "pinned {name} to {version}"),
+            Self::Sorted => write!(f, "sorted the entries"),
+            Self::Reformatted => write!(f, "reformatted the file"),
+        }
+    }
+}
+
+/// Rename the keys of `mapping` that are one of `keys` in another case, unless the mapping has
+/// the right one too.
+fn fix_key_case(mapping: &mut Mapping, keys: &[&str], changes: &mut Vec<Change>) {
+    let renames: Vec<(String, &str)> = mapping
+        .keys()
+        .filter_map(Value::as_str)
+        .filter(|key| !keys.contains(key))
+        .filter_map(|key| {
+            let to = keys.iter().find(|known| known.eq_ignore_ascii_case(key))?;
+            (!mapping.contains_key(*to)).then(|| (key.to_string(), *to))
+        })
+        .collect();
+    if renames.is_empty() {
+        return;
+    }
+    *mapping = std::mem::take(mapping)
+        .into_iter()
+        .map(|(key, value)| {
+            match renames
+                .iter()
+                .find(|(from, _)| key.as_str() == Some(from.as_str()))
+            {
+                Some((_, to)) => (Value::from(*to), value),
+                None => (key, value),
+            }
+        })
+        .collect();
+    changes.extend(renames.into_iter().map(|(from, to)| Change::RenamedKey {
+        from,
+        to: to.to_string(),
+    }));
+}
+
+/// Fix the key case of the top level and of the `packages:` entries of `mapping`, and count
+/// its group and `one-of` entries.
+fn fix_keys(mapping: &mut Mapping, changes: &mut Vec<Change>) -> usize {
+    fix_key_case(mapping, CONFIG_KEYS, changes);
+    let Some(Value::Sequence(entries)) = mapping.get_mut("packages") else {
+        return 0;
+    };
+    let entry_keys: Vec<&str> = [PACKAGE_KEYS, GROUP_KEYS, ALTERNATIVES_KEYS].concat();
+    let mut collections = 0;
+    for entry in entries.iter_mut() {
+        if let Value::Mapping(entry) = entry {
+            fix_key_case(entry, &entry_keys, changes);
+            if entry.contains_key("group") || entry.contains_key("one-of") {
+                collections += 1;
+            }
+        }
+    }
+    collections
+}
+
+/// Drop nameless groups, and turn `one-of` entries with a single package into plain entries.
+fn collapse(list: &mut PackageList, changes: &mut Vec<Change>) {
+    list.groups.retain(|group| {
+        let keep = !group.name.trim().is_empty();
+        if !keep {
+            changes.push(Change::DroppedEmptyGroup);
+        }
+        keep
+    });
+    for alternatives in std::mem::take(&mut list.alternatives) {
+        match alternatives.names.as_slice() {
+            [name] => {
+                changes.push(Change::CollapsedAlternatives(name.clone()));
+                if !list.contains(name) {
+                    list.insert(Package {
+                        when: alternatives.when,
+                        ..Package::new(name.clone())
+                    });
+                }
+            }
+            _ => list.alternatives.push(alternatives),
+        }
+    }
+}
+
+/// Remove repeated names from the list sections.
+fn dedup_lists(config: &mut Config, changes: &mut Vec<Change>) {
+    for (section, names) in [
+        ("flatpak", &mut config.flatpak),
+        ("cargo", &mut config.cargo),
+        ("pipx", &mut config.pipx),
+        ("npm", &mut config.npm),
+        ("critical", &mut config.critical),
+        ("ignore", &mut config.ignore),
+        ("banned", &mut config.banned),
+    ] {
+        let Some(names) = names else { continue };
+        let mut seen = Vec::with_capacity(names.len());
+        for name in std::mem::take(names) {
+            if seen.contains(&name) {
+                changes.push(Change::RemovedListRepeat { section, name });
+            } else {
+                seen.push(name);
+            }
+        }
+        *names = seen;
+    }
+}
+
+/// Strip or add versions as `versions` says, pinning to the versions of `installed`.
+fn set_versions(
+    list: &mut PackageList,
+    versions: Versions,
+    installed: &PackageList,
+    changes: &mut Vec<Change>,
+) {
+    for pkg in list.packages.values_mut() {
+        match versions {
+            Versions::Keep => {}
+            Versions::Strip => {
+                if pkg.version.take().is_some() | pkg.requirement.take().is_some() {
+                    changes.push(Change::Unpinned(pkg.name.clone()));
+                }
+            }
+            Versions::Pin => {
+                if pkg.version.is_none()
+                    && pkg.requirement.is_none()
+                    && let Some(version) = installed.get(&pkg.name).and_then(|i| i.version.clone())
+                {
+                    changes.push(Change::Pinned {
+                        name: pkg.name.clone(),
+                        version: version.to_string(),
+                    });
+                    pkg.version = Some(version);
+                }
+            }
+        }
+    }
+}
+
+/// Whether `config` is in the order nixman writes it in.
+fn is_sorted(config: &Config) -> bool {
+    let mut sorted = config.clone();
+    sorted.sort(config.sort_order());
+    // `IndexMap` equality ignores the order, so the package names are compared on their own.
+    sorted
+        .packages
+        .packages
+        .keys()
+        .eq(config.packages.packages.keys())
+        && sorted == *config
+}
+
+/// Tidy the YAML `content`, with `installed` holding the versions for [`Versions::Pin`].
+///
+/// Returns the tidied config and what changed, apart from [`Change::Reformatted`], which only
+/// [`tidy_file`] can tell.
+///
+/// # Errors
+/// Returns an error if `content` is not a valid config once its keys are fixed.
+pub fn tidy(
+    content: &str,
+    versions: Versions,
+    installed: &PackageList,
+) -> std::io::Result<(Config, Vec<Change>)> {
+    let mut changes = Vec::new();
+    let mut mapping: Mapping = schema::from_str(content)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let collections = fix_keys(&mut mapping, &mut changes);
+    let mut config = if changes.is_empty() {
+        schema::parse(content)?
+    } else {
+        let fixed = serde_yml::to_string(&mapping).map_err(std::io::Error::other)?;
+        schema::parse(&fixed)?
+    };
+    let list = &mut config.packages;
+    changes.extend(
+        list.dedup()
+            .into_iter()
+            .map(|duplicate| Change::MergedDuplicate(duplicate.name)),
+    );
+    let repeats = collections.saturating_sub(list.groups.len() + list.alternatives.len());
+    if repeats > 0 {
+        changes.push(Change::RemovedRepeats(repeats));
+    }
+    collapse(list, &mut changes);
+    set_versions(list, versions, installed, &mut changes);
+    dedup_lists(&mut config, &mut changes);
+    if !is_sorted(&config) {
+        changes.push(Change::Sorted);
+    }
+    Ok((config, changes))
+}
+
+/// Tidy the YAML at `path`, and write it back unless `check`. Returns what changed, or would
+/// change with `check`; nothing if the file is tidy already.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, parsed, or written.
+pub fn tidy_file(
+    path: &Path,
+    versions: Versions,
+    installed: &PackageList,
+    check: bool,
+) -> std::io::Result<Vec<Change>> {
+    let content = std::fs::read_to_string(path)?;
+    let (config, mut changes) = tidy(&content, versions, installed)
+        .map_err(|e| crate::diagnostics::locate(path, &content, e))?;
+    let rendered = crate::render_config(&config)?;
+    if rendered == content {
+        return Ok(Vec::new());
+    }
+    if changes.is_empty() {
+        changes.push(Change::Reformatted);
+    }
+    if !check {
+        std::fs::write(path, rendered)?;
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keys are fixed, repeats merged, empty groups and single alternatives collapsed, lists
+    /// deduplicated, and the entries sorted.
+    #[test]
+    fn tidies_config() {
+        let content = "\
+Packages:
+- vim
+- name: htop
+  Version: 3.3.0-1
+- htop
+- group: base-devel
+- group: base-devel
+- one-of: [neovim]
+- group: ''
+ignore: [steam, steam]
+";
+        let (config, changes) = tidy(content, Versions::Keep, &PackageList::default()).unwrap();
+        assert_eq!(
+            changes,
+            [
+                Change::RenamedKey {
+                    from: "Packages".to_string(),
+                    to: "packages".to_string()
+                },
+                Change::RenamedKey {
+                    from: "Version".to_string(),
+                    to: "version".to_string()
+                },
+                Change::MergedDuplicate("htop".to_string()),
+                Change::RemovedRepeats(1),
+                Change::DroppedEmptyGroup,
+                Change::CollapsedAlternatives("neovim".to_string()),
+                Change::RemovedListRepeat {
+                    section: "ignore",
+                    name: "steam".to_string()
+                },
+                Change::Sorted,
+            ]
+        );
+        assert_eq!(config.packages.groups.len(), 1);
+        assert!(config.packages.alternatives.is_empty());
+        assert!(config.packages.contains("neovim"));
+        assert_eq!(config.ignore, Some(vec!["steam".to_string()]));
+
+        let installed = crate::parse_explicit_packages("vim 9.1.0-1\nneovim 0.10.0-1", true);
+        let (config, changes) = tidy(content, Versions::Pin, &installed).unwrap();
+        assert!(changes.contains(&Change::Pinned {
+            name: "vim".to_string(),
+            version: "9.1.0-1".to_string()
+        }));
+        assert_eq!(
+            config
+                .packages
+                .get("htop")
+                .unwrap()
+                .version
+                .as_ref()
+                .unwrap()
+                .to_string(),
+            "3.3.0-1"
+        );
+        let (config, changes) = tidy(content, Versions::Strip, &installed).unwrap();
+        assert!(changes.contains(&Change::Unpinned("htop".to_string())));
+        assert!(config.packages.get("htop").unwrap().version.is_none());
+    }
+}
@@ -0,0 +1,205 @@
+//! Sorting unmanaged packages when adopting an existing system
+//!
+//! # Overview
+//!
+//! On a system that was set up by hand, the first `nixman diff` typically lists hundreds of
+//! installed packages missing from the YAML. `nixman adopt-system` walks through them and lets
+//! the user decide, one package or a range at a time, whether to:
+//!
+//! - **manage** it: add it to `packages:`,
+//! - **ignore** it: add it to `ignore:`, so `apply` leaves it alone,
+//! - **remove** it: leave it out of the YAML, so the next `apply` removes it.
+//!
+//! Commands are an action followed by a selection of the listed numbers, e.g. `m 1-5,8`,
+//! `i 9`, `r 10-12`, or `m rest` for every undecided package.
+
+use std::path::Path;
+
+/// What to do with an unmanaged package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Manage,
+    Ignore,
+    Remove,
+}
+
+/// A command entered during triage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Decide on the selected packages (0-based indices).
+    Decide(Decision, Vec<usize>),
+    /// Show the undecided packages again.
+    List,
+    /// Stop and save the decisions made so far.
+    Done,
+}
+
+/// Parse a selection such as `1-5,8`, `all`, or `rest` into 0-based indices.
+///
+/// `rest` selects the packages that are still `undecided`.
+///
+/// # Errors
+/// Returns a message if a number is malformed or out of range.
+pub fn parse_selection(
+    selection: &str,
+    undecided: &[usize],
+    len: usize,
+) -> Result<Vec<usize>, String> {
+    match selection.trim() {
+        "all" => return Ok((0..len).collect()),
+        "rest" => return Ok(undecided.to_vec()),
+        _ => {}
+    }
+    let number = |s: &str| -> Result<usize, String> {
+        let n: usize = s
+            .trim()
+            .parse()
+            .map_err(|_| format!("`{s}` is not a number"))?;
+        if (1..=len).contains(&n) {
+            Ok(n - 1)
+        } else {
+            Err(format!("{n} is out of range (1-{len})"))
+        }
+    };
+    let mut indices = Vec::new();
+    for part in selection.split(',').filter(|part| !part.trim().is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => indices.extend(number(start)?..=number(end)?),
+            None => indices.push(number(part)?),
+        }
+    }
+    if indices.is_empty() {
+        return Err("nothing selected".to_string());
+    }
+    Ok(indices)
+}
+
+/// Parse one line of input.
+///
+/// # Errors
+/// Returns a usage message for unknown actions or invalid selections.
+pub fn parse_command(line: &str, undecided: &[usize], len: usize) -> Result<Command, String> {
+    let line = line.trim();
+    let (action, selection) = line.split_once(' ').unwrap_or((line, ""));
+    let decision = match action {
+        "m" | "manage" => Decision::Manage,
+        "i" | "ignore" => Decision::Ignore,
+        "r" | "remove" => Decision::Remove,
+        "l" | "list" => return Ok(Command::List),
+        "d" | "done" | "q" | "quit" => return Ok(Command::Done),
+        _ => {
+            return Err(
+                "usage: m|i|r <selection> (manage, ignore, remove), l (list), d (done)".to_string(),
+            );
+        }
+    };
+    Ok(Command::Decide(
+        decision,
+        parse_selection(selection, undecided, len)?,
+    ))
+}
+
+/// The unmanaged packages being triaged and the decisions made so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Triage {
+    pub packages: Vec<String>,
+    pub decisions: Vec<Option<Decision>>,
+}
+
+impl Triage {
+    /// Start triaging `packages`, with nothing decided yet.
+    #[must_use]
+    pub fn new(packages: Vec<String>) -> Self {
+        let decisions = vec![None; packages.len()];
+        Self {
+            packages,
+            decisions,
+        }
+    }
+
+    /// Indices of the packages without a decision.
+    #[must_use]
+    pub fn undecided(&self) -> Vec<usize> {
+        (0..self.packages.len())
+            .filter(|&i| self.decisions[i].is_none())
+            .collect()
+    }
+
+    /// Record `decision` for the packages at `indices`, replacing earlier decisions.
+    pub fn decide(&mut self, decision: Decision, indices: &[usize]) {
+        for &i in indices {
+            if let Some(slot) = self.decisions.get_mut(i) {
+                *slot = Some(decision);
+            }
+        }
+    }
+
+    /// The packages with the given decision.
+    #[must_use]
+    pub fn with(&self, decision: Decision) -> Vec<String> {
+        self.packages
+            .iter()
+            .zip(&self.decisions)
+            .filter(|(_, d)| **d == Some(decision))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Write the decisions to the YAML: managed packages are added to `packages:` and ignored
+    /// ones to `ignore:`. Packages to remove need no entry.
+    ///
+    /// # Errors
+    /// Returns an error if the YAML cannot be read, parsed, or written.
+    pub fn save<P: AsRef<Path>>(&self, yml_path: P) -> std::io::Result<()> {
+        crate::set_install_reason(
+            &yml_path,
+            &self.with(Decision::Manage),
+            crate::InstallReason::Explicit,
+        )?;
+        let ignored = self.with(Decision::Ignore);
+        if ignored.is_empty() {
+            return Ok(());
+        }
+        let mut config = crate::read_config(&yml_path)?;
+        let ignore = config.ignore.get_or_insert_with(Vec::new);
+        for name in ignored {
+            if !ignore.contains(&name) {
+                ignore.push(name);
+            }
+        }
+        crate::write_config(&config, yml_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ranges, lists, and `rest` select 0-based indices; bad input is rejected.
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse_command("m 1-3,5", &[], 5),
+            Ok(Command::Decide(Decision::Manage, vec![0, 1, 2, 4]))
+        );
+        assert_eq!(
+            parse_command("ignore rest", &[3, 4], 5),
+            Ok(Command::Decide(Decision::Ignore, vec![3, 4]))
+        );
+        assert_eq!(parse_command("d", &[], 5), Ok(Command::Done));
+        assert!(parse_command("r 6", &[], 5).is_err());
+        assert!(parse_command("r", &[], 5).is_err());
+        assert!(parse_command("x 1", &[], 5).is_err());
+    }
+
+    /// Later decisions replace earlier ones, and decided packages leave the undecided list.
+    #[test]
+    fn records_decisions() {
+        let mut triage = Triage::new(vec!["a".into(), "b".into(), "c".into()]);
+        triage.decide(Decision::Remove, &[0, 1]);
+        triage.decide(Decision::Manage, &[1]);
+        assert_eq!(triage.undecided(), vec![2]);
+        assert_eq!(triage.with(Decision::Manage), vec!["b"]);
+        assert_eq!(triage.with(Decision::Remove), vec!["a"]);
+    }
+}
@@ -0,0 +1,365 @@
+//! Reviewing and partially applying a plan in the terminal (feature `tui`)
+//!
+//! # Overview
+//!
+//! `nixman tui` shows the [plan](crate::plan) of an apply in three panes: packages to install,
+//! packages to remove, and pinned packages whose installed version drifted from the pin. Each
+//! entry can be staged or left out, so only part of the plan is applied:
+//!
+//! | Key                 | Action                               |
+//! |---------------------|--------------------------------------|
+//! | `↑`/`↓`, `k`/`j`    | Move within the pane                 |
+//! | `←`/`→`, `Tab`      | Switch panes                         |
+//! | `Space`             | Stage or unstage the entry           |
+//! | `a` / `n`           | Stage / unstage the whole pane       |
+//! | `Enter`             | Apply what is staged                 |
+//! | `q`, `Esc`          | Quit without changing anything       |
+//!
+//! Installs and removals start out staged, except removals of
+//! [critical packages](crate::safety), which must be staged by hand. Pin drift starts out
+//! unstaged; staging it installs the pinned version from the [Arch Linux Archive](crate::archive).
+//!
+//! [`Review`] holds the selection and works without a terminal; [`run`] draws it with ratatui.
+
+use crate::plan::{ChangePlan, PinViolation};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeSet;
+
+/// One of the three lists of a [`Review`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Install,
+    Remove,
+    /// Pinned packages installed at another version.
+    Pins,
+}
+
+impl Pane {
+    /// The panes from left to right.
+    pub const ALL: [Self; 3] = [Self::Install, Self::Remove, Self::Pins];
+
+    const fn index(self) -> usize {
+        match self {
+            Self::Install => 0,
+            Self::Remove => 1,
+            Self::Pins => 2,
+        }
+    }
+
+    const fn title(self) -> &'static str {
+        match self {
+            Self::Install => "Install",
+            Self::Remove => "Remove",
+            Self::Pins => "Pin drift",
+        }
+    }
+}
+
+/// The part of a plan staged in a [`Review`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Staged {
+    /// The staged installs and removals.
+    pub plan: ChangePlan,
+    /// The staged pins, to be installed at the pinned version.
+    pub pins: Vec<PinViolation>,
+}
+
+impl Staged {
+    /// Whether nothing was staged.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.plan.is_empty() && self.pins.is_empty()
+    }
+}
+
+/// A plan with a staged flag for each entry and a cursor in each pane.
+#[derive(Debug, Clone)]
+pub struct Review {
+    plan: ChangePlan,
+    critical: Vec<bool>,
+    staged: [Vec<bool>; 3],
+    cursors: [usize; 3],
+    pane: Pane,
+}
+
+impl Review {
+    /// Review `plan`, with removals of the `critical` packages left unstaged.
+    #[must_use]
+    pub fn new(plan: ChangePlan, critical: &BTreeSet<String>) -> Self {
+        let critical: Vec<bool> = plan
+            .remove
+            .iter()
+            .map(|change| change.source == "pacman" && critical.contains(&change.name))
+            .collect();
+        let staged = [
+            vec![true; plan.install.len()],
+            critical.iter().map(|critical| !critical).collect(),
+            vec![false; plan.pin_violations.len()],
+        ];
+        Self {
+            plan,
+            critical,
+            staged,
+            cursors: [0; 3],
+            pane: Pane::Install,
+        }
+    }
+
+    /// The pane with the cursor.
+    #[must_use]
+    pub const fn pane(&self) -> Pane {
+        self.pane
+    }
+
+    /// The number of entries in `pane`.
+    #[must_use]
+    pub const fn len(&self, pane: Pane) -> usize {
+        self.staged[pane.index()].len()
+    }
+
+    /// Whether there is nothing to review.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.plan.is_empty() && self.plan.pin_violations.is_empty()
+    }
+
+    /// Whether entry `index` of `pane` is staged.
+    #[must_use]
+    pub fn is_staged(&self, pane: Pane, index: usize) -> bool {
+        self.staged[pane.index()]
+            .get(index)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// How entry `index` of `pane` is shown, e.g. `htop`, `org.gimp.GIMP (flatpak)`, or
+    /// `git: pinned 2.44.0-1, installed 2.45.0-1 (newer)`.
+    #[must_use]
+    pub fn label(&self, pane: Pane, index: usize) -> String {
+        let change = match pane {
+            Pane::Install => &self.plan.install[index],
+            Pane::Remove => &self.plan.remove[index],
+            Pane::Pins => {
+                let pin = &self.plan.pin_violations[index];
+                return format!(
+                    "{}: pinned {}, installed {} ({})",
+                    pin.name, pin.pinned, pin.installed, pin.relation
+                );
+            }
+        };
+        let mut label = change.name.clone();
+        if let Some(pinned) = &change.pinned {
+            label.push(' ');
+            label.push_str(pinned);
+        }
+        if change.source != "pacman" {
+            label = format!("{label} ({})", change.source);
+        }
+        if pane == Pane::Remove && self.critical[index] {
+            label.push_str(" [critical]");
+        }
+        label
+    }
+
+    /// Move the cursor to the pane on the right, wrapping around.
+    pub const fn next_pane(&mut self) {
+        self.pane = Pane::ALL[(self.pane.index() + 1) % 3];
+    }
+
+    /// Move the cursor to the pane on the left, wrapping around.
+    pub const fn previous_pane(&mut self) {
+        self.pane = Pane::ALL[(self.pane.index() + 2) % 3];
+    }
+
+    /// Move the cursor one entry down, or up, within its pane.
+    pub fn move_cursor(&mut self, down: bool) {
+        let len = self.len(self.pane);
+        let cursor = &mut self.cursors[self.pane.index()];
+        *cursor = if down {
+            (*cursor + 1).min(len.saturating_sub(1))
+        } else {
+            cursor.saturating_sub(1)
+        };
+    }
+
+    /// The entry under the cursor in `pane`.
+    #[must_use]
+    pub const fn cursor(&self, pane: Pane) -> usize {
+        self.cursors[pane.index()]
+    }
+
+    /// Stage or unstage the entry under the cursor.
+    pub fn toggle(&mut self) {
+        let pane = self.pane.index();
+        if let Some(staged) = self.staged[pane].get_mut(self.cursors[pane]) {
+            *staged = !*staged;
+        }
+    }
+
+    /// Stage or unstage every entry of the current pane.
+    pub fn stage_all(&mut self, staged: bool) {
+        self.staged[self.pane.index()].fill(staged);
+    }
+
+    /// The staged part of the plan.
+    #[must_use]
+    pub fn staged(&self) -> Staged {
+        Staged {
+            plan: ChangePlan {
+                install: keep(&self.plan.install, &self.staged[0]),
+                remove: keep(&self.plan.remove, &self.staged[1]),
+                pin_violations: Vec::new(),
+                requirement_violations: self.plan.requirement_violations.clone(),
+                resolved: None,
+            },
+            pins: keep(&self.plan.pin_violations, &self.staged[2]),
+        }
+    }
+}
+
+/// The `items` whose flag in `staged` is set.
+fn keep<T: Clone>(items: &[T], staged: &[bool]) -> Vec<T> {
+    items
+        .iter()
+        .zip(staged)
+        .filter(|(_, staged)| **staged)
+        .map(|(item, _)| item.clone())
+        .collect()
+}
+
+/// Show `review` full-screen until the user applies or quits; returns whether to apply.
+///
+/// # Errors
+/// Returns an error if the terminal cannot be drawn to or read from.
+pub fn run(review: &mut Review) -> std::io::Result<bool> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, review);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, review: &mut Review) -> std::io::Result<bool> {
+    loop {
+        terminal.draw(|frame| draw(frame, review))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Enter => return Ok(true),
+            KeyCode::Up | KeyCode::Char('k') => review.move_cursor(false),
+            KeyCode::Down | KeyCode::Char('j') => review.move_cursor(true),
+            KeyCode::Right | KeyCode::Tab | KeyCode::Char('l') => review.next_pane(),
+            KeyCode::Left | KeyCode::BackTab | KeyCode::Char('h') => review.previous_pane(),
+            KeyCode::Char(' ') => review.toggle(),
+            KeyCode::Char('a') => review.stage_all(true),
+            KeyCode::Char('n') => review.stage_all(false),
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, review: &Review) {
+    let [panes, footer] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(2)]).areas(frame.area());
+    let columns = Layout::horizontal([Constraint::Ratio(1, 3); 3]).split(panes);
+    for (pane, area) in Pane::ALL.into_iter().zip(columns.iter()) {
+        let len = review.len(pane);
+        let staged = (0..len).filter(|&i| review.is_staged(pane, i)).count();
+        let items: Vec<ListItem> = (0..len)
+            .map(|i| {
+                let mark = if review.is_staged(pane, i) {
+                    "[x]"
+                } else {
+                    "[ ]"
+                };
+                ListItem::new(format!("{mark} {}", review.label(pane, i)))
+            })
+            .collect();
+        let active = pane == review.pane();
+        let border = if active {
+            Style::new().fg(Color::Cyan)
+        } else {
+            Style::new()
+        };
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title(format!(" {} ({staged}/{len}) ", pane.title()))
+                    .border_style(border),
+            )
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(active.then(|| review.cursor(pane)));
+        frame.render_stateful_widget(list, *area, &mut state);
+    }
+    let help = Paragraph::new(vec![
+        Line::from("↑↓ move  ←→ pane  space toggle  a/n all/none  enter apply  q quit"),
+        Line::from("Removals of critical packages and pin drift are not staged by default."),
+    ]);
+    frame.render_widget(help, footer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::PlannedChange;
+    use crate::versioning::VersionRelation;
+
+    fn change(source: &'static str, name: &str) -> PlannedChange {
+        PlannedChange {
+            source,
+            name: name.to_string(),
+            pinned: None,
+        }
+    }
+
+    /// Critical removals and pins start unstaged, and toggles decide what is applied.
+    #[test]
+    fn stages_part_of_a_plan() {
+        let plan = ChangePlan {
+            install: vec![change("pacman", "htop"), change("flatpak", "org.gimp.GIMP")],
+            remove: vec![change("pacman", "nano"), change("pacman", "linux")],
+            pin_violations: vec![PinViolation {
+                name: "git".to_string(),
+                pinned: "2.44.0-1".to_string(),
+                installed: "2.45.0-1".to_string(),
+                relation: VersionRelation::Upgrade,
+            }],
+            requirement_violations: Vec::new(),
+            resolved: None,
+        };
+        let critical: BTreeSet<String> = ["linux".to_string()].into();
+        let mut review = Review::new(plan, &critical);
+        assert_eq!(review.label(Pane::Install, 1), "org.gimp.GIMP (flatpak)");
+        assert_eq!(review.label(Pane::Remove, 1), "linux [critical]");
+        assert!(!review.is_staged(Pane::Remove, 1));
+        assert!(!review.is_staged(Pane::Pins, 0));
+        assert_eq!(
+            review.label(Pane::Pins, 0),
+            "git: pinned 2.44.0-1, installed 2.45.0-1 (newer)"
+        );
+
+        review.move_cursor(true);
+        review.move_cursor(true);
+        review.toggle();
+        review.previous_pane();
+        review.toggle();
+        let staged = review.staged();
+        assert_eq!(staged.plan.for_source("pacman").0, vec!["htop"]);
+        assert_eq!(staged.plan.for_source("pacman").1, vec!["nano"]);
+        assert_eq!(staged.pins.len(), 1);
+
+        review.next_pane();
+        review.next_pane();
+        review.stage_all(false);
+        assert!(review.staged().plan.remove.is_empty());
+    }
+}
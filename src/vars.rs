@@ -0,0 +1,192 @@
+//! Variables in the YAML
+//!
+//! # Overview
+//!
+//! A `vars:` section names values the rest of the file refers to as `${name}`, so that entries
+//! such as the editor or the GPU driver can differ per machine within one file:
+//!
+//! ```yaml
+//! vars:
+//!   editor: neovim
+//!   gpu: nvidia-open
+//! packages:
+//!   - ${editor}
+//!   - ${gpu}
+//!   - name: ${gpu}-settings
+//!     reason: dependency
+//! ```
+//!
+//! `${hostname}`, `${arch}`, and `${user}` are always known ([`builtins`]); a declared variable
+//! of the same name replaces them, and declared values may refer to them. A
+//! [machine-local file](crate::local) can declare `vars:` too, which replace the shared ones,
+//! so one machine sets `gpu: mesa` without touching the shared file. `$${` stands for a literal
+//! `${`, and a reference to an unknown variable is an error.
+//!
+//! References are replaced in every string of the file except `vars:` itself when it is read
+//! for `apply` and the other commands ([`expand`]). Commands that rewrite the YAML keep them as
+//! written, and `freeze` keeps an entry like `${editor}` instead of the package it names.
+
+use crate::Config;
+use crate::condition::Host;
+use serde_yml::Value;
+use std::collections::BTreeMap;
+
+/// The variables every file can refer to: `hostname` (when it can be determined), `arch`, and
+/// `user` (from `$USER` or `$LOGNAME`).
+#[must_use]
+pub fn builtins() -> BTreeMap<String, String> {
+    let host = Host::current();
+    let mut vars = BTreeMap::from([("arch".to_string(), host.arch)]);
+    if let Some(hostname) = host.hostname {
+        vars.insert("hostname".to_string(), hostname);
+    }
+    if let Some(user) = ["USER", "LOGNAME"]
+        .into_iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|user| !user.is_empty())
+    {
+        vars.insert("user".to_string(), user);
+    }
+    vars
+}
+
+/// The variables of a file declaring `declared`: the `builtins`, replaced by the declared ones,
+/// whose values may refer to the builtins.
+///
+/// # Errors
+/// Returns a message if a declared value refers to an unknown variable.
+pub fn resolve(
+    declared: Option<&BTreeMap<String, String>>,
+    builtins: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, String> {
+    let mut vars = builtins.clone();
+    for (name, value) in declared.into_iter().flatten() {
+        let value = substitute(value, builtins)
+            .map_err(|e| format!("vars.{name}: {e}"))?
+            .unwrap_or_else(|| value.clone());
+        vars.insert(name.clone(), value);
+    }
+    Ok(vars)
+}
+
+/// Replace the `${name}` references in `text` with their values, or `None` if there are none.
+///
+/// # Errors
+/// Returns a message if `text` refers to an unknown variable or a `${` is not closed.
+pub fn substitute(text: &str, vars: &BTreeMap<String, String>) -> Result<Option<String>, String> {
+    if !text.contains("${") {
+        return Ok(None);
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(escaped) = after.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = after.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| format!("`{text}` has a `${{` without a closing `}}`"))?;
+            let name = &reference[..end];
+            let value = vars
+                .get(name)
+                .ok_or_else(|| format!("`{text}` refers to the unknown variable `{name}`"))?;
+            result.push_str(value);
+            rest = &reference[end + 1..];
+        } else {
+            result.push('$');
+            rest = &after[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(Some(result))
+}
+
+/// Replace the references in every string of `value`; returns whether any were found.
+fn substitute_value(value: &mut Value, vars: &BTreeMap<String, String>) -> Result<bool, String> {
+    match value {
+        Value::String(text) => {
+            let substituted = substitute(text, vars)?;
+            let found = substituted.is_some();
+            if let Some(substituted) = substituted {
+                *text = substituted;
+            }
+            Ok(found)
+        }
+        Value::Sequence(items) => items.iter_mut().try_fold(false, |found, item| {
+            Ok(substitute_value(item, vars)? || found)
+        }),
+        Value::Mapping(mapping) => mapping.values_mut().try_fold(false, |found, item| {
+            Ok(substitute_value(item, vars)? || found)
+        }),
+        Value::Tagged(tagged) => substitute_value(&mut tagged.value, vars),
+        Value::Null | Value::Bool(_) | Value::Number(_) => Ok(false),
+    }
+}
+
+/// Replace the references in `config` following the [module docs](self); `vars:` is left as
+/// it is.
+///
+/// # Errors
+/// Returns an error if the config refers to an unknown variable or a replaced value is no
+/// longer valid, e.g. a `version:` that is not a pacman version.
+pub fn expand(config: &mut Config) -> std::io::Result<()> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let vars = resolve(config.vars.as_ref(), &builtins()).map_err(invalid)?;
+    let mut value = serde_yml::to_value(&*config).map_err(std::io::Error::other)?;
+    let Value::Mapping(mapping) = &mut value else {
+        return Ok(());
+    };
+    let mut found = false;
+    for (key, section) in mapping.iter_mut() {
+        if key.as_str() != Some("vars") {
+            found |= substitute_value(section, &vars).map_err(invalid)?;
+        }
+    }
+    // Configs without references are not round-tripped.
+    if found {
+        *config = serde_yml::from_value(value).map_err(|e| invalid(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Declared variables replace builtins and may use them; references are replaced in
+    /// entries but not in `vars:`, and unknown ones are errors.
+    #[test]
+    #[allow(clippy::literal_string_with_formatting_args)]
+    fn expands_references() {
+        let builtins = BTreeMap::from([
+            ("arch".to_string(), "x86_64".to_string()),
+            ("hostname".to_string(), "laptop".to_string()),
+        ]);
+        let declared = BTreeMap::from([
+            ("editor".to_string(), "neovim".to_string()),
+            ("hostname".to_string(), "${arch}-box".to_string()),
+        ]);
+        let vars = resolve(Some(&declared), &builtins).unwrap();
+        assert_eq!(vars["hostname"], "x86_64-box");
+        assert_eq!(
+            substitute("${editor}-qt for ${hostname}, $${editor} costs $5", &vars).unwrap(),
+            Some("neovim-qt for x86_64-box, ${editor} costs $5".to_string())
+        );
+        assert_eq!(substitute("git", &vars).unwrap(), None);
+        assert!(substitute("${gpu}", &vars).unwrap_err().contains("`gpu`"));
+        assert!(substitute("${editor", &vars).is_err());
+
+        let mut config: Config = crate::schema::parse(
+            "vars:\n  editor: vim\npackages:\n  - ${editor}\n  - name: ${arch}-tool\n    when:\n      arch: ${arch}\n",
+        )
+        .unwrap();
+        expand(&mut config).unwrap();
+        let arch = std::env::consts::ARCH;
+        assert!(config.packages.contains("vim"));
+        assert!(config.packages.contains(&format!("{arch}-tool")));
+        assert_eq!(config.vars.unwrap()["editor"], "vim");
+    }
+}
@@ -0,0 +1,139 @@
+//! File integrity checks for managed packages
+//!
+//! # Overview
+//!
+//! `nixman verify` runs `pacman -Qkk` for the packages declared in the YAML and summarizes, per
+//! package, which files were modified or are missing compared to pacman's file database. That
+//! way the YAML not only describes what is installed but can also attest that the installed
+//! files have not been tampered with.
+//!
+//! Edited backup files (usually configuration under `/etc`) are expected to differ and are
+//! reported separately.
+
+use crate::pacman;
+use serde::Serialize;
+
+/// The result of checking one package's files.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PackageIntegrity {
+    pub name: String,
+    pub total_files: usize,
+    pub modified: Vec<String>,
+    pub missing: Vec<String>,
+    /// Backup files that differ from the packaged version, e.g. edited configuration.
+    pub backup_modified: Vec<String>,
+}
+
+impl PackageIntegrity {
+    /// Whether no file is modified or missing; edited backup files do not count.
+    #[must_use]
+    pub const fn is_intact(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty()
+    }
+}
+
+fn push_unique(list: &mut Vec<String>, path: &str) {
+    if !list.iter().any(|existing| existing == path) {
+        list.push(path.to_string());
+    }
+}
+
+/// The entry for `name`, added if this is the first line about it.
+fn entry<'a>(packages: &'a mut Vec<PackageIntegrity>, name: &str) -> &'a mut PackageIntegrity {
+    let index = packages
+        .iter()
+        .position(|package| package.name == name)
+        .unwrap_or_else(|| {
+            packages.push(PackageIntegrity {
+                name: name.to_string(),
+                ..PackageIntegrity::default()
+            });
+            packages.len() - 1
+        });
+    &mut packages[index]
+}
+
+/// Parse the combined stdout and stderr of `pacman -Qkk`, in the order packages were checked.
+///
+/// A file can be reported several times (e.g. for a size and a checksum mismatch); it is listed
+/// once.
+#[must_use]
+pub fn parse_check(output: &str) -> Vec<PackageIntegrity> {
+    let mut packages = Vec::new();
+    for line in output.lines() {
+        let (backup, rest) = if let Some(rest) = line.strip_prefix("warning: ") {
+            (false, rest)
+        } else if let Some(rest) = line.strip_prefix("backup file: ") {
+            (true, rest)
+        } else {
+            // `htop: 23 total files, 0 altered files`
+            if let Some((name, summary)) = line.split_once(": ")
+                && let Some((total, _)) = summary.split_once(" total files")
+                && let Ok(total) = total.parse()
+            {
+                entry(&mut packages, name).total_files = total;
+            }
+            continue;
+        };
+        let Some((name, file)) = rest.split_once(": ") else {
+            continue;
+        };
+        let Some((path, reason)) = file.rsplit_once(" (") else {
+            continue;
+        };
+        let package = entry(&mut packages, name);
+        if backup {
+            push_unique(&mut package.backup_modified, path);
+        } else if reason.starts_with("No such file") {
+            push_unique(&mut package.missing, path);
+        } else {
+            push_unique(&mut package.modified, path);
+        }
+    }
+    packages
+}
+
+/// Check the files of the given installed packages with `pacman -Qkk`.
+///
+/// Packages that are not installed are left out of the result.
+///
+/// # Errors
+/// Returns an error if the pacman command could not be executed.
+pub fn verify(names: &[String]) -> std::io::Result<Vec<PackageIntegrity>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let output = crate::run::output(pacman::pacman_command().arg("-Qkk").args(names))?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push('\n');
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(parse_check(&text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Missing, modified, and backup files are told apart and listed once per package.
+    #[test]
+    fn summarizes_pacman_check_output() {
+        let output = "\
+warning: openssh: /usr/bin/ssh (Modification time mismatch)
+warning: openssh: /usr/bin/ssh (Size mismatch)
+backup file: openssh: /etc/ssh/sshd_config (Modification time mismatch)
+warning: openssh: /usr/share/man/man1/ssh.1.gz (No such file or directory)
+openssh: 97 total files, 2 altered files
+htop: 23 total files, 0 altered files
+error: package 'nope' was not found
+";
+        let packages = parse_check(output);
+        assert_eq!(packages.len(), 2);
+        let openssh = &packages[0];
+        assert_eq!(openssh.total_files, 97);
+        assert_eq!(openssh.modified, vec!["/usr/bin/ssh"]);
+        assert_eq!(openssh.missing, vec!["/usr/share/man/man1/ssh.1.gz"]);
+        assert_eq!(openssh.backup_modified, vec!["/etc/ssh/sshd_config"]);
+        assert!(!openssh.is_intact());
+        assert!(packages[1].is_intact());
+    }
+}
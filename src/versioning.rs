@@ -17,11 +17,42 @@
 //! assert_eq!(v.version.patch, 4);
 //! assert_eq!(v.release.0, 5);
 //! ```
+//!
+//! Versions from the YAML and other untrusted input are parsed with [`str::parse`], which
+//! rejects anything that is not a pacman version; `From<&str>` is a lossy shorthand for
+//! versions pacman itself reported:
+//!
+//! ```rust
+//! use nixman::versioning::FullVersion;
+//! assert!("1:2.3.4-5".parse::<FullVersion>().is_ok());
+//! assert!("garbage".parse::<FullVersion>().is_err());
+//! ```
+//!
+//! A [`VersionReq`] such as `>=1.6` or `~5.15` accepts a range of versions instead of one:
+//!
+//! ```rust
+//! use nixman::versioning::VersionReq;
+//! let req: VersionReq = ">=1.6, <2".parse().unwrap();
+//! assert!(req.matches("1.7.2-1"));
+//! assert!(!req.matches("2.0-1"));
+//! ```
+//!
+//! [`FullVersion`] only keeps three numeric version components, so it cannot order versions such
+//! as `1.2.40.599` or `1.0rc1`, although it displays and serializes them exactly as written. Use [`vercmp`] to decide which of two version strings is newer
+//! exactly as pacman does:
+//!
+//! ```rust
+//! use nixman::versioning::vercmp;
+//! use std::cmp::Ordering;
+//! assert_eq!(vercmp("1.0rc1-1", "1.0-1"), Ordering::Less);
+//! assert_eq!(vercmp("1:1.0-1", "2.0-1"), Ordering::Greater);
+//! ```
 
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::fmt::Write as FmtWrite;
+use std::cmp::Ordering;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
 /// The optional epoch component in Pacman versioning.
 ///
@@ -48,17 +79,152 @@ pub struct Release(pub u32);
 
 /// The full Pacman version: epoch:version-release.
 ///
-/// This struct combines the epoch, version, and release components.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// This struct combines the epoch, version, and release components, and keeps the text it was
+/// parsed from so it is displayed and serialized exactly as written. Versions are ordered like
+/// [`vercmp`] orders that text.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FullVersion {
     pub epoch: Epoch,
     pub version: Version,
     pub release: Release,
+    /// The text the version was parsed from, such as `1:1.2.3.r45.gabcdef-2`, which the
+    /// components above cannot always represent; `None` for versions built from components.
+    pub original: Option<String>,
+}
+
+/// A string that is not a pacman version or version component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseVersionError {
+    /// The text that failed to parse.
+    pub input: String,
+    /// What is wrong with it.
+    pub reason: &'static str,
+}
+
+impl ParseVersionError {
+    fn new(input: &str, reason: &'static str) -> Self {
+        Self {
+            input: input.to_string(),
+            reason,
+        }
+    }
+}
+
+impl Display for ParseVersionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid version `{}`: {}", self.input, self.reason)
+    }
+}
+
+impl std::error::Error for ParseVersionError {}
+
+/// Parses an epoch: empty for `None`, or a number.
+impl FromStr for Epoch {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Self(None));
+        }
+        s.parse()
+            .map(|epoch| Self(Some(epoch)))
+            .map_err(|_| ParseVersionError::new(s, "the epoch must be a number"))
+    }
+}
+
+/// Parses an upstream version (pacman's `pkgver`): letters, digits, and `.`, `_`, `+`, `~`.
+/// The leading digits of the first three dot-separated parts become major, minor, and patch;
+/// parts without leading digits count as 0.
+impl FromStr for Version {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ParseVersionError::new(s, "the version is empty"));
+        }
+        if !s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '~'))
+        {
+            return Err(ParseVersionError::new(
+                s,
+                "the version may only contain letters, digits, and `.`, `_`, `+`, `~`",
+            ));
+        }
+        let mut parts = s.split('.').map(|part| {
+            let digits = part.bytes().take_while(u8::is_ascii_digit).count();
+            part[..digits].parse().unwrap_or(0)
+        });
+        Ok(Self {
+            major: parts.next().unwrap_or(0),
+            minor: parts.next().unwrap_or(0),
+            patch: parts.next().unwrap_or(0),
+        })
+    }
+}
+
+/// Parses a release (pacman's `pkgrel`): a number, optionally with a `.1`-style suffix for
+/// rebuilds, which is dropped.
+impl FromStr for Release {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, suffix) = s.split_once('.').unwrap_or((s, "0"));
+        if suffix.is_empty() || !suffix.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseVersionError::new(s, "the release must be a number"));
+        }
+        number
+            .parse()
+            .map(Self)
+            .map_err(|_| ParseVersionError::new(s, "the release must be a number"))
+    }
+}
+
+/// Parses a full version, `[epoch:]pkgver-pkgrel`.
+impl FromStr for FullVersion {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, version, release) = Self::components(s)?;
+        Ok(Self {
+            epoch,
+            version,
+            release,
+            original: Some(s.to_string()),
+        })
+    }
+}
+
+impl FullVersion {
+    /// The components of `[epoch:]pkgver-pkgrel`, without keeping the text.
+    fn components(s: &str) -> Result<(Epoch, Version, Release), ParseVersionError> {
+        let (epoch_str, rest) = s.split_once(':').unwrap_or(("", s));
+        let Some((version_str, release_str)) = rest.rsplit_once('-') else {
+            return Err(ParseVersionError::new(
+                s,
+                "expected `[epoch:]version-release`, e.g. `2.45.0-1`",
+            ));
+        };
+        let within = |e: ParseVersionError| ParseVersionError::new(s, e.reason);
+        Ok((
+            epoch_str.parse().map_err(within)?,
+            version_str.parse().map_err(within)?,
+            release_str.parse().map_err(within)?,
+        ))
+    }
+
+    /// Whether `s` parses as a full version; unlike [`str::parse`], a valid version is checked
+    /// without allocating.
+    #[must_use]
+    pub fn is_valid(s: &str) -> bool {
+        Self::components(s).is_ok()
+    }
 }
 
 /// Conversion from a string to the `Epoch` struct.
 ///
-/// Accepts an empty string for `None`, or a stringified integer for `Some`.
+/// Accepts an empty string for `None`, or a stringified integer for `Some`. Lossy; prefer
+/// [`str::parse`] for untrusted input.
 impl From<&str> for Epoch {
     /// Converts a string to an `Epoch`. If the string is empty, it returns `None`.
     fn from(s: &str) -> Self {
@@ -72,7 +238,8 @@ impl From<&str> for Epoch {
 
 /// Conversion from a string to the `Version` struct.
 ///
-/// Accepts strings in the format `major.minor.patch`. Missing components default to 0.
+/// Accepts strings in the format `major.minor.patch`. Missing components default to 0. Lossy;
+/// prefer [`str::parse`] for untrusted input.
 impl From<&str> for Version {
     /// Converts a string to a `Version`. If the string is malformed, it defaults to 0.0.0.
     fn from(s: &str) -> Self {
@@ -91,6 +258,7 @@ impl From<&str> for Version {
 /// Conversion from a string to the `Release` struct.
 ///
 /// Accepts a string representing the release number. Malformed or empty strings default to 0.
+/// Lossy; prefer [`str::parse`] for untrusted input.
 impl From<&str> for Release {
     /// Converts a string to a `Release`. If the string is empty or malformed, it defaults to 0.
     fn from(s: &str) -> Self {
@@ -101,6 +269,9 @@ impl From<&str> for Release {
 /// Conversion from a string to the `FullVersion` struct.
 ///
 /// Accepts strings in the format `epoch:version-release`, `version-release`, or just `version`.
+/// Lossy: malformed parts become 0, so `"garbage"` compares as `0.0.0-0`, although it is still
+/// displayed as written. Use it for versions pacman reported, and [`str::parse`] for anything
+/// else.
 impl From<&str> for FullVersion {
     /// Converts a string to a `FullVersion`. The string should be in the format `epoch:version-release`.
     fn from(s: &str) -> Self {
@@ -114,28 +285,20 @@ impl From<&str> for FullVersion {
             epoch: Epoch::from(epoch_str),
             version: Version::from(version_str),
             release: Release::from(release_str),
+            original: Some(s.to_string()),
         }
     }
 }
 
 /// Serialization for the `FullVersion` struct.
 impl Serialize for FullVersion {
-    /// Serializes the `FullVersion` struct into a string format `epoch:version-release`.
+    /// Serializes the `FullVersion` struct into a string format `epoch:version-release`, or
+    /// the text it was parsed from.
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut version_str = String::new();
-        if let Some(epoch) = self.epoch.0 {
-            write!(&mut version_str, "{epoch}:").unwrap();
-        }
-        write!(
-            &mut version_str,
-            "{}.{}.{}-{}",
-            self.version.major, self.version.minor, self.version.patch, self.release.0
-        )
-        .unwrap();
-        serializer.serialize_str(&version_str)
+        serializer.collect_str(self)
     }
 }
 
@@ -156,16 +319,20 @@ impl<'_de> Deserialize<'_de> for FullVersion {
             where
                 E: de::Error,
             {
-                Ok(FullVersion::from(v))
+                v.parse().map_err(E::custom)
             }
         }
         deserializer.deserialize_str(FullVersionVisitor)
     }
 }
 
-/// Formats the `FullVersion` struct as a string in the format `epoch:version-release`.
+/// Formats the `FullVersion` struct as the text it was parsed from, or else in the format
+/// `epoch:version-release`.
 impl Display for FullVersion {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(original) = &self.original {
+            return f.write_str(original);
+        }
         if let Some(epoch) = self.epoch.0 {
             write!(f, "{epoch}:")?;
         }
@@ -176,3 +343,483 @@ impl Display for FullVersion {
         )
     }
 }
+
+/// How a version relates to another, as returned by [`FullVersion::relation_to`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionRelation {
+    /// Newer than the other version, with the same epoch.
+    Upgrade,
+    /// Older than the other version, with the same epoch.
+    Downgrade,
+    /// The same version; a missing epoch is the same as epoch 0.
+    Same,
+    /// The epochs differ, so the epoch decides which is newer whatever the versions say.
+    EpochOverride,
+}
+
+impl Display for VersionRelation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Upgrade => "newer",
+            Self::Downgrade => "older",
+            Self::Same => "the same version",
+            Self::EpochOverride => "of another epoch",
+        })
+    }
+}
+
+impl Ord for FullVersion {
+    /// Compares the displayed versions with [`vercmp`]; versions `vercmp` finds equal but
+    /// written differently, such as `1.0-1` and `1.00-1`, are ordered by their text.
+    fn cmp(&self, other: &Self) -> Ordering {
+        vercmp(&self.to_string(), &other.to_string())
+            .then_with(|| self.original.cmp(&other.original))
+            .then_with(|| {
+                (&self.epoch, &self.version, &self.release).cmp(&(
+                    &other.epoch,
+                    &other.version,
+                    &other.release,
+                ))
+            })
+    }
+}
+
+impl PartialOrd for FullVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FullVersion {
+    /// How this version relates to `other`: [`VersionRelation::Upgrade`] if it is newer.
+    ///
+    /// Versions are compared like [`vercmp`], so a version without an epoch is the same as
+    /// one with epoch 0.
+    #[must_use]
+    pub fn relation_to(&self, other: &Self) -> VersionRelation {
+        if self.epoch.0.unwrap_or(0) != other.epoch.0.unwrap_or(0) {
+            return VersionRelation::EpochOverride;
+        }
+        match vercmp(&self.to_string(), &other.to_string()) {
+            Ordering::Greater => VersionRelation::Upgrade,
+            Ordering::Less => VersionRelation::Downgrade,
+            Ordering::Equal => VersionRelation::Same,
+        }
+    }
+}
+
+/// How a [`Comparator`] compares a version with its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `=`: the same version; without a release, any release of it.
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    /// `~`: the same version or a newer one with the same leading parts, e.g. `~5.15` accepts
+    /// `5.15.160` but not `5.16`.
+    Tilde,
+}
+
+impl Op {
+    const fn symbol(self) -> &'static str {
+        match self {
+            Self::Exact => "=",
+            Self::Greater => ">",
+            Self::GreaterEq => ">=",
+            Self::Less => "<",
+            Self::LessEq => "<=",
+            Self::Tilde => "~",
+        }
+    }
+}
+
+/// One condition of a [`VersionReq`], such as `>=1.6`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparator {
+    pub op: Op,
+    /// The version compared against, `[epoch:]pkgver[-pkgrel]`.
+    pub version: String,
+}
+
+impl Comparator {
+    /// Whether `version` meets this condition, compared with [`vercmp`].
+    #[must_use]
+    pub fn matches(&self, version: &str) -> bool {
+        let ordering = vercmp(version, &self.version);
+        match self.op {
+            Op::Exact => ordering == Ordering::Equal,
+            Op::Greater => ordering == Ordering::Greater,
+            Op::GreaterEq => ordering != Ordering::Less,
+            Op::Less => ordering == Ordering::Less,
+            Op::LessEq => ordering != Ordering::Greater,
+            Op::Tilde => {
+                ordering != Ordering::Less
+                    && vercmp(version, &tilde_bound(&self.version)) == Ordering::Less
+            }
+        }
+    }
+}
+
+/// The first version a `~` requirement excludes: `5.15` → `5.16`, `1:2` → `1:3`.
+fn tilde_bound(version: &str) -> String {
+    let (epoch, rest) = version
+        .split_once(':')
+        .map_or(("", version), |(epoch, rest)| (epoch, rest));
+    let pkgver = rest.split_once('-').map_or(rest, |(pkgver, _)| pkgver);
+    let (head, last) = pkgver.rsplit_once('.').unwrap_or(("", pkgver));
+    let digits = last.bytes().take_while(u8::is_ascii_digit).count();
+    let next = last[..digits].parse::<u64>().map_or(1, |n| n + 1);
+    let mut bound = String::new();
+    if !epoch.is_empty() {
+        bound.push_str(epoch);
+        bound.push(':');
+    }
+    if !head.is_empty() {
+        bound.push_str(head);
+        bound.push('.');
+    }
+    bound.push_str(&next.to_string());
+    bound
+}
+
+impl Display for Comparator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op.symbol(), self.version)
+    }
+}
+
+/// A version requirement from the YAML, such as `>=1.6`, `~5.15`, or `>=1.6, <2`: one or more
+/// comma-separated [`Comparator`]s that must all hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    pub comparators: Vec<Comparator>,
+}
+
+impl VersionReq {
+    /// Whether `s` is written as a requirement rather than an exact version: it starts with an
+    /// operator.
+    #[must_use]
+    pub fn is_requirement(s: &str) -> bool {
+        s.trim_start().starts_with(['<', '>', '=', '~'])
+    }
+
+    /// Whether `version` meets every condition.
+    #[must_use]
+    pub fn matches(&self, version: &str) -> bool {
+        self.comparators
+            .iter()
+            .all(|comparator| comparator.matches(version))
+    }
+}
+
+/// Parses comma-separated conditions, each an operator (`=`, `>`, `>=`, `<`, `<=`, `~`)
+/// followed by a version with or without a release.
+impl FromStr for VersionReq {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let comparators = s
+            .split(',')
+            .map(|part| {
+                let part = part.trim();
+                let (op, version) = [
+                    (">=", Op::GreaterEq),
+                    ("<=", Op::LessEq),
+                    (">", Op::Greater),
+                    ("<", Op::Less),
+                    ("=", Op::Exact),
+                    ("~", Op::Tilde),
+                ]
+                .into_iter()
+                .find_map(|(symbol, op)| Some((op, part.strip_prefix(symbol)?.trim_start())))
+                .ok_or_else(|| {
+                    ParseVersionError::new(
+                        s,
+                        "each condition must start with =, >, >=, <, <=, or ~",
+                    )
+                })?;
+                let (epoch, rest) = version.split_once(':').unwrap_or(("", version));
+                let (pkgver, pkgrel) = rest
+                    .rsplit_once('-')
+                    .map_or((rest, None), |(pkgver, pkgrel)| (pkgver, Some(pkgrel)));
+                let within = |e: ParseVersionError| ParseVersionError::new(s, e.reason);
+                epoch.parse::<Epoch>().map_err(within)?;
+                pkgver.parse::<Version>().map_err(within)?;
+                if let Some(pkgrel) = pkgrel {
+                    pkgrel.parse::<Release>().map_err(within)?;
+                }
+                Ok(Comparator {
+                    op,
+                    version: version.to_string(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { comparators })
+    }
+}
+
+impl Display for VersionReq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, comparator) in self.comparators.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{comparator}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for VersionReq {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionReq {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Compare two pacman version strings (`epoch:version-release`) like pacman's `vercmp`.
+///
+/// A missing epoch counts as `0`, and the release is only compared if both versions have one.
+#[must_use]
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (epoch_a, version_a, release_a) = split_evr(a);
+    let (epoch_b, version_b, release_b) = split_evr(b);
+    rpmvercmp(epoch_a, epoch_b)
+        .then_with(|| rpmvercmp(version_a, version_b))
+        .then_with(|| match (release_a, release_b) {
+            (Some(release_a), Some(release_b)) => rpmvercmp(release_a, release_b),
+            _ => Ordering::Equal,
+        })
+}
+
+/// Split a version string into epoch, version, and optional release.
+fn split_evr(s: &str) -> (&str, &str, Option<&str>) {
+    let (rest, release) = s
+        .rsplit_once('-')
+        .map_or((s, None), |(version, release)| (version, Some(release)));
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    if rest.as_bytes().get(digits) == Some(&b':') {
+        let epoch = if digits == 0 { "0" } else { &rest[..digits] };
+        (epoch, &rest[digits + 1..], release)
+    } else {
+        ("0", rest, release)
+    }
+}
+
+/// libalpm's segment-wise comparison: numeric and alphabetic runs are compared in turn, numbers
+/// beat letters, and a trailing letter run (`1.0rc1`) is older than nothing at all (`1.0`).
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (mut one, mut two) = (a.as_bytes(), b.as_bytes());
+    while !one.is_empty() && !two.is_empty() {
+        let separator_one = one
+            .iter()
+            .take_while(|c| !c.is_ascii_alphanumeric())
+            .count();
+        let separator_two = two
+            .iter()
+            .take_while(|c| !c.is_ascii_alphanumeric())
+            .count();
+        one = &one[separator_one..];
+        two = &two[separator_two..];
+        if one.is_empty() || two.is_empty() {
+            break;
+        }
+        if separator_one != separator_two {
+            return separator_one.cmp(&separator_two);
+        }
+        let numeric = one[0].is_ascii_digit();
+        let in_segment = |c: &&u8| {
+            if numeric {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+        let length_one = one.iter().take_while(in_segment).count();
+        let length_two = two.iter().take_while(in_segment).count();
+        if length_two == 0 {
+            return if numeric {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+        let (mut segment_one, mut segment_two) = (&one[..length_one], &two[..length_two]);
+        if numeric {
+            segment_one = &segment_one[segment_one.iter().take_while(|c| **c == b'0').count()..];
+            segment_two = &segment_two[segment_two.iter().take_while(|c| **c == b'0').count()..];
+            let by_length = segment_one.len().cmp(&segment_two.len());
+            if by_length != Ordering::Equal {
+                return by_length;
+            }
+        }
+        let by_content = segment_one.cmp(segment_two);
+        if by_content != Ordering::Equal {
+            return by_content;
+        }
+        one = &one[length_one..];
+        two = &two[length_two..];
+    }
+    if one.is_empty() && two.is_empty() {
+        return Ordering::Equal;
+    }
+    let alphabetic = |s: &[u8]| s.first().is_some_and(u8::is_ascii_alphabetic);
+    if (one.is_empty() && !alphabetic(two)) || alphabetic(one) {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cases from pacman's own vercmp test suite.
+    #[test]
+    fn vercmp_matches_pacman() {
+        for (a, b, expected) in [
+            ("1.5.0", "1.5.0", Ordering::Equal),
+            ("1.5.1", "1.5.0", Ordering::Greater),
+            ("1.5.10", "1.5.9", Ordering::Greater),
+            ("1.2.40.599", "1.2.40", Ordering::Greater),
+            ("1.5.0a", "1.5.0", Ordering::Less),
+            ("1.5rc1", "1.5", Ordering::Less),
+            ("1.5b", "1.5a", Ordering::Greater),
+            ("1.5.1", "1.5a", Ordering::Greater),
+            ("1.001", "1.1", Ordering::Equal),
+            ("1.5-2", "1.5-1", Ordering::Greater),
+            ("1.5-1", "1.5", Ordering::Equal),
+            ("1:1.0-1", "2.0-1", Ordering::Greater),
+            ("0:1.0", "1.0", Ordering::Equal),
+            ("1.0", "1.0.", Ordering::Less),
+            ("1..0", "1.0", Ordering::Greater),
+        ] {
+            assert_eq!(vercmp(a, b), expected, "vercmp({a}, {b})");
+            assert_eq!(vercmp(b, a), expected.reverse(), "vercmp({b}, {a})");
+        }
+    }
+
+    /// Requirements accept the versions in their range, with or without a release.
+    #[test]
+    fn matches_requirements() {
+        let req = |s: &str| s.parse::<VersionReq>().unwrap();
+        assert!(req(">=1.6").matches("1.6-1"));
+        assert!(req(">=1.6").matches("1.10.2-3"));
+        assert!(!req(">=1.6").matches("1.5.9-1"));
+        assert!(req("~5.15").matches("5.15.160-1"));
+        assert!(!req("~5.15").matches("5.16-1"));
+        assert!(!req("~5.15").matches("5.14.9-1"));
+        assert!(req("=2.45.0").matches("2.45.0-3"));
+        assert!(!req("=2.45.0-1").matches("2.45.0-3"));
+        assert!(req(">=1.6, <2").matches("1.9-1"));
+        assert!(!req(">=1.6, <2").matches("2.0-1"));
+        assert!(req("<1:1.0").matches("5.0-1"));
+        assert_eq!(req(">= 1.6,<2").to_string(), ">=1.6, <2");
+        assert!(VersionReq::is_requirement("~5.15"));
+        assert!(!VersionReq::is_requirement("5.15-1"));
+        assert!("1.6".parse::<VersionReq>().is_err());
+        assert!(">=1.6-x".parse::<VersionReq>().is_err());
+    }
+
+    /// Versions are classified against each other, with a missing epoch counting as 0.
+    #[test]
+    fn relates_versions() {
+        let relation = |a: &str, b: &str| FullVersion::from(a).relation_to(&FullVersion::from(b));
+        assert_eq!(relation("2.45.1-1", "2.44.0-1"), VersionRelation::Upgrade);
+        assert_eq!(relation("2.44.0-1", "2.44.0-2"), VersionRelation::Downgrade);
+        assert_eq!(relation("1.2.3-1", "0:1.2.3-1"), VersionRelation::Same);
+        assert_eq!(
+            relation("1:1.0.0-1", "2.0.0-1"),
+            VersionRelation::EpochOverride
+        );
+        assert_eq!(
+            relation("3.0.0-1", "1:1.0.0-1"),
+            VersionRelation::EpochOverride
+        );
+        assert_eq!(VersionRelation::Upgrade.to_string(), "newer");
+    }
+
+    /// Versions are shown and serialized exactly as they were written.
+    #[test]
+    fn keeps_original_text() {
+        let text = "1:1.2.3.r45.gabcdef-2";
+        let parsed: FullVersion = text.parse().unwrap();
+        assert_eq!(parsed.to_string(), text);
+        assert_eq!(FullVersion::from("9.1.0330-1").to_string(), "9.1.0330-1");
+        let yaml = serde_yml::to_string(&parsed).unwrap();
+        assert_eq!(serde_yml::from_str::<FullVersion>(&yaml).unwrap(), parsed);
+        let built = FullVersion {
+            original: None,
+            ..parsed
+        };
+        assert_eq!(built.to_string(), "1:1.2.3-2");
+    }
+
+    /// Versions are ordered like `vercmp`, also past the third component and for pre-releases.
+    #[test]
+    fn orders_like_vercmp() {
+        let version = |text: &str| text.parse::<FullVersion>().unwrap();
+        assert!(version("1.2.40.599-1") < version("1.2.40.1000-1"));
+        assert!(version("1.0rc1-1") < version("1.0-1"));
+        assert!(version("1:0.9-1") > version("2.0-1"));
+        assert_eq!(version("1.0-1").cmp(&version("1.0-1")), Ordering::Equal);
+        assert_ne!(version("1.0-1").cmp(&version("1.00-1")), Ordering::Equal);
+    }
+
+    /// Pacman versions parse; anything else is an error naming what is wrong.
+    #[test]
+    fn parses_versions_strictly() {
+        let parsed: FullVersion = "1:2.3.4-5".parse().unwrap();
+        assert_eq!(parsed, FullVersion::from("1:2.3.4-5"));
+        let vcs: FullVersion = "r1142.4e3d2c8-1.1".parse().unwrap();
+        assert_eq!((vcs.version.major, vcs.release.0), (0, 1));
+        assert_eq!("1.0rc1".parse::<Version>().unwrap().minor, 0);
+        for (input, reason) in [
+            ("garbage", "expected `[epoch:]version-release`"),
+            ("x:1.0-1", "the epoch must be a number"),
+            ("1.0 beta-1", "the version may only contain"),
+            ("-1", "the version is empty"),
+            ("1.0-a", "the release must be a number"),
+        ] {
+            let err = input.parse::<FullVersion>().unwrap_err();
+            assert_eq!(err.input, input);
+            assert!(err.reason.starts_with(reason), "{input}: {err}");
+        }
+    }
+
+    proptest::proptest! {
+        /// Any string can be parsed, converted, and compared without panicking, and comparisons
+        /// agree in both directions.
+        #[test]
+        fn arbitrary_versions_never_panic(a in "\\PC{0,24}", b in "[0-9a-z.:_+~<>=, -]{0,24}") {
+            let _ = a.parse::<FullVersion>();
+            let _ = a.parse::<Version>();
+            let _ = b.parse::<VersionReq>().map(|req| req.matches(&a));
+            let _ = FullVersion::from(a.as_str()).relation_to(&FullVersion::from(b.as_str()));
+            proptest::prop_assert_eq!(vercmp(&a, &b), vercmp(&b, &a).reverse());
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Scheduled drift detection
+//!
+//! # Overview
+//!
+//! `nixman watch` periodically compares the system with the YAML (the same logic as `diff`),
+//! records the result in a status file (`<state dir>/drift.json`), and sends a
+//! [desktop notification](crate::notify) when the system drifts.
+//!
+//! Instead of keeping a process running, `nixman install-timer` writes a systemd user service
+//! and timer ([`TIMER_NAME`]) that run `nixman watch --once` on a schedule. With `--clean`,
+//! each check also [cleans the package caches](crate::clean).
+
+use crate::SourceChanges;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::process::{Command, ExitStatus};
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the systemd user service that runs a single drift check.
+pub const SERVICE_NAME: &str = "nixman-drift.service";
+
+/// Name of the systemd user timer that schedules [`SERVICE_NAME`].
+pub const TIMER_NAME: &str = "nixman-drift.timer";
+
+/// The result of one drift check, as written to the status file.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct DriftStatus {
+    /// When the check ran, in seconds since the Unix epoch.
+    pub checked_at: u64,
+    pub in_sync: bool,
+    pub changes: Vec<SourceChanges>,
+}
+
+impl DriftStatus {
+    /// One-line summary for notifications, e.g. `"3 to install, 1 to remove"`.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let to_install: usize = self.changes.iter().map(|c| c.to_install.len()).sum();
+        let to_remove: usize = self.changes.iter().map(|c| c.to_remove.len()).sum();
+        format!("{to_install} to install, {to_remove} to remove")
+    }
+}
+
+/// Default location of the status file (`<state dir>/drift.json`).
+///
+/// # Errors
+/// Returns an error if the state directory cannot be created.
+pub fn status_path() -> std::io::Result<PathBuf> {
+    crate::paths::drift_file()
+}
+
+/// Compare the system with the YAML and write the result to `status_file`.
+///
+/// # Errors
+/// Returns an error if the changes cannot be computed or the status file cannot be written.
+#[cfg(target_os = "linux")]
+pub fn check<P: AsRef<Path>>(yml_path: P, status_file: &Path) -> std::io::Result<DriftStatus> {
+    let changes = crate::pending_changes(yml_path)?;
+    let status = DriftStatus {
+        checked_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        in_sync: changes.iter().all(SourceChanges::is_empty),
+        changes,
+    };
+    let json = serde_json::to_string_pretty(&status).map_err(std::io::Error::other)?;
+    std::fs::write(status_file, json)?;
+    Ok(status)
+}
+
+/// Parse an interval such as `6h`, `30m`, `1d`, `45s`, or a plain number of seconds.
+///
+/// # Errors
+/// Returns a message if the interval is malformed or zero.
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid interval `{s}`: expected e.g. 6h, 30m, 1d"))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid interval unit `{unit}`: use s, m, h, or d")),
+    };
+    match number.checked_mul(seconds) {
+        Some(0) => Err("interval must be greater than zero".to_string()),
+        Some(total) => Ok(Duration::from_secs(total)),
+        None => Err(format!("interval `{s}` is too large")),
+    }
+}
+
+/// Render the systemd user service and timer that run `<exe> watch --once` every `interval`,
+/// adding `--clean` if `clean`.
+#[must_use]
+pub fn timer_units(exe: &Path, interval: Duration, clean: bool) -> (String, String) {
+    let service = format!(
+        "[Unit]\n\
+         Description=Check for drift from the nixman package list\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={exe} watch --once{clean}\n\
+         # Exit code 2 means drift was found, which is not a failure of the check.\n\
+         SuccessExitStatus=2\n",
+        exe = exe.display(),
+        clean = if clean { " --clean" } else { "" },
+    );
+    let timer = format!(
+        "[Unit]\n\
+         Description=Periodically check for drift from the nixman package list\n\
+         \n\
+         [Timer]\n\
+         OnBootSec=5min\n\
+         OnUnitActiveSec={secs}s\n\
+         Persistent=true\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        secs = interval.as_secs(),
+    );
+    (service, timer)
+}
+
+/// Directory for systemd user units (`$XDG_CONFIG_HOME/systemd/user`).
+#[cfg(target_os = "linux")]
+fn user_unit_dir() -> PathBuf {
+    crate::paths::systemd_user_dir()
+}
+
+/// Write the drift service and timer to the user's systemd directory, then enable the timer.
+///
+/// # Errors
+/// Returns an error if the current executable cannot be resolved, the units cannot be written,
+/// or `systemctl` could not be executed.
+#[cfg(target_os = "linux")]
+pub fn install_timer(interval: Duration, clean: bool) -> std::io::Result<ExitStatus> {
+    let exe = std::env::current_exe()?;
+    let dir = user_unit_dir();
+    std::fs::create_dir_all(&dir)?;
+    let (service, timer) = timer_units(&exe, interval, clean);
+    std::fs::write(dir.join(SERVICE_NAME), service)?;
+    std::fs::write(dir.join(TIMER_NAME), timer)?;
+    let status = Command::new("systemctl")
+        .args(["--user", "daemon-reload"])
+        .status()?;
+    if !status.success() {
+        return Ok(status);
+    }
+    Command::new("systemctl")
+        .args(["--user", "enable", "--now", TIMER_NAME])
+        .status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Units are optional suffixes, and zero or unknown units are rejected.
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("6h"), Ok(Duration::from_hours(6)));
+        assert_eq!(parse_interval("30m"), Ok(Duration::from_mins(30)));
+        assert_eq!(parse_interval("1d"), Ok(Duration::from_hours(24)));
+        assert_eq!(parse_interval("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_interval("0h").is_err());
+        assert!(parse_interval("6w").is_err());
+        assert!(parse_interval("h").is_err());
+    }
+
+    /// The timer reruns the one-shot check at the requested interval.
+    #[test]
+    fn timer_runs_watch_once() {
+        let (service, timer) =
+            timer_units(Path::new("/usr/bin/nixman"), Duration::from_hours(6), false);
+        assert!(service.contains("ExecStart=/usr/bin/nixman watch --once\n"));
+        let (service, _) = timer_units(Path::new("/usr/bin/nixman"), Duration::from_hours(6), true);
+        assert!(service.contains("ExecStart=/usr/bin/nixman watch --once --clean\n"));
+        assert!(timer.contains("OnUnitActiveSec=21600s"));
+    }
+}